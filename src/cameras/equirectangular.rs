@@ -0,0 +1,130 @@
+use nalgebra_glm::{Vec2, Vec3};
+use serde_json::Value;
+
+use crate::cameras::{Camera, Shutter};
+use crate::core::ray::Ray;
+use crate::core::transform::AnimatedTransform;
+use crate::core::utils::{read_or, spherical_coordinates_to_direction};
+
+/// A 360° camera with a latitude-longitude (equirectangular) projection.
+///
+/// The horizontal axis of the image spans the full 360° of longitude, with the viewing direction (-z) in the middle,
+/// and the vertical axis spans 180° of latitude from the local up direction (+y) to the bottom. Images usually have a
+/// 2:1 aspect ratio and can be viewed as VR panoramas.
+#[derive(Debug)]
+pub struct EquirectangularCamera {
+    /// Local coordinate system
+    transform: AnimatedTransform,
+    /// Exposure interval
+    shutter: Shutter,
+    /// Image resolution
+    resolution: Vec2,
+}
+
+#[derive(Debug, Clone)]
+pub struct EquirectangularCameraBuilder {
+    transform: AnimatedTransform,
+    shutter: Shutter,
+    resolution: Vec2,
+}
+
+impl EquirectangularCameraBuilder {
+    pub fn new() -> EquirectangularCameraBuilder {
+        EquirectangularCameraBuilder {
+            transform: AnimatedTransform::default(),
+            shutter: Shutter::default(),
+            resolution: Vec2::new(1024., 512.),
+        }
+    }
+
+    /// Placement of the camera, either a `Transform` or an `AnimatedTransform` for a moving camera
+    pub fn transform(
+        mut self,
+        transform: impl Into<AnimatedTransform>,
+    ) -> EquirectangularCameraBuilder {
+        self.transform = transform.into();
+        self
+    }
+
+    /// Time interval during which the camera is exposed
+    pub fn shutter(mut self, shutter: Shutter) -> EquirectangularCameraBuilder {
+        self.shutter = shutter;
+        self
+    }
+
+    /// Image resolution in pixels
+    pub fn resolution(mut self, resolution: Vec2) -> EquirectangularCameraBuilder {
+        self.resolution = resolution;
+        self
+    }
+
+    pub fn build(self) -> EquirectangularCamera {
+        EquirectangularCamera {
+            transform: self.transform,
+            shutter: self.shutter,
+            resolution: self.resolution,
+        }
+    }
+}
+
+impl Default for EquirectangularCameraBuilder {
+    fn default() -> EquirectangularCameraBuilder {
+        EquirectangularCameraBuilder::new()
+    }
+}
+
+impl EquirectangularCamera {
+    pub fn new(json: &Value) -> EquirectangularCamera {
+        EquirectangularCameraBuilder::new()
+            .resolution(read_or(json, "resolution", Vec2::new(1024., 512.)))
+            .shutter(Shutter::read(json))
+            .transform(AnimatedTransform::read(json))
+            .build()
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn generate_ray(&self, pixel: Vec2, _rv: Vec2, rv_time: f32) -> Option<Ray> {
+        let uv = pixel.component_div(&self.resolution);
+        let phi_theta = Vec2::new(
+            (uv.x - 0.5) * 2.0 * std::f32::consts::PI,
+            uv.y * std::f32::consts::PI,
+        );
+        // spherical coordinates have their pole on z, rotate it to the local up direction
+        let d = spherical_coordinates_to_direction(phi_theta);
+        let direction = Vec3::new(d.y, d.z, -d.x);
+        let time = self.shutter.sample(rv_time);
+        Some(
+            self.transform
+                .ray(&Ray::new(Vec3::zeros(), direction).with_time(time)),
+        )
+    }
+
+    fn resolution(&self) -> Vec2 {
+        self.resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{normalize, Vec2, Vec3};
+
+    use crate::cameras::{Camera, EquirectangularCameraBuilder};
+
+    #[test]
+    fn covers_the_full_sphere() {
+        let camera = EquirectangularCameraBuilder::new()
+            .resolution(Vec2::new(360.0, 180.0))
+            .build();
+        let direction = |x: f32, y: f32| {
+            let ray = camera.generate_ray(Vec2::new(x, y), Vec2::new(0.5, 0.5), 0.5);
+            normalize(&ray.unwrap().direction)
+        };
+
+        approx::assert_abs_diff_eq!(direction(180.0, 90.0), -Vec3::z(), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(direction(270.0, 90.0), Vec3::x(), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(direction(0.0, 90.0), Vec3::z(), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(direction(180.0, 0.0), Vec3::y(), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(direction(180.0, 180.0), -Vec3::y(), epsilon = 1e-5);
+    }
+}
@@ -0,0 +1,159 @@
+use nalgebra_glm::{length, Vec2, Vec3};
+use serde_json::Value;
+
+use crate::cameras::{Camera, Shutter};
+use crate::core::ray::Ray;
+use crate::core::transform::AnimatedTransform;
+use crate::core::utils::{deg2rad, read_or};
+
+/// An equidistant fisheye camera.
+///
+/// The angle between a ray and the viewing direction (-z) is proportional to the distance of its pixel to the center
+/// of the image, up to `fov / 2` on the border of the image circle. The image circle fits the smallest dimension of
+/// the image, pixels outside of it do not generate any ray.
+#[derive(Debug)]
+pub struct FisheyeCamera {
+    /// Local coordinate system
+    transform: AnimatedTransform,
+    /// Exposure interval
+    shutter: Shutter,
+    /// Field of view across the image circle in radians
+    fov: f32,
+    /// Image resolution
+    resolution: Vec2,
+}
+
+#[derive(Debug, Clone)]
+pub struct FisheyeCameraBuilder {
+    transform: AnimatedTransform,
+    shutter: Shutter,
+    resolution: Vec2,
+    fov: f32,
+}
+
+impl FisheyeCameraBuilder {
+    pub fn new() -> FisheyeCameraBuilder {
+        FisheyeCameraBuilder {
+            transform: AnimatedTransform::default(),
+            shutter: Shutter::default(),
+            resolution: Vec2::new(512., 512.),
+            fov: 180.,
+        }
+    }
+
+    /// Placement of the camera, either a `Transform` or an `AnimatedTransform` for a moving camera
+    pub fn transform(mut self, transform: impl Into<AnimatedTransform>) -> FisheyeCameraBuilder {
+        self.transform = transform.into();
+        self
+    }
+
+    /// Time interval during which the camera is exposed
+    pub fn shutter(mut self, shutter: Shutter) -> FisheyeCameraBuilder {
+        self.shutter = shutter;
+        self
+    }
+
+    /// Image resolution in pixels
+    pub fn resolution(mut self, resolution: Vec2) -> FisheyeCameraBuilder {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Field of view across the image circle in degrees, can go up to 360
+    pub fn fov(mut self, fov: f32) -> FisheyeCameraBuilder {
+        self.fov = fov;
+        self
+    }
+
+    pub fn build(self) -> FisheyeCamera {
+        assert!(
+            self.fov > 0.0 && self.fov <= 360.0,
+            "fisheye fov should be in (0, 360]"
+        );
+        FisheyeCamera {
+            transform: self.transform,
+            shutter: self.shutter,
+            fov: deg2rad(self.fov),
+            resolution: self.resolution,
+        }
+    }
+}
+
+impl Default for FisheyeCameraBuilder {
+    fn default() -> FisheyeCameraBuilder {
+        FisheyeCameraBuilder::new()
+    }
+}
+
+impl FisheyeCamera {
+    pub fn new(json: &Value) -> FisheyeCamera {
+        FisheyeCameraBuilder::new()
+            .resolution(read_or(json, "resolution", Vec2::new(512., 512.)))
+            .fov(read_or(json, "fov", 180.))
+            .shutter(Shutter::read(json))
+            .transform(AnimatedTransform::read(json))
+            .build()
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn generate_ray(&self, pixel: Vec2, _rv: Vec2, rv_time: f32) -> Option<Ray> {
+        // position in the image circle, with y pointing up
+        let radius = self.resolution.min() / 2.0;
+        let p = Vec2::new(
+            pixel.x - self.resolution.x / 2.0,
+            self.resolution.y / 2.0 - pixel.y,
+        ) / radius;
+        let r = length(&p);
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = r * self.fov / 2.0;
+        let phi = f32::atan2(p.y, p.x);
+        let direction = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            -theta.cos(),
+        );
+        let time = self.shutter.sample(rv_time);
+        Some(
+            self.transform
+                .ray(&Ray::new(Vec3::zeros(), direction).with_time(time)),
+        )
+    }
+
+    fn resolution(&self) -> Vec2 {
+        self.resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{normalize, Vec2, Vec3};
+
+    use crate::cameras::{Camera, FisheyeCameraBuilder};
+
+    #[test]
+    fn angle_is_proportional_to_radius() {
+        let camera = FisheyeCameraBuilder::new()
+            .resolution(Vec2::new(100.0, 100.0))
+            .fov(180.0)
+            .build();
+        let rv = Vec2::new(0.5, 0.5);
+
+        let center = camera.generate_ray(Vec2::new(50.0, 50.0), rv, 0.5).unwrap();
+        approx::assert_abs_diff_eq!(normalize(&center.direction), -Vec3::z(), epsilon = 1e-5);
+
+        // the border of the image circle looks sideways, halfway looks at 45 degrees
+        let right = camera
+            .generate_ray(Vec2::new(100.0, 50.0), rv, 0.5)
+            .unwrap();
+        approx::assert_abs_diff_eq!(normalize(&right.direction), Vec3::x(), epsilon = 1e-5);
+        let up = camera.generate_ray(Vec2::new(50.0, 25.0), rv, 0.5).unwrap();
+        let expected = normalize(&Vec3::new(0.0, 1.0, -1.0));
+        approx::assert_abs_diff_eq!(normalize(&up.direction), expected, epsilon = 1e-5);
+
+        assert!(camera.generate_ray(Vec2::new(0.0, 0.0), rv, 0.5).is_none());
+    }
+}
@@ -0,0 +1,113 @@
+mod equirectangular;
+mod fisheye;
+mod orthographic;
+mod pinhole;
+
+use enum_dispatch::enum_dispatch;
+use nalgebra_glm::{Vec2, Vec3};
+use serde_json::Value;
+
+use crate::core::ray::{Ray, RayDifferentials};
+use crate::core::utils::read_or;
+
+/// This is the trait for all cameras.
+///
+/// The camera is responsible for generating primary rays. Cameras are positioned using a Transform and look along
+/// the -z axis of their local coordinate system, with y pointing up.
+#[enum_dispatch]
+pub trait Camera {
+    /// Generate a ray going through `pixel`, which is a continuous position in raster coordinates.
+    ///
+    /// `rv` is used to sample the lens and `rv_time` the time of the ray inside the shutter interval. Return `None`
+    /// if no ray goes through this pixel (e.g. outside of the image circle of a fisheye or when it is blocked by the
+    /// vignetting).
+    fn generate_ray(&self, pixel: Vec2, rv: Vec2, rv_time: f32) -> Option<Ray>;
+
+    /// Generate a ray through `pixel` like `generate_ray`, with the differentials of the rays through the next
+    /// pixels.
+    ///
+    /// The offset rays are traced with the same random variables, a short step away and scaled to one pixel, on the
+    /// other side when no ray goes through the next pixel. The ray has no differentials when neither side works.
+    fn generate_ray_differential(&self, pixel: Vec2, rv: Vec2, rv_time: f32) -> Option<Ray> {
+        let ray = self.generate_ray(pixel, rv, rv_time)?;
+        let offset = |axis: Vec2| -> Option<(Vec3, Vec3)> {
+            [0.05, -0.05].into_iter().find_map(|eps| {
+                let offset = self.generate_ray(pixel + axis * eps, rv, rv_time)?;
+                Some((
+                    ray.origin + (offset.origin - ray.origin) / eps,
+                    ray.direction + (offset.direction - ray.direction) / eps,
+                ))
+            })
+        };
+        let differentials = offset(Vec2::x()).zip(offset(Vec2::y())).map(
+            |((rx_origin, rx_direction), (ry_origin, ry_direction))| RayDifferentials {
+                rx_origin,
+                rx_direction,
+                ry_origin,
+                ry_direction,
+            },
+        );
+        Some(match differentials {
+            Some(differentials) => ray.with_differentials(differentials),
+            None => ray,
+        })
+    }
+
+    /// Image resolution in pixels
+    fn resolution(&self) -> Vec2;
+}
+
+/// Time interval during which the camera is exposed, for motion blur
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+}
+
+impl Shutter {
+    pub fn new(open: f32, close: f32) -> Shutter {
+        assert!(open <= close, "the shutter should open before it closes");
+        Shutter { open, close }
+    }
+
+    pub fn read(json: &Value) -> Shutter {
+        Shutter::new(
+            read_or(json, "shutter_open", 0.0),
+            read_or(json, "shutter_close", 0.0),
+        )
+    }
+
+    /// Uniformly sample a time while the shutter is open
+    pub fn sample(&self, rv: f32) -> f32 {
+        self.open + rv * (self.close - self.open)
+    }
+}
+
+pub use crate::cameras::equirectangular::{EquirectangularCamera, EquirectangularCameraBuilder};
+pub use crate::cameras::fisheye::{FisheyeCamera, FisheyeCameraBuilder};
+pub use crate::cameras::orthographic::{OrthographicCamera, OrthographicCameraBuilder};
+pub use crate::cameras::pinhole::{Aperture, PinholeCamera, PinholeCameraBuilder};
+
+#[enum_dispatch(Camera)]
+#[derive(Debug)]
+pub enum CameraType {
+    Pinhole(PinholeCamera),
+    Orthographic(OrthographicCamera),
+    Fisheye(FisheyeCamera),
+    Equirectangular(EquirectangularCamera),
+}
+
+pub fn create_camera(json: &Value) -> CameraType {
+    let camera_type = json
+        .get("type")
+        .map(|t| t.as_str().expect("could not get camera type"))
+        .unwrap_or("pinhole");
+
+    match camera_type {
+        "pinhole" | "perspective" => CameraType::Pinhole(PinholeCamera::new(json)),
+        "orthographic" => CameraType::Orthographic(OrthographicCamera::new(json)),
+        "fisheye" => CameraType::Fisheye(FisheyeCamera::new(json)),
+        "equirectangular" => CameraType::Equirectangular(EquirectangularCamera::new(json)),
+        _ => unimplemented!("Camera type {}", camera_type),
+    }
+}
@@ -0,0 +1,153 @@
+use nalgebra_glm::{Vec2, Vec3};
+use serde_json::Value;
+
+use crate::cameras::{Camera, Shutter};
+use crate::core::ray::Ray;
+use crate::core::transform::AnimatedTransform;
+use crate::core::utils::read_or;
+
+/// A camera with a parallel projection.
+///
+/// All the rays start on the local xy plane and travel along -z, so there is no perspective : objects keep the same
+/// size whatever their distance. The visible region is a rectangle of `view_size` world units, centered on the
+/// camera, which is useful for architectural elevations and plans.
+#[derive(Debug)]
+pub struct OrthographicCamera {
+    /// Local coordinate system
+    transform: AnimatedTransform,
+    /// Exposure interval
+    shutter: Shutter,
+    /// World-space size of the visible region
+    size: Vec2,
+    /// Image resolution
+    resolution: Vec2,
+}
+
+#[derive(Debug, Clone)]
+pub struct OrthographicCameraBuilder {
+    transform: AnimatedTransform,
+    shutter: Shutter,
+    resolution: Vec2,
+    view_size: f32,
+}
+
+impl OrthographicCameraBuilder {
+    pub fn new() -> OrthographicCameraBuilder {
+        OrthographicCameraBuilder {
+            transform: AnimatedTransform::default(),
+            shutter: Shutter::default(),
+            resolution: Vec2::new(512., 512.),
+            view_size: 2.,
+        }
+    }
+
+    /// Placement of the camera, either a `Transform` or an `AnimatedTransform` for a moving camera
+    pub fn transform(
+        mut self,
+        transform: impl Into<AnimatedTransform>,
+    ) -> OrthographicCameraBuilder {
+        self.transform = transform.into();
+        self
+    }
+
+    /// Time interval during which the camera is exposed
+    pub fn shutter(mut self, shutter: Shutter) -> OrthographicCameraBuilder {
+        self.shutter = shutter;
+        self
+    }
+
+    /// Image resolution in pixels
+    pub fn resolution(mut self, resolution: Vec2) -> OrthographicCameraBuilder {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Height of the visible region in world units, the width follows the aspect ratio of the image
+    pub fn view_size(mut self, view_size: f32) -> OrthographicCameraBuilder {
+        self.view_size = view_size;
+        self
+    }
+
+    pub fn build(self) -> OrthographicCamera {
+        let width = self.resolution[0] / self.resolution[1] * self.view_size;
+        OrthographicCamera {
+            transform: self.transform,
+            shutter: self.shutter,
+            size: Vec2::new(width, -self.view_size),
+            resolution: self.resolution,
+        }
+    }
+}
+
+impl Default for OrthographicCameraBuilder {
+    fn default() -> OrthographicCameraBuilder {
+        OrthographicCameraBuilder::new()
+    }
+}
+
+impl OrthographicCamera {
+    pub fn new(json: &Value) -> OrthographicCamera {
+        OrthographicCameraBuilder::new()
+            .resolution(read_or(json, "resolution", Vec2::new(512., 512.)))
+            .view_size(read_or(json, "view_size", 2.))
+            .shutter(Shutter::read(json))
+            .transform(AnimatedTransform::read(json))
+            .build()
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn generate_ray(&self, pixel: Vec2, _rv: Vec2, rv_time: f32) -> Option<Ray> {
+        let xy = self
+            .size
+            .component_mul(&pixel)
+            .component_div(&self.resolution)
+            - self.size / 2.0;
+        let origin = Vec3::new(xy.x, xy.y, 0.0);
+        let time = self.shutter.sample(rv_time);
+        Some(
+            self.transform
+                .ray(&Ray::new(origin, -Vec3::z()).with_time(time)),
+        )
+    }
+
+    fn resolution(&self) -> Vec2 {
+        self.resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{normalize, Vec2, Vec3};
+
+    use crate::cameras::{Camera, OrthographicCameraBuilder};
+    use crate::core::transform::Transform;
+
+    #[test]
+    fn rays_are_parallel() {
+        let camera = OrthographicCameraBuilder::new()
+            .resolution(Vec2::new(200.0, 100.0))
+            .view_size(4.0)
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, 5.0)))
+            .build();
+
+        let rv = Vec2::new(0.5, 0.5);
+        let top_left = camera.generate_ray(Vec2::new(0.0, 0.0), rv, 0.5).unwrap();
+        let bottom_right = camera
+            .generate_ray(Vec2::new(200.0, 100.0), rv, 0.5)
+            .unwrap();
+
+        approx::assert_abs_diff_eq!(top_left.origin, Vec3::new(-4.0, 2.0, 5.0), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(
+            bottom_right.origin,
+            Vec3::new(4.0, -2.0, 5.0),
+            epsilon = 1e-5
+        );
+        approx::assert_abs_diff_eq!(normalize(&top_left.direction), -Vec3::z(), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(
+            normalize(&bottom_right.direction),
+            -Vec3::z(),
+            epsilon = 1e-5
+        );
+    }
+}
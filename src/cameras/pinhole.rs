@@ -0,0 +1,421 @@
+use nalgebra_glm::{length, Vec2, Vec3};
+use serde_json::Value;
+
+use crate::cameras::{Camera, Shutter};
+use crate::core::assets::load_image_as;
+use crate::core::distribution::Distribution1d;
+use crate::core::image2d::{Channel, ColorSpace, Image2d};
+use crate::core::ray::Ray;
+use crate::core::sampling::{sample_disk, sample_regular_polygon};
+use crate::core::transform::AnimatedTransform;
+use crate::core::utils::{deg2rad, luminance, read, read_or};
+
+/// A virtual pinhole camera, with an optional thin lens for depth of field.
+///
+/// The camera is responsible for generating primary rays. It is positioned
+/// using a Transform and points along the -z axis of the local coordinate
+/// system. It has an image plane positioned a z = -dist with size
+/// (width, height), where dist is the focus distance.
+///
+/// When the aperture radius is not zero, ray origins are sampled on the lens
+/// (in the local xy plane) and all the rays of a pixel converge on the focus
+/// plane. The aperture can be circular, polygonal (like the blades of a real
+/// lens) or given by a mask image, which shapes the out of focus highlights.
+///
+/// The camera setup looks something like this, where the
+/// up vector points out of the screen:
+///
+/// ```text
+///         top view                         side view
+///            ^                    up
+///            |                     ^
+///            |                     |             _,-'
+///          width                   |         _,-'   |
+///       +----|----+     +          |     _,-'       | h
+///        \   |   /    d |        e | _,-'           | e
+///         \  |  /     i |        y +'---------------+-i----->
+///          \ | /      s |        e  '-,_   dist     | g
+///           \|/       t |               '-,_        | h
+///            +          +                   '-,_    | t
+///           eye                                 '-,_|
+/// ```
+
+#[derive(Debug)]
+pub struct PinholeCamera {
+    /// Local coordinate system
+    transform: AnimatedTransform,
+    /// Exposure interval
+    shutter: Shutter,
+    /// Physical size of the image plane
+    size: Vec2,
+    /// Distance to the plane in focus along local z axis
+    focus_distance: f32,
+    /// Image resolution
+    resolution: Vec2,
+    /// The size of the aperture for depth of field
+    aperture_radius: f32,
+    /// The shape of the aperture
+    aperture: Aperture,
+    /// Strength of the optical (cat-eye) vignetting, 0 to disable it
+    cat_eye: f32,
+}
+
+/// Shape of the lens aperture
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aperture {
+    Circular,
+    /// Regular polygon, `rotation` is in radians
+    Polygonal {
+        blades: u32,
+        rotation: f32,
+    },
+    /// Opening given by the luminance of an image
+    Mask {
+        width: usize,
+        height: usize,
+        distribution: Distribution1d,
+    },
+}
+
+impl Aperture {
+    pub fn mask(image: &Image2d) -> Aperture {
+        let weights: Vec<f32> = image.data.iter().map(|c| luminance(c).max(0.0)).collect();
+        let distribution = Distribution1d::new(&weights);
+        assert!(
+            distribution.func_sum() > 0.0,
+            "the aperture mask should not be completely black"
+        );
+        Aperture::Mask {
+            width: image.size_x,
+            height: image.size_y,
+            distribution,
+        }
+    }
+
+    /// Sample a point on the aperture, which fits inside the unit disk (or the unit square for masks)
+    pub fn sample(&self, rv: Vec2) -> Vec2 {
+        match self {
+            Aperture::Circular => sample_disk(rv),
+            Aperture::Polygonal { blades, rotation } => {
+                sample_regular_polygon(*blades, *rotation, rv)
+            }
+            Aperture::Mask {
+                width,
+                height,
+                distribution,
+            } => {
+                let (index, _, remapped) = distribution.sample(rv.x);
+                let x = (index % width) as f32 + remapped;
+                let y = (index / width) as f32 + rv.y;
+                // keep the aspect ratio of the mask, image rows go down
+                let scale = usize::max(*width, *height) as f32;
+                Vec2::new(
+                    (2.0 * x - *width as f32) / scale,
+                    (*height as f32 - 2.0 * y) / scale,
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PinholeCameraBuilder {
+    transform: AnimatedTransform,
+    shutter: Shutter,
+    resolution: Vec2,
+    aperture_radius: f32,
+    aperture: Aperture,
+    focus_distance: f32,
+    vfov: f32,
+    cat_eye: f32,
+}
+
+impl PinholeCameraBuilder {
+    pub fn new() -> PinholeCameraBuilder {
+        PinholeCameraBuilder {
+            transform: AnimatedTransform::default(),
+            shutter: Shutter::default(),
+            resolution: Vec2::new(512., 512.),
+            aperture_radius: 0.,
+            aperture: Aperture::Circular,
+            focus_distance: 1.,
+            vfov: 90.,
+            cat_eye: 0.,
+        }
+    }
+
+    /// Placement of the camera, either a `Transform` or an `AnimatedTransform` for a moving camera
+    pub fn transform(mut self, transform: impl Into<AnimatedTransform>) -> PinholeCameraBuilder {
+        self.transform = transform.into();
+        self
+    }
+
+    /// Time interval during which the camera is exposed
+    pub fn shutter(mut self, shutter: Shutter) -> PinholeCameraBuilder {
+        self.shutter = shutter;
+        self
+    }
+
+    /// Image resolution in pixels
+    pub fn resolution(mut self, resolution: Vec2) -> PinholeCameraBuilder {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Radius of the aperture, 0 for a pinhole
+    pub fn aperture(mut self, aperture_radius: f32) -> PinholeCameraBuilder {
+        self.aperture_radius = aperture_radius;
+        self
+    }
+
+    /// Set the aperture radius from an f-number and a focal length in scene units
+    pub fn fstop(mut self, fstop: f32, focal_length: f32) -> PinholeCameraBuilder {
+        assert!(fstop > 0.0, "fstop should be positive");
+        self.aperture_radius = focal_length / (2.0 * fstop);
+        self
+    }
+
+    /// Polygonal aperture with `blades` sides, rotated by `rotation` degrees
+    pub fn blades(mut self, blades: u32, rotation: f32) -> PinholeCameraBuilder {
+        assert!(blades >= 3, "an aperture needs at least 3 blades");
+        self.aperture = Aperture::Polygonal {
+            blades,
+            rotation: deg2rad(rotation),
+        };
+        self
+    }
+
+    /// Aperture shaped like the luminance of `mask`, scaled to fit the aperture radius
+    pub fn aperture_mask(mut self, mask: &Image2d) -> PinholeCameraBuilder {
+        self.aperture = Aperture::mask(mask);
+        self
+    }
+
+    /// Distance to the plane in focus
+    pub fn focus_distance(mut self, focus_distance: f32) -> PinholeCameraBuilder {
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    /// Vertical field of view in degrees
+    pub fn vfov(mut self, vfov: f32) -> PinholeCameraBuilder {
+        self.vfov = vfov;
+        self
+    }
+
+    /// Optical vignetting : at 1, the aperture seen from the corners of the image is cut in half
+    pub fn cat_eye(mut self, cat_eye: f32) -> PinholeCameraBuilder {
+        self.cat_eye = cat_eye;
+        self
+    }
+
+    pub fn build(self) -> PinholeCamera {
+        let height = 2.0 * self.focus_distance * deg2rad(self.vfov / 2.0).tan();
+        let width = self.resolution[0] / self.resolution[1] * height;
+        let size = Vec2::new(width, -height);
+
+        PinholeCamera {
+            transform: self.transform,
+            shutter: self.shutter,
+            size,
+            focus_distance: self.focus_distance,
+            resolution: self.resolution,
+            aperture_radius: self.aperture_radius,
+            aperture: self.aperture,
+            cat_eye: self.cat_eye,
+        }
+    }
+}
+
+impl Default for PinholeCameraBuilder {
+    fn default() -> PinholeCameraBuilder {
+        PinholeCameraBuilder::new()
+    }
+}
+
+impl PinholeCamera {
+    pub fn new(json: &Value) -> PinholeCamera {
+        // "fdist" is the name of the focus distance in older scenes
+        let focus_distance = read_or(json, "focus_distance", read_or(json, "fdist", 1.));
+        let mut builder = PinholeCameraBuilder::new()
+            .resolution(read_or(json, "resolution", Vec2::new(512., 512.)))
+            .aperture(read_or(json, "aperture", 0.))
+            .focus_distance(focus_distance)
+            .vfov(read_or(json, "vfov", 90.))
+            .cat_eye(read_or(json, "cat_eye", 0.))
+            .shutter(Shutter::read(json))
+            .transform(AnimatedTransform::read(json));
+
+        if json.get("fstop").is_some() {
+            builder = builder.fstop(read(json, "fstop"), read_or(json, "focal_length", 0.05));
+        }
+        if json.get("blades").is_some() {
+            builder = builder.blades(read(json, "blades"), read_or(json, "blade_rotation", 0.));
+        }
+        if json.get("aperture_mask").is_some() {
+            // the mask is a transmittance, not a color
+            let mask = load_image_as(
+                &read::<String>(json, "aperture_mask"),
+                ColorSpace::Linear,
+                Channel::Rgb,
+            );
+            builder = builder.aperture_mask(&mask);
+        }
+        builder.build()
+    }
+}
+
+impl Camera for PinholeCamera {
+    fn generate_ray(&self, pixel: Vec2, rv: Vec2, rv_time: f32) -> Option<Ray> {
+        // point on the plane in focus
+        let xy = self
+            .size
+            .component_mul(&pixel)
+            .component_div(&self.resolution)
+            - self.size / 2.0;
+
+        let lens = self.aperture.sample(rv);
+        if self.cat_eye > 0.0 {
+            // the aperture is clipped by a second pupil shifted towards the edges of the image
+            let shift = self.cat_eye * xy / length(&(self.size / 2.0));
+            if length(&(lens - shift)) > 1.0 {
+                return None;
+            }
+        }
+
+        let offset = self.aperture_radius * lens;
+        let origin = Vec3::new(offset.x, offset.y, 0.0);
+        let xy = xy - offset;
+        let direction = Vec3::new(xy.x, xy.y, -self.focus_distance);
+        let time = self.shutter.sample(rv_time);
+        Some(
+            self.transform
+                .ray(&Ray::new(origin, direction).with_time(time)),
+        )
+    }
+
+    fn resolution(&self) -> Vec2 {
+        self.resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{length, Vec2, Vec3};
+
+    use crate::cameras::{Aperture, Camera, PinholeCameraBuilder, Shutter};
+    use crate::core::image2d::Image2d;
+    use crate::core::transform::{AnimatedTransform, Transform};
+    use crate::materials::LambertianBuilder;
+    use crate::surfaces::{QuadBuilder, Surface};
+
+    #[test]
+    fn rays_converge_on_focus_plane() {
+        let focus_distance = 3.0;
+        let camera = PinholeCameraBuilder::new()
+            .resolution(Vec2::new(64.0, 64.0))
+            .aperture(0.5)
+            .blades(6, 15.0)
+            .focus_distance(focus_distance)
+            .build();
+
+        let pixel = Vec2::new(10.5, 40.5);
+        let reference = camera
+            .generate_ray(pixel, Vec2::new(0.5, 0.5), 0.5)
+            .unwrap();
+        let reference = reference.at(-focus_distance / reference.direction.z);
+        for rv in [
+            Vec2::new(0.1, 0.2),
+            Vec2::new(0.7, 0.9),
+            Vec2::new(0.99, 0.01),
+        ] {
+            let ray = camera.generate_ray(pixel, rv, 0.5).unwrap();
+            assert!(length(&ray.origin) <= 0.5 + 1e-5);
+            let p = ray.at(-focus_distance / ray.direction.z);
+            approx::assert_abs_diff_eq!(p, reference, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn differentials_span_a_pixel() {
+        // a quad filling the view, so that each pixel covers 1/64 of its uv square
+        let camera = PinholeCameraBuilder::new()
+            .resolution(Vec2::new(64.0, 64.0))
+            .vfov(90.0)
+            .build();
+        let quad = QuadBuilder::new(LambertianBuilder::new().build())
+            .size(Vec2::new(2.0, 2.0))
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, -1.0)))
+            .build();
+
+        let ray = camera
+            .generate_ray_differential(Vec2::new(20.5, 40.5), Vec2::zeros(), 0.0)
+            .unwrap();
+        let mut hit = quad.intersect(&ray).unwrap();
+        hit.compute_differentials(&ray);
+        approx::assert_abs_diff_eq!(hit.duvdx.abs(), Vec2::new(1.0 / 64.0, 0.0), epsilon = 1e-4);
+        approx::assert_abs_diff_eq!(hit.duvdy.abs(), Vec2::new(0.0, 1.0 / 64.0), epsilon = 1e-4);
+    }
+
+    #[test]
+    fn mask_aperture_samples_open_pixels() {
+        // only the top-right pixel of the mask is open
+        let mut mask = Image2d::new(2, 2);
+        mask[(1, 0)] = Vec3::new(1.0, 1.0, 1.0);
+        let aperture = Aperture::mask(&mask);
+
+        for rv in [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.5, 0.5),
+            Vec2::new(0.99, 0.99),
+        ] {
+            let p = aperture.sample(rv);
+            assert!((0.0..=1.0).contains(&p.x) && (0.0..=1.0).contains(&p.y));
+        }
+    }
+
+    #[test]
+    fn cat_eye_vignetting_blocks_corners() {
+        let camera = PinholeCameraBuilder::new()
+            .resolution(Vec2::new(64.0, 64.0))
+            .aperture(0.1)
+            .cat_eye(1.0)
+            .build();
+
+        let blocked = |pixel: Vec2| {
+            (0..100)
+                .filter(|i| {
+                    let rv = Vec2::new((*i % 10) as f32 / 10.0, (*i / 10) as f32 / 10.0);
+                    camera.generate_ray(pixel, rv, 0.5).is_none()
+                })
+                .count()
+        };
+        assert_eq!(blocked(Vec2::new(32.0, 32.0)), 0);
+        assert!(blocked(Vec2::new(0.0, 0.0)) > 20);
+    }
+
+    #[test]
+    fn moving_camera_follows_shutter() {
+        let motion = AnimatedTransform::new(vec![
+            (0.0, Transform::default()),
+            (1.0, Transform::translate(&Vec3::new(2.0, 0.0, 0.0))),
+        ]);
+        let camera = PinholeCameraBuilder::new()
+            .transform(motion)
+            .shutter(Shutter::new(0.5, 1.0))
+            .build();
+
+        let pixel = Vec2::new(256.0, 256.0);
+        let ray = camera
+            .generate_ray(pixel, Vec2::new(0.5, 0.5), 0.0)
+            .unwrap();
+        approx::assert_abs_diff_eq!(ray.time, 0.5);
+        approx::assert_abs_diff_eq!(ray.origin, Vec3::new(1.0, 0.0, 0.0), epsilon = 1e-5);
+
+        let ray = camera
+            .generate_ray(pixel, Vec2::new(0.5, 0.5), 0.5)
+            .unwrap();
+        approx::assert_abs_diff_eq!(ray.time, 0.75);
+        approx::assert_abs_diff_eq!(ray.origin, Vec3::new(1.5, 0.0, 0.0), epsilon = 1e-5);
+    }
+}
@@ -11,6 +11,12 @@ pub struct Aabb {
     pub max: Vec3,
 }
 
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb::new()
+    }
+}
+
 impl Aabb {
     pub const fn new() -> Aabb {
         Aabb {
@@ -0,0 +1,299 @@
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::core::assets::evict_unused_assets;
+use crate::core::scene::{Scene, SceneCache};
+use crate::core::utils::{read, read_or};
+
+/// Scalar value interpolated linearly between keyframes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalarTrack {
+    /// `(time, value)`, sorted by time
+    keyframes: Vec<(f32, f32)>,
+}
+
+impl ScalarTrack {
+    pub fn new(mut keyframes: Vec<(f32, f32)>) -> ScalarTrack {
+        assert!(!keyframes.is_empty(), "a track needs keyframes");
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        ScalarTrack { keyframes }
+    }
+
+    /// Read `[{"time": 0, "value": 1}, ...]`
+    fn read(v: &Value) -> ScalarTrack {
+        let keyframes = v
+            .as_array()
+            .expect("scalar keyframes should be an array")
+            .iter()
+            .map(|keyframe| (read(keyframe, "time"), read(keyframe, "value")))
+            .collect();
+        ScalarTrack::new(keyframes)
+    }
+
+    pub fn at(&self, time: f32) -> f32 {
+        let i = self.keyframes.partition_point(|k| k.0 <= time);
+        if i == 0 {
+            return self.keyframes[0].1;
+        }
+        if i == self.keyframes.len() {
+            return self.keyframes[i - 1].1;
+        }
+        let (t0, v0) = self.keyframes[i - 1];
+        let (t1, v1) = self.keyframes[i];
+        v0 + (v1 - v0) * (time - t0) / (t1 - t0)
+    }
+}
+
+/// The "animation" section of a scene.
+///
+/// Frame `f` is rendered at time `f / fps` seconds. The camera and the named surfaces follow transform keyframes,
+/// written like the "motion" of an object : `[{"time": 0, "transform": {...}}, ...]`. They are shifted to the time
+/// of each frame and become the "motion" of the object, so a camera shutter (in seconds) also gives motion blur.
+/// Scalar parameters of named materials follow `[{"time": 0, "value": 0.1}, ...]` keyframes.
+///
+/// ```json
+/// "animation": {
+///     "frames": [1, 48],
+///     "fps": 24,
+///     "camera": [{"time": 0, "transform": {...}}, {"time": 2, "transform": {...}}],
+///     "surfaces": {"teapot": [{"time": 0, "transform": {...}}, ...]},
+///     "materials": {"floor": {"roughness": [{"time": 0, "value": 0.1}, ...]}}
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    /// First and last frames, both included
+    pub frame_start: i32,
+    pub frame_end: i32,
+    /// Frames per second
+    pub fps: f32,
+    /// Transform keyframes of the camera
+    camera: Option<Vec<Value>>,
+    /// Transform keyframes of the surfaces, by name
+    surfaces: HashMap<String, Vec<Value>>,
+    /// Scalar tracks of the materials, by material name then parameter name
+    materials: HashMap<String, HashMap<String, ScalarTrack>>,
+}
+
+impl Animation {
+    pub fn new(v: &Value) -> Animation {
+        let [frame_start, frame_end] = read_or(v, "frames", [1, 1]);
+        assert!(frame_start <= frame_end, "the animation has no frames");
+        let fps = read_or(v, "fps", 24.0);
+        assert!(fps > 0.0, "fps should be positive");
+
+        let keyframes = |v: &Value| -> Vec<Value> {
+            v.as_array()
+                .expect("transform keyframes should be an array")
+                .clone()
+        };
+        let camera = v.get("camera").map(keyframes);
+        let surfaces = v
+            .get("surfaces")
+            .and_then(Value::as_object)
+            .map(|m| m.iter().map(|(k, v)| (k.clone(), keyframes(v))).collect())
+            .unwrap_or_default();
+        let materials = v
+            .get("materials")
+            .and_then(Value::as_object)
+            .map(|m| {
+                m.iter()
+                    .map(|(name, params)| {
+                        let tracks = params
+                            .as_object()
+                            .expect("material tracks should be an object")
+                            .iter()
+                            .map(|(param, track)| (param.clone(), ScalarTrack::read(track)))
+                            .collect();
+                        (name.clone(), tracks)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Animation {
+            frame_start,
+            frame_end,
+            fps,
+            camera,
+            surfaces,
+            materials,
+        }
+    }
+
+    /// Read the "animation" section of a scene, if there is one
+    pub fn read(scene_json: &Value) -> Option<Animation> {
+        scene_json.get("animation").map(Animation::new)
+    }
+
+    pub fn frames(&self) -> RangeInclusive<i32> {
+        self.frame_start..=self.frame_end
+    }
+
+    /// Time of a frame in seconds
+    pub fn frame_time(&self, frame: i32) -> f32 {
+        frame as f32 / self.fps
+    }
+
+    /// Scene description of a single frame, with all the animated values applied
+    pub fn frame_json(&self, scene_json: &Value, frame: i32) -> Value {
+        let time = self.frame_time(frame);
+        let mut frame_json = scene_json.clone();
+        let map = frame_json.as_object_mut().unwrap();
+        map.remove("animation");
+
+        if let Some(keyframes) = &self.camera {
+            let camera = map
+                .get_mut("camera")
+                .and_then(Value::as_object_mut)
+                .expect("No camera specified in scene!");
+            camera.insert("motion".to_string(), shift_keyframes(keyframes, time));
+        }
+
+        for (name, keyframes) in &self.surfaces {
+            let surfaces = map.get_mut("surfaces").expect("No surfaces to animate");
+            let found = set_surface_motion(surfaces, name, &shift_keyframes(keyframes, time));
+            assert!(found, "no surface named '{name}' to animate");
+        }
+
+        for (name, tracks) in &self.materials {
+            let material = map
+                .get_mut("materials")
+                .and_then(Value::as_array_mut)
+                .and_then(|materials| {
+                    materials
+                        .iter_mut()
+                        .find(|m| m.get("name").and_then(Value::as_str) == Some(name))
+                })
+                .and_then(Value::as_object_mut)
+                .unwrap_or_else(|| panic!("no material named '{name}' to animate"));
+            for (param, track) in tracks {
+                material.insert(param.clone(), json!(track.at(time)));
+            }
+        }
+
+        frame_json
+    }
+
+    /// Build the scene of every frame.
+    ///
+    /// Surfaces whose description did not change since the previous frame are reused, and the mesh and image files
+    /// still used by the previous frame are not loaded again. The files only the previous frames used are evicted from
+    /// the asset caches once each frame is built.
+    pub fn scenes<'a>(&'a self, scene_json: &'a Value) -> impl Iterator<Item = (i32, Scene)> + 'a {
+        let mut cache = SceneCache::default();
+        self.frames().map(move |frame| {
+            let frame_json = self.frame_json(scene_json, frame);
+            let scene = Scene::new_cached(&frame_json, &mut cache);
+            evict_unused_assets();
+            (frame, scene)
+        })
+    }
+}
+
+/// Move the keyframes so that `time` becomes the time 0 of the frame
+fn shift_keyframes(keyframes: &[Value], time: f32) -> Value {
+    keyframes
+        .iter()
+        .map(|keyframe| {
+            let mut keyframe = keyframe.clone();
+            let keyframe_time: f32 = read_or(&keyframe, "time", 0.0);
+            keyframe["time"] = json!(keyframe_time - time);
+            keyframe
+        })
+        .collect()
+}
+
+/// Set the "motion" of the surfaces called `name`, looking inside groups. Return whether one was found.
+fn set_surface_motion(surfaces: &mut Value, name: &str, motion: &Value) -> bool {
+    let mut found = false;
+    for surface in surfaces
+        .as_array_mut()
+        .expect("Surfaces should be in an array")
+    {
+        let surface: &mut Map<String, Value> = surface.as_object_mut().unwrap();
+        if surface.get("name").and_then(Value::as_str) == Some(name) {
+            surface.insert("motion".to_string(), motion.clone());
+            found = true;
+        }
+        if let Some(children) = surface.get_mut("children") {
+            found |= set_surface_motion(children, name, motion);
+        }
+    }
+    found
+}
+
+/// Name of the image of a frame : `out.png` becomes `out_0001.png`
+pub fn frame_filename(outfile: &Path, frame: i32) -> PathBuf {
+    let stem = outfile.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match outfile.extension() {
+        Some(extension) => format!("{stem}_{frame:04}.{}", extension.to_string_lossy()),
+        None => format!("{stem}_{frame:04}"),
+    };
+    outfile.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    use crate::core::animation::{frame_filename, Animation, ScalarTrack};
+    use crate::core::transform::AnimatedTransform;
+
+    #[test]
+    fn scalar_track_interpolates() {
+        let track = ScalarTrack::new(vec![(2.0, 1.0), (0.0, 0.0)]);
+        approx::assert_abs_diff_eq!(track.at(-1.0), 0.0);
+        approx::assert_abs_diff_eq!(track.at(0.5), 0.25);
+        approx::assert_abs_diff_eq!(track.at(3.0), 1.0);
+    }
+
+    #[test]
+    fn frames_apply_keyframes() {
+        let scene = json!({
+            "camera": {"resolution": [8, 8]},
+            "materials": [{"type": "metal", "name": "shiny", "roughness": 0.0}],
+            "surfaces": [
+                {"type": "group", "children": [
+                    {"type": "sphere", "name": "ball", "material": "shiny"}
+                ]}
+            ],
+            "animation": {
+                "frames": [0, 4],
+                "fps": 2,
+                "surfaces": {"ball": [
+                    {"time": 0, "transform": {"translate": [0, 0, 0]}},
+                    {"time": 2, "transform": {"translate": [4, 0, 0]}}
+                ]},
+                "materials": {"shiny": {"roughness": [
+                    {"time": 0, "value": 0.0},
+                    {"time": 2, "value": 1.0}
+                ]}}
+            }
+        });
+        let animation = Animation::read(&scene).unwrap();
+        assert_eq!(animation.frames().count(), 5);
+
+        // frame 1 is at 0.5s, a quarter of the way
+        let frame = animation.frame_json(&scene, 1);
+        assert!(frame.get("animation").is_none());
+        approx::assert_abs_diff_eq!(frame["materials"][0]["roughness"].as_f64().unwrap(), 0.25);
+
+        let ball = &frame["surfaces"][0]["children"][0];
+        let motion = AnimatedTransform::read(ball);
+        let p = motion.at(0.0).point(&Vec3::zeros());
+        approx::assert_abs_diff_eq!(p, Vec3::new(1.0, 0.0, 0.0), epsilon = 1e-5);
+    }
+
+    #[test]
+    fn frame_filenames_are_numbered() {
+        assert_eq!(
+            frame_filename(Path::new("renders/out.png"), 12),
+            PathBuf::from("renders/out_0012.png")
+        );
+    }
+}
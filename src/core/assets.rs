@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use crate::core::ies::IesProfile;
+use crate::core::image2d::{Channel, ColorSpace, Image2d};
+use crate::core::merl::MerlBrdf;
+use crate::core::mipmap::{build_levels, FilterMode, MipLevels, MipMap, WrapMode};
+
+type ImageKey = (String, ColorSpace, Channel);
+
+lazy_static::lazy_static! {
+    static ref IMAGES: Mutex<HashMap<ImageKey, Arc<Image2d>>> = Mutex::new(HashMap::new());
+    static ref MIPMAPS: Mutex<HashMap<(ImageKey, WrapMode), Arc<MipLevels>>> =
+        Mutex::new(HashMap::new());
+    static ref MODELS: Mutex<HashMap<String, Arc<Vec<tobj::Model>>>> = Mutex::new(HashMap::new());
+    static ref BRDFS: Mutex<HashMap<String, Arc<MerlBrdf>>> = Mutex::new(HashMap::new());
+    static ref PROFILES: Mutex<HashMap<String, Arc<IesProfile>>> = Mutex::new(HashMap::new());
+}
+
+/// Entry of `cache` for `key`, or the value of `load` stored there.
+///
+/// Files are loaded without holding the lock, so that a file failing to load does not poison the cache for the other ones.
+fn cached<K: Eq + Hash, V>(
+    cache: &Mutex<HashMap<K, Arc<V>>>,
+    key: K,
+    load: impl FnOnce() -> V,
+) -> Arc<V> {
+    if let Some(value) = cache.lock().unwrap().get(&key) {
+        return value.clone();
+    }
+    let value = Arc::new(load());
+    cache.lock().unwrap().entry(key).or_insert(value).clone()
+}
+
+/// Load an image file, or return the already loaded copy.
+///
+/// Files are cached by name so that scenes rebuilt for every frame of an animation only decode each image once. They
+/// stay cached until `evict_unused_assets` finds them unused. The color space is guessed from the format of the file, see `load_image_as` to choose it.
+pub fn load_image(filename: &str) -> Arc<Image2d> {
+    load_image_as(filename, ColorSpace::Auto, Channel::Rgb)
+}
+
+/// Load an image file decoded from `color_space`, keeping only `channel`, or return the already loaded copy
+pub fn load_image_as(filename: &str, color_space: ColorSpace, channel: Channel) -> Arc<Image2d> {
+    cached(
+        &IMAGES,
+        (filename.to_string(), color_space, channel),
+        || Image2d::load(filename, color_space, channel),
+    )
+}
+
+/// Mipmap of an image file, the levels are only built once for each file and wrap mode
+pub fn load_mipmap(
+    filename: &str,
+    color_space: ColorSpace,
+    channel: Channel,
+    wrap: WrapMode,
+    filter: FilterMode,
+) -> MipMap {
+    let key = (filename.to_string(), color_space, channel);
+    let levels = cached(&MIPMAPS, (key, wrap), || {
+        build_levels(load_image_as(filename, color_space, channel), wrap)
+    });
+    MipMap::new(levels, wrap, filter)
+}
+
+/// Load the models of an OBJ file, or return the already loaded copy
+pub fn load_obj(filename: &str) -> Arc<Vec<tobj::Model>> {
+    cached(&MODELS, filename.to_string(), || {
+        let obj = tobj::load_obj(filename, &tobj::OFFLINE_RENDERING_LOAD_OPTIONS);
+        let (models, _) = obj.expect("Failed to load OBJ file");
+        models
+    })
+}
+
+/// Load a MERL measured BRDF, or return the already loaded copy
+pub fn load_merl(filename: &str) -> Arc<MerlBrdf> {
+    cached(&BRDFS, filename.to_string(), || MerlBrdf::load(filename))
+}
+
+/// Load an IES photometric profile, or return the already loaded copy
+pub fn load_ies(filename: &str) -> Arc<IesProfile> {
+    cached(&PROFILES, filename.to_string(), || {
+        IesProfile::load(filename)
+    })
+}
+
+/// Forget the loaded files that are not used anymore, only the cache still holds them.
+///
+/// Called after building each frame of an animation, so that the files of the previous frames do not pile up. Applications
+/// building many scenes can call it once the scenes they dropped are gone.
+pub fn evict_unused_assets() {
+    // the mipmaps hold their images, release them first
+    MIPMAPS
+        .lock()
+        .unwrap()
+        .retain(|_, levels| Arc::strong_count(levels) > 1);
+    IMAGES
+        .lock()
+        .unwrap()
+        .retain(|_, image| Arc::strong_count(image) > 1);
+    MODELS
+        .lock()
+        .unwrap()
+        .retain(|_, models| Arc::strong_count(models) > 1);
+    BRDFS
+        .lock()
+        .unwrap()
+        .retain(|_, brdf| Arc::strong_count(brdf) > 1);
+    PROFILES
+        .lock()
+        .unwrap()
+        .retain(|_, profile| Arc::strong_count(profile) > 1);
+}
+
+/// Forget all the loaded files, e.g. after they have been modified on disk
+pub fn clear_cache() {
+    MIPMAPS.lock().unwrap().clear();
+    IMAGES.lock().unwrap().clear();
+    MODELS.lock().unwrap().clear();
+    BRDFS.lock().unwrap().clear();
+    PROFILES.lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgb};
+    use std::sync::Arc;
+
+    use crate::core::assets::{evict_unused_assets, load_image_as, load_mipmap, IMAGES, MIPMAPS};
+    use crate::core::image2d::{Channel, ColorSpace};
+    use crate::core::mipmap::{FilterMode, WrapMode};
+
+    #[test]
+    fn unused_assets_are_evicted() {
+        let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        ImageBuffer::from_pixel(4, 2, Rgb([10u8, 20, 30]))
+            .save(file.path())
+            .unwrap();
+        let filename = file.path().to_str().unwrap();
+        let key = (filename.to_string(), ColorSpace::Linear, Channel::Rgb);
+        let load = || {
+            load_mipmap(
+                filename,
+                ColorSpace::Linear,
+                Channel::Rgb,
+                WrapMode::Clamp,
+                FilterMode::Bilinear,
+            )
+        };
+
+        // textures of the same file share their levels
+        let (first, second) = (load(), load());
+        let cached = MIPMAPS.lock().unwrap()[&(key.clone(), WrapMode::Clamp)].clone();
+        assert_eq!(Arc::strong_count(&cached), 4);
+        drop(cached);
+
+        // files still used by a texture stay cached
+        evict_unused_assets();
+        let image = load_image_as(filename, ColorSpace::Linear, Channel::Rgb);
+        assert!(Arc::ptr_eq(&image, &IMAGES.lock().unwrap()[&key]));
+        drop(image);
+
+        drop((first, second));
+        evict_unused_assets();
+        assert!(!MIPMAPS
+            .lock()
+            .unwrap()
+            .contains_key(&(key.clone(), WrapMode::Clamp)));
+        assert!(!IMAGES.lock().unwrap().contains_key(&key));
+    }
+}
@@ -0,0 +1,278 @@
+use nalgebra_glm::Vec2;
+
+/// Discrete probability distribution over `n` values, proportional to a non-negative weight function
+#[derive(Debug, PartialEq, Clone)]
+pub struct Distribution1d {
+    /// Unnormalized weights
+    func: Vec<f32>,
+    /// Cumulative distribution, `cdf[0] = 0` and `cdf[n] = 1`
+    cdf: Vec<f32>,
+    /// Sum of the weights
+    func_sum: f32,
+}
+
+impl Distribution1d {
+    /// Build the distribution from the weights.
+    ///
+    /// If all the weights are zero, the distribution falls back to uniform.
+    pub fn new(func: &[f32]) -> Distribution1d {
+        assert!(!func.is_empty(), "cannot build an empty distribution");
+        let n = func.len();
+        let mut cdf = Vec::with_capacity(n + 1);
+        cdf.push(0.0);
+        for f in func {
+            assert!(*f >= 0.0, "distribution weights should be non-negative");
+            cdf.push(cdf.last().unwrap() + f);
+        }
+        let func_sum = cdf[n];
+        if func_sum > 0.0 {
+            for c in &mut cdf {
+                *c /= func_sum;
+            }
+        } else {
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f32 / n as f32;
+            }
+        }
+        // avoid precision issues on the last bucket
+        cdf[n] = 1.0;
+
+        Distribution1d {
+            func: func.to_vec(),
+            cdf,
+            func_sum,
+        }
+    }
+
+    /// Number of values in the distribution
+    pub fn size(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Sum of the weights the distribution was built from
+    pub fn func_sum(&self) -> f32 {
+        self.func_sum
+    }
+
+    /// Probability of picking the value `index`
+    pub fn pmf(&self, index: usize) -> f32 {
+        self.cdf[index + 1] - self.cdf[index]
+    }
+
+    /// Pick a value with a random variable in [0, 1).
+    ///
+    /// Return the index of the value, its probability and the random variable remapped to [0, 1) inside the
+    /// bucket of the value, so that it can be reused.
+    pub fn sample(&self, rv: f32) -> (usize, f32, f32) {
+        // index of the last cdf entry <= rv, skipping the zero probability buckets
+        let index = self
+            .cdf
+            .partition_point(|c| *c <= rv)
+            .saturating_sub(1)
+            .min(self.size() - 1);
+        let pmf = self.pmf(index);
+        let remapped = if pmf > 0.0 {
+            ((rv - self.cdf[index]) / pmf).clamp(0.0, 1.0 - f32::EPSILON)
+        } else {
+            0.0
+        };
+        (index, pmf, remapped)
+    }
+}
+
+/// Piecewise constant probability density over [0, 1]^2, proportional to a grid of non-negative weights
+#[derive(Debug, PartialEq, Clone)]
+pub struct Distribution2d {
+    /// Distribution of the rows
+    marginal: Distribution1d,
+    /// Distribution of the cells inside each row
+    conditionals: Vec<Distribution1d>,
+}
+
+impl Distribution2d {
+    /// Build the distribution from `size_x` by `size_y` weights, stored row by row
+    pub fn new(func: &[f32], size_x: usize, size_y: usize) -> Distribution2d {
+        assert_eq!(func.len(), size_x * size_y, "weights should fill the grid");
+        let conditionals: Vec<Distribution1d> =
+            func.chunks(size_x).map(Distribution1d::new).collect();
+        let marginal: Vec<f32> = conditionals.iter().map(Distribution1d::func_sum).collect();
+        Distribution2d {
+            marginal: Distribution1d::new(&marginal),
+            conditionals,
+        }
+    }
+
+    /// Sum of the weights the distribution was built from
+    pub fn func_sum(&self) -> f32 {
+        self.marginal.func_sum()
+    }
+
+    /// Sample a point of [0, 1]^2, return it with its density
+    pub fn sample(&self, rv: Vec2) -> (Vec2, f32) {
+        let (y, _, remapped_y) = self.marginal.sample(rv.y);
+        let (x, _, remapped_x) = self.conditionals[y].sample(rv.x);
+        let p = Vec2::new(
+            (x as f32 + remapped_x) / self.conditionals[y].size() as f32,
+            (y as f32 + remapped_y) / self.marginal.size() as f32,
+        );
+        (p, self.pdf(p))
+    }
+
+    /// Density of `sample` at the point `p` of [0, 1]^2
+    pub fn pdf(&self, p: Vec2) -> f32 {
+        let (size_x, size_y) = (self.conditionals[0].size(), self.marginal.size());
+        let x = ((p.x * size_x as f32) as usize).min(size_x - 1);
+        let y = ((p.y * size_y as f32) as usize).min(size_y - 1);
+        self.marginal.pmf(y) * self.conditionals[y].pmf(x) * (size_x * size_y) as f32
+    }
+}
+
+/// Discrete probability distribution sampled in constant time with Vose's alias method.
+///
+/// Each bucket holds one value with probability `q` and otherwise redirects to its alias, so a single random variable
+/// picks the bucket and decides between the two.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AliasTable {
+    /// Probability of each value
+    pmf: Vec<f32>,
+    /// Probability of keeping the value of the bucket rather than its alias
+    q: Vec<f32>,
+    alias: Vec<usize>,
+}
+
+impl AliasTable {
+    /// Build the table from non-negative weights.
+    ///
+    /// If all the weights are zero, the distribution falls back to uniform.
+    pub fn new(func: &[f32]) -> AliasTable {
+        assert!(!func.is_empty(), "cannot build an empty distribution");
+        assert!(
+            func.iter().all(|f| *f >= 0.0),
+            "distribution weights should be non-negative"
+        );
+        let n = func.len();
+        let sum: f64 = func.iter().map(|f| f64::from(*f)).sum();
+        let pmf: Vec<f64> = if sum > 0.0 {
+            func.iter().map(|f| f64::from(*f) / sum).collect()
+        } else {
+            vec![1.0 / n as f64; n]
+        };
+
+        // split the buckets by whether their scaled probability is under or over the average
+        let mut q: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let mut alias: Vec<usize> = (0..n).collect();
+        let (mut under, mut over): (Vec<usize>, Vec<usize>) = (0..n).partition(|i| q[*i] < 1.0);
+        while let (Some(&small), Some(&large)) = (under.last(), over.last()) {
+            under.pop();
+            alias[small] = large;
+            // the large value fills the rest of the small bucket
+            q[large] -= 1.0 - q[small];
+            if q[large] < 1.0 {
+                over.pop();
+                under.push(large);
+            }
+        }
+        // whatever is left is full, up to rounding errors
+        for i in under.into_iter().chain(over) {
+            q[i] = 1.0;
+        }
+
+        AliasTable {
+            pmf: pmf.iter().map(|p| *p as f32).collect(),
+            q: q.iter().map(|q| *q as f32).collect(),
+            alias,
+        }
+    }
+
+    /// Number of values in the distribution
+    pub fn size(&self) -> usize {
+        self.pmf.len()
+    }
+
+    /// Probability of picking the value `index`
+    pub fn pmf(&self, index: usize) -> f32 {
+        self.pmf[index]
+    }
+
+    /// Pick a value with a random variable in [0, 1), return its index and probability
+    pub fn sample(&self, rv: f32) -> (usize, f32) {
+        let scaled = rv * self.size() as f32;
+        let bucket = (scaled as usize).min(self.size() - 1);
+        let up = scaled - bucket as f32;
+        let index = if up < self.q[bucket] {
+            bucket
+        } else {
+            self.alias[bucket]
+        };
+        (index, self.pmf[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec2;
+
+    use crate::core::distribution::{AliasTable, Distribution1d, Distribution2d};
+    use crate::core::sampling::{stratified, stratified_grid};
+
+    #[test]
+    fn sample_follows_weights() {
+        let distribution = Distribution1d::new(&[1.0, 0.0, 3.0]);
+        approx::assert_abs_diff_eq!(distribution.pmf(0), 0.25);
+        approx::assert_abs_diff_eq!(distribution.pmf(1), 0.0);
+        approx::assert_abs_diff_eq!(distribution.pmf(2), 0.75);
+
+        let n = 1000;
+        let mut counts = [0; 3];
+        for rv in stratified(n) {
+            let (index, pmf, remapped) = distribution.sample(rv);
+            approx::assert_abs_diff_eq!(pmf, distribution.pmf(index));
+            assert!((0.0..1.0).contains(&remapped));
+            counts[index] += 1;
+        }
+        assert_eq!(counts, [250, 0, 750]);
+    }
+
+    #[test]
+    fn zero_weights_are_uniform() {
+        let distribution = Distribution1d::new(&[0.0, 0.0]);
+        approx::assert_abs_diff_eq!(distribution.pmf(0), 0.5);
+        assert_eq!(distribution.sample(0.75).0, 1);
+    }
+
+    #[test]
+    fn density_2d_follows_weights() {
+        let distribution = Distribution2d::new(&[1.0, 0.0, 2.0, 0.0, 4.0, 1.0], 3, 2);
+        approx::assert_abs_diff_eq!(distribution.func_sum(), 8.0);
+        approx::assert_abs_diff_eq!(distribution.pdf(Vec2::new(0.1, 0.2)), 6.0 / 8.0);
+        approx::assert_abs_diff_eq!(distribution.pdf(Vec2::new(0.5, 0.2)), 0.0);
+        approx::assert_abs_diff_eq!(distribution.pdf(Vec2::new(0.5, 0.7)), 6.0 * 4.0 / 8.0);
+
+        let mut counts = [0; 6];
+        for rv in stratified_grid(120) {
+            let (p, pdf) = distribution.sample(rv);
+            approx::assert_abs_diff_eq!(pdf, distribution.pdf(p));
+            counts[(p.y * 2.0) as usize * 3 + (p.x * 3.0) as usize] += 1;
+        }
+        assert_eq!(counts, [1800, 0, 3600, 0, 7200, 1800]);
+    }
+
+    #[test]
+    fn alias_table_follows_weights() {
+        let table = AliasTable::new(&[1.0, 0.0, 3.0, 4.0]);
+        approx::assert_abs_diff_eq!(table.pmf(0), 0.125);
+        approx::assert_abs_diff_eq!(table.pmf(1), 0.0);
+
+        let n = 8000;
+        let mut counts = [0; 4];
+        for rv in stratified(n) {
+            let (index, pmf) = table.sample(rv);
+            approx::assert_abs_diff_eq!(pmf, table.pmf(index));
+            counts[index] += 1;
+        }
+        assert_eq!(counts, [1000, 0, 3000, 4000]);
+
+        let table = AliasTable::new(&[0.0, 0.0]);
+        approx::assert_abs_diff_eq!(table.pmf(1), 0.5);
+    }
+}
@@ -0,0 +1,246 @@
+use nalgebra_glm::{normalize, Vec3};
+use std::fs;
+
+/// Candela distribution of a luminaire, read from an IES LM-63 photometric file.
+///
+/// Only type C photometry is supported, the most common one: the vertical angle goes from the nadir (0°) to the
+/// zenith (180°), and the horizontal angle turns around the vertical axis. Values are normalized so that the
+/// brightest direction has a value of one, the light using the profile keeps control of the overall intensity.
+#[derive(Debug, PartialEq, Clone)]
+pub struct IesProfile {
+    /// Increasing vertical angles, in degrees
+    vertical_angles: Vec<f32>,
+    /// Increasing horizontal angles, in degrees, starting at 0
+    horizontal_angles: Vec<f32>,
+    /// Normalized candela values, all the vertical angles of the first horizontal angle come first
+    candela: Vec<f32>,
+}
+
+impl IesProfile {
+    /// Parse the content of an IES LM-63 file, any revision of the format.
+    ///
+    /// The keyword lines are skipped up to the `TILT=` line, the tilt data being ignored. The numbers that follow
+    /// describe the luminaire, then list the vertical angles, horizontal angles and candela values.
+    pub fn parse(text: &str) -> Result<IesProfile, String> {
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .find_map(|line| line.trim().strip_prefix("TILT="))
+            .ok_or("missing TILT line")?;
+        let mut numbers =
+            lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','));
+        let mut next = || -> Result<f32, String> {
+            let word = numbers
+                .by_ref()
+                .find(|word| !word.is_empty())
+                .ok_or("unexpected end of file")?;
+            word.parse::<f32>()
+                .map_err(|e| format!("invalid number {word}: {e}"))
+        };
+
+        if tilt.trim() == "INCLUDE" {
+            // lamp to luminaire geometry, then the tilt angles and their multiplying factors
+            next()?;
+            let tilt_count = next()? as usize;
+            for _ in 0..2 * tilt_count {
+                next()?;
+            }
+        }
+
+        // number of lamps, lumens per lamp
+        next()?;
+        next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        let photometric_type = next()? as i32;
+        // units, width, length, height, ballast factor, ballast-lamp factor, input watts
+        for _ in 0..7 {
+            next()?;
+        }
+        if photometric_type != 1 {
+            return Err(format!(
+                "unsupported photometric type {photometric_type}, only type C is handled"
+            ));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err("the profile should have at least one angle in each direction".to_string());
+        }
+
+        let vertical_angles = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let horizontal_angles = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let candela = (0..vertical_count * horizontal_count)
+            .map(|_| next().map(|value| value * multiplier))
+            .collect::<Result<Vec<_>, _>>()?;
+        IesProfile::new(vertical_angles, horizontal_angles, candela)
+    }
+
+    /// Build a profile from its angles in degrees and candela values, any scale
+    pub fn new(
+        vertical_angles: Vec<f32>,
+        horizontal_angles: Vec<f32>,
+        candela: Vec<f32>,
+    ) -> Result<IesProfile, String> {
+        if candela.len() != vertical_angles.len() * horizontal_angles.len() {
+            return Err(format!(
+                "expected {} candela values, found {}",
+                vertical_angles.len() * horizontal_angles.len(),
+                candela.len()
+            ));
+        }
+        let increasing = |angles: &[f32]| angles.windows(2).all(|w| w[0] < w[1]);
+        if !increasing(&vertical_angles) || !increasing(&horizontal_angles) {
+            return Err("the angles should be increasing".to_string());
+        }
+        let max = candela.iter().copied().fold(0.0, f32::max);
+        if max <= 0.0 {
+            return Err("the profile emits no light".to_string());
+        }
+        Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela: candela.iter().map(|value| value / max).collect(),
+        })
+    }
+
+    pub fn load(path: &str) -> IesProfile {
+        let text =
+            fs::read_to_string(path).unwrap_or_else(|e| panic!("unable to read {path}: {e}"));
+        IesProfile::parse(&text).unwrap_or_else(|e| panic!("unable to parse {path}: {e}"))
+    }
+
+    /// Relative intensity towards the direction `dir` of the luminaire frame, where the nadir is along -z and the
+    /// horizontal angle 0° along +x
+    pub fn value(&self, dir: &Vec3) -> f32 {
+        let dir = normalize(dir);
+        let vertical = f32::acos((-dir.z).clamp(-1.0, 1.0)).to_degrees();
+        let horizontal = f32::atan2(dir.y, dir.x).to_degrees().rem_euclid(360.0);
+        self.candela(vertical, self.unfold(horizontal))
+    }
+
+    /// Map a horizontal angle into the range covered by the file, using the symmetry given by the last angle
+    fn unfold(&self, horizontal: f32) -> f32 {
+        let last = *self.horizontal_angles.last().unwrap();
+        let mirrored = if horizontal > 180.0 {
+            360.0 - horizontal
+        } else {
+            horizontal
+        };
+        if last <= 0.0 {
+            // rotationally symmetric
+            0.0
+        } else if last <= 90.0 {
+            // symmetric in each quadrant
+            if mirrored > 90.0 {
+                180.0 - mirrored
+            } else {
+                mirrored
+            }
+        } else if last <= 180.0 {
+            // symmetric about the 0-180° plane
+            mirrored
+        } else {
+            horizontal
+        }
+    }
+
+    /// Bilinear interpolation of the normalized candela values, zero outside of the vertical range
+    fn candela(&self, vertical: f32, horizontal: f32) -> f32 {
+        let Some((v0, v1, tv)) = bracket(&self.vertical_angles, vertical) else {
+            return 0.0;
+        };
+        let (h0, h1, th) = bracket(&self.horizontal_angles, horizontal).unwrap_or_else(|| {
+            // past the last angle of a full turn, wrap around to the first one
+            let last = self.horizontal_angles.len() - 1;
+            let span = 360.0 - self.horizontal_angles[last] + self.horizontal_angles[0];
+            let t = if span > 0.0 {
+                (horizontal - self.horizontal_angles[last]).rem_euclid(360.0) / span
+            } else {
+                0.0
+            };
+            (last, 0, t.clamp(0.0, 1.0))
+        });
+        let stride = self.vertical_angles.len();
+        let at = |h: usize, v: usize| self.candela[h * stride + v];
+        let c0 = at(h0, v0) * (1.0 - tv) + at(h0, v1) * tv;
+        let c1 = at(h1, v0) * (1.0 - tv) + at(h1, v1) * tv;
+        c0 * (1.0 - th) + c1 * th
+    }
+}
+
+/// Indices of the angles surrounding `angle` and the interpolation weight of the second one
+fn bracket(angles: &[f32], angle: f32) -> Option<(usize, usize, f32)> {
+    let last = angles.len() - 1;
+    if angles.len() == 1 {
+        return ((angle - angles[0]).abs() < 1e-3).then_some((0, 0, 0.0));
+    }
+    if angle < angles[0] - 1e-3 || angle > angles[last] + 1e-3 {
+        return None;
+    }
+    let i = angles.partition_point(|&a| a <= angle).clamp(1, last);
+    let t = (angle - angles[i - 1]) / (angles[i] - angles[i - 1]);
+    Some((i - 1, i, t.clamp(0.0, 1.0)))
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+
+    use crate::core::ies::IesProfile;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] downlight
+[MANUFAC] nobody
+TILT=NONE
+1 1000 2.0 3 1 1 2 0.1 0.1 0.0
+1.0 1.0 20
+0 45 90
+0
+100 50 0
+";
+
+    #[test]
+    fn parse_symmetric_profile() {
+        let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+        approx::assert_abs_diff_eq!(profile.value(&-Vec3::z()), 1.0);
+        approx::assert_abs_diff_eq!(
+            profile.value(&Vec3::new(1.0, 0.0, -1.0)),
+            0.5,
+            epsilon = 1e-5
+        );
+        // half way between 0° and 45°, in any horizontal direction
+        let dir = Vec3::new(
+            0.0,
+            -f32::sin(22.5f32.to_radians()),
+            -f32::cos(22.5f32.to_radians()),
+        );
+        approx::assert_abs_diff_eq!(profile.value(&dir), 0.75, epsilon = 1e-5);
+        // the upper hemisphere is not covered
+        assert_eq!(profile.value(&Vec3::z()), 0.0);
+
+        assert!(IesProfile::parse("TILT=NONE\n1 1000 1 3 1").is_err());
+        assert!(IesProfile::parse(&DOWNLIGHT.replace("1 1 2 0.1", "1 2 2 0.1")).is_err());
+    }
+
+    #[test]
+    fn horizontal_symmetries() {
+        let candela = vec![1.0, 2.0, 3.0];
+        // bilateral symmetry about the 0-180° plane
+        let profile = IesProfile::new(vec![90.0], vec![0.0, 90.0, 180.0], candela.clone()).unwrap();
+        let at = |profile: &IesProfile, degrees: f32| {
+            let (s, c) = degrees.to_radians().sin_cos();
+            profile.value(&Vec3::new(c, s, 0.0))
+        };
+        approx::assert_abs_diff_eq!(at(&profile, 45.0), 0.5, epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(at(&profile, 315.0), 0.5, epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(at(&profile, 180.0), 1.0, epsilon = 1e-5);
+
+        // a full turn wraps from the last angle back to the first
+        let profile = IesProfile::new(vec![90.0], vec![0.0, 120.0, 240.0], candela).unwrap();
+        approx::assert_abs_diff_eq!(at(&profile, 300.0), 2.0 / 3.0, epsilon = 1e-5);
+    }
+}
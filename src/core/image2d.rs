@@ -1,6 +1,7 @@
-use image::Rgb;
-use image::{ImageReader, GenericImageView, Pixel};
+use image::ImageReader;
+use image::{ColorType, Rgb};
 use nalgebra_glm::{clamp, comp_max, comp_min, Vec3};
+use serde::{Deserialize, Serialize};
 use std::ops::{Index, IndexMut};
 use std::path::Path;
 
@@ -62,6 +63,41 @@ fn to_srgb(c: &Vec3) -> Vec3 {
     result
 }
 
+/// Convert from `sRGB` to linear RGB
+fn from_srgb(c: &Vec3) -> Vec3 {
+    c.map(|value| {
+        if value <= 0.040_45 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+/// Encoding of the values stored in an image file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    /// Display referred values, decoded with the `sRGB` transfer function
+    Srgb,
+    /// Values used as they are
+    Linear,
+    /// `sRGB` for 8 and 16 bits images, linear for float images like `.hdr` and `.exr`
+    Auto,
+}
+
+/// Channels of an image file kept when loading it, a single channel is copied to the three components
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Rgb,
+    R,
+    G,
+    B,
+    /// Opacity, e.g. for masks stored in the alpha channel of a color image
+    A,
+}
+
 impl Image2d {
     pub fn save(&self, path: &Path) {
         let mut img_buffer = image::RgbImage::new(self.size_x as u32, self.size_y as u32);
@@ -82,20 +118,86 @@ impl Image2d {
         img.save(path).unwrap();
     }
 
-    pub fn load(path: String) -> Image2d {
-        let img = ImageReader::open(path).unwrap().decode().unwrap();
+    /// Load an image file, decoding its values to linear ones according to `color_space` and keeping `channel`
+    pub fn load(path: &str, color_space: ColorSpace, channel: Channel) -> Image2d {
+        let img = ImageReader::open(path)
+            .unwrap_or_else(|e| panic!("unable to open {path}: {e}"))
+            .decode()
+            .unwrap_or_else(|e| panic!("unable to decode {path}: {e}"));
+        let srgb = match color_space {
+            ColorSpace::Srgb => true,
+            ColorSpace::Linear => false,
+            // integer images are display referred, float images hold linear radiance
+            ColorSpace::Auto => !matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F),
+        };
+        // 8 and 16 bits images are mapped to [0, 1], float images keep their values, single-channel images are gray
+        let rgba = img.to_rgba32f();
         let mut image2d = Image2d::new(img.width() as usize, img.height() as usize);
 
         for x in 0..image2d.size_x {
             for y in 0..image2d.size_y {
-                let pixel = img.get_pixel(x as u32, y as u32);
-                let pixel = pixel.to_rgb();
-                let r = f32::from(pixel[0]) / 255.0;
-                let g = f32::from(pixel[1]) / 255.0;
-                let b = f32::from(pixel[2]) / 255.0;
-                image2d[(x, y)] = Vec3::new(r, g, b);
+                let [r, g, b, a] = rgba.get_pixel(x as u32, y as u32).0;
+                let color = Vec3::new(r, g, b);
+                // the alpha channel is always linear
+                let color = if srgb { from_srgb(&color) } else { color };
+                image2d[(x, y)] = match channel {
+                    Channel::Rgb => color,
+                    Channel::R => Vec3::repeat(color.x),
+                    Channel::G => Vec3::repeat(color.y),
+                    Channel::B => Vec3::repeat(color.z),
+                    Channel::A => Vec3::repeat(a),
+                };
             }
         }
         image2d
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma, Rgb, Rgba};
+
+    use crate::core::image2d::{Channel, ColorSpace, Image2d};
+
+    #[test]
+    fn decode_color_spaces() {
+        let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        ImageBuffer::from_pixel(2, 1, Rgba([128u8, 255, 0, 64]))
+            .save(file.path())
+            .unwrap();
+        let png = file.path().to_str().unwrap();
+
+        // 8 bits images are sRGB unless stated otherwise
+        let auto = Image2d::load(png, ColorSpace::Auto, Channel::Rgb);
+        approx::assert_abs_diff_eq!(auto[(1, 0)].x, 0.215_861, epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(auto[(1, 0)].y, 1.0, epsilon = 1e-6);
+        let linear = Image2d::load(png, ColorSpace::Linear, Channel::Rgb);
+        approx::assert_abs_diff_eq!(linear[(0, 0)].x, 128.0 / 255.0, epsilon = 1e-6);
+        // the alpha channel is never decoded
+        let alpha = Image2d::load(png, ColorSpace::Srgb, Channel::A);
+        approx::assert_abs_diff_eq!(alpha[(0, 0)].z, 64.0 / 255.0, epsilon = 1e-6);
+        let red = Image2d::load(png, ColorSpace::Linear, Channel::R);
+        assert_eq!(red[(0, 0)].y, red[(0, 0)].x);
+
+        // 16 bits single-channel images keep their precision
+        let gray = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        ImageBuffer::from_pixel(1, 1, Luma([1000u16]))
+            .save(gray.path())
+            .unwrap();
+        let loaded = Image2d::load(
+            gray.path().to_str().unwrap(),
+            ColorSpace::Linear,
+            Channel::Rgb,
+        );
+        approx::assert_abs_diff_eq!(loaded[(0, 0)].y, 1000.0 / 65535.0, epsilon = 1e-7);
+
+        // float images are linear and keep values above one
+        let exr = tempfile::Builder::new().suffix(".exr").tempfile().unwrap();
+        ImageBuffer::from_pixel(1, 1, Rgb([3.5f32, 0.25, 0.0]))
+            .save(exr.path())
+            .unwrap();
+        let loaded = Image2d::load(exr.path().to_str().unwrap(), ColorSpace::Auto, Channel::Rgb);
+        approx::assert_abs_diff_eq!(loaded[(0, 0)].x, 3.5, epsilon = 1e-6);
+        approx::assert_abs_diff_eq!(loaded[(0, 0)].y, 0.25, epsilon = 1e-6);
+    }
+}
@@ -0,0 +1,171 @@
+use nalgebra_glm::{cross, dot, normalize, Vec3};
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::fs;
+
+/// Number of half angle elevations, stored with a square root spacing to refine the specular peak
+pub const RES_THETA_H: usize = 90;
+/// Number of difference angle elevations
+pub const RES_THETA_D: usize = 90;
+/// Number of difference angle azimuths, only half a turn is stored thanks to reciprocity
+pub const RES_PHI_D: usize = 180;
+/// Number of samples for each color channel
+pub const SAMPLE_COUNT: usize = RES_THETA_H * RES_THETA_D * RES_PHI_D;
+
+/// Scale factors converting the stored values of each channel to reflectance
+const SCALE: [f32; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+/// Isotropic BRDF measured in the MERL 100 database, tabulated over half and difference angles
+#[derive(Debug, PartialEq, Clone)]
+pub struct MerlBrdf {
+    /// Scaled samples of the red, green then blue channels
+    data: Vec<f32>,
+}
+
+impl MerlBrdf {
+    /// Build the BRDF from already scaled samples of the red, green then blue channels
+    pub fn new(data: Vec<f32>) -> MerlBrdf {
+        assert_eq!(
+            data.len(),
+            3 * SAMPLE_COUNT,
+            "a MERL BRDF should have {SAMPLE_COUNT} samples per channel"
+        );
+        MerlBrdf { data }
+    }
+
+    /// Parse the content of a MERL `.binary` file.
+    ///
+    /// The file starts with the three table dimensions as 32 bits integers, followed by the samples of the red, green
+    /// then blue channels as 64 bits floats, all little endian.
+    pub fn from_bytes(bytes: &[u8]) -> Result<MerlBrdf, String> {
+        if bytes.len() < 12 {
+            return Err("missing MERL header".to_string());
+        }
+        let dims: Vec<usize> = bytes[..12]
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes(b.try_into().unwrap()) as usize)
+            .collect();
+        if dims.iter().product::<usize>() != SAMPLE_COUNT {
+            return Err(format!("unexpected MERL dimensions {dims:?}"));
+        }
+        let values = &bytes[12..];
+        if values.len() != 3 * SAMPLE_COUNT * 8 {
+            return Err(format!(
+                "expected {} bytes of samples, found {}",
+                3 * SAMPLE_COUNT * 8,
+                values.len()
+            ));
+        }
+        let data = values
+            .chunks_exact(8)
+            .enumerate()
+            .map(|(i, b)| {
+                let value = f64::from_le_bytes(b.try_into().unwrap()) as f32;
+                value * SCALE[i / SAMPLE_COUNT]
+            })
+            .collect();
+        Ok(MerlBrdf::new(data))
+    }
+
+    pub fn load(path: &str) -> MerlBrdf {
+        let bytes = fs::read(path).unwrap_or_else(|e| panic!("unable to read {path}: {e}"));
+        MerlBrdf::from_bytes(&bytes).unwrap_or_else(|e| panic!("unable to parse {path}: {e}"))
+    }
+
+    /// BRDF value for a pair of directions expressed in the local frame, with the normal along z
+    pub fn eval(&self, wi: &Vec3, wo: &Vec3) -> Vec3 {
+        if wi.z <= 0.0 || wo.z <= 0.0 {
+            return Vec3::zeros();
+        }
+        let (theta_h, theta_d, phi_d) = half_diff_coordinates(wi, wo);
+        let index = phi_d_index(phi_d)
+            + theta_d_index(theta_d) * RES_PHI_D
+            + theta_h_index(theta_h) * RES_PHI_D * RES_THETA_D;
+        // unmeasured samples are stored as negative values
+        Vec3::new(
+            self.data[index].max(0.0),
+            self.data[index + SAMPLE_COUNT].max(0.0),
+            self.data[index + 2 * SAMPLE_COUNT].max(0.0),
+        )
+    }
+}
+
+/// Rotate `v` around the unit vector `axis`
+fn rotate(v: &Vec3, axis: &Vec3, angle: f32) -> Vec3 {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + axis * dot(axis, v) * (1.0 - cos) + cross(axis, v) * sin
+}
+
+/// Rusinkiewicz's half and difference angles `(theta_h, theta_d, phi_d)` of a pair of local directions
+pub fn half_diff_coordinates(wi: &Vec3, wo: &Vec3) -> (f32, f32, f32) {
+    let half = normalize(&(wi + wo));
+    let theta_h = half.z.clamp(-1.0, 1.0).acos();
+    let phi_h = half.y.atan2(half.x);
+
+    // express wi in the frame where the half vector is the normal
+    let diff = rotate(wi, &Vec3::z(), -phi_h);
+    let diff = rotate(&diff, &Vec3::y(), -theta_h);
+    let theta_d = diff.z.clamp(-1.0, 1.0).acos();
+    let phi_d = diff.y.atan2(diff.x);
+    (theta_h, theta_d, phi_d)
+}
+
+/// The half angle is stored with a square root mapping, the samples get denser near the specular direction
+pub fn theta_h_index(theta_h: f32) -> usize {
+    if theta_h <= 0.0 {
+        return 0;
+    }
+    let index = f32::sqrt(theta_h / FRAC_PI_2) * RES_THETA_H as f32;
+    (index as usize).min(RES_THETA_H - 1)
+}
+
+fn theta_d_index(theta_d: f32) -> usize {
+    let index = theta_d / FRAC_PI_2 * RES_THETA_D as f32;
+    (index.max(0.0) as usize).min(RES_THETA_D - 1)
+}
+
+fn phi_d_index(phi_d: f32) -> usize {
+    // reciprocity makes phi_d and phi_d + pi equivalent
+    let phi_d = if phi_d < 0.0 { phi_d + PI } else { phi_d };
+    let index = phi_d / PI * RES_PHI_D as f32;
+    (index.max(0.0) as usize).min(RES_PHI_D - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{normalize, Vec3};
+
+    use crate::core::merl::{half_diff_coordinates, MerlBrdf, SAMPLE_COUNT};
+
+    #[test]
+    fn parse_binary_file() {
+        let mut bytes = Vec::new();
+        for dim in [90i32, 90, 180] {
+            bytes.extend_from_slice(&dim.to_le_bytes());
+        }
+        for channel in 0..3 {
+            for _ in 0..SAMPLE_COUNT {
+                bytes.extend_from_slice(&(300.0f64 * (channel + 1) as f64).to_le_bytes());
+            }
+        }
+        let brdf = MerlBrdf::from_bytes(&bytes).unwrap();
+        let value = brdf.eval(&Vec3::new(0.3, 0.1, 0.9).normalize(), &Vec3::z());
+        approx::assert_abs_diff_eq!(value, Vec3::new(0.2, 0.46, 0.996), epsilon = 1e-5);
+
+        assert!(MerlBrdf::from_bytes(&bytes[..100]).is_err());
+    }
+
+    #[test]
+    fn half_diff_angles() {
+        // mirror configuration: the half vector is the normal
+        let wi = normalize(&Vec3::new(0.5, 0.0, 1.0));
+        let wo = Vec3::new(-wi.x, 0.0, wi.z);
+        let (theta_h, theta_d, _) = half_diff_coordinates(&wi, &wo);
+        approx::assert_abs_diff_eq!(theta_h, 0.0, epsilon = 1e-3);
+        approx::assert_abs_diff_eq!(theta_d, wi.z.acos(), epsilon = 1e-5);
+
+        // retro-reflection: the difference angle vanishes
+        let (theta_h, theta_d, _) = half_diff_coordinates(&wi, &wi);
+        approx::assert_abs_diff_eq!(theta_h, wi.z.acos(), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(theta_d, 0.0, epsilon = 1e-3);
+    }
+}
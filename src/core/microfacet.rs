@@ -0,0 +1,297 @@
+use nalgebra_glm::{cross, dot, normalize, Vec2, Vec3};
+use std::f32::consts::PI;
+
+use crate::core::utils::sincos;
+
+/// Smallest roughness used by the microfacet models, below it the distribution is numerically a dirac
+const MIN_ALPHA: f32 = 1e-3;
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals.
+///
+/// All the directions are expressed in the local shading frame, where the macro normal is +z
+/// and the roughness is `alpha_x` along the tangent (+x) and `alpha_y` along the bitangent (+y).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Ggx {
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Ggx {
+        Ggx {
+            alpha_x: f32::max(alpha_x, MIN_ALPHA),
+            alpha_y: f32::max(alpha_y, MIN_ALPHA),
+        }
+    }
+
+    /// Distribution from a perceptual roughness in [0, 1] and an anisotropy in [0, 1).
+    ///
+    /// The roughness is squared to get alpha, and the anisotropy stretches the lobe along the tangent.
+    pub fn from_roughness(roughness: f32, anisotropy: f32) -> Ggx {
+        let alpha = roughness * roughness;
+        let aspect = f32::sqrt(1.0 - 0.9 * anisotropy.clamp(0.0, 1.0));
+        Ggx::new(alpha / aspect, alpha * aspect)
+    }
+
+    /// Density of microfacet normals `m`, normalized so that the projected area is 1
+    pub fn d(&self, m: &Vec3) -> f32 {
+        if m.z <= 0.0 {
+            return 0.0;
+        }
+        let e = (m.x / self.alpha_x).powi(2) + (m.y / self.alpha_y).powi(2) + m.z * m.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    /// Smith auxiliary function Λ(w)
+    pub fn lambda(&self, w: &Vec3) -> f32 {
+        if w.z == 0.0 {
+            return f32::INFINITY;
+        }
+        let alpha2_tan2 =
+            ((w.x * self.alpha_x).powi(2) + (w.y * self.alpha_y).powi(2)) / (w.z * w.z);
+        0.5 * (f32::sqrt(1.0 + alpha2_tan2) - 1.0)
+    }
+
+    /// Smith masking function for a single direction
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated Smith masking-shadowing function
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normals visible from `w`, which is the density `sample_visible` samples from
+    pub fn d_visible(&self, w: &Vec3, m: &Vec3) -> f32 {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) * f32::max(dot(w, m), 0.0) * self.d(m) / w.z.abs()
+    }
+
+    /// Sample a microfacet normal visible from `w` (Heitz 2018, "Sampling the GGX Distribution of Visible Normals").
+    ///
+    /// `w` should lie in the upper hemisphere.
+    pub fn sample_visible(&self, w: &Vec3, rv: Vec2) -> Vec3 {
+        // stretch the view direction to the hemisphere configuration
+        let wh = normalize(&Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z));
+
+        // orthonormal basis around the view direction
+        let len2 = wh.x * wh.x + wh.y * wh.y;
+        let t1 = if len2 > 0.0 {
+            Vec3::new(-wh.y, wh.x, 0.0) / f32::sqrt(len2)
+        } else {
+            Vec3::x()
+        };
+        let t2 = cross(&wh, &t1);
+
+        // sample the projected area of the visible hemisphere
+        let r = f32::sqrt(rv.x);
+        let (sin_phi, cos_phi) = sincos(2.0 * PI * rv.y);
+        let p1 = r * cos_phi;
+        let s = 0.5 * (1.0 + wh.z);
+        let p2 = (1.0 - s) * f32::sqrt(1.0 - p1 * p1) + s * r * sin_phi;
+        let nh = p1 * t1 + p2 * t2 + f32::sqrt(f32::max(0.0, 1.0 - p1 * p1 - p2 * p2)) * wh;
+
+        // unstretch back to the ellipsoid configuration
+        normalize(&Vec3::new(
+            self.alpha_x * nh.x,
+            self.alpha_y * nh.y,
+            f32::max(1e-6, nh.z),
+        ))
+    }
+}
+
+/// Microfacet normal refracting `wo` into `wl`, in the upper hemisphere (Walter et al. 2007).
+///
+/// `wo` lies in the upper hemisphere and `wl` in the lower one, `eta` is the index of refraction
+/// below the surface over the one above it.
+/// Returns None when no microfacet can refract `wo` into `wl`.
+pub fn half_vector_transmission(wo: &Vec3, wl: &Vec3, eta: f32) -> Option<Vec3> {
+    let m = wo + eta * wl;
+    if m.norm_squared() == 0.0 || wl.z >= 0.0 {
+        return None;
+    }
+    let m = normalize(&m);
+    let m = if m.z < 0.0 { -m } else { m };
+
+    // discard back facing microfacets
+    (dot(wo, &m) > 0.0 && dot(wl, &m) < 0.0).then_some(m)
+}
+
+/// Jacobian of the mapping from microfacet normals to refracted directions
+pub fn transmission_jacobian(wo: &Vec3, wl: &Vec3, m: &Vec3, eta: f32) -> f32 {
+    let denom = dot(wl, m) + dot(wo, m) / eta;
+    f32::abs(dot(wl, m)) / (denom * denom)
+}
+
+/// Refract `wo` through the microfacet `m`, or None on total internal reflection.
+///
+/// `eta` is the index of refraction on the other side of the microfacet over the one on the side of `wo`.
+pub fn refract(wo: &Vec3, m: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = dot(wo, m);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+    Some(-wo / eta + (cos_i / eta - cos_t) * m)
+}
+
+/// Schlick's approximation of the Fresnel reflectance, from the reflectance at normal incidence
+pub fn fresnel_schlick(cos_theta: f32, f0: &Vec3) -> Vec3 {
+    let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * weight
+}
+
+/// Unpolarized Fresnel reflectance at the interface between two dielectrics.
+///
+/// \param cos_theta Cosine between the incident direction and the normal, negative when arriving from inside
+/// \param eta       Relative index of refraction, inside over outside
+pub fn fresnel_dielectric(cos_theta: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_theta < 0.0 {
+        (-cos_theta.max(-1.0), 1.0 / eta)
+    } else {
+        (cos_theta.min(1.0), eta)
+    };
+
+    // total internal reflection
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = f32::sqrt(1.0 - sin2_t);
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction `eta + i k`, per color channel.
+///
+/// \param cos_theta Cosine between the incident direction and the normal
+pub fn fresnel_conductor(cos_theta: f32, eta: &Vec3, k: &Vec3) -> Vec3 {
+    let cos2 = cos_theta.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    Vec3::from_fn(|c, _| {
+        let eta2 = eta[c] * eta[c];
+        let k2 = k[c] * k[c];
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = f32::sqrt(t0 * t0 + 4.0 * eta2 * k2);
+        let t1 = a2_plus_b2 + cos2;
+        let a = f32::sqrt(f32::max(0.0, 0.5 * (a2_plus_b2 + t0)));
+        let t2 = 2.0 * f32::sqrt(cos2) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    })
+}
+
+/// Complex index of refraction `(eta, k)` of common metals, sampled at red, green and blue wavelengths
+pub fn conductor_ior(name: &str) -> Option<(Vec3, Vec3)> {
+    let ior = match name {
+        "Au" => (
+            Vec3::new(0.143, 0.374, 1.442),
+            Vec3::new(3.983, 2.385, 1.603),
+        ),
+        "Ag" => (
+            Vec3::new(0.155, 0.117, 0.138),
+            Vec3::new(4.828, 3.122, 2.147),
+        ),
+        "Cu" => (
+            Vec3::new(0.200, 0.924, 1.102),
+            Vec3::new(3.912, 2.452, 2.142),
+        ),
+        "Al" => (
+            Vec3::new(1.657, 0.880, 0.521),
+            Vec3::new(9.224, 6.270, 4.837),
+        ),
+        _ => return None,
+    };
+    Some(ior)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use std::f32::consts::PI;
+
+    use crate::core::microfacet::{conductor_ior, fresnel_conductor, fresnel_dielectric, Ggx};
+    use crate::core::utils::spherical_coordinates_to_direction;
+
+    /// Integrate `f` over the upper hemisphere with a midpoint rule
+    fn integrate_hemisphere(f: impl Fn(&Vec3) -> f32) -> f32 {
+        const N: usize = 512;
+        let d_theta = 0.5 * PI / N as f32;
+        let d_phi = 2.0 * PI / (2 * N) as f32;
+        let mut sum = 0.0;
+        for i in 0..N {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..2 * N {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let w = spherical_coordinates_to_direction(Vec2::new(phi, theta));
+                sum += f(&w) * theta.sin() * d_theta * d_phi;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn ggx_projected_area_is_normalized() {
+        let ggx = Ggx::from_roughness(0.6, 0.5);
+        let integral = integrate_hemisphere(|m| ggx.d(m) * m.z);
+        approx::assert_abs_diff_eq!(integral, 1.0, epsilon = 1e-2);
+
+        let w = Vec3::new(0.5, -0.3, 0.6).normalize();
+        let integral = integrate_hemisphere(|m| ggx.d_visible(&w, m));
+        approx::assert_abs_diff_eq!(integral, 1.0, epsilon = 1e-2);
+    }
+
+    #[test]
+    fn visible_normals_face_the_viewer() {
+        let ggx = Ggx::new(0.8, 0.2);
+        let w = Vec3::new(0.9, 0.1, 0.2).normalize();
+        for i in 0..16 {
+            for j in 0..16 {
+                let rv = Vec2::new(i as f32 / 16.0, j as f32 / 16.0);
+                let m = ggx.sample_visible(&w, rv);
+                approx::assert_abs_diff_eq!(m.norm(), 1.0, epsilon = 1e-5);
+                assert!(m.z > 0.0 && m.dot(&w) >= -1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel() {
+        // 4% reflectance for glass at normal incidence, from both sides
+        approx::assert_abs_diff_eq!(fresnel_dielectric(1.0, 1.5), 0.04, epsilon = 1e-6);
+        approx::assert_abs_diff_eq!(fresnel_dielectric(-1.0, 1.5), 0.04, epsilon = 1e-6);
+        // total internal reflection past the critical angle
+        let critical = f32::asin(1.0 / 1.5);
+        assert_eq!(fresnel_dielectric(-(critical + 0.01).cos(), 1.5), 1.0);
+        assert!(fresnel_dielectric(-(critical - 0.01).cos(), 1.5) < 1.0);
+    }
+
+    #[test]
+    fn conductor_fresnel() {
+        let (eta, k) = conductor_ior("Au").unwrap();
+        // at normal incidence the reflectance is ((n - 1)² + k²) / ((n + 1)² + k²)
+        let expected = Vec3::from_fn(|c, _| {
+            ((eta[c] - 1.0).powi(2) + k[c] * k[c]) / ((eta[c] + 1.0).powi(2) + k[c] * k[c])
+        });
+        approx::assert_abs_diff_eq!(fresnel_conductor(1.0, &eta, &k), expected, epsilon = 1e-4);
+        // and every metal becomes a perfect mirror at grazing angles
+        approx::assert_abs_diff_eq!(
+            fresnel_conductor(0.0, &eta, &k),
+            Vec3::new(1.0, 1.0, 1.0),
+            epsilon = 1e-4
+        );
+        assert!(conductor_ior("Fe").is_none());
+    }
+}
@@ -0,0 +1,390 @@
+use nalgebra_glm::{length2, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::core::image2d::Image2d;
+use crate::core::utils::lerp;
+
+/// How texel coordinates outside of the image are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    /// Tile the image
+    Repeat,
+    /// Extend the texels of the border
+    Clamp,
+    /// Tile the image, flipping every other copy
+    Mirror,
+    /// Black outside of the image
+    Black,
+}
+
+/// How the texels covered by a lookup are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    /// Closest texel of the level matching the footprint
+    Nearest,
+    /// Bilinear interpolation in the level matching the footprint
+    Bilinear,
+    /// Bilinear interpolation in the two levels surrounding the footprint
+    Trilinear,
+    /// Elliptically weighted average of the texels under the footprint, which may be anisotropic
+    Ewa,
+}
+
+/// Images of a mipmap, from the full resolution one to a single texel
+pub type MipLevels = Vec<Arc<Image2d>>;
+
+/// Pyramid of images, each level half the size of the previous one, to filter minified textures.
+///
+/// Lookups use the `st` coordinates of the image, from (0, 0) at the top left corner to (1, 1) at the bottom right,
+/// and a footprint given by the change of `st` to the next pixels.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MipMap {
+    /// Shared by the textures using the same file, see `load_mipmap`
+    levels: Arc<MipLevels>,
+    wrap: WrapMode,
+    filter: FilterMode,
+    /// Largest ratio between the axes of the EWA footprint, longer footprints are widened
+    max_anisotropy: f32,
+}
+
+impl MipMap {
+    /// Mipmap over levels built by `build_levels` with the same `wrap` mode
+    pub fn new(levels: Arc<MipLevels>, wrap: WrapMode, filter: FilterMode) -> MipMap {
+        MipMap {
+            levels,
+            wrap,
+            filter,
+            max_anisotropy: 8.0,
+        }
+    }
+
+    pub fn max_anisotropy(mut self, max_anisotropy: f32) -> MipMap {
+        self.max_anisotropy = max_anisotropy;
+        self
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Value of the texel (`x`, `y`) of `level`, wrapped into the image
+    pub fn texel(&self, level: usize, x: i64, y: i64) -> Vec3 {
+        let image = &self.levels[level];
+        match (
+            wrap(x, image.size_x, self.wrap),
+            wrap(y, image.size_y, self.wrap),
+        ) {
+            (Some(x), Some(y)) => image[(x, y)],
+            _ => Vec3::zeros(),
+        }
+    }
+
+    /// Filter the texture around `st`, over the footprint spanned by `dst0` and `dst1`
+    pub fn filter(&self, st: Vec2, dst0: Vec2, dst1: Vec2) -> Vec3 {
+        if self.filter == FilterMode::Ewa {
+            return self.ewa(st, dst0, dst1);
+        }
+
+        let width = 2.0 * dst0.abs().max().max(dst1.abs().max());
+        // the level whose texels are as wide as the footprint
+        let last = self.levels() - 1;
+        let level = last as f32 + f32::max(width, 1e-8).log2();
+        if level >= last as f32 {
+            return self.texel(last, 0, 0);
+        }
+        let level = level.max(0.0);
+        let i_level = level.floor() as usize;
+        match self.filter {
+            FilterMode::Nearest => {
+                let image = &self.levels[i_level];
+                self.texel(
+                    i_level,
+                    (st.x * image.size_x as f32).floor() as i64,
+                    (st.y * image.size_y as f32).floor() as i64,
+                )
+            }
+            FilterMode::Bilinear => self.bilerp(i_level, st),
+            _ => lerp(
+                self.bilerp(i_level, st),
+                // a single texel image has no coarser level
+                self.bilerp((i_level + 1).min(last), st),
+                level - i_level as f32,
+            ),
+        }
+    }
+
+    /// Bilinear interpolation of the texels of `level` around `st`
+    pub fn bilerp(&self, level: usize, st: Vec2) -> Vec3 {
+        let image = &self.levels[level];
+        let x = st.x * image.size_x as f32 - 0.5;
+        let y = st.y * image.size_y as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        (1.0 - dx) * (1.0 - dy) * self.texel(level, x0, y0)
+            + dx * (1.0 - dy) * self.texel(level, x0 + 1, y0)
+            + (1.0 - dx) * dy * self.texel(level, x0, y0 + 1)
+            + dx * dy * self.texel(level, x0 + 1, y0 + 1)
+    }
+
+    /// Elliptically weighted average, interpolated between the two levels matching the minor axis of the footprint
+    fn ewa(&self, st: Vec2, mut dst0: Vec2, mut dst1: Vec2) -> Vec3 {
+        if length2(&dst0) < length2(&dst1) {
+            std::mem::swap(&mut dst0, &mut dst1);
+        }
+        let major = dst0.norm();
+        let mut minor = dst1.norm();
+        // clamp the eccentricity, very thin ellipses would cover too many texels
+        if minor * self.max_anisotropy < major && minor > 0.0 {
+            let scale = major / (minor * self.max_anisotropy);
+            dst1 *= scale;
+            minor *= scale;
+        }
+        if minor == 0.0 {
+            return self.bilerp(0, st);
+        }
+        let lod = f32::max(0.0, (self.levels() - 1) as f32 + minor.log2());
+        let i_lod = lod.floor() as usize;
+        lerp(
+            self.ewa_level(i_lod, st, dst0, dst1),
+            self.ewa_level(i_lod + 1, st, dst0, dst1),
+            lod - i_lod as f32,
+        )
+    }
+
+    fn ewa_level(&self, level: usize, st: Vec2, dst0: Vec2, dst1: Vec2) -> Vec3 {
+        if level >= self.levels() {
+            return self.texel(self.levels() - 1, 0, 0);
+        }
+        let image = &self.levels[level];
+        let resolution = Vec2::new(image.size_x as f32, image.size_y as f32);
+        let st = st.component_mul(&resolution).add_scalar(-0.5);
+        let dst0 = dst0.component_mul(&resolution);
+        let dst1 = dst1.component_mul(&resolution);
+
+        // implicit equation of the ellipse, a * s^2 + b * s * t + c * t^2 < 1
+        let mut a = dst0.y * dst0.y + dst1.y * dst1.y + 1.0;
+        let mut b = -2.0 * (dst0.x * dst0.y + dst1.x * dst1.y);
+        let mut c = dst0.x * dst0.x + dst1.x * dst1.x + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        a *= inv_f;
+        b *= inv_f;
+        c *= inv_f;
+
+        // bounding box of the ellipse in texels
+        let det = -b * b + 4.0 * a * c;
+        let inv_det = 1.0 / det;
+        let u_sqrt = f32::sqrt(f32::max(det * c, 0.0));
+        let v_sqrt = f32::sqrt(f32::max(det * a, 0.0));
+        let s0 = (st.x - 2.0 * inv_det * u_sqrt).ceil() as i64;
+        let s1 = (st.x + 2.0 * inv_det * u_sqrt).floor() as i64;
+        let t0 = (st.y - 2.0 * inv_det * v_sqrt).ceil() as i64;
+        let t1 = (st.y + 2.0 * inv_det * v_sqrt).floor() as i64;
+
+        // gaussian weights, falling to zero on the border of the ellipse
+        let mut sum = Vec3::zeros();
+        let mut weights = 0.0;
+        for it in t0..=t1 {
+            let tt = it as f32 - st.y;
+            for is in s0..=s1 {
+                let ss = is as f32 - st.x;
+                let r2 = a * ss * ss + b * ss * tt + c * tt * tt;
+                if r2 < 1.0 {
+                    let weight = f32::exp(-2.0 * r2) - f32::exp(-2.0);
+                    sum += weight * self.texel(level, is, it);
+                    weights += weight;
+                }
+            }
+        }
+        if weights > 0.0 {
+            sum / weights
+        } else {
+            self.bilerp(level, st.add_scalar(0.5).component_div(&resolution))
+        }
+    }
+}
+
+/// Downsample `image` until a single texel is left, the first level being the image itself
+pub fn build_levels(image: Arc<Image2d>, wrap: WrapMode) -> MipLevels {
+    let mut levels = vec![image];
+    loop {
+        let last = levels.last().unwrap();
+        if last.size_x <= 1 && last.size_y <= 1 {
+            break;
+        }
+        let next = downsample(last, wrap);
+        levels.push(Arc::new(next));
+    }
+    levels
+}
+
+/// Index of the texel `x` in an image of `size` texels, or `None` outside of a black border
+fn wrap(x: i64, size: usize, mode: WrapMode) -> Option<usize> {
+    let size = size as i64;
+    let x = match mode {
+        WrapMode::Repeat => x.rem_euclid(size),
+        WrapMode::Clamp => x.clamp(0, size - 1),
+        WrapMode::Mirror => {
+            let x = x.rem_euclid(2 * size);
+            if x < size {
+                x
+            } else {
+                2 * size - 1 - x
+            }
+        }
+        WrapMode::Black => {
+            if x < 0 || x >= size {
+                return None;
+            }
+            x
+        }
+    };
+    Some(x as usize)
+}
+
+/// Average blocks of two by two texels, a level with an odd size is wrapped to cover its last row or column
+fn downsample(image: &Image2d, mode: WrapMode) -> Image2d {
+    let mut next = Image2d::new(
+        image.size_x.div_ceil(2).max(1),
+        image.size_y.div_ceil(2).max(1),
+    );
+    // black borders would darken the coarse levels, clamp them instead
+    let mode = if mode == WrapMode::Black {
+        WrapMode::Clamp
+    } else {
+        mode
+    };
+    let texel = |x: usize, y: usize| match (
+        wrap(x as i64, image.size_x, mode),
+        wrap(y as i64, image.size_y, mode),
+    ) {
+        (Some(x), Some(y)) => image[(x, y)],
+        _ => Vec3::zeros(),
+    };
+    for y in 0..next.size_y {
+        for x in 0..next.size_x {
+            next[(x, y)] = (texel(2 * x, 2 * y)
+                + texel(2 * x + 1, 2 * y)
+                + texel(2 * x, 2 * y + 1)
+                + texel(2 * x + 1, 2 * y + 1))
+                / 4.0;
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use std::sync::Arc;
+
+    use crate::core::image2d::Image2d;
+    use crate::core::mipmap::{build_levels, FilterMode, MipMap, WrapMode};
+
+    fn mipmap(image: Arc<Image2d>, wrap: WrapMode, filter: FilterMode) -> MipMap {
+        MipMap::new(Arc::new(build_levels(image, wrap)), wrap, filter)
+    }
+
+    /// Checkerboard of black and white texels
+    fn checkerboard(size: usize) -> Arc<Image2d> {
+        let mut image = Image2d::new(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                image[(x, y)] = Vec3::repeat(((x + y) % 2) as f32);
+            }
+        }
+        Arc::new(image)
+    }
+
+    #[test]
+    fn single_texel() {
+        let mut image = Image2d::new(1, 1);
+        image[(0, 0)] = Vec3::new(0.25, 0.5, 1.0);
+        let image = Arc::new(image);
+        for filter in [
+            FilterMode::Nearest,
+            FilterMode::Bilinear,
+            FilterMode::Trilinear,
+            FilterMode::Ewa,
+        ] {
+            let mipmap = mipmap(image.clone(), WrapMode::Repeat, filter);
+            // without a footprint, and with a wide one
+            for d in [0.0, 2.0] {
+                let texel =
+                    mipmap.filter(Vec2::new(0.3, 0.7), Vec2::new(d, 0.0), Vec2::new(0.0, d));
+                approx::assert_abs_diff_eq!(texel, Vec3::new(0.25, 0.5, 1.0), epsilon = 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let mut image = Image2d::new(4, 1);
+        for x in 0..4 {
+            image[(x, 0)] = Vec3::repeat(x as f32);
+        }
+        let image = Arc::new(image);
+        let at = |mode, x| {
+            mipmap(image.clone(), mode, FilterMode::Nearest)
+                .texel(0, x, 0)
+                .x
+        };
+        assert_eq!(at(WrapMode::Repeat, 5), 1.0);
+        assert_eq!(at(WrapMode::Repeat, -1), 3.0);
+        assert_eq!(at(WrapMode::Clamp, 7), 3.0);
+        assert_eq!(at(WrapMode::Clamp, -2), 0.0);
+        assert_eq!(at(WrapMode::Mirror, 4), 3.0);
+        assert_eq!(at(WrapMode::Mirror, -1), 0.0);
+        assert_eq!(at(WrapMode::Black, 4), 0.0);
+        assert_eq!(at(WrapMode::Black, 2), 2.0);
+    }
+
+    #[test]
+    fn minified_lookups_average_the_texels() {
+        let image = checkerboard(64);
+        let point = Vec2::new(0.3, 0.6);
+        for filter in [FilterMode::Bilinear, FilterMode::Trilinear, FilterMode::Ewa] {
+            let mipmap = mipmap(image.clone(), WrapMode::Repeat, filter);
+            assert_eq!(mipmap.levels(), 7);
+            // a footprint covering many texels sees gray
+            let gray = mipmap.filter(point, Vec2::new(0.1, 0.0), Vec2::new(0.0, 0.1));
+            approx::assert_abs_diff_eq!(gray, Vec3::repeat(0.5), epsilon = 1e-3);
+            // a point lookup at the center of a texel sees it alone
+            let texel = mipmap.filter(Vec2::new(0.5, 0.5) / 64.0, Vec2::zeros(), Vec2::zeros());
+            approx::assert_abs_diff_eq!(texel, Vec3::zeros(), epsilon = 1e-5);
+        }
+
+        // an anisotropic footprint, long along s and a texel wide along t, stays within the row
+        let mut stripes = Image2d::new(64, 64);
+        for y in 0..64 {
+            for x in 0..64 {
+                stripes[(x, y)] = Vec3::repeat((y % 2) as f32);
+            }
+        }
+        let mipmap = mipmap(Arc::new(stripes), WrapMode::Repeat, FilterMode::Ewa);
+        let row = mipmap.filter(
+            Vec2::new(0.5, 1.5 / 64.0),
+            Vec2::new(0.05, 0.0),
+            Vec2::new(0.0, 0.1 / 64.0),
+        );
+        assert!(row.x > 0.9, "{row:?}");
+    }
+
+    #[test]
+    fn uv_on_the_border() {
+        // lookups of exactly 1 or outside [0, 1] no longer index out of the image
+        let mipmap = mipmap(checkerboard(3), WrapMode::Repeat, FilterMode::Nearest);
+        for st in [
+            Vec2::new(1.0, 1.0),
+            Vec2::new(-0.2, 1.7),
+            Vec2::new(0.0, 0.0),
+        ] {
+            let value = mipmap.filter(st, Vec2::zeros(), Vec2::zeros());
+            assert!(value.x == 0.0 || value.x == 1.0);
+        }
+        assert_eq!(mipmap.levels(), 3);
+    }
+}
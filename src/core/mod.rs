@@ -1,9 +1,16 @@
 pub mod aabb;
-pub mod camera;
+pub mod animation;
+pub mod assets;
+pub mod distribution;
+pub mod ies;
 pub mod image2d;
+pub mod merl;
+pub mod mipmap;
+pub mod microfacet;
 pub mod onb;
 pub mod ray;
 pub mod sampling;
 pub mod scene;
+pub mod tiles;
 pub mod transform;
 pub mod utils;
@@ -1,6 +1,7 @@
-use nalgebra_glm::{cross, normalize, Vec3};
+use nalgebra_glm::{cross, dot, normalize, Vec3};
 
 /// `OrthoNormal` Basis
+#[derive(Debug, PartialEq, Clone)]
 pub struct Onb {
     axis: [Vec3; 3],
 }
@@ -18,7 +19,29 @@ impl Onb {
         Onb { axis: [u, v, w] }
     }
 
+    /// Basis around `normal` with `u` along the part of `tangent` orthogonal to it, to orient anisotropic lobes with the
+    /// surface. Falls back to `build_from_w` when the tangent is parallel to the normal.
+    pub fn build_from_w_u(normal: &Vec3, tangent: &Vec3) -> Self {
+        let w = normalize(normal);
+        let u = tangent - dot(tangent, &w) * w;
+        if u.norm_squared() < 1e-12 {
+            return Onb::build_from_w(&w);
+        }
+        let u = normalize(&u);
+        let v = cross(&w, &u);
+        Onb { axis: [u, v, w] }
+    }
+
     pub fn local(&self, a: &Vec3) -> Vec3 {
         a.x * self.axis[0] + a.y * self.axis[1] + a.z * self.axis[2]
     }
+
+    /// Express a world space vector in the basis, the inverse of `local`
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(
+            dot(a, &self.axis[0]),
+            dot(a, &self.axis[1]),
+            dot(a, &self.axis[2]),
+        )
+    }
 }
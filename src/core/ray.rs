@@ -10,6 +10,19 @@ pub struct Ray {
     pub direction: Vec3,
     pub min_t: f32,
     pub max_t: f32,
+    /// Time at which the ray is traced, inside the shutter interval of the camera
+    pub time: f32,
+    /// Rays through the neighbouring pixels, only camera rays have them
+    pub differentials: Option<RayDifferentials>,
+}
+
+/// Rays offset by one pixel along the x and y axes of the image, to estimate the footprint of a camera ray
+#[derive(Debug, Clone)]
+pub struct RayDifferentials {
+    pub rx_origin: Vec3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Vec3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
@@ -20,6 +33,28 @@ impl Ray {
             direction,
             min_t: 0.0001, // TODO : maybe change this
             max_t: f32::INFINITY,
+            time: 0.0,
+            differentials: None,
+        }
+    }
+
+    pub fn with_time(mut self, time: f32) -> Ray {
+        self.time = time;
+        self
+    }
+
+    pub fn with_differentials(mut self, differentials: RayDifferentials) -> Ray {
+        self.differentials = Some(differentials);
+        self
+    }
+
+    /// Shrink the differentials by `scale`, when several samples share the footprint of a pixel
+    pub fn scale_differentials(&mut self, scale: f32) {
+        if let Some(d) = &mut self.differentials {
+            d.rx_origin = self.origin + (d.rx_origin - self.origin) * scale;
+            d.ry_origin = self.origin + (d.ry_origin - self.origin) * scale;
+            d.rx_direction = self.direction + (d.rx_direction - self.direction) * scale;
+            d.ry_direction = self.direction + (d.ry_direction - self.direction) * scale;
         }
     }
 
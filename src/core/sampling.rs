@@ -1,5 +1,6 @@
-use nalgebra_glm::{cross, length, length2, Vec2, Vec3};
+use nalgebra_glm::{cross, dot, length, length2, normalize, Vec2, Vec3};
 use rand::Rng;
+use std::f32::consts::PI;
 
 use crate::core::utils::{lerp, sincos, FRAC_1_TWOPI, INV_FOURPI};
 
@@ -20,6 +21,30 @@ pub fn sample_disk_pdf(p: Vec2) -> f32 {
     }
 }
 
+/// Uniformly sample a point inside a regular polygon inscribed in the unit disk
+///
+/// \param n_sides  Number of sides of the polygon (at least 3)
+/// \param rotation Angle in radians of the first vertex with respect to the x axis
+/// \param rv       Two random variables uniformly distributed in [0,1)
+pub fn sample_regular_polygon(n_sides: u32, rotation: f32, rv: Vec2) -> Vec2 {
+    // pick one of the triangles fanning from the center, they all have the same area
+    let sector = rv.x * n_sides as f32;
+    let index = f32::min(sector.floor(), (n_sides - 1) as f32);
+    let u = sector - index;
+
+    let angle = std::f32::consts::TAU / n_sides as f32;
+    let (sin0, cos0) = sincos(rotation + index * angle);
+    let (sin1, cos1) = sincos(rotation + (index + 1.0) * angle);
+
+    // uniform sampling of the triangle (0, v0, v1)
+    let a = f32::sqrt(u);
+    let b = rv.y;
+    Vec2::new(
+        a * ((1.0 - b) * cos0 + b * cos1),
+        a * ((1.0 - b) * sin0 + b * sin1),
+    )
+}
+
 /// Uniformly sample a vector on the unit sphere with respect to solid angles
 pub fn sample_sphere(rv: Vec2) -> Vec3 {
     let cos_theta = 2.0 * rv.y - 1.0;
@@ -29,7 +54,6 @@ pub fn sample_sphere(rv: Vec2) -> Vec3 {
 }
 
 /// Probability density of `sample_sphere`()
-#[allow(unused)]
 pub const fn sample_sphere_pdf() -> f32 {
     INV_FOURPI
 }
@@ -114,6 +138,202 @@ pub fn sample_triangle_pdf(v0: &Vec3, v1: &Vec3, v2: &Vec3) -> f32 {
     1.0 / area
 }
 
+/// Solid angles within which sampling a spherical triangle or rectangle is reliable. Below, the area sampling of the
+/// surface is accurate enough and cheaper; above, the shading point is so close to the plane of the surface that the
+/// spherical polygon degenerates.
+pub const MIN_SPHERICAL_SAMPLE_AREA: f32 = 3e-4;
+pub const MAX_SPHERICAL_SAMPLE_AREA: f32 = 6.22;
+
+/// Angle between two unit vectors, accurate even when they are almost parallel
+fn angle_between(a: &Vec3, b: &Vec3) -> f32 {
+    if dot(a, b) < 0.0 {
+        PI - 2.0 * f32::asin((length(&(a + b)) / 2.0).min(1.0))
+    } else {
+        2.0 * f32::asin((length(&(b - a)) / 2.0).min(1.0))
+    }
+}
+
+/// Component of `v` orthogonal to the unit vector `w`
+fn gram_schmidt(v: &Vec3, w: &Vec3) -> Vec3 {
+    v - dot(v, w) * w
+}
+
+/// Interior angles of the spherical triangle with vertices `a`, `b`, `c` on the unit sphere
+fn spherical_triangle_angles(a: &Vec3, b: &Vec3, c: &Vec3) -> Option<(f32, f32, f32)> {
+    let n_ab = cross(a, b);
+    let n_bc = cross(b, c);
+    let n_ca = cross(c, a);
+    if length2(&n_ab) == 0.0 || length2(&n_bc) == 0.0 || length2(&n_ca) == 0.0 {
+        return None;
+    }
+    let (n_ab, n_bc, n_ca) = (normalize(&n_ab), normalize(&n_bc), normalize(&n_ca));
+    Some((
+        angle_between(&n_ab, &-n_ca),
+        angle_between(&n_bc, &-n_ab),
+        angle_between(&n_ca, &-n_bc),
+    ))
+}
+
+/// Solid angle subtended by the triangle with vertices `v0`, `v1`, `v2` as seen from `o`
+pub fn spherical_triangle_area(v0: &Vec3, v1: &Vec3, v2: &Vec3, o: &Vec3) -> f32 {
+    let a = normalize(&(v0 - o));
+    let b = normalize(&(v1 - o));
+    let c = normalize(&(v2 - o));
+    spherical_triangle_angles(&a, &b, &c).map_or(0.0, |(alpha, beta, gamma)| {
+        f32::max(alpha + beta + gamma - PI, 0.0)
+    })
+}
+
+/// Uniformly sample a direction from `o` towards the triangle with vertices `v0`, `v1`, `v2`, with respect to solid
+/// angles (Arvo, "Stratified sampling of spherical triangles", 1995).
+///
+/// The density is one over `spherical_triangle_area`(), which should not be degenerate.
+///
+/// \param v0,v1,v2 The vertices of the triangle to sample
+/// \param o        The point the triangle is seen from
+/// \param rv       Two random variables uniformly distributed in [0,1)
+pub fn sample_spherical_triangle(v0: &Vec3, v1: &Vec3, v2: &Vec3, o: &Vec3, rv: Vec2) -> Vec3 {
+    let a = normalize(&(v0 - o));
+    let b = normalize(&(v1 - o));
+    let c = normalize(&(v2 - o));
+    let Some((alpha, beta, gamma)) = spherical_triangle_angles(&a, &b, &c) else {
+        return a;
+    };
+
+    // choose the sub-triangle a, b, c' whose area is the fraction rv.x of the whole one
+    let area_pi = lerp(PI, alpha + beta + gamma, rv.x);
+    let (sin_alpha, cos_alpha) = sincos(alpha);
+    let (sin_area, cos_area) = sincos(area_pi);
+    let sin_phi = sin_area * cos_alpha - cos_area * sin_alpha;
+    let cos_phi = cos_area * cos_alpha + sin_area * sin_alpha;
+    let k1 = cos_phi + cos_alpha;
+    let k2 = sin_phi - sin_alpha * dot(&a, &b);
+    let cos_b = ((k2 + (k2 * cos_phi - k1 * sin_phi) * cos_alpha)
+        / ((k2 * sin_phi + k1 * cos_phi) * sin_alpha))
+        .clamp(-1.0, 1.0);
+    let sin_b = f32::sqrt(f32::max(1.0 - cos_b * cos_b, 0.0));
+    let c_prime = cos_b * a + sin_b * normalize(&gram_schmidt(&c, &a));
+
+    // then a point on the arc between b and c'
+    let cos_theta = 1.0 - rv.y * (1.0 - dot(&c_prime, &b));
+    let sin_theta = f32::sqrt(f32::max(1.0 - cos_theta * cos_theta, 0.0));
+    let ortho = gram_schmidt(&c_prime, &b);
+    if length2(&ortho) == 0.0 {
+        return b;
+    }
+    cos_theta * b + sin_theta * normalize(&ortho)
+}
+
+/// The spherical rectangle spanned by a rectangle, in a frame where the rectangle lies in the plane z = `z0` < 0
+struct SphericalRectangle {
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+    x0: f32,
+    x1: f32,
+    y0: f32,
+    y1: f32,
+    z0: f32,
+    b0: f32,
+    b1: f32,
+    k: f32,
+    solid_angle: f32,
+}
+
+impl SphericalRectangle {
+    fn new(o: &Vec3, corner: &Vec3, ex: &Vec3, ey: &Vec3) -> Option<SphericalRectangle> {
+        let ex_length = length(ex);
+        let ey_length = length(ey);
+        if ex_length == 0.0 || ey_length == 0.0 {
+            return None;
+        }
+        let x = ex / ex_length;
+        let y = ey / ey_length;
+        let mut z = cross(&x, &y);
+        let d = corner - o;
+        let mut z0 = dot(&d, &z);
+        if z0 > 0.0 {
+            z = -z;
+            z0 = -z0;
+        }
+        if z0 == 0.0 {
+            return None;
+        }
+        let x0 = dot(&d, &x);
+        let y0 = dot(&d, &y);
+        let x1 = x0 + ex_length;
+        let y1 = y0 + ey_length;
+
+        let v00 = Vec3::new(x0, y0, z0);
+        let v01 = Vec3::new(x0, y1, z0);
+        let v10 = Vec3::new(x1, y0, z0);
+        let v11 = Vec3::new(x1, y1, z0);
+        let n0 = normalize(&cross(&v00, &v10));
+        let n1 = normalize(&cross(&v10, &v11));
+        let n2 = normalize(&cross(&v11, &v01));
+        let n3 = normalize(&cross(&v01, &v00));
+        let g0 = angle_between(&-n0, &n1);
+        let g1 = angle_between(&-n1, &n2);
+        let g2 = angle_between(&-n2, &n3);
+        let g3 = angle_between(&-n3, &n0);
+
+        Some(SphericalRectangle {
+            x,
+            y,
+            z,
+            x0,
+            x1,
+            y0,
+            y1,
+            z0,
+            b0: n0.z,
+            b1: n2.z,
+            k: -g2 - g3,
+            solid_angle: f32::max(g0 + g1 + g2 + g3 - 2.0 * PI, 0.0),
+        })
+    }
+}
+
+/// Solid angle subtended by the rectangle with corner `corner` and orthogonal edges `ex`, `ey` as seen from `o`
+pub fn spherical_rectangle_area(o: &Vec3, corner: &Vec3, ex: &Vec3, ey: &Vec3) -> f32 {
+    SphericalRectangle::new(o, corner, ex, ey).map_or(0.0, |rectangle| rectangle.solid_angle)
+}
+
+/// Sample a point on a rectangle uniformly with respect to the solid angle it subtends from `o` (Ureña et al., "An
+/// Area-Preserving Parametrization for Spherical Rectangles", 2013).
+///
+/// The density is one over `spherical_rectangle_area`(), which should not be degenerate.
+///
+/// \param o      The point the rectangle is seen from
+/// \param corner A corner of the rectangle
+/// \param ex,ey  The two orthogonal edges leaving `corner`
+/// \param rv     Two random variables uniformly distributed in [0,1)
+pub fn sample_spherical_rectangle(o: &Vec3, corner: &Vec3, ex: &Vec3, ey: &Vec3, rv: Vec2) -> Vec3 {
+    let Some(r) = SphericalRectangle::new(o, corner, ex, ey) else {
+        return *corner;
+    };
+    // the abscissa splitting the solid angle at the fraction rv.x
+    let au = rv.x * r.solid_angle + r.k;
+    let (sin_au, cos_au) = sincos(au);
+    let fu = (cos_au * r.b0 - r.b1) / sin_au;
+    let cu = (1.0 / f32::sqrt(fu * fu + r.b0 * r.b0))
+        .copysign(fu)
+        .clamp(-0.999_999_9, 0.999_999_9);
+    let xu = (-(cu * r.z0) / f32::sqrt(f32::max(1.0 - cu * cu, 0.0))).clamp(r.x0, r.x1);
+
+    // then the ordinate along the segment at xu
+    let d = f32::sqrt(xu * xu + r.z0 * r.z0);
+    let h0 = r.y0 / f32::sqrt(d * d + r.y0 * r.y0);
+    let h1 = r.y1 / f32::sqrt(d * d + r.y1 * r.y1);
+    let hv = lerp(h0, h1, rv.y);
+    let yv = if hv * hv < 1.0 - 1e-6 {
+        hv * d / f32::sqrt(1.0 - hv * hv)
+    } else {
+        r.y1
+    };
+    o + xu * r.x + yv * r.y + r.z0 * r.z
+}
+
 pub fn random_in_unit_sphere(rng: &mut impl Rng) -> Vec3 {
     const ONES: Vec3 = Vec3::new(1.0, 1.0, 1.0);
     loop {
@@ -124,3 +344,13 @@ pub fn random_in_unit_sphere(rng: &mut impl Rng) -> Vec3 {
         }
     }
 }
+
+/// Centers of `n` equal intervals of [0,1), for deterministic estimates with stratified random variables
+pub fn stratified(n: usize) -> impl Iterator<Item = f32> {
+    (0..n).map(move |i| (i as f32 + 0.5) / n as f32)
+}
+
+/// Centers of the cells of an `n`×`n` grid over [0,1)², for deterministic estimates with stratified random variables
+pub fn stratified_grid(n: usize) -> impl Iterator<Item = Vec2> {
+    stratified(n).flat_map(move |x| stratified(n).map(move |y| Vec2::new(x, y)))
+}
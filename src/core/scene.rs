@@ -3,27 +3,226 @@ use rand::SeedableRng;
 use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
 use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
 
-use crate::core::camera::PinholeCamera;
+use crate::cameras::{create_camera, Camera, CameraType};
+use crate::core::aabb::Aabb;
 use crate::core::image2d::Image2d;
 use crate::core::ray::Ray;
-use crate::core::utils::{get_progress_bar, read_v_or_f, Factory};
-use crate::integrators::{create_integrator, Integrator, IntegratorType};
+use crate::core::tiles::{
+    CancellationToken, ProgressBarProgress, RenderProgress, Tile, TileScheduler,
+};
+use crate::core::utils::{read_or, Factory};
+use crate::integrators::{create_integrator, Integrator, IntegratorType, PathTracerMatsIntegrator};
+use crate::lights::{
+    create_background, create_light, create_light_sampler, read_portals, Background,
+    BackgroundType, Emitter, Light, LightSample, LightSampler, LightSamplerType, LightSelection,
+    LightType, Portals,
+};
 use crate::materials::MaterialFactory;
-use crate::samplers::{create_sampler, Sampler, SamplerType};
-use crate::surfaces::{create_surface_group, HitInfo, Surface, SurfaceFactory, SurfaceGroupType};
+use crate::samplers::{create_sampler, IndependentSampler, Sampler, SamplerType};
+use crate::surfaces::{
+    build_surface_group, collect_emitters, read_split_method, HitInfo, Quad, SplitMethod, Surface,
+    SurfaceFactory, SurfaceGroupType, SurfaceType,
+};
+
+/// Read a JSON scene description from a file
+pub fn read_scene_from_file<P: AsRef<Path>>(path: P) -> Result<Value, Box<dyn Error>> {
+    // Open the file in read-only mode with buffer.
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+
+    // Read the JSON contents of the file
+    let j = serde_json::from_reader(reader)?;
+
+    Ok(j)
+}
 
 pub struct Scene {
     surfaces: SurfaceGroupType,
-    pub emitters: SurfaceGroupType,
+    /// Emissive surfaces and lights, the background is sampled apart
+    pub emitters: Vec<Emitter>,
+    /// Chooses among the emitters, followed by the background when it emits
+    light_sampler: LightSamplerType,
+    /// Indices of the emitters at infinity, the only ones reached by rays leaving the scene
+    distant_emitters: Vec<usize>,
     integrator: IntegratorType,
     sampler: SamplerType,
-    camera: PinholeCamera,
-    pub background: Vec3,
+    camera: CameraType,
+    pub background: BackgroundType,
+    /// Openings through which the background is sampled, over the whole sphere when there are none
+    portals: Portals,
+}
+
+/// Assemble a scene from already constructed parts
+pub struct SceneBuilder {
+    camera: CameraType,
+    sampler: SamplerType,
+    integrator: IntegratorType,
+    background: BackgroundType,
+    split_method: Option<SplitMethod>,
+    surfaces: Vec<SurfaceType>,
+    lights: Vec<LightType>,
+    light_selection: LightSelection,
+    portals: Vec<Quad>,
+}
+
+impl SceneBuilder {
+    pub fn new(camera: impl Into<CameraType>) -> SceneBuilder {
+        SceneBuilder {
+            camera: camera.into(),
+            sampler: SamplerType::from(IndependentSampler::new(1)),
+            integrator: IntegratorType::from(PathTracerMatsIntegrator::new(64)),
+            background: BackgroundType::from(Vec3::zeros()),
+            split_method: None,
+            surfaces: Vec::new(),
+            lights: Vec::new(),
+            light_selection: LightSelection::Power,
+            portals: Vec::new(),
+        }
+    }
+
+    pub fn sampler(mut self, sampler: impl Into<SamplerType>) -> SceneBuilder {
+        self.sampler = sampler.into();
+        self
+    }
+
+    pub fn integrator(mut self, integrator: impl Into<IntegratorType>) -> SceneBuilder {
+        self.integrator = integrator.into();
+        self
+    }
+
+    /// Light arriving from outside the scene, a constant color or an emitter like `EnvironmentMap`
+    pub fn background(mut self, background: impl Into<BackgroundType>) -> SceneBuilder {
+        self.background = background.into();
+        self
+    }
+
+    /// Use a BVH built with `split_method`, or a naive linear accelerator for `None`
+    pub fn accelerator(mut self, split_method: Option<SplitMethod>) -> SceneBuilder {
+        self.split_method = split_method;
+        self
+    }
+
+    pub fn surface(mut self, surface: impl Into<SurfaceType>) -> SceneBuilder {
+        self.surfaces.push(surface.into());
+        self
+    }
+
+    pub fn surfaces(mut self, surfaces: impl IntoIterator<Item = SurfaceType>) -> SceneBuilder {
+        self.surfaces.extend(surfaces);
+        self
+    }
+
+    pub fn light(mut self, light: impl Into<LightType>) -> SceneBuilder {
+        self.lights.push(light.into());
+        self
+    }
+
+    pub fn lights(mut self, lights: impl IntoIterator<Item = LightType>) -> SceneBuilder {
+        self.lights.extend(lights);
+        self
+    }
+
+    /// How light samples choose among the emitters, proportionally to their power by default
+    pub fn light_selection(mut self, light_selection: LightSelection) -> SceneBuilder {
+        self.light_selection = light_selection;
+        self
+    }
+
+    /// Opening through which the background lights an interior, its directions are only sampled through the portals
+    pub fn portal(mut self, portal: Quad) -> SceneBuilder {
+        self.portals.push(portal);
+        self
+    }
+
+    pub fn portals(mut self, portals: impl IntoIterator<Item = Quad>) -> SceneBuilder {
+        self.portals.extend(portals);
+        self
+    }
+
+    pub fn build(self) -> Scene {
+        let mut surfaces_vec = self.surfaces;
+        let mut bounds = Aabb::new();
+        for surface in &surfaces_vec {
+            bounds.enclose(&surface.bounds());
+        }
+
+        // not sure about this cloned ... FIXME!
+        let emitters: Vec<Emitter> = collect_emitters(&mut surfaces_vec)
+            .into_iter()
+            .map(Emitter::from)
+            .chain(self.lights.into_iter().map(Emitter::from))
+            .collect();
+        let distant_emitters = emitters
+            .iter()
+            .enumerate()
+            .filter(|(_, emitter)| matches!(emitter, Emitter::Light(light) if light.light_bounds().is_none()))
+            .map(|(index, _)| index)
+            .collect();
+        // the surfaces know their emitter before being grouped
+        let surfaces = build_surface_group(self.split_method.as_ref(), &mut surfaces_vec);
+        let light_sampler =
+            create_light_sampler(self.light_selection, &emitters, &self.background, &bounds);
+
+        Scene {
+            surfaces,
+            emitters,
+            light_sampler,
+            distant_emitters,
+            integrator: self.integrator,
+            sampler: self.sampler,
+            camera: self.camera,
+            background: self.background,
+            portals: Portals::new(self.portals),
+        }
+    }
+}
+
+/// Surfaces built for a previous scene, reused by the next one when their description did not change
+#[derive(Default)]
+pub struct SceneCache {
+    /// Keyed by the JSON description of the surface, with its named materials inlined
+    surfaces: HashMap<String, Vec<SurfaceType>>,
+}
+
+/// Replace the material names by their description, so that a surface changes when its material does
+fn inline_materials(v: &Value, materials: &HashMap<&str, &Value>) -> Value {
+    match v {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let value = match (key.as_str(), value.as_str()) {
+                        ("material", Some(name)) if materials.contains_key(name) => {
+                            materials[name].clone()
+                        }
+                        _ => inline_materials(value, materials),
+                    };
+                    (key.clone(), value)
+                })
+                .collect::<Map<String, Value>>(),
+        ),
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| inline_materials(value, materials))
+                .collect(),
+        ),
+        _ => v.clone(),
+    }
 }
 
 impl Scene {
     pub fn new(scene_json: &Value) -> Scene {
+        Scene::new_cached(scene_json, &mut SceneCache::default())
+    }
+
+    /// Parse a scene, reusing the surfaces of `cache` that did not change and storing the new ones
+    pub fn new_cached(scene_json: &Value, cache: &mut SceneCache) -> Scene {
         println!("Parsing...");
         let map_json = scene_json.as_object().unwrap();
 
@@ -37,6 +236,10 @@ impl Scene {
             "camera",
             "sampler",
             "background",
+            "lights",
+            "light_selection",
+            "portals",
+            "animation",
         ];
 
         for key in map_json.keys() {
@@ -47,7 +250,7 @@ impl Scene {
         }
 
         // camera
-        let camera = PinholeCamera::new(
+        let camera = create_camera(
             scene_json
                 .get("camera")
                 .expect("No camera specified in scene!"),
@@ -58,9 +261,24 @@ impl Scene {
         // integrator
         let integrator = create_integrator(map_json);
 
-        // scene background
-        // TODO replace by let background = create_texture(&scene_json, "background")
-        let background = read_v_or_f(scene_json, "background");
+        // scene background and lights
+        let background = create_background(scene_json);
+        let lights: Vec<LightType> = map_json
+            .get("lights")
+            .map(|lights| {
+                lights
+                    .as_array()
+                    .expect("Lights should be in an array")
+                    .iter()
+                    .map(create_light)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let portals = map_json
+            .get("portals")
+            .map(read_portals)
+            .unwrap_or_default();
 
         // materials
         let mut material_factory = MaterialFactory::new();
@@ -81,92 +299,215 @@ impl Scene {
             panic!("No surfaces to render :(");
         };
 
+        let named_materials: HashMap<&str, &Value> = map_json
+            .get("materials")
+            .and_then(Value::as_array)
+            .map(|materials| {
+                materials
+                    .iter()
+                    .filter_map(|mat| Some((mat.get("name")?.as_str()?, mat)))
+                    .collect()
+            })
+            .unwrap_or_default();
+
         let mut surface_facory = SurfaceFactory { material_factory };
-        let mut surfaces_vec = surfaces
-            .as_array()
-            .expect("Surfaces should be in an array")
-            .iter()
-            .flat_map(|sur| {
-                surface_facory.make(sur).unwrap_or_else(|| {
+        let mut built_surfaces = HashMap::new();
+        let mut surfaces_vec: Vec<SurfaceType> = Vec::new();
+        for sur in surfaces.as_array().expect("Surfaces should be in an array") {
+            let key = inline_materials(sur, &named_materials).to_string();
+            let built = match built_surfaces.get(&key).or(cache.surfaces.get(&key)) {
+                Some(built) => built.clone(),
+                None => surface_facory.make(sur).unwrap_or_else(|| {
                     panic!("surface of type : {} not yet supported", sur["type"])
-                })
-            })
-            .collect();
+                }),
+            };
+            surfaces_vec.extend(built.iter().cloned());
+            built_surfaces.insert(key, built);
+        }
+        // only keep what this scene uses
+        cache.surfaces = built_surfaces;
 
-        let surfaces = create_surface_group(map_json, &mut surfaces_vec);
+        SceneBuilder::new(camera)
+            .sampler(sampler)
+            .integrator(integrator)
+            .background(background)
+            .lights(lights)
+            .light_selection(read_or(
+                scene_json,
+                "light_selection",
+                LightSelection::Power,
+            ))
+            .accelerator(read_split_method(map_json))
+            .surfaces(surfaces_vec)
+            .portals(portals)
+            .build()
+    }
 
-        // not sure about this cloned ... FIXME!
-        let mut emitters_vec = surfaces_vec
+    pub fn intersect(&self, ray: &Ray) -> Option<HitInfo> {
+        let mut hit = self.surfaces.intersect(ray)?;
+        hit.compute_differentials(ray);
+        Some(hit)
+    }
+
+    /// Radiance arriving along a ray leaving the scene in the direction `dir`
+    pub fn escaped_radiance(&self, dir: &Vec3) -> Vec3 {
+        self.emitters
             .iter()
-            .filter(|surface| surface.is_emissive())
-            .cloned()
-            .collect();
+            .filter_map(|emitter| match emitter {
+                Emitter::Light(light) => Some(light.radiance(dir)),
+                Emitter::Surface(_) => None,
+            })
+            .sum::<Vec3>()
+            + self.background.radiance(dir)
+    }
 
-        let emitters = create_surface_group(&Map::new(), &mut emitters_vec);
+    /// Sample a direction towards one of the emitters or the background, chosen by the light sampler with `rv1`
+    pub fn sample_light(&self, p: &Vec3, time: f32, rv: Vec2, rv1: f32) -> Option<LightSample> {
+        let (index, pmf) = self.light_sampler.sample(p, rv1)?;
+        let mut sample = match self.emitters.get(index) {
+            Some(emitter) => emitter.sample(p, time, rv)?,
+            None => self.portals.sample(&self.background, p, time, rv)?,
+        };
+        sample.pdf *= pmf;
+        Some(sample)
+    }
 
-        Scene {
-            surfaces,
-            emitters,
-            integrator,
-            sampler,
-            camera,
-            background,
+    /// Solid angle density of `sample_light` for the direction `dir` from `p`, which reaches `hit`, or leaves the
+    /// scene for `None`. Delta lights are left out since only light samples reach them.
+    pub fn light_pdf(&self, p: &Vec3, time: f32, dir: &Vec3, hit: Option<&HitInfo>) -> f32 {
+        if let Some(hit) = hit {
+            // only the emitter of the surface that was hit could have sampled it
+            let Some(index) = hit.emitter else {
+                return 0.0;
+            };
+            let emitter_pdf = self.emitters[index].pdf(p, time, dir, Some(hit));
+            if emitter_pdf > 0.0 {
+                return emitter_pdf * self.light_sampler.pmf(p, index);
+            }
+            return 0.0;
+        }
+
+        let mut pdf: f32 = self
+            .distant_emitters
+            .iter()
+            .map(|&index| {
+                let emitter_pdf = self.emitters[index].pdf(p, time, dir, None);
+                if emitter_pdf > 0.0 {
+                    emitter_pdf * self.light_sampler.pmf(p, index)
+                } else {
+                    0.0
+                }
+            })
+            .sum();
+        if self.background.is_emissive() {
+            pdf += self.portals.pdf(&self.background, p, time, dir)
+                * self.light_sampler.pmf(p, self.emitters.len());
         }
+        pdf
     }
 
-    pub fn intersect(&self, ray: &Ray) -> Option<HitInfo> {
-        self.surfaces.intersect(ray)
+    /// Whether nothing blocks the light sample `sample` from `p`
+    pub fn light_visible(&self, p: &Vec3, time: f32, sample: &LightSample) -> bool {
+        let ray = Ray::new(*p, sample.wi).with_time(time);
+        match self.intersect(&ray) {
+            // the emissive surfaces are hit at the sampled distance, only lights can be reached without a hit
+            None => sample.is_delta || sample.distance.is_infinite(),
+            Some(hit) => hit.t > sample.distance - 1e-4,
+        }
     }
 
     /// Raytrace a single pixel given its position
-    fn raytrace_pixel(&self, x: usize, y: usize) -> Vec3 {
+    pub fn raytrace_pixel(&self, x: usize, y: usize) -> Vec3 {
         let mut sampler = self.sampler.clone();
         let sample_count = sampler.sample_count();
         let mut rng = ChaCha8Rng::seed_from_u64(sampler.seed());
-        rng.set_stream((y * (self.camera.resolution.x as usize) + x) as u64);
+        rng.set_stream((y * (self.camera.resolution().x as usize) + x) as u64);
+        sampler.start_pixel(x as i32, y as i32);
+        let differential_scale = f32::max(0.125, 1.0 / (sample_count as f32).sqrt());
         // Generate multiple rays for each pixel in the image
         (0..sample_count)
             .map(|_| {
                 let pixel = Vec2::new(x as f32, y as f32) + sampler.next2f(&mut rng);
-                let ray = self.camera.generate_ray(pixel, sampler.next2f(&mut rng));
-                self.integrator.li(self, &mut sampler, &mut rng, &ray)
+                // rays blocked by the lens vignetting bring no light
+                let rv_lens = sampler.next2f(&mut rng);
+                let rv_time = sampler.next1f(&mut rng);
+                let li = match self
+                    .camera
+                    .generate_ray_differential(pixel, rv_lens, rv_time)
+                {
+                    Some(mut ray) => {
+                        // the samples of a pixel share its footprint
+                        ray.scale_differentials(differential_scale);
+                        self.integrator.li(self, &mut sampler, &mut rng, &ray)
+                    }
+                    None => Vec3::zeros(),
+                };
+                sampler.advance();
+                li
             })
             .sum::<Vec3>()
             / (sample_count as f32)
     }
 
-    /// Raytrace a whole image
+    /// Raytrace the pixels of a tile, in the order given by `Tile::pixels`
+    pub fn raytrace_tile(&self, tile: &Tile) -> Vec<Vec3> {
+        tile.pixels()
+            .map(|(x, y)| self.raytrace_pixel(x, y))
+            .collect()
+    }
+
+    /// Resolution of the rendered image
+    pub fn resolution(&self) -> (usize, usize) {
+        (
+            self.camera.resolution().x as usize,
+            self.camera.resolution().y as usize,
+        )
+    }
+
+    /// Raytrace a whole image, reporting the progress on stdout
     pub fn raytrace(&self) -> Image2d {
-        let mut image = Image2d::new(
-            self.camera.resolution.x as usize,
-            self.camera.resolution.y as usize,
-        );
+        self.raytrace_tiles(
+            &TileScheduler::default(),
+            &ProgressBarProgress::new(),
+            &CancellationToken::new(),
+        )
+    }
 
-        println!("Rendering ...");
-        let progress_bar = get_progress_bar(image.size());
-
-        // Compute each pixel in parallel
-        let img: Vec<Vec<Vec3>> = (0..image.size_y)
-            .into_par_iter() // rows in parallel
-            .map(|y| {
-                (0..image.size_x)
-                    .into_par_iter() // columns in parallel
-                    .map(|x| {
-                        let pixel_value = self.raytrace_pixel(x, y);
-                        progress_bar.inc(1);
-                        pixel_value
-                    })
-                    .collect()
+    /// Raytrace a whole image tile by tile
+    ///
+    /// Tiles are started in the order given by the scheduler. If the render is cancelled, the tiles that were not
+    /// rendered yet are left black.
+    pub fn raytrace_tiles(
+        &self,
+        scheduler: &TileScheduler,
+        progress: &dyn RenderProgress,
+        cancel: &CancellationToken,
+    ) -> Image2d {
+        let (width, height) = self.resolution();
+        let mut image = Image2d::new(width, height);
+
+        let tiles = scheduler.tiles(image.size_x, image.size_y);
+        progress.on_start(tiles.len(), image.size());
+
+        // Compute each tile in parallel, `par_bridge` hands them out in the scheduler order
+        let rendered: Vec<(Tile, Vec<Vec3>)> = tiles
+            .into_iter()
+            .par_bridge()
+            .filter(|_| !cancel.is_cancelled())
+            .map(|tile| {
+                let pixels = self.raytrace_tile(&tile);
+                progress.on_tile_done(&tile);
+                (tile, pixels)
             })
             .collect();
 
-        for (y, row) in img.iter().enumerate() {
-            for (x, pixel) in row.iter().enumerate() {
+        for (tile, pixels) in &rendered {
+            for ((x, y), pixel) in tile.pixels().zip(pixels) {
                 image[(x, y)] = *pixel;
             }
         }
 
-        println!("Rendering time : {:?}", progress_bar.elapsed());
+        progress.on_finish(cancel.is_cancelled());
         image
     }
 }
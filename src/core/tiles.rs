@@ -0,0 +1,332 @@
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::core::utils::get_progress_bar;
+
+/// Order in which the tiles of an image are handed to the render threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileOrder {
+    /// Row by row, starting from the top-left corner
+    Scanline,
+    /// Outward from the center of the image
+    Spiral,
+    /// Along a Hilbert curve, which keeps consecutive tiles spatially coherent
+    Hilbert,
+}
+
+/// A rectangular block of pixels `[x0, x1) x [y0, y1)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tile {
+    /// Position of the tile in the render order
+    pub index: usize,
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn size(&self) -> usize {
+        self.width() * self.height()
+    }
+
+    /// Iterate over the pixels of the tile in scanline order
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (self.y0..self.y1).flat_map(move |y| (self.x0..self.x1).map(move |x| (x, y)))
+    }
+}
+
+/// Splits an image into tiles and decides in which order they are rendered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileScheduler {
+    pub tile_size: usize,
+    pub order: TileOrder,
+}
+
+impl Default for TileScheduler {
+    fn default() -> TileScheduler {
+        TileScheduler {
+            tile_size: 32,
+            order: TileOrder::Spiral,
+        }
+    }
+}
+
+impl TileScheduler {
+    pub fn new(tile_size: usize, order: TileOrder) -> TileScheduler {
+        assert!(tile_size > 0, "tile size should be positive");
+        TileScheduler { tile_size, order }
+    }
+
+    /// Return all the tiles covering a `width` x `height` image, in render order
+    pub fn tiles(&self, width: usize, height: usize) -> Vec<Tile> {
+        let nx = width.div_ceil(self.tile_size);
+        let ny = height.div_ceil(self.tile_size);
+
+        let coords = match self.order {
+            TileOrder::Scanline => (0..ny).flat_map(|j| (0..nx).map(move |i| (i, j))).collect(),
+            TileOrder::Spiral => spiral_order(nx, ny),
+            TileOrder::Hilbert => hilbert_order(nx, ny),
+        };
+
+        coords
+            .into_iter()
+            .enumerate()
+            .map(|(index, (i, j))| Tile {
+                index,
+                x0: i * self.tile_size,
+                y0: j * self.tile_size,
+                x1: usize::min((i + 1) * self.tile_size, width),
+                y1: usize::min((j + 1) * self.tile_size, height),
+            })
+            .collect()
+    }
+}
+
+/// Walk a square spiral around the center tile, keeping the tiles inside the grid
+fn spiral_order(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let n = nx * ny;
+    let mut coords = Vec::with_capacity(n);
+    if n == 0 {
+        return coords;
+    }
+
+    let (mut x, mut y) = (((nx - 1) / 2) as i64, ((ny - 1) / 2) as i64);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg = 0;
+    let push = |x: i64, y: i64, coords: &mut Vec<(usize, usize)>| {
+        if (0..nx as i64).contains(&x) && (0..ny as i64).contains(&y) {
+            coords.push((x as usize, y as usize));
+        }
+    };
+    push(x, y, &mut coords);
+    while coords.len() < n {
+        // the leg length grows by one every two turns : 1, 1, 2, 2, 3, 3, ...
+        let (dx, dy) = directions[leg % 4];
+        for _ in 0..(leg / 2 + 1) {
+            x += dx;
+            y += dy;
+            push(x, y, &mut coords);
+        }
+        leg += 1;
+    }
+    coords
+}
+
+/// Order the grid cells along a Hilbert curve covering the smallest enclosing power of two square
+fn hilbert_order(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let side = usize::max(nx, ny).next_power_of_two();
+    (0..side * side)
+        .map(|d| hilbert_d2xy(side, d))
+        .filter(|&(x, y)| x < nx && y < ny)
+        .collect()
+}
+
+/// Convert a distance `d` along a Hilbert curve into a position on a `side` x `side` grid
+fn hilbert_d2xy(side: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < side {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        // rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
+
+/// Receives the progress events of a render.
+///
+/// Implementations are called from the render threads, so they must be thread safe.
+pub trait RenderProgress: Sync {
+    /// Called once before any tile is rendered
+    fn on_start(&self, _n_tiles: usize, _n_pixels: usize) {}
+
+    /// Called every time a tile is completed
+    fn on_tile_done(&self, _tile: &Tile) {}
+
+    /// Called once after the last tile, or after the render has been cancelled
+    fn on_finish(&self, _cancelled: bool) {}
+}
+
+/// Ignores all the progress events
+pub struct SilentProgress;
+
+impl RenderProgress for SilentProgress {}
+
+/// Reports the progress on stdout with an `indicatif` progress bar
+pub struct ProgressBarProgress {
+    progress_bar: ProgressBar,
+}
+
+impl ProgressBarProgress {
+    pub fn new() -> ProgressBarProgress {
+        ProgressBarProgress {
+            progress_bar: get_progress_bar(0),
+        }
+    }
+}
+
+impl Default for ProgressBarProgress {
+    fn default() -> ProgressBarProgress {
+        ProgressBarProgress::new()
+    }
+}
+
+impl RenderProgress for ProgressBarProgress {
+    fn on_start(&self, _n_tiles: usize, n_pixels: usize) {
+        println!("Rendering ...");
+        self.progress_bar.set_length(n_pixels as u64);
+    }
+
+    fn on_tile_done(&self, tile: &Tile) {
+        self.progress_bar.inc(tile.size() as u64);
+    }
+
+    fn on_finish(&self, cancelled: bool) {
+        if cancelled {
+            self.progress_bar.abandon();
+            println!(
+                "Rendering cancelled after {:?}",
+                self.progress_bar.elapsed()
+            );
+        } else {
+            self.progress_bar.finish();
+            println!("Rendering time : {:?}", self.progress_bar.elapsed());
+        }
+    }
+}
+
+/// Shared flag used to stop a render from another thread.
+///
+/// Tiles that already started are finished, the remaining ones are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use nalgebra_glm::{Vec2, Vec3};
+
+    use crate::cameras::PinholeCameraBuilder;
+    use crate::core::scene::SceneBuilder;
+    use crate::core::tiles::{CancellationToken, RenderProgress, Tile, TileOrder, TileScheduler};
+    use crate::core::transform::Transform;
+    use crate::materials::LambertianBuilder;
+    use crate::surfaces::SphereBuilder;
+
+    fn check_coverage(order: TileOrder) {
+        let (width, height) = (100, 70);
+        let tiles = TileScheduler::new(16, order).tiles(width, height);
+        assert_eq!(tiles.len(), 7 * 5);
+
+        let mut covered = vec![0; width * height];
+        for (index, tile) in tiles.iter().enumerate() {
+            assert_eq!(tile.index, index);
+            for (x, y) in tile.pixels() {
+                covered[y * width + x] += 1;
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn tiles_cover_image() {
+        check_coverage(TileOrder::Scanline);
+        check_coverage(TileOrder::Spiral);
+        check_coverage(TileOrder::Hilbert);
+    }
+
+    #[test]
+    fn spiral_starts_at_center() {
+        let tiles = TileScheduler::new(10, TileOrder::Spiral).tiles(50, 50);
+        assert_eq!((tiles[0].x0, tiles[0].y0), (20, 20));
+    }
+
+    #[test]
+    fn hilbert_tiles_are_adjacent() {
+        let tiles = TileScheduler::new(8, TileOrder::Hilbert).tiles(64, 64);
+        for (a, b) in tiles.iter().tuple_windows() {
+            let dx = a.x0.abs_diff(b.x0);
+            let dy = a.y0.abs_diff(b.y0);
+            assert_eq!(dx + dy, 8);
+        }
+    }
+
+    /// Cancels the render after a given number of tiles
+    struct CancelAfter {
+        tiles_done: AtomicUsize,
+        max_tiles: usize,
+        cancel: CancellationToken,
+    }
+
+    impl RenderProgress for CancelAfter {
+        fn on_tile_done(&self, _tile: &Tile) {
+            if self.tiles_done.fetch_add(1, Ordering::SeqCst) + 1 >= self.max_tiles {
+                self.cancel.cancel();
+            }
+        }
+    }
+
+    #[test]
+    fn cancelled_render_stops_early() {
+        let camera = PinholeCameraBuilder::new()
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, 4.0)))
+            .resolution(Vec2::new(64.0, 64.0))
+            .build();
+        let sphere = SphereBuilder::new(LambertianBuilder::new().build()).build();
+        let scene = SceneBuilder::new(camera).surface(sphere).build();
+
+        let cancel = CancellationToken::new();
+        let progress = CancelAfter {
+            tiles_done: AtomicUsize::new(0),
+            max_tiles: 1,
+            cancel: cancel.clone(),
+        };
+        let scheduler = TileScheduler::new(8, TileOrder::Scanline);
+        scene.raytrace_tiles(&scheduler, &progress, &cancel);
+
+        assert!(cancel.is_cancelled());
+        assert!(progress.tiles_done.load(Ordering::SeqCst) < 64);
+    }
+}
@@ -1,9 +1,10 @@
-use nalgebra_glm::{cross, normalize, Mat3x4, Mat4, Vec3};
+use nalgebra::{Rotation3, UnitQuaternion};
+use nalgebra_glm::{cross, lerp, normalize, Mat3, Mat3x4, Mat4, Vec3};
 use serde_json::{from_value, Value};
 use std::ops::Mul;
 
 use crate::core::aabb::Aabb;
-use crate::core::ray::Ray;
+use crate::core::ray::{Ray, RayDifferentials};
 use crate::core::utils::{deg2rad, read_or};
 
 /// Homogeneous coordinate transformation
@@ -66,6 +67,13 @@ impl Transform {
             direction: self.vector(&r.direction),
             min_t: r.min_t,
             max_t: r.max_t,
+            time: r.time,
+            differentials: r.differentials.as_ref().map(|d| RayDifferentials {
+                rx_origin: self.point(&d.rx_origin),
+                rx_direction: self.vector(&d.rx_direction),
+                ry_origin: self.point(&d.ry_origin),
+                ry_direction: self.vector(&d.ry_direction),
+            }),
         }
     }
 
@@ -76,10 +84,16 @@ impl Transform {
             return box3.clone();
         }
 
-        // create the transformed bounding box
+        // create the transformed bounding box from the 8 corners
         let mut bb = Aabb::new();
-        bb.enclose_point(&self.point(&box3.min));
-        bb.enclose_point(&self.point(&box3.max));
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { box3.min.x } else { box3.max.x },
+                if i & 2 == 0 { box3.min.y } else { box3.max.y },
+                if i & 4 == 0 { box3.min.z } else { box3.max.z },
+            );
+            bb.enclose_point(&self.point(&corner));
+        }
 
         bb
     }
@@ -91,9 +105,231 @@ impl Transform {
         Transform::new(matrix)
     }
 
+    /// Frame positioned at `from` whose -z axis points towards `at`
+    pub fn look_at(from: &Vec3, at: &Vec3, up: &Vec3) -> Transform {
+        let dir = normalize(&(from - at));
+        let left = normalize(&cross(up, &dir));
+        let new_up = normalize(&cross(&dir, &left));
+
+        Transform::axis_offset(&left, &new_up, &dir, from)
+    }
+
     pub fn translate(t: &Vec3) -> Transform {
         Transform::new(Mat4::new_translation(t))
     }
+
+    pub fn scale(s: &Vec3) -> Transform {
+        Transform::new(Mat4::new_nonuniform_scaling(s))
+    }
+
+    /// Rotation of `angle` degrees around `axis`
+    pub fn rotate(axis: &Vec3, angle: f32) -> Transform {
+        Transform::new(Mat4::from_scaled_axis(normalize(axis) * deg2rad(angle)))
+    }
+}
+
+/// Transform interpolated between keyframes, used to move objects and cameras during the exposure.
+///
+/// Each keyframe is decomposed into a translation, a rotation and a scale (which also holds any shear). Translations
+/// and scales are interpolated linearly and rotations are slerped. Before the first keyframe and after the last one
+/// the transform does not move.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AnimatedTransform {
+    /// Sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Keyframe {
+    time: f32,
+    transform: Transform,
+    translation: Vec3,
+    rotation: UnitQuaternion<f32>,
+    scale: Mat3,
+}
+
+impl Keyframe {
+    fn new(time: f32, transform: Transform) -> Keyframe {
+        let (translation, rotation, scale) = decompose(&transform.m);
+        Keyframe {
+            time,
+            transform,
+            translation,
+            rotation,
+            scale,
+        }
+    }
+}
+
+/// Decompose an affine matrix into a translation, a rotation and a scale using a polar decomposition
+fn decompose(m: &Mat4) -> (Vec3, UnitQuaternion<f32>, Mat3) {
+    let translation = m.column(3).xyz();
+    let linear: Mat3 = m.fixed_view::<3, 3>(0, 0).into();
+
+    // iterate R = (R + R^-T) / 2 until it converges to the rotation
+    let mut rotation = linear;
+    for _ in 0..100 {
+        let Some(inverse) = rotation.try_inverse() else {
+            break;
+        };
+        let next = 0.5 * (rotation + inverse.transpose());
+        let delta = (next - rotation).abs().max();
+        rotation = next;
+        if delta < 1e-6 {
+            break;
+        }
+    }
+    // keep a proper rotation, mirroring goes into the scale
+    if rotation.determinant() < 0.0 {
+        rotation = -rotation;
+    }
+    let scale = rotation.transpose() * linear;
+    let rotation = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix(&rotation));
+    (translation, rotation, scale)
+}
+
+impl AnimatedTransform {
+    /// Build from `(time, transform)` keyframes, in any order
+    pub fn new(keyframes: Vec<(f32, Transform)>) -> AnimatedTransform {
+        assert!(
+            !keyframes.is_empty(),
+            "an animated transform needs keyframes"
+        );
+        let mut keyframes: Vec<Keyframe> = keyframes
+            .into_iter()
+            .map(|(time, transform)| Keyframe::new(time, transform))
+            .collect();
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        // take the shortest path between consecutive rotations
+        for i in 1..keyframes.len() {
+            if keyframes[i - 1]
+                .rotation
+                .coords
+                .dot(&keyframes[i].rotation.coords)
+                < 0.0
+            {
+                let flipped = -keyframes[i].rotation.into_inner();
+                keyframes[i].rotation = UnitQuaternion::new_unchecked(flipped);
+            }
+        }
+        AnimatedTransform { keyframes }
+    }
+
+    /// Read the "motion" keyframes of an object, or fall back to its static "transform"
+    pub fn read(v: &Value) -> AnimatedTransform {
+        let Some(motion) = v.get("motion") else {
+            return AnimatedTransform::from(Transform::read(v));
+        };
+        let keyframes = motion
+            .as_array()
+            .expect("'motion' should be an array of keyframes")
+            .iter()
+            .map(|keyframe| (read_or(keyframe, "time", 0.0), Transform::read(keyframe)))
+            .collect();
+        AnimatedTransform::new(keyframes)
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.keyframes.len() > 1
+    }
+
+    /// Return the transform at a given time
+    pub fn at(&self, time: f32) -> Transform {
+        let first = &self.keyframes[0];
+        let last = &self.keyframes[self.keyframes.len() - 1];
+        if time <= first.time {
+            return first.transform.clone();
+        }
+        if time >= last.time {
+            return last.transform.clone();
+        }
+
+        let i = self.keyframes.partition_point(|k| k.time <= time);
+        let (k0, k1) = (&self.keyframes[i - 1], &self.keyframes[i]);
+        let t = (time - k0.time) / (k1.time - k0.time);
+
+        let translation = lerp(&k0.translation, &k1.translation, t);
+        let rotation = k0
+            .rotation
+            .try_slerp(&k1.rotation, t, 1e-6)
+            .unwrap_or(k0.rotation);
+        let scale = k0.scale * (1.0 - t) + k1.scale * t;
+
+        Transform::new(
+            Mat4::new_translation(&translation)
+                * rotation.to_homogeneous()
+                * scale.to_homogeneous(),
+        )
+    }
+
+    /// Apply the transform at the time of the ray
+    pub fn ray(&self, r: &Ray) -> Ray {
+        self.at(r.time).ray(r)
+    }
+
+    /// Bounding box of the transformed box over the whole motion
+    pub fn motion_bounds(&self, box3: &Aabb) -> Aabb {
+        // rotations sweep arcs, so bound many intermediate positions between each pair of keyframes
+        const STEPS: usize = 64;
+        if box3.is_empty() {
+            return box3.clone();
+        }
+        let mut bb = self.keyframes[0].transform.aabb(box3);
+        for pair in self.keyframes.windows(2) {
+            let mut segment = pair[0].transform.aabb(box3);
+            for step in 1..=STEPS {
+                let time =
+                    pair[0].time + (pair[1].time - pair[0].time) * step as f32 / STEPS as f32;
+                segment.enclose(&self.at(time).aabb(box3));
+            }
+            // the corners move along arcs that bulge out of the chords between the steps
+            let pad = chord_deviation(&pair[0], &pair[1], box3, STEPS);
+            segment.min.add_scalar_mut(-pad);
+            segment.max.add_scalar_mut(pad);
+            bb.enclose(&segment);
+        }
+        bb
+    }
+}
+
+/// Bound on the distance between a corner of `box3` moving from `k0` to `k1` and the chords joining `steps` evenly
+/// spaced positions along the way.
+///
+/// A corner `p` moves along `R(t) S(t) p` plus a linear translation, where the rotation `R` has a constant angular
+/// speed `w` around a fixed axis and the scaled corner `q = S p` moves linearly. Its acceleration is bounded by
+/// `w^2 |q| + 2 w |q'|`, using the components of `q` perpendicular to the axis, and a curve deviates from its chord by at
+/// most an eighth of its largest acceleration times the squared duration of the chord.
+fn chord_deviation(k0: &Keyframe, k1: &Keyframe, box3: &Aabb, steps: usize) -> f32 {
+    let Some((axis, angle)) = (k0.rotation.inverse() * k1.rotation).axis_angle() else {
+        // without rotation the corners move on straight lines
+        return 0.0;
+    };
+    let perpendicular = |v: Vec3| (v - axis.dot(&v) * axis.into_inner()).norm();
+    let mut deviation: f32 = 0.0;
+    for i in 0..8 {
+        let corner = Vec3::new(
+            if i & 1 == 0 { box3.min.x } else { box3.max.x },
+            if i & 2 == 0 { box3.min.y } else { box3.max.y },
+            if i & 4 == 0 { box3.min.z } else { box3.max.z },
+        );
+        let (q0, q1) = (k0.scale * corner, k1.scale * corner);
+        let radius = perpendicular(q0).max(perpendicular(q1));
+        deviation = deviation.max(angle * angle * radius + 2.0 * angle * perpendicular(q1 - q0));
+    }
+    deviation / (8 * steps * steps) as f32
+}
+
+impl From<Transform> for AnimatedTransform {
+    fn from(transform: Transform) -> AnimatedTransform {
+        AnimatedTransform::new(vec![(0.0, transform)])
+    }
+}
+
+impl Default for AnimatedTransform {
+    fn default() -> AnimatedTransform {
+        AnimatedTransform::from(Transform::default())
+    }
 }
 
 impl Mul<Transform> for Transform {
@@ -138,11 +374,7 @@ fn parse(json: &Value) -> Transform {
         let at = read_or(json, "at", Vec3::zeros()) + to;
         let up = read_or(json, "up", Vec3::y());
 
-        let dir = normalize(&(from - at));
-        let left = normalize(&cross(&up, &dir));
-        let new_up = normalize(&cross(&dir, &left));
-
-        Transform::axis_offset(&left, &new_up, &dir, &from)
+        Transform::look_at(&from, &at, &up)
     } else if json_map.contains_key("o")
         || json_map.contains_key("x")
         || json_map.contains_key("y")
@@ -164,7 +396,7 @@ fn parse(json: &Value) -> Transform {
             return Transform::new(Mat4::new_scaling(sn));
         }
         let sv: Vec3 = from_value(scale).expect("could not load 'scale' vector Transform");
-        Transform::new(Mat4::new_nonuniform_scaling(&sv))
+        Transform::scale(&sv)
     } else if json_map.contains_key("axis") || json_map.contains_key("angle") {
         let axis = read_or(json, "axis", Vec3::x());
         let angle = deg2rad(read_or(json, "angle", 0.0));
@@ -178,8 +410,9 @@ fn parse(json: &Value) -> Transform {
 
 #[cfg(test)]
 mod tests {
+    use crate::core::aabb::Aabb;
     use crate::core::ray::Ray;
-    use crate::core::transform::Transform;
+    use crate::core::transform::{AnimatedTransform, Transform};
     use approx::assert_abs_diff_eq;
     use nalgebra::{Matrix4, Vector3};
     use nalgebra_glm::Mat4;
@@ -275,4 +508,62 @@ mod tests {
             epsilon = 1e-5
         );
     }
+
+    #[test]
+    fn animated_transform_interpolates() {
+        let start = Transform::translate(&Vector3::new(0.0, 0.0, 0.0));
+        let end = Transform::translate(&Vector3::new(2.0, 0.0, 0.0))
+            * Transform::rotate(&Vector3::z(), 90.0)
+            * Transform::scale(&Vector3::new(3.0, 3.0, 3.0));
+        let animated = AnimatedTransform::new(vec![(1.0, end.clone()), (0.0, start.clone())]);
+        assert!(animated.is_animated());
+
+        assert_abs_diff_eq!(animated.at(-1.0).m, start.m, epsilon = 1e-5);
+        assert_abs_diff_eq!(animated.at(1.0).m, end.m, epsilon = 1e-5);
+
+        // halfway : translated by 1, rotated by 45 degrees and scaled by 2
+        let p = animated.at(0.5).point(&Vector3::x());
+        let expected = Vector3::new(1.0 + 2.0_f32.sqrt(), 2.0_f32.sqrt(), 0.0);
+        assert_abs_diff_eq!(p, expected, epsilon = 1e-4);
+
+        // the bounds enclose the rotation arc, not only the keyframes
+        let spin = AnimatedTransform::new(vec![
+            (0.0, Transform::default()),
+            (1.0, Transform::rotate(&Vector3::z(), 90.0)),
+        ]);
+        let unit = Aabb {
+            min: Vector3::new(-1.0, -1.0, -1.0),
+            max: Vector3::new(1.0, 1.0, 1.0),
+        };
+        let bounds = spin.motion_bounds(&unit);
+        assert_abs_diff_eq!(bounds.max.y, 2.0_f32.sqrt(), epsilon = 1e-3);
+    }
+
+    #[test]
+    fn motion_bounds_enclose_times_between_steps() {
+        // a box far from the axis of a quick rotation, where the chords cut deep inside the arcs
+        let spin = AnimatedTransform::new(vec![
+            (0.0, Transform::default()),
+            (
+                1.0,
+                Transform::rotate(&Vector3::z(), 170.0)
+                    * Transform::scale(&Vector3::new(2.0, 1.0, 1.0)),
+            ),
+            (2.0, Transform::translate(&Vector3::new(0.0, 5.0, 0.0))),
+        ]);
+        let far = Aabb {
+            min: Vector3::new(99.0, -1.0, -1.0),
+            max: Vector3::new(101.0, 1.0, 1.0),
+        };
+        let bounds = spin.motion_bounds(&far);
+
+        let n = 10_000;
+        for i in 0..=n {
+            let b = spin.at(2.0 * i as f32 / n as f32).aabb(&far);
+            assert!(
+                b.min >= bounds.min && b.max <= bounds.max,
+                "{b:?} not in {bounds:?}"
+            );
+        }
+    }
 }
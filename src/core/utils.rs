@@ -29,7 +29,7 @@ pub fn sincos(x: f32) -> (f32, f32) {
 }
 
 /// Always-positive modulo operation
-fn modulo(a_: f32, b: f32) -> f32 {
+pub fn modulo(a_: f32, b: f32) -> f32 {
     let mut a = a_;
     let n = (a / b).floor();
     a -= n * b;
@@ -70,7 +70,6 @@ pub fn spherical_coordinates_to_direction(phi_theta: Vec2) -> Vec3 {
     Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
 }
 
-#[allow(unused)]
 pub fn spherical_uv_to_direction(uv: Vec2) -> Vec3 {
     spherical_coordinates_to_direction(Vec2::new(
         (uv.x - 0.5) * 2.0 * std::f32::consts::PI,
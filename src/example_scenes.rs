@@ -1,10 +1,15 @@
-use nalgebra_glm::{length, lerp, Vec3};
+use nalgebra_glm::{length, lerp, Vec2, Vec3};
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
-use serde_json::Value;
-use serde_json::{self, json};
+use std::sync::Arc;
 
-pub fn create_example_scene(scene_number: i32) -> Value {
+use rustrt::cameras::PinholeCameraBuilder;
+use rustrt::materials::{DielectricBuilder, LambertianBuilder, MaterialType, MetalBuilder};
+use rustrt::samplers::IndependentSampler;
+use rustrt::surfaces::{QuadBuilder, SphereBuilder, SplitMethod};
+use rustrt::{Scene, SceneBuilder, Transform};
+
+pub fn create_example_scene(scene_number: i32) -> Scene {
     match scene_number {
         0 => create_sphere_scene(),
         1 => create_sphere_plane_scene(),
@@ -16,76 +21,78 @@ pub fn create_example_scene(scene_number: i32) -> Value {
     }
 }
 
-fn create_sphere_scene() -> Value {
-    json!(
-    {
-        "camera":
-        {
-            "transform": { "o": [0,0,4] },
-            "resolution": [ 512, 512 ],
-            "vfov": 45
-        },
-        "surfaces": [
-            {
-                "type": "sphere",
-                "material": { "type": "lambertian", "albedo": [0.6,0.4,0.4] }
-            }
-        ],
-        "sampler": {"samples": 1},
-        "background": [1, 1, 1]
-    })
+/// Horizontal ground plane facing up
+fn ground_plane(height: f32, material: impl Into<Arc<MaterialType>>) -> QuadBuilder {
+    QuadBuilder::new(material)
+        .size(Vec2::new(100.0, 100.0))
+        .transform(Transform::axis_offset(
+            &Vec3::x(),
+            &-Vec3::z(),
+            &Vec3::y(),
+            &Vec3::new(0.0, height, 0.0),
+        ))
 }
 
-fn create_sphere_plane_scene() -> Value {
-    json!(
-    {
-        "camera":
-        {
-            "transform": { "o": [0,0,4] },
-            "resolution": [ 512, 512 ],
-            "vfov": 45
-        },
-        "surfaces": [
-            {
-                "type": "sphere",
-                "radius": 1,
-                "material": { "type": "lambertian", "albedo": [0.6,0.4,0.4] }
-            },
-            {
-                "type": "quad",
-                "transform": { "o": [0,-1,0], "x": [1,0,0], "y": [0,0,-1], "z": [0,1,0] },
-                "size": [ 100, 100 ],
-                "material": { "type": "lambertian", "albedo": [1,1,1] }
-            }
-        ],
-        "sampler": {"samples": 1},
-        "background": [1, 1, 1],
-        "accelerator" : { "type": "bbh"}
-    })
+fn create_sphere_scene() -> Scene {
+    let camera = PinholeCameraBuilder::new()
+        .transform(Transform::translate(&Vec3::new(0.0, 0.0, 4.0)))
+        .resolution(Vec2::new(512.0, 512.0))
+        .vfov(45.0)
+        .build();
+
+    let material = LambertianBuilder::new()
+        .albedo(Vec3::new(0.6, 0.4, 0.4))
+        .build();
+
+    SceneBuilder::new(camera)
+        .sampler(IndependentSampler::new(1))
+        .background(Vec3::new(1.0, 1.0, 1.0))
+        .surface(SphereBuilder::new(material).build())
+        .build()
+}
+
+fn create_sphere_plane_scene() -> Scene {
+    let camera = PinholeCameraBuilder::new()
+        .transform(Transform::translate(&Vec3::new(0.0, 0.0, 4.0)))
+        .resolution(Vec2::new(512.0, 512.0))
+        .vfov(45.0)
+        .build();
+
+    let sphere_material = LambertianBuilder::new()
+        .albedo(Vec3::new(0.6, 0.4, 0.4))
+        .build();
+    let plane_material = LambertianBuilder::new().albedo(1.0).build();
+
+    SceneBuilder::new(camera)
+        .sampler(IndependentSampler::new(1))
+        .background(Vec3::new(1.0, 1.0, 1.0))
+        .accelerator(Some(SplitMethod::Middle))
+        .surface(SphereBuilder::new(sphere_material).radius(1.0).build())
+        .surface(ground_plane(-1.0, plane_material).build())
+        .build()
 }
 
-fn create_steinbach_scene() -> Value {
+fn create_steinbach_scene() -> Scene {
     // Compose the camera
-    let mut scene = json!({
-        "camera":{
-            "transform":{
-                "from": [-10.0, 10.0, 40.0],
-                "to": [0.0, -1.0, 0.0],
-                "up": [0.0, 1.0, 0.0]
-            },
-            "vfov" : 18.,
-            "resolution": [512, 512]
-        },
-        "sampler": {"samples": 10},
-        "background": [1, 1, 1],
-        "accelerator" : { "type": "bbh"}
-    });
+    let camera = PinholeCameraBuilder::new()
+        .transform(Transform::look_at(
+            &Vec3::new(-10.0, 10.0, 40.0),
+            &Vec3::new(0.0, -1.0, 0.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+        ))
+        .vfov(18.0)
+        .resolution(Vec2::new(512.0, 512.0))
+        .build();
+
+    let mut scene = SceneBuilder::new(camera)
+        .sampler(IndependentSampler::new(10))
+        .background(Vec3::new(1.0, 1.0, 1.0))
+        .accelerator(Some(SplitMethod::Middle));
 
     let object_center = Vec3::new(0.0, 0.0, 0.0);
     let radius = 0.5;
     let num_s = 40;
     let num_t = 40;
-    let mut surfaces: Vec<Value> = Vec::new();
 
     for is in 0..num_s {
         for it in 0..num_t {
@@ -102,79 +109,48 @@ fn create_steinbach_scene() -> Value {
                     s,
                 );
 
-            surfaces.push(json!({
-                "type": "sphere",
-                "radius": radius,
-                "transform":{
-                    "o": object_center + center,
-                    "x": [1.0, 0.0, 0.0],
-                    "y": [0.0, 1.0, 0.0],
-                    "z": [0.0, 0.0, 1.0]
-                },
-                "material": {
-                    "type": "lambertian",
-                    "albedo": kd
-                }
-            }));
+            let material = LambertianBuilder::new().albedo(kd).build();
+            scene = scene.surface(
+                SphereBuilder::new(material)
+                    .radius(radius)
+                    .transform(Transform::translate(&(object_center + center)))
+                    .build(),
+            );
         }
     }
 
-    surfaces.push(json!({
-        "type": "quad",
-        "size": [100, 100],
-        "transform":{
-            "o": [0.0, -5.0, 0.0],
-            "x": [1.0, 0.0, 0.0],
-            "y": [0.0, 0.0, -1.0],
-            "z": [0.0, 1.0, 0.0]
-        },
-        "material": {
-            "type": "lambertian",
-            "albedo": 1.0
-        }
-    }));
-    scene["surfaces"] = Value::Array(surfaces);
-
+    let plane_material = LambertianBuilder::new().albedo(1.0).build();
     scene
+        .surface(ground_plane(-5.0, plane_material).build())
+        .build()
 }
 
-fn create_shirley_scene() -> Value {
+fn create_shirley_scene() -> Scene {
     let mut rng = ChaCha8Rng::seed_from_u64(420);
 
     // Compose the camera
-    let mut scene = json!({
-        "camera": {
-            "transform":{
-                "from":[13, 2, 3],
-                "to": [0, 0, 0],
-                "up": [0, 1, 0]
-            },
-            "vfov": 20,
-            "fdist": 10,
-            "aperture": 0.1,
-            "resolution": [600, 400]
-        },
-        "sampler": {"samples": 10},
-        "background": [1, 1, 1],
-        "accelerator" : { "type": "bbh"}
-    });
-
-    let mut surfaces: Vec<Value> = Vec::new();
+    let camera = PinholeCameraBuilder::new()
+        .transform(Transform::look_at(
+            &Vec3::new(13.0, 2.0, 3.0),
+            &Vec3::new(0.0, 0.0, 0.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+        ))
+        .vfov(20.0)
+        .focus_distance(10.0)
+        .aperture(0.1)
+        .resolution(Vec2::new(600.0, 400.0))
+        .build();
+
+    let mut scene = SceneBuilder::new(camera)
+        .sampler(IndependentSampler::new(10))
+        .background(Vec3::new(1.0, 1.0, 1.0))
+        .accelerator(Some(SplitMethod::Middle));
 
     // ground plane
-    surfaces.push(json!({
-        "type": "quad",
-        "size": [100, 100],
-        "transform":{
-            "o": [0.0, 0.0, 0.0],
-            "x": [1.0, 0.0, 0.0],
-            "y": [0.0, 0.0, -1.0],
-            "z": [0.0, 1.0, 0.0]},
-         "material": {
-            "type": "lambertian",
-            "albedo": [0.5, 0.5, 0.5]
-        }
-    }));
+    let ground_material = LambertianBuilder::new()
+        .albedo(Vec3::new(0.5, 0.5, 0.5))
+        .build();
+    scene = scene.surface(ground_plane(0.0, ground_material).build());
 
     for a in -11..11 {
         for b in -11..11 {
@@ -185,17 +161,14 @@ fn create_shirley_scene() -> Value {
                 b as f32 + 0.9 * rng.gen::<f32>(),
             );
             if length(&(center - Vec3::new(4.0, 0.2, 0.0))) > 0.9 {
-                let mut sphere =
-                    json!({"type": "sphere", "radius": 0.2, "transform": {"translate": center}});
-
-                if choose_mat < 0.8 {
+                let material: Arc<MaterialType> = if choose_mat < 0.8 {
                     // diffuse
                     let albedo = Vec3::new(
                         rng.gen::<f32>() * rng.gen::<f32>(),
                         rng.gen::<f32>() * rng.gen::<f32>(),
                         rng.gen::<f32>() * rng.gen::<f32>(),
                     );
-                    sphere["material"] = json!({"type": "lambertian", "albedo": albedo});
+                    LambertianBuilder::new().albedo(albedo).build().into()
                 } else if choose_mat < 0.95 {
                     // metal
                     let albedo = Vec3::new(
@@ -204,53 +177,50 @@ fn create_shirley_scene() -> Value {
                         0.5 * (1.0 + rng.gen::<f32>()),
                     );
                     let rough = 0.5 * rng.gen::<f32>();
-                    sphere["material"] =
-                        json!({"type": "metal", "albedo": albedo, "roughness": rough});
+                    MetalBuilder::new()
+                        .albedo(albedo)
+                        .roughness(rough)
+                        .build()
+                        .into()
                 } else {
                     // glass
-                    sphere["material"] = json!({"type": "dielectric", "ior": 1.5});
-                }
-                surfaces.push(sphere);
+                    DielectricBuilder::new().ior(1.5).build().into()
+                };
+
+                scene = scene.surface(
+                    SphereBuilder::new(material)
+                        .radius(0.2)
+                        .transform(Transform::translate(&center))
+                        .build(),
+                );
             }
         }
     }
 
-    surfaces.push(json!({
-        "type": "sphere",
-        "radius": 1.0,
-        "transform": {
-            "translate": [0, 1, 0]
-        },
-        "material": {
-            "type": "dielectric",
-            "ior": 1.5
-        }
-    }));
-    surfaces.push(json!({
-        "type": "sphere",
-        "radius": 1.0,
-        "transform": {
-            "translate": [-4, 1, 0]
-        },
-        "material": {
-            "type": "lambertian",
-            "albedo": [0.4, 0.2, 0.1]
-        }
-    }));
-    surfaces.push(json!({
-        "type": "sphere",
-        "radius": 1.0,
-        "transform": {
-            "translate": [4, 1, 0]
-        },
-        "material": {
-            "type": "metal",
-            "albedo": [0.7, 0.6, 0.5],
-            "roughness": 0.0
-        }
-    }));
-
-    scene["surfaces"] = Value::Array(surfaces);
+    let glass = DielectricBuilder::new().ior(1.5).build();
+    let diffuse = LambertianBuilder::new()
+        .albedo(Vec3::new(0.4, 0.2, 0.1))
+        .build();
+    let metal = MetalBuilder::new()
+        .albedo(Vec3::new(0.7, 0.6, 0.5))
+        .roughness(0.0)
+        .build();
 
     scene
+        .surface(
+            SphereBuilder::new(glass)
+                .transform(Transform::translate(&Vec3::new(0.0, 1.0, 0.0)))
+                .build(),
+        )
+        .surface(
+            SphereBuilder::new(diffuse)
+                .transform(Transform::translate(&Vec3::new(-4.0, 1.0, 0.0)))
+                .build(),
+        )
+        .surface(
+            SphereBuilder::new(metal)
+                .transform(Transform::translate(&Vec3::new(4.0, 1.0, 0.0)))
+                .build(),
+        )
+        .build()
 }
@@ -15,7 +15,7 @@ impl Integrator for AmbientOcclusionIntegrator {
         if let Some(hit) = scene.intersect(ray) {
             let rv = sampler.next2f(rng);
             if let Some(srec) = hit.mat.sample(&ray.direction, &hit, rv) {
-                let shadow_ray = Ray::new(hit.p, srec.wo);
+                let shadow_ray = Ray::new(hit.p, srec.wo).with_time(ray.time);
                 // if shadow ray doesnt hit anything return white
                 if scene.intersect(&shadow_ray).is_none() {
                     return Vec3::new(1.0, 1.0, 1.0);
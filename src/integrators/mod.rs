@@ -20,11 +20,11 @@ pub trait Integrator {
     fn li(&self, scene: &Scene, sampler: &mut SamplerType, rng: &mut impl Rng, ray: &Ray) -> Vec3;
 }
 
-use crate::integrators::ambiant_occlusion::AmbientOcclusionIntegrator;
-use crate::integrators::normals::NormalsIntegrator;
-use crate::integrators::path_tracer_mats::PathTracerMatsIntegrator;
-use crate::integrators::path_tracer_mis::PathTracerMISIntegrator;
-use crate::integrators::path_tracer_nee::PathTracerNEEIntegrator;
+pub use crate::integrators::ambiant_occlusion::AmbientOcclusionIntegrator;
+pub use crate::integrators::normals::NormalsIntegrator;
+pub use crate::integrators::path_tracer_mats::PathTracerMatsIntegrator;
+pub use crate::integrators::path_tracer_mis::PathTracerMISIntegrator;
+pub use crate::integrators::path_tracer_nee::PathTracerNEEIntegrator;
 
 #[enum_dispatch(Integrator)]
 #[derive(Debug, Clone)]
@@ -68,3 +68,139 @@ pub fn create_integrator(m: &Map<String, Value>) -> IntegratorType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma};
+    use nalgebra_glm::{Vec2, Vec3};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use serde_json::json;
+
+    use crate::cameras::PinholeCameraBuilder;
+    use crate::core::ray::{Ray, RayDifferentials};
+    use crate::core::scene::{Scene, SceneBuilder};
+    use crate::core::transform::Transform;
+    use crate::integrators::{
+        Integrator, IntegratorType, PathTracerMISIntegrator, PathTracerMatsIntegrator,
+        PathTracerNEEIntegrator,
+    };
+    use crate::materials::{DiffuseLightBuilder, LambertianBuilder, MaterialFactory};
+    use crate::samplers::{IndependentSampler, SamplerType};
+    use crate::surfaces::{QuadBuilder, SphereBuilder};
+
+    /// Average radiance along `ray` over `n` paths
+    fn estimate(integrator: &IntegratorType, scene: &Scene, ray: &Ray, n: usize) -> Vec3 {
+        let mut sampler = SamplerType::from(IndependentSampler::new(1));
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let total: Vec3 = (0..n)
+            .map(|_| integrator.li(scene, &mut sampler, &mut rng, ray))
+            .sum();
+        total / n as f32
+    }
+
+    fn path_tracers() -> [IntegratorType; 3] {
+        [
+            PathTracerMatsIntegrator::new(64).into(),
+            PathTracerNEEIntegrator::new(64).into(),
+            PathTracerMISIntegrator::new(64).into(),
+        ]
+    }
+
+    #[test]
+    fn furnace() {
+        // a convex diffuse object under a white sky reflects its albedo, each escaping path is counted once
+        let scene = SceneBuilder::new(PinholeCameraBuilder::new().build())
+            .surface(
+                SphereBuilder::new(
+                    LambertianBuilder::new()
+                        .albedo(Vec3::new(0.5, 0.5, 0.5))
+                        .build(),
+                )
+                .build(),
+            )
+            .background(Vec3::new(1.0, 1.0, 1.0))
+            .build();
+        let ray = Ray::new(Vec3::new(0.2, 0.1, 5.0), -Vec3::z());
+        for integrator in path_tracers() {
+            let radiance = estimate(&integrator, &scene, &ray, 20000);
+            approx::assert_abs_diff_eq!(radiance, Vec3::new(0.5, 0.5, 0.5), epsilon = 1e-2);
+        }
+    }
+
+    #[test]
+    fn quad_light_is_counted_once() {
+        // a floor lit by a square light facing it, the light samples replace the emission found by the material samples
+        let floor = QuadBuilder::new(
+            LambertianBuilder::new()
+                .albedo(Vec3::new(0.5, 0.5, 0.5))
+                .build(),
+        )
+        .size(Vec2::new(20.0, 20.0))
+        .build();
+        let light = QuadBuilder::new(DiffuseLightBuilder::new().two_sided(true).build())
+            .size(Vec2::new(2.0, 2.0))
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, 1.0)))
+            .build();
+        let scene = SceneBuilder::new(PinholeCameraBuilder::new().build())
+            .surface(floor)
+            .surface(light)
+            .background(Vec3::zeros())
+            .build();
+        let ray = Ray::new(
+            Vec3::new(0.0, 0.0, 0.5),
+            Vec3::new(0.3, 0.1, -1.0).normalize(),
+        );
+
+        let [mats, nee, mis] = path_tracers();
+        let reference = estimate(&mats, &scene, &ray, 200000);
+        assert!(reference.x > 0.05, "{reference}");
+        for integrator in [nee, mis] {
+            let radiance = estimate(&integrator, &scene, &ray, 20000);
+            approx::assert_relative_eq!(radiance, reference, max_relative = 3e-2);
+        }
+    }
+
+    #[test]
+    fn bounces_drop_the_camera_footprint() {
+        // a light whose left half is white and right half is black, only seen in a mirror
+        let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        ImageBuffer::from_fn(64, 64, |x, _| Luma([if x < 32 { 255u8 } else { 0 }]))
+            .save(file.path())
+            .unwrap();
+        let mf = MaterialFactory::new();
+        let light = mf.create_material(&json!({
+            "type": "diffuse_light",
+            "emit": {"type": "image", "filename": file.path().to_str().unwrap()},
+            "two_sided": true
+        }));
+        let mirror = mf.create_material(&json!({"type": "metal", "albedo": 1.0, "roughness": 0.0}));
+        let scene = SceneBuilder::new(PinholeCameraBuilder::new().build())
+            .surface(
+                QuadBuilder::new(mirror)
+                    .size(Vec2::new(4.0, 4.0))
+                    .transform(Transform::translate(&Vec3::new(1.0, 0.0, -1.0)))
+                    .build(),
+            )
+            .surface(
+                QuadBuilder::new(light)
+                    .size(Vec2::new(2.0, 2.0))
+                    .transform(Transform::translate(&Vec3::new(4.5, 0.0, 2.0)))
+                    .build(),
+            )
+            .background(Vec3::zeros())
+            .build();
+
+        // a camera ray with a wide footprint, reflected towards the middle of the white half
+        let direction = Vec3::new(1.0, 0.0, -1.0).normalize();
+        let ray = Ray::new(Vec3::zeros(), direction).with_differentials(RayDifferentials {
+            rx_origin: Vec3::zeros(),
+            rx_direction: direction + Vec3::new(0.05, 0.0, 0.0),
+            ry_origin: Vec3::zeros(),
+            ry_direction: direction + Vec3::new(0.0, 0.05, 0.0),
+        });
+        // the texels under the reflected ray, not the gray a mip level of the camera footprint would give
+        let radiance = estimate(&PathTracerMatsIntegrator::new(4).into(), &scene, &ray, 1);
+        approx::assert_abs_diff_eq!(radiance, Vec3::new(1.0, 1.0, 1.0), epsilon = 1e-2);
+    }
+}
@@ -28,7 +28,8 @@ impl Integrator for PathTracerMatsIntegrator {
         for _ in 0..=self.max_bounces {
             // find next intersection
             let Some(hit) = scene.intersect(&ray) else {
-                return radiance + scene.background.component_mul(&attenuation);
+                let background = scene.escaped_radiance(&ray.direction);
+                return radiance + background.component_mul(&attenuation);
             };
 
             // sample next direction
@@ -51,9 +52,8 @@ impl Integrator for PathTracerMatsIntegrator {
             };
             attenuation = attenuation.component_mul(&a);
 
-            // update the ray for the next bounce
-            ray.origin = hit.p;
-            ray.direction = srec.wo;
+            // update the ray for the next bounce, the differentials only describe the footprint of the camera ray
+            ray = Ray::new(hit.p, srec.wo).with_time(ray.time);
         }
         radiance
     }
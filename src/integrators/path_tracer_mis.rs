@@ -6,7 +6,6 @@ use crate::core::scene::Scene;
 use crate::integrators::Integrator;
 use crate::materials::Material;
 use crate::samplers::{Sampler, SamplerType};
-use crate::surfaces::Surface;
 
 /// Multiple Importance Sampling Integrator
 #[derive(Debug, Clone)]
@@ -20,7 +19,6 @@ impl PathTracerMISIntegrator {
     }
 }
 
-#[allow(unused)]
 fn power_heuristic(pdf1: f32, pdf2: f32, power: f32) -> (f32, f32) {
     let pdf1_pow = pdf1.powf(power);
     let pdf2_pow = pdf2.powf(power);
@@ -39,12 +37,32 @@ impl Integrator for PathTracerMISIntegrator {
         let mut radiance = Vec3::zeros();
         let mut attenuation = Vec3::new(1.0, 1.0, 1.0);
         let mut ray = ray_.clone();
-        let mut previous_weight_mat = 1.0;
+        // density of the material sample which generated `ray`, `None` from the camera and after specular bounces
+        let mut previous_pdf_mat: Option<f32> = None;
 
-        for bounce in 0..=self.max_bounces {
+        for _ in 0..=self.max_bounces {
             // find next intersection hit point
-            let Some(hit) = scene.intersect(&ray) else {
-                return radiance + scene.background.component_mul(&attenuation);
+            let hit = scene.intersect(&ray);
+
+            // emitted contibution, weighted against the light sample which could have reached it
+            let emitted = match &hit {
+                Some(hit) => hit.mat.emmitted(&ray, hit),
+                None => Some(scene.escaped_radiance(&ray.direction)),
+            };
+            if let Some(emitted) = emitted {
+                let weight_mat = match previous_pdf_mat {
+                    Some(pdf_mat) => {
+                        let pdf_light =
+                            scene.light_pdf(&ray.origin, ray.time, &ray.direction, hit.as_ref());
+                        power_heuristic(pdf_mat, pdf_light, 2.0).0
+                    }
+                    None => 1.0,
+                };
+                radiance += emitted.component_mul(&attenuation) * weight_mat;
+            }
+
+            let Some(hit) = hit else {
+                break;
             };
 
             // sample material
@@ -53,69 +71,47 @@ impl Integrator for PathTracerMISIntegrator {
                 break;
             };
 
-            // sample light
+            // light contibution, no light samples from specular materials
             let rv_light = sampler.next2f(rng);
-            let Some(emit_rec) =
-                scene
-                    .emitters
-                    .sample_from_group(&hit.p, rv_light, sampler.next1f(rng))
-            else {
-                break;
-            };
-
-            // mixture weight
-            let pdf_mat = hit.mat.pdf(&ray.direction, &srec.wo, &hit);
-            let select_probability = scene.emitters.pdf(&hit.p, &emit_rec.wi);
-            let pdf_light = select_probability * emit_rec.pdf;
-
-            let (weight_mat, weight_light) = if srec.is_specular {
-                (1.0, 0.0)
-            } else {
-                power_heuristic(pdf_mat, pdf_light, 2.0)
-            };
-
-            // light contibution
+            let rv_select = sampler.next1f(rng);
             if !srec.is_specular {
-                // no light samples from specular materials
-                let visibility_ray = Ray::new(hit.p, emit_rec.wi);
-                if let Some(visibility_hit) = scene.intersect(&visibility_ray) {
-                    let light_visible = (visibility_hit.t - emit_rec.hit.t).abs() < 1e-5;
-                    if light_visible {
+                if let Some(light_sample) =
+                    scene.sample_light(&hit.p, ray.time, rv_light, rv_select)
+                {
+                    if scene.light_visible(&hit.p, ray.time, &light_sample) {
+                        let pdf_mat = hit.mat.pdf(&ray.direction, &light_sample.wi, &hit);
+                        // material samples never reach delta lights
+                        let weight_light = if light_sample.is_delta {
+                            1.0
+                        } else {
+                            power_heuristic(light_sample.pdf, pdf_mat, 2.0).0
+                        };
                         let light = hit
                             .mat
-                            .eval(&ray.direction, &emit_rec.wi, &hit)
-                            .component_mul(&emit_rec.emitted)
+                            .eval(&ray.direction, &light_sample.wi, &hit)
+                            .component_mul(&light_sample.radiance)
                             .component_mul(&attenuation)
-                            / pdf_light
+                            / light_sample.pdf
                             * weight_light;
                         radiance += light;
                     }
                 }
             }
 
-            // emitted contibution
-            if let Some(emitted) = hit.mat.emmitted(&ray, &hit) {
-                if bounce == 0 || srec.is_specular {
-                    radiance += emitted.component_mul(&attenuation);
-                } else {
-                    radiance += emitted.component_mul(&attenuation) * previous_weight_mat;
-                }
-            }
-
             // update for next bounce
             let mat_attenuation = if srec.is_specular {
+                previous_pdf_mat = None;
                 srec.attenuation
             } else {
-                let mat_eval = hit.mat.eval(&ray.direction, &srec.wo, &hit);
-                mat_eval / pdf_mat
+                let pdf_mat = hit.mat.pdf(&ray.direction, &srec.wo, &hit);
+                previous_pdf_mat = Some(pdf_mat);
+                hit.mat.eval(&ray.direction, &srec.wo, &hit) / pdf_mat
             };
 
             attenuation = attenuation.component_mul(&mat_attenuation);
 
-            // update the ray for the next bounce
-            ray.origin = hit.p;
-            ray.direction = srec.wo;
-            previous_weight_mat = weight_mat;
+            // update the ray for the next bounce, the differentials only describe the footprint of the camera ray
+            ray = Ray::new(hit.p, srec.wo).with_time(ray.time);
         }
         radiance
     }
@@ -6,7 +6,6 @@ use crate::core::scene::Scene;
 use crate::integrators::Integrator;
 use crate::materials::Material;
 use crate::samplers::{Sampler, SamplerType};
-use crate::surfaces::Surface;
 
 /// Next Event Estimation Integrator
 #[derive(Debug, Clone)]
@@ -25,16 +24,25 @@ impl Integrator for PathTracerNEEIntegrator {
         let mut radiance = Vec3::zeros();
         let mut attenuation = Vec3::new(1.0, 1.0, 1.0);
         let mut ray = ray.clone();
+        // emitters reached by the material samples were already accounted for by the light samples, except from the
+        // camera and after specular bounces
+        let mut count_emitted = true;
 
         for _ in 0..self.max_bounces {
             // find next intersection
             let Some(hit) = scene.intersect(&ray) else {
-                return radiance + scene.background.component_mul(&attenuation);
+                if count_emitted {
+                    let background = scene.escaped_radiance(&ray.direction);
+                    radiance += background.component_mul(&attenuation);
+                }
+                break;
             };
 
             // add emitted light contribution
-            if let Some(emitted) = hit.mat.emmitted(&ray, &hit) {
-                radiance += emitted.component_mul(&attenuation);
+            if count_emitted {
+                if let Some(emitted) = hit.mat.emmitted(&ray, &hit) {
+                    radiance += emitted.component_mul(&attenuation);
+                }
             }
 
             // sample material
@@ -46,24 +54,15 @@ impl Integrator for PathTracerNEEIntegrator {
             if !srec.is_specular {
                 // no need to sample light for specular materials
                 let rv_light = sampler.next2f(rng);
-                if let Some(emit_rec) =
-                    scene
-                        .emitters
-                        .sample_from_group(&hit.p, rv_light, sampler.next1f(rng))
+                if let Some(light_sample) =
+                    scene.sample_light(&hit.p, ray.time, rv_light, sampler.next1f(rng))
                 {
-                    // visibility
-                    let visibility_ray = Ray::new(hit.p, emit_rec.wi);
-                    if let Some(visibility_hit) = scene.intersect(&visibility_ray) {
-                        let light_visible = (visibility_hit.t - emit_rec.hit.t).abs() < 1e-5;
-                        if light_visible {
-                            let select_probability = scene.emitters.pdf(&hit.p, &emit_rec.wi);
-                            let light_pdf = select_probability * emit_rec.pdf;
-                            let light =
-                                hit.mat.eval(&ray.direction, &emit_rec.wi, &hit) / light_pdf;
-                            let light = light.component_mul(&emit_rec.emitted);
-                            let light = light.component_mul(&attenuation);
-                            radiance += light;
-                        }
+                    if scene.light_visible(&hit.p, ray.time, &light_sample) {
+                        let light =
+                            hit.mat.eval(&ray.direction, &light_sample.wi, &hit) / light_sample.pdf;
+                        let light = light.component_mul(&light_sample.radiance);
+                        let light = light.component_mul(&attenuation);
+                        radiance += light;
                     }
                 }
             }
@@ -76,10 +75,10 @@ impl Integrator for PathTracerNEEIntegrator {
                     / hit.mat.pdf(&ray.direction, &srec.wo, &hit)
             };
             attenuation = attenuation.component_mul(&a);
+            count_emitted = srec.is_specular;
 
-            // update the ray for the next bounce
-            ray.origin = hit.p;
-            ray.direction = srec.wo;
+            // update the ray for the next bounce, the differentials only describe the footprint of the camera ray
+            ray = Ray::new(hit.p, srec.wo).with_time(ray.time);
         }
         radiance
     }
@@ -0,0 +1,40 @@
+//! RustRT - Yet another Rust Ray Tracer
+//!
+//! Scenes are usually described in JSON and loaded with [`Scene::new`], then rendered with [`Scene::raytrace`] or
+//! [`Scene::raytrace_tiles`]. The `cameras`, `materials`, `surfaces`, `textures`, `lights`, `integrators` and
+//! `samplers` modules expose the building blocks and the traits they implement, the geometry and image types they
+//! use are re-exported here. The rest of the renderer (sampling, distributions, asset loading, ...) is private.
+//!
+//! Scenes can also be built directly in Rust with [`SceneBuilder`] and the typed builders of each module
+//! (`SphereBuilder`, `LambertianBuilder`, ...), see the example scenes of the `rustrt-base` binary.
+
+pub mod cameras;
+pub(crate) mod core;
+pub mod integrators;
+pub mod lights;
+pub mod materials;
+pub mod samplers;
+pub mod surfaces;
+pub mod textures;
+
+#[cfg(test)]
+mod tests;
+
+pub use crate::cameras::{Camera, CameraType};
+pub use crate::core::aabb::Aabb;
+pub use crate::core::animation::{frame_filename, Animation};
+pub use crate::core::assets::{clear_cache, evict_unused_assets};
+pub use crate::core::image2d::{Channel, ColorSpace, Image2d};
+pub use crate::core::ray::{Ray, RayDifferentials};
+pub use crate::core::scene::{read_scene_from_file, Scene, SceneBuilder};
+pub use crate::core::tiles::{
+    CancellationToken, ProgressBarProgress, RenderProgress, SilentProgress, Tile, TileOrder,
+    TileScheduler,
+};
+pub use crate::core::transform::{AnimatedTransform, Transform};
+pub use crate::core::utils::{Factory, INTERSECTION_TEST, RAYS};
+pub use crate::integrators::{Integrator, IntegratorType};
+pub use crate::materials::{CustomMaterial, Material, MaterialFactory, MaterialType};
+pub use crate::samplers::{Sampler, SamplerType};
+pub use crate::surfaces::{Surface, SurfaceFactory, SurfaceType};
+pub use crate::textures::{Texture, TextureType};
@@ -0,0 +1,40 @@
+use nalgebra_glm::{Vec2, Vec3};
+
+use crate::core::sampling::{sample_sphere, sample_sphere_pdf};
+use crate::lights::{Background, LightSample};
+
+/// Background of the same color in every direction
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConstantBackground {
+    color: Vec3,
+}
+
+impl ConstantBackground {
+    pub fn new(color: Vec3) -> ConstantBackground {
+        ConstantBackground { color }
+    }
+}
+
+impl Background for ConstantBackground {
+    fn radiance(&self, _dir: &Vec3) -> Vec3 {
+        self.color
+    }
+
+    fn sample(&self, rv: Vec2) -> Option<LightSample> {
+        Some(LightSample {
+            wi: sample_sphere(rv),
+            distance: f32::INFINITY,
+            radiance: self.color,
+            pdf: sample_sphere_pdf(),
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, _dir: &Vec3) -> f32 {
+        sample_sphere_pdf()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.color != Vec3::zeros()
+    }
+}
@@ -0,0 +1,190 @@
+use nalgebra_glm::{dot, normalize, Vec2, Vec3};
+use serde_json::Value;
+use std::f32::consts::{PI, TAU};
+
+use crate::core::onb::Onb;
+use crate::core::sampling::{sample_sphere_cap, sample_sphere_cap_pdf};
+use crate::core::transform::Transform;
+use crate::core::utils::{deg2rad, luminance, read_or, read_v_or_f};
+use crate::lights::{Light, LightBounds, LightSample};
+
+/// Light arriving from infinitely far away, travelling along the -z axis of its `transform`.
+///
+/// With a zero angular diameter all the light arrives along a single direction. Otherwise it comes uniformly from a
+/// disk in the sky, like the sun, which the rays leaving the scene can reach.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DirectionalLight {
+    /// Direction towards the light
+    direction: Vec3,
+    /// Irradiance on a surface facing the light
+    irradiance: Vec3,
+    cos_angular_radius: f32,
+}
+
+impl DirectionalLight {
+    pub fn new(v: &Value) -> DirectionalLight {
+        let mut builder = DirectionalLightBuilder::new()
+            .transform(Transform::read(v))
+            .angular_diameter(read_or(v, "angular_diameter", 0.0));
+        if v.get("irradiance").is_some() {
+            builder = builder.irradiance(read_v_or_f(v, "irradiance"));
+        }
+        builder.build()
+    }
+
+    fn is_delta(&self) -> bool {
+        self.cos_angular_radius >= 1.0
+    }
+
+    fn solid_angle(&self) -> f32 {
+        TAU * (1.0 - self.cos_angular_radius)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectionalLightBuilder {
+    transform: Transform,
+    irradiance: Vec3,
+    angular_diameter: f32,
+}
+
+impl DirectionalLightBuilder {
+    pub fn new() -> DirectionalLightBuilder {
+        DirectionalLightBuilder {
+            transform: Transform::default(),
+            irradiance: Vec3::new(1.0, 1.0, 1.0),
+            angular_diameter: 0.0,
+        }
+    }
+
+    /// Orientation of the light, which travels along -z
+    pub fn transform(mut self, transform: Transform) -> DirectionalLightBuilder {
+        self.transform = transform;
+        self
+    }
+
+    pub fn irradiance(mut self, irradiance: Vec3) -> DirectionalLightBuilder {
+        self.irradiance = irradiance;
+        self
+    }
+
+    /// Apparent size of the light, in degrees
+    pub fn angular_diameter(mut self, angular_diameter: f32) -> DirectionalLightBuilder {
+        self.angular_diameter = angular_diameter;
+        self
+    }
+
+    pub fn build(self) -> DirectionalLight {
+        DirectionalLight {
+            direction: normalize(&self.transform.vector(&Vec3::z())),
+            irradiance: self.irradiance,
+            cos_angular_radius: f32::cos(deg2rad(0.5 * self.angular_diameter)),
+        }
+    }
+}
+
+impl Default for DirectionalLightBuilder {
+    fn default() -> DirectionalLightBuilder {
+        DirectionalLightBuilder::new()
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Vec3, rv: Vec2) -> Option<LightSample> {
+        if self.is_delta() {
+            return Some(LightSample {
+                wi: self.direction,
+                distance: f32::INFINITY,
+                radiance: self.irradiance,
+                pdf: 1.0,
+                is_delta: true,
+            });
+        }
+        let wi = Onb::build_from_w(&self.direction)
+            .local(&sample_sphere_cap(rv, self.cos_angular_radius));
+        let wi = normalize(&wi);
+        Some(LightSample {
+            wi,
+            distance: f32::INFINITY,
+            radiance: self.radiance(&wi),
+            pdf: self.pdf(&wi),
+            is_delta: false,
+        })
+    }
+
+    fn radiance(&self, dir: &Vec3) -> Vec3 {
+        if self.is_delta() || dot(&normalize(dir), &self.direction) < self.cos_angular_radius {
+            return Vec3::zeros();
+        }
+        self.irradiance / self.solid_angle()
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f32 {
+        if self.is_delta() || dot(&normalize(dir), &self.direction) < self.cos_angular_radius {
+            return 0.0;
+        }
+        sample_sphere_cap_pdf(1.0, self.cos_angular_radius)
+    }
+
+    fn power(&self, scene_radius: f32) -> f32 {
+        // the light crossing a disk as large as the scene
+        PI * scene_radius * scene_radius * luminance(&self.irradiance)
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{dot, Vec2, Vec3};
+    use serde_json::json;
+
+    use crate::core::sampling::stratified_grid;
+    use crate::lights::{create_light, Light};
+
+    #[test]
+    fn delta_direction() {
+        // light travelling down
+        let light = create_light(&json!({
+            "type": "directional",
+            "transform": {"from": [0.0, 1.0, 0.0], "at": [0.0, 0.0, 0.0], "up": [0.0, 0.0, 1.0]},
+            "irradiance": 3.0
+        }));
+        let sample = light
+            .sample(&Vec3::new(5.0, 0.0, 2.0), Vec2::new(0.2, 0.9))
+            .unwrap();
+        assert!(sample.is_delta);
+        assert!(sample.distance.is_infinite());
+        approx::assert_abs_diff_eq!(sample.wi, Vec3::y());
+        approx::assert_abs_diff_eq!(sample.radiance, Vec3::new(3.0, 3.0, 3.0));
+        assert_eq!(light.radiance(&Vec3::y()), Vec3::zeros());
+    }
+
+    #[test]
+    fn disk_keeps_irradiance() {
+        let light = create_light(&json!({
+            "type": "directional",
+            "transform": {"from": [0.0, 1.0, 0.0], "at": [0.0, 0.0, 0.0], "up": [0.0, 0.0, 1.0]},
+            "irradiance": 2.0,
+            "angular_diameter": 10.0
+        }));
+        // integrate the irradiance on a surface facing the light
+        let n = 64;
+        let mut irradiance = Vec3::zeros();
+        for rv in stratified_grid(n) {
+            let sample = light.sample(&Vec3::zeros(), rv).unwrap();
+            assert!(!sample.is_delta);
+            approx::assert_relative_eq!(sample.pdf, light.pdf(&sample.wi));
+            assert_eq!(sample.radiance, light.radiance(&sample.wi));
+            irradiance += sample.radiance * dot(&sample.wi, &Vec3::y()) / sample.pdf;
+        }
+        approx::assert_relative_eq!(
+            irradiance / (n * n) as f32,
+            Vec3::new(2.0, 2.0, 2.0),
+            max_relative = 1e-2
+        );
+        assert_eq!(light.radiance(&Vec3::new(0.0, 1.0, 0.5)), Vec3::zeros());
+    }
+}
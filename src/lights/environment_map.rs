@@ -0,0 +1,207 @@
+use nalgebra_glm::{normalize, Vec2, Vec3};
+use serde_json::Value;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::core::assets::load_image;
+use crate::core::distribution::Distribution2d;
+use crate::core::image2d::Image2d;
+use crate::core::transform::Transform;
+use crate::core::utils::{
+    direction_to_spherical_uv, luminance, read, read_or, spherical_uv_to_direction,
+};
+use crate::lights::{Background, LightSample};
+
+/// Latitude-longitude image surrounding the scene, usually a HDR capture.
+///
+/// The image covers the directions around the z axis of its `transform`, and is scaled by `intensity`. Directions are
+/// importance sampled with a piecewise constant distribution over the pixels.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnvironmentMap {
+    image: Arc<Image2d>,
+    transform: Transform,
+    intensity: f32,
+    /// Distribution of the pixels, the first row is the top of the image
+    distribution: Distribution2d,
+}
+
+impl EnvironmentMap {
+    pub fn new(v: &Value) -> EnvironmentMap {
+        let filename: String = read(v, "filename");
+        EnvironmentMapBuilder::new(load_image(&filename))
+            .transform(Transform::read(v))
+            .intensity(read_or(v, "intensity", 1.0))
+            .build()
+    }
+
+    /// Pixel seen along a direction of the local frame of the map
+    fn pixel(&self, local: &Vec3) -> (usize, usize) {
+        let uv = direction_to_spherical_uv(local);
+        let x = (uv.x * self.image.size_x as f32) as usize;
+        let y = ((1.0 - uv.y) * self.image.size_y as f32) as usize;
+        (x.min(self.image.size_x - 1), y.min(self.image.size_y - 1))
+    }
+
+    fn to_local(&self, dir: &Vec3) -> Vec3 {
+        normalize(&self.transform.inverse().vector(dir))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnvironmentMapBuilder {
+    image: Arc<Image2d>,
+    transform: Transform,
+    intensity: f32,
+}
+
+impl EnvironmentMapBuilder {
+    pub fn new(image: impl Into<Arc<Image2d>>) -> EnvironmentMapBuilder {
+        EnvironmentMapBuilder {
+            image: image.into(),
+            transform: Transform::default(),
+            intensity: 1.0,
+        }
+    }
+
+    /// Orientation of the map, its z axis points to the top row of the image
+    pub fn transform(mut self, transform: Transform) -> EnvironmentMapBuilder {
+        self.transform = transform;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> EnvironmentMapBuilder {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn build(self) -> EnvironmentMap {
+        let (width, height) = (self.image.size_x, self.image.size_y);
+        let weights: Vec<f32> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| luminance(&self.image[(x, y)]).max(0.0))
+            .collect();
+
+        EnvironmentMap {
+            distribution: lat_long_distribution(&weights, width, height),
+            image: self.image,
+            transform: self.transform,
+            intensity: self.intensity,
+        }
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn radiance(&self, dir: &Vec3) -> Vec3 {
+        self.image[self.pixel(&self.to_local(dir))] * self.intensity
+    }
+
+    fn sample(&self, rv: Vec2) -> Option<LightSample> {
+        let (p, _) = self.distribution.sample(rv);
+        let local = spherical_uv_to_direction(Vec2::new(p.x, 1.0 - p.y));
+        let wi = normalize(&self.transform.vector(&local));
+
+        let pdf = self.pdf(&wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance: f32::INFINITY,
+            radiance: self.radiance(&wi),
+            pdf,
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f32 {
+        lat_long_pdf(&self.distribution, &self.to_local(dir))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.intensity > 0.0 && self.distribution.func_sum() > 0.0
+    }
+}
+
+/// Distribution of the cells of a latitude-longitude grid, from their weights stored row by row from the top.
+///
+/// Rows near the poles cover a smaller solid angle, so their weights are scaled down by sin theta.
+pub(crate) fn lat_long_distribution(
+    weights: &[f32],
+    width: usize,
+    height: usize,
+) -> Distribution2d {
+    let weights: Vec<f32> = weights
+        .iter()
+        .enumerate()
+        .map(|(i, w)| w * f32::sin(((i / width) as f32 + 0.5) / height as f32 * PI))
+        .collect();
+    Distribution2d::new(&weights, width, height)
+}
+
+/// Solid angle density of the direction `local`, in the frame of a grid sampled with `lat_long_distribution`
+pub(crate) fn lat_long_pdf(distribution: &Distribution2d, local: &Vec3) -> f32 {
+    let sin_theta = f32::sqrt(1.0 - local.z * local.z);
+    if sin_theta <= 0.0 {
+        return 0.0;
+    }
+    let uv = direction_to_spherical_uv(local);
+    // each cell covers 2 pi / width by pi / height in spherical coordinates
+    distribution.pdf(Vec2::new(uv.x, 1.0 - uv.y)) / (2.0 * PI * PI * sin_theta)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    use crate::core::image2d::Image2d;
+    use crate::core::sampling::stratified_grid;
+    use crate::core::transform::Transform;
+    use crate::lights::{Background, EnvironmentMap, EnvironmentMapBuilder};
+    use crate::tests::sample_test::DirectionTest;
+
+    /// Dim map with a bright spot
+    fn spot_map() -> EnvironmentMap {
+        let mut image = Image2d::new(32, 16);
+        for x in 0..32 {
+            for y in 0..16 {
+                image[(x, y)] = Vec3::new(0.1, 0.2, 0.3);
+            }
+        }
+        image[(5, 4)] = Vec3::new(500.0, 400.0, 300.0);
+        EnvironmentMapBuilder::new(Arc::new(image))
+            .transform(Transform::rotate(&Vec3::new(1.0, 1.0, 0.0), 30.0))
+            .intensity(2.0)
+            .build()
+    }
+
+    #[test]
+    fn environment_map_monte_carlo() {
+        let map = spot_map();
+        let (test, mut parameters) = DirectionTest::new(
+            &json!({"name": "environment_map"}),
+            |rv, _| {
+                let sample = map.sample(rv)?;
+                approx::assert_abs_diff_eq!(sample.wi.norm(), 1.0, epsilon = 1e-5);
+                assert_eq!(sample.radiance, map.radiance(&sample.wi));
+                Some((sample.wi, sample.pdf))
+            },
+            |dir| map.pdf(dir),
+        );
+        parameters.run(&test, 1.0, 1e-2);
+    }
+
+    #[test]
+    fn samples_favor_the_bright_spot() {
+        let map = spot_map();
+        let bright = stratified_grid(64)
+            .filter(|rv| map.sample(*rv).unwrap().radiance.x > 1.0)
+            .count();
+        // the spot carries most of the power of the map
+        assert!(
+            bright > 64 * 64 / 2,
+            "only {bright} samples towards the spot"
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use nalgebra_glm::{cross, distance2, dot, length, length2, normalize, Vec3};
+use std::f32::consts::PI;
+
+use crate::core::aabb::Aabb;
+use crate::lights::LightSampler;
+
+/// Spatial and directional extent of the light leaving an emitter, used to estimate its contribution at a point
+#[derive(Debug, PartialEq, Clone)]
+pub struct LightBounds {
+    /// Region the light leaves from
+    pub bounds: Aabb,
+    /// Axis of the cone bounding the directions of emission
+    pub w: Vec3,
+    /// Emitted power, or any proportional estimate
+    pub phi: f32,
+    /// Cosine of the half angle of the cone bounding the emission axes
+    pub cos_theta_o: f32,
+    /// Cosine of the angle past the emission axes where the light still leaves
+    pub cos_theta_e: f32,
+    /// Whether the light also leaves against the emission axes
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    /// Bounds of both `self` and `other`
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.phi <= 0.0 {
+            return other.clone();
+        }
+        if other.phi <= 0.0 {
+            return self.clone();
+        }
+        let mut bounds = self.bounds.clone();
+        bounds.enclose(&other.bounds);
+        let (w, cos_theta_o) = cone_union((self.w, self.cos_theta_o), (other.w, other.cos_theta_o));
+        LightBounds {
+            bounds,
+            w,
+            phi: self.phi + other.phi,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// Conservative estimate of the light arriving at `p`, zero only when none can reach it
+    pub fn importance(&self, p: &Vec3) -> f32 {
+        if self.phi <= 0.0 {
+            return 0.0;
+        }
+        let center = self.bounds.center();
+        let radius = 0.5 * length(&self.bounds.diagonal());
+        // do not let the importance blow up close to the emitter
+        let d2 = distance2(p, &center).max(radius).max(1e-6);
+
+        // angle between the emission axis and the direction towards `p`
+        let to_p = p - center;
+        let mut cos_theta_w = if length2(&to_p) > 0.0 {
+            dot(&self.w, &normalize(&to_p))
+        } else {
+            1.0
+        };
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // half angle of the cone of directions from `p` to the bounds
+        let cos_theta_b = if distance2(p, &center) < radius * radius {
+            -1.0
+        } else {
+            safe_sqrt(1.0 - radius * radius / distance2(p, &center))
+        };
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        // smallest angle between an emission axis and a direction towards `p`
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+        (self.phi * cos_theta_p / d2).max(0.0)
+    }
+}
+
+fn safe_sqrt(x: f32) -> f32 {
+    x.max(0.0).sqrt()
+}
+
+/// cos(max(0, a - b)) from the sines and cosines of the angles
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+/// sin(max(0, a - b)) from the sines and cosines of the angles
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+/// Smallest cone, given by its axis and the cosine of its half angle, containing the cones `a` and `b`
+fn cone_union(a: (Vec3, f32), b: (Vec3, f32)) -> (Vec3, f32) {
+    let theta_a = a.1.clamp(-1.0, 1.0).acos();
+    let theta_b = b.1.clamp(-1.0, 1.0).acos();
+    let theta_d = dot(&a.0, &b.0).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let axis = cross(&a.0, &b.0);
+    if theta_o >= PI || length2(&axis) == 0.0 {
+        return (a.0, -1.0);
+    }
+    // rotate the axis of `a` towards `b`
+    let theta_r = theta_o - theta_a;
+    let k = normalize(&axis);
+    let (sin_r, cos_r) = theta_r.sin_cos();
+    let w = a.0 * cos_r + cross(&k, &a.0) * sin_r + k * dot(&k, &a.0) * (1.0 - cos_r);
+    (normalize(&w), theta_o.cos())
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum LightBvhNode {
+    Leaf {
+        bounds: LightBounds,
+        emitter: usize,
+    },
+    /// The first child follows its parent, the second one is at `second_child`
+    Interior {
+        bounds: LightBounds,
+        second_child: usize,
+    },
+}
+
+impl LightBvhNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightBvhNode::Leaf { bounds, .. } | LightBvhNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Choose emitters by their estimated contribution at the shading point, walking down a BVH of their light bounds.
+///
+/// Emitters without bounds, like the background, are chosen uniformly with a probability given by their count, as if
+/// the whole BVH were one more of them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BvhLightSampler {
+    nodes: Vec<LightBvhNode>,
+    /// Emitters without bounds
+    infinite: Vec<usize>,
+    /// Branches taken from the root to reach each emitter of the BVH, the first one in the lowest bit
+    trails: Vec<Option<u64>>,
+}
+
+impl BvhLightSampler {
+    /// Build the BVH from the bounds of each emitter, `None` for the emitters at infinity
+    pub fn new(bounds: Vec<Option<LightBounds>>) -> BvhLightSampler {
+        let mut sampler = BvhLightSampler {
+            nodes: Vec::new(),
+            infinite: Vec::new(),
+            trails: vec![None; bounds.len()],
+        };
+        let mut bounded = Vec::new();
+        for (index, bounds) in bounds.into_iter().enumerate() {
+            match bounds {
+                Some(bounds) if bounds.phi > 0.0 => bounded.push((index, bounds)),
+                // emitters without power are never chosen
+                Some(_) => (),
+                None => sampler.infinite.push(index),
+            }
+        }
+        if !bounded.is_empty() {
+            sampler.build_node(&mut bounded, 0, 0);
+        }
+        sampler
+    }
+
+    fn build_node(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        if let [(emitter, bounds)] = lights {
+            self.nodes.push(LightBvhNode::Leaf {
+                bounds: bounds.clone(),
+                emitter: *emitter,
+            });
+            self.trails[*emitter] = Some(trail);
+            return bounds.clone();
+        }
+
+        // split at the median of the centers along the largest axis
+        let mut centers = Aabb::new();
+        for (_, bounds) in lights.iter() {
+            centers.enclose_point(&bounds.bounds.center());
+        }
+        let axis = centers.diagonal().imax();
+        lights
+            .sort_by(|(_, a), (_, b)| a.bounds.center()[axis].total_cmp(&b.bounds.center()[axis]));
+        let (first, second) = lights.split_at_mut(lights.len() / 2);
+
+        let index = self.nodes.len();
+        // placeholder until the children are known
+        self.nodes.push(LightBvhNode::Interior {
+            bounds: first[0].1.clone(),
+            second_child: 0,
+        });
+        let first_bounds = self.build_node(first, trail, depth + 1);
+        let second_child = self.nodes.len();
+        let second_bounds = self.build_node(second, trail | (1 << depth), depth + 1);
+        let bounds = first_bounds.union(&second_bounds);
+        self.nodes[index] = LightBvhNode::Interior {
+            bounds: bounds.clone(),
+            second_child,
+        };
+        bounds
+    }
+
+    /// Probability of choosing among the emitters at infinity rather than in the BVH
+    fn infinite_probability(&self) -> f32 {
+        let count = self.infinite.len() as f32;
+        if self.nodes.is_empty() {
+            return if count > 0.0 { 1.0 } else { 0.0 };
+        }
+        count / (count + 1.0)
+    }
+
+    /// Probabilities of choosing each child of the interior node `node`, from `p`
+    fn child_probabilities(
+        &self,
+        p: &Vec3,
+        node: usize,
+        second_child: usize,
+    ) -> Option<(f32, f32)> {
+        let c0 = self.nodes[node + 1].bounds().importance(p);
+        let c1 = self.nodes[second_child].bounds().importance(p);
+        if c0 + c1 <= 0.0 {
+            return None;
+        }
+        Some((c0 / (c0 + c1), c1 / (c0 + c1)))
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn sample(&self, p: &Vec3, rv: f32) -> Option<(usize, f32)> {
+        let p_infinite = self.infinite_probability();
+        if rv < p_infinite {
+            let count = self.infinite.len();
+            let index = ((rv / p_infinite * count as f32) as usize).min(count - 1);
+            return Some((self.infinite[index], p_infinite / count as f32));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut rv = ((rv - p_infinite) / (1.0 - p_infinite)).min(1.0 - f32::EPSILON);
+        let mut pmf = 1.0 - p_infinite;
+        let mut node = 0;
+        loop {
+            match &self.nodes[node] {
+                LightBvhNode::Leaf { bounds, emitter } => {
+                    // interior nodes already checked that the leaves they chose can contribute
+                    let reachable = node > 0 || bounds.importance(p) > 0.0;
+                    return reachable.then_some((*emitter, pmf));
+                }
+                LightBvhNode::Interior { second_child, .. } => {
+                    let (p0, p1) = self.child_probabilities(p, node, *second_child)?;
+                    if rv < p0 {
+                        node += 1;
+                        pmf *= p0;
+                        rv = (rv / p0).min(1.0 - f32::EPSILON);
+                    } else {
+                        node = *second_child;
+                        pmf *= p1;
+                        rv = ((rv - p0) / p1).min(1.0 - f32::EPSILON);
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, p: &Vec3, index: usize) -> f32 {
+        let Some(mut trail) = self.trails.get(index).copied().flatten() else {
+            if !self.infinite.contains(&index) {
+                return 0.0;
+            }
+            return self.infinite_probability() / self.infinite.len() as f32;
+        };
+
+        let mut pmf = 1.0 - self.infinite_probability();
+        let mut node = 0;
+        loop {
+            match &self.nodes[node] {
+                LightBvhNode::Leaf { bounds, .. } => {
+                    let reachable = node > 0 || bounds.importance(p) > 0.0;
+                    return if reachable { pmf } else { 0.0 };
+                }
+                LightBvhNode::Interior { second_child, .. } => {
+                    let Some((p0, p1)) = self.child_probabilities(p, node, *second_child) else {
+                        return 0.0;
+                    };
+                    if trail & 1 == 0 {
+                        node += 1;
+                        pmf *= p0;
+                    } else {
+                        node = *second_child;
+                        pmf *= p1;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+
+    use crate::core::aabb::Aabb;
+    use crate::core::sampling::stratified;
+    use crate::lights::light_bvh::{cone_union, BvhLightSampler, LightBounds};
+    use crate::lights::LightSampler;
+
+    fn point_bounds(p: Vec3, phi: f32) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb { min: p, max: p },
+            w: Vec3::z(),
+            phi,
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+
+    #[test]
+    fn cones_union() {
+        let (w, cos_theta) = cone_union((Vec3::x(), 1.0), (Vec3::y(), 1.0));
+        approx::assert_abs_diff_eq!(w, Vec3::new(1.0, 1.0, 0.0).normalize(), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(
+            cos_theta,
+            f32::cos(std::f32::consts::FRAC_PI_4),
+            epsilon = 1e-5
+        );
+
+        // a cone inside another one
+        assert_eq!(
+            cone_union((Vec3::z(), 0.0), (Vec3::z(), 0.5)),
+            (Vec3::z(), 0.0)
+        );
+        // opposite directions cover the sphere
+        assert_eq!(cone_union((Vec3::z(), 1.0), (-Vec3::z(), 1.0)).1, -1.0);
+    }
+
+    #[test]
+    fn pmf_matches_sample() {
+        let sampler = BvhLightSampler::new(vec![
+            point_bounds(Vec3::new(0.0, 0.0, 0.0), 1.0),
+            None,
+            point_bounds(Vec3::new(4.0, 0.0, 0.0), 2.0),
+            point_bounds(Vec3::new(0.0, 3.0, 1.0), 1.0),
+            point_bounds(Vec3::new(9.0, 0.0, 0.0), 0.0),
+        ]);
+        let p = Vec3::new(1.0, 0.5, 0.0);
+        let n = 10000;
+        let mut counts = [0; 5];
+        for rv in stratified(n) {
+            let (index, pmf) = sampler.sample(&p, rv).unwrap();
+            approx::assert_relative_eq!(pmf, sampler.pmf(&p, index), max_relative = 1e-5);
+            counts[index] += 1;
+        }
+        let total: f32 = (0..5).map(|i| sampler.pmf(&p, i)).sum();
+        approx::assert_abs_diff_eq!(total, 1.0, epsilon = 1e-5);
+        for (i, count) in counts.iter().enumerate() {
+            approx::assert_abs_diff_eq!(
+                *count as f32 / n as f32,
+                sampler.pmf(&p, i),
+                epsilon = 1e-3
+            );
+        }
+        // half of the samples go to the background, as many as the whole BVH
+        approx::assert_abs_diff_eq!(sampler.pmf(&p, 1), 0.5);
+        assert_eq!(sampler.pmf(&p, 4), 0.0);
+        // the closest light is the most likely
+        assert!(counts[0] > counts[2] && counts[0] > counts[3]);
+    }
+}
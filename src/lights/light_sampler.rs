@@ -0,0 +1,62 @@
+use nalgebra_glm::Vec3;
+
+use crate::core::distribution::AliasTable;
+use crate::lights::LightSampler;
+
+/// Choose every emitter with the same probability
+#[derive(Debug, PartialEq, Clone)]
+pub struct UniformLightSampler {
+    count: usize,
+}
+
+impl UniformLightSampler {
+    pub fn new(count: usize) -> UniformLightSampler {
+        UniformLightSampler { count }
+    }
+}
+
+impl LightSampler for UniformLightSampler {
+    fn sample(&self, _p: &Vec3, rv: f32) -> Option<(usize, f32)> {
+        if self.count == 0 {
+            return None;
+        }
+        let index = ((rv * self.count as f32) as usize).min(self.count - 1);
+        Some((index, 1.0 / self.count as f32))
+    }
+
+    fn pmf(&self, _p: &Vec3, index: usize) -> f32 {
+        if index < self.count {
+            1.0 / self.count as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Choose the emitters proportionally to their power, wherever the shading point is
+#[derive(Debug, PartialEq, Clone)]
+pub struct PowerLightSampler {
+    distribution: Option<AliasTable>,
+}
+
+impl PowerLightSampler {
+    /// Build the sampler from the power of each emitter, uniform if none has any
+    pub fn new(powers: &[f32]) -> PowerLightSampler {
+        let distribution = (!powers.is_empty()).then(|| AliasTable::new(powers));
+        PowerLightSampler { distribution }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn sample(&self, _p: &Vec3, rv: f32) -> Option<(usize, f32)> {
+        let (index, pmf) = self.distribution.as_ref()?.sample(rv);
+        (pmf > 0.0).then_some((index, pmf))
+    }
+
+    fn pmf(&self, _p: &Vec3, index: usize) -> f32 {
+        match &self.distribution {
+            Some(distribution) if index < distribution.size() => distribution.pmf(index),
+            _ => 0.0,
+        }
+    }
+}
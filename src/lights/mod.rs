@@ -0,0 +1,460 @@
+mod constant;
+mod directional;
+mod environment_map;
+mod light_bvh;
+mod light_sampler;
+mod point;
+mod portal;
+mod sky;
+mod spot;
+
+use enum_dispatch::enum_dispatch;
+use nalgebra_glm::{length, normalize, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::f32::consts::PI;
+
+use crate::core::aabb::Aabb;
+use crate::core::ray::Ray;
+use crate::core::sampling::stratified_grid;
+use crate::core::utils::{luminance, read_v_or_f};
+use crate::materials::Material;
+use crate::surfaces::{HitInfo, Surface, SurfaceType};
+
+/// Direction sampled towards an emitter from a shading point
+pub struct LightSample {
+    /// Normalized direction from the shading point towards the emitter
+    pub wi: Vec3,
+    /// Distance to the sampled point, infinite for the background
+    pub distance: f32,
+    /// Radiance arriving at the shading point along `wi`
+    pub radiance: Vec3,
+    /// Solid angle density, including the probability of choosing the emitter
+    pub pdf: f32,
+    /// Whether the emitter can only be reached by light samples, `pdf` is then a discrete probability
+    pub is_delta: bool,
+}
+
+/// Light arriving from infinitely far away, seen by the rays leaving the scene
+#[enum_dispatch]
+pub trait Background {
+    /// Radiance arriving along the direction `dir`, pointing away from the scene
+    fn radiance(&self, dir: &Vec3) -> Vec3;
+
+    /// Sample a direction towards the background
+    fn sample(&self, rv: Vec2) -> Option<LightSample>;
+
+    /// Solid angle density of `sample` for the direction `dir`
+    fn pdf(&self, dir: &Vec3) -> f32;
+
+    /// Whether the background emits any light, only then is it sampled as an emitter
+    fn is_emissive(&self) -> bool;
+}
+
+/// Emitter which is not a surface, like the point and directional lights.
+///
+/// Lights at finite distance cannot be hit by rays, so they are only reached by light samples.
+#[enum_dispatch]
+pub trait Light {
+    /// Sample a direction from the shading point `p` towards the light
+    fn sample(&self, p: &Vec3, rv: Vec2) -> Option<LightSample>;
+
+    /// Radiance arriving along the direction `dir` leaving the scene, from the lights at infinity with a size
+    fn radiance(&self, dir: &Vec3) -> Vec3;
+
+    /// Solid angle density of `sample` for the direction `dir` leaving the scene, zero for delta lights
+    fn pdf(&self, dir: &Vec3) -> f32;
+
+    /// Estimate of the emitted power, as a luminance, in a scene of bounding radius `scene_radius`
+    fn power(&self, scene_radius: f32) -> f32;
+
+    /// Extent of the emitted light, `None` for the lights at infinity
+    fn light_bounds(&self) -> Option<LightBounds>;
+}
+
+/// Strategy choosing which emitter a light sample is taken from
+#[enum_dispatch]
+pub trait LightSampler {
+    /// Choose an emitter for the shading point `p` with a random variable in [0, 1), return its index and probability
+    fn sample(&self, p: &Vec3, rv: f32) -> Option<(usize, f32)>;
+
+    /// Probability that `sample` chooses the emitter `index` from `p`
+    fn pmf(&self, p: &Vec3, index: usize) -> f32;
+}
+
+pub use crate::lights::constant::ConstantBackground;
+pub use crate::lights::directional::{DirectionalLight, DirectionalLightBuilder};
+pub use crate::lights::environment_map::{EnvironmentMap, EnvironmentMapBuilder};
+pub use crate::lights::light_bvh::{BvhLightSampler, LightBounds};
+pub use crate::lights::light_sampler::{PowerLightSampler, UniformLightSampler};
+pub use crate::lights::point::{PointLight, PointLightBuilder};
+pub use crate::lights::portal::{read_portals, Portals};
+pub use crate::lights::sky::{sun_position, Sky, SkyBuilder};
+pub use crate::lights::spot::{SpotLight, SpotLightBuilder};
+
+#[enum_dispatch(Background)]
+#[derive(Debug, PartialEq, Clone)]
+// there is a single background per scene, so the size of the largest variant doesn't matter
+#[allow(clippy::large_enum_variant)]
+pub enum BackgroundType {
+    Constant(ConstantBackground),
+    EnvironmentMap(EnvironmentMap),
+    Sky(Sky),
+}
+
+impl From<Vec3> for BackgroundType {
+    fn from(color: Vec3) -> BackgroundType {
+        BackgroundType::Constant(ConstantBackground::new(color))
+    }
+}
+
+/// Read the "background" field of a scene, a constant color or an emitter description.
+///
+/// Scenes without background are black.
+pub fn create_background(scene: &Value) -> BackgroundType {
+    let Some(v) = scene.get("background") else {
+        return BackgroundType::from(Vec3::zeros());
+    };
+    if !v.is_object() {
+        return BackgroundType::from(read_v_or_f(scene, "background"));
+    }
+
+    let background_type = v
+        .get("type")
+        .expect("background should have a type")
+        .as_str()
+        .expect("background type should be a string");
+    match background_type {
+        "environment_map" => BackgroundType::EnvironmentMap(EnvironmentMap::new(v)),
+        "sky" => BackgroundType::Sky(Sky::new(v)),
+        _ => unimplemented!("Background type {}", background_type),
+    }
+}
+
+#[enum_dispatch(Light)]
+#[derive(Debug, PartialEq, Clone)]
+pub enum LightType {
+    Point(PointLight),
+    Spot(SpotLight),
+    Directional(DirectionalLight),
+}
+
+pub fn create_light(v: &Value) -> LightType {
+    let light_type = v
+        .get("type")
+        .expect("light should have a type")
+        .as_str()
+        .expect("light type should be a string");
+    match light_type {
+        "point" => LightType::Point(PointLight::new(v)),
+        "spot" => LightType::Spot(SpotLight::new(v)),
+        "directional" => LightType::Directional(DirectionalLight::new(v)),
+        _ => unimplemented!("Light type {}", light_type),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightSelection {
+    Uniform,
+    Power,
+    Bvh,
+}
+
+#[enum_dispatch(LightSampler)]
+#[derive(Debug, PartialEq, Clone)]
+pub enum LightSamplerType {
+    Uniform(UniformLightSampler),
+    Power(PowerLightSampler),
+    Bvh(BvhLightSampler),
+}
+
+/// Build the sampler choosing among `emitters`, followed by the background when it emits
+pub fn create_light_sampler(
+    selection: LightSelection,
+    emitters: &[Emitter],
+    background: &BackgroundType,
+    scene_bounds: &Aabb,
+) -> LightSamplerType {
+    let count = emitters.len() + usize::from(background.is_emissive());
+    let scene_radius = if scene_bounds.is_empty() {
+        1.0
+    } else {
+        0.5 * length(&scene_bounds.diagonal())
+    };
+    match selection {
+        LightSelection::Uniform => UniformLightSampler::new(count).into(),
+        LightSelection::Power => {
+            let mut powers: Vec<f32> = emitters
+                .iter()
+                .map(|emitter| emitter.power(scene_radius))
+                .collect();
+            if background.is_emissive() {
+                powers.push(background_power(background, scene_radius));
+            }
+            PowerLightSampler::new(&powers).into()
+        }
+        LightSelection::Bvh => {
+            let mut bounds: Vec<Option<LightBounds>> =
+                emitters.iter().map(Emitter::light_bounds).collect();
+            if background.is_emissive() {
+                bounds.push(None);
+            }
+            BvhLightSampler::new(bounds).into()
+        }
+    }
+}
+
+/// Power of the background reaching a scene of bounding radius `scene_radius`, from the integral of its radiance
+fn background_power(background: &BackgroundType, scene_radius: f32) -> f32 {
+    let n = 16;
+    let integral: f32 = stratified_grid(n)
+        .filter_map(|rv| background.sample(rv))
+        .filter(|sample| sample.pdf > 0.0)
+        .map(|sample| luminance(&sample.radiance) / sample.pdf)
+        .sum();
+    PI * scene_radius * scene_radius * integral / (n * n) as f32
+}
+
+/// Entry of the list of emitters light samples are taken from, an emissive surface or a light
+#[derive(Debug, PartialEq, Clone)]
+pub enum Emitter {
+    Surface(SurfaceType),
+    Light(LightType),
+}
+
+impl Emitter {
+    /// Sample a direction from the shading point `p` towards the emitter, without the probability of choosing it
+    pub fn sample(&self, p: &Vec3, time: f32, rv: Vec2) -> Option<LightSample> {
+        match self {
+            Emitter::Surface(surface) => {
+                let erec = surface.sample(p, time, rv)?;
+                if !(erec.pdf > 0.0 && erec.pdf.is_finite()) {
+                    return None;
+                }
+                Some(LightSample {
+                    wi: normalize(&erec.wi),
+                    distance: erec.hit.t * length(&erec.wi),
+                    radiance: erec.emitted,
+                    pdf: erec.pdf,
+                    is_delta: false,
+                })
+            }
+            Emitter::Light(light) => light.sample(p, rv),
+        }
+    }
+
+    /// Solid angle density of `sample` for the direction `dir` from `p`, which reaches `hit` on this emitter, or
+    /// leaves the scene for `None`
+    pub fn pdf(&self, p: &Vec3, time: f32, dir: &Vec3, hit: Option<&HitInfo>) -> f32 {
+        match (self, hit) {
+            (Emitter::Surface(surface), Some(hit)) => surface.pdf_hit(p, time, dir, hit),
+            (Emitter::Light(light), None) => light.pdf(dir),
+            _ => 0.0,
+        }
+    }
+
+    /// Estimate of the emitted power, as a luminance, in a scene of bounding radius `scene_radius`
+    pub fn power(&self, scene_radius: f32) -> f32 {
+        match self {
+            Emitter::Surface(surface) => {
+                // average radiance of points sampled from outside of the surface, each one seen from its front
+                let bounds = surface.bounds();
+                let (axis, cos_theta) = surface.normal_bounds();
+                let side = if cos_theta >= 1.0 {
+                    axis
+                } else {
+                    normalize(&Vec3::new(1.0, 1.0, 1.0))
+                };
+                let o = bounds.center() + side * (length(&bounds.diagonal()) + 1.0);
+                let n = 4;
+                let mut radiance = 0.0;
+                let mut count = 0;
+                for rv in stratified_grid(n) {
+                    if let Some(erec) = surface.sample(&o, 0.0, rv) {
+                        let hit = &erec.hit;
+                        let front = Ray::new(hit.p + hit.sn, -hit.sn);
+                        let emitted = hit.mat.emmitted(&front, hit).unwrap_or_default();
+                        radiance += luminance(&emitted);
+                        count += 1;
+                    }
+                }
+                if count == 0 {
+                    return 0.0;
+                }
+                PI * surface.area() * radiance / count as f32
+            }
+            Emitter::Light(light) => light.power(scene_radius),
+        }
+    }
+
+    /// Extent of the emitted light, `None` for the emitters at infinity
+    pub fn light_bounds(&self) -> Option<LightBounds> {
+        match self {
+            Emitter::Surface(surface) => {
+                let (w, cos_theta_o) = surface.normal_bounds();
+                // the side a surface emits from is up to its material, assume both
+                Some(LightBounds {
+                    bounds: surface.bounds(),
+                    w,
+                    phi: self.power(1.0),
+                    cos_theta_o,
+                    cos_theta_e: 0.0,
+                    two_sided: true,
+                })
+            }
+            Emitter::Light(light) => light.light_bounds(),
+        }
+    }
+}
+
+impl From<SurfaceType> for Emitter {
+    fn from(surface: SurfaceType) -> Emitter {
+        Emitter::Surface(surface)
+    }
+}
+
+impl From<LightType> for Emitter {
+    fn from(light: LightType) -> Emitter {
+        Emitter::Light(light)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+
+    use crate::cameras::PinholeCameraBuilder;
+    use crate::core::ray::Ray;
+    use crate::core::sampling::stratified;
+    use crate::core::scene::SceneBuilder;
+    use crate::core::transform::Transform;
+    use crate::lights::{
+        create_background, Background, BackgroundType, LightSelection, PointLightBuilder,
+    };
+    use crate::materials::DiffuseLightBuilder;
+    use crate::surfaces::{QuadBuilder, SphereBuilder, Surface};
+
+    #[test]
+    fn constant_backgrounds() {
+        let background = create_background(&json!({"background": [0.1, 0.2, 0.3]}));
+        assert_eq!(background, BackgroundType::from(Vec3::new(0.1, 0.2, 0.3)));
+        assert!(background.is_emissive());
+
+        let background = create_background(&json!({}));
+        assert_eq!(background.radiance(&Vec3::z()), Vec3::zeros());
+        assert!(!background.is_emissive());
+    }
+
+    #[test]
+    fn scene_mixes_emitters() {
+        let quad = QuadBuilder::new(DiffuseLightBuilder::new().build())
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, -2.0)))
+            .build();
+        let point = PointLightBuilder::new()
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, 1.0)))
+            .intensity(Vec3::new(4.0, 4.0, 4.0))
+            .build();
+        let scene = SceneBuilder::new(PinholeCameraBuilder::new().build())
+            .surface(quad)
+            .light(point)
+            .light_selection(LightSelection::Uniform)
+            .build();
+        assert_eq!(scene.emitters.len(), 2);
+        let p = Vec3::zeros();
+        let rv = Vec2::new(0.3, 0.6);
+
+        // the surface is chosen half of the time, and its pdf is found again from a ray reaching it
+        let sample = scene.sample_light(&p, 0.0, rv, 0.25).unwrap();
+        assert!(!sample.is_delta && scene.light_visible(&p, 0.0, &sample));
+        let hit = scene.intersect(&Ray::new(p, sample.wi)).unwrap();
+        approx::assert_relative_eq!(
+            scene.light_pdf(&p, 0.0, &sample.wi, Some(&hit)),
+            sample.pdf,
+            max_relative = 1e-4
+        );
+
+        let sample = scene.sample_light(&p, 0.0, rv, 0.75).unwrap();
+        assert!(sample.is_delta && scene.light_visible(&p, 0.0, &sample));
+        approx::assert_abs_diff_eq!(sample.pdf, 0.5);
+        approx::assert_abs_diff_eq!(sample.radiance, Vec3::new(4.0, 4.0, 4.0));
+
+        // rays leaving the scene cannot reach the point light
+        assert_eq!(scene.light_pdf(&p, 0.0, &Vec3::z(), None), 0.0);
+        assert_eq!(scene.escaped_radiance(&Vec3::z()), Vec3::zeros());
+    }
+
+    #[test]
+    fn light_pdf_only_counts_the_hit_emitter() {
+        // a small light in front of a larger one
+        let light = || DiffuseLightBuilder::new().build();
+        let front = QuadBuilder::new(light())
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, -2.0)))
+            .build();
+        let back = QuadBuilder::new(light())
+            .size(Vec2::new(8.0, 8.0))
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, -4.0)))
+            .build();
+        let scene = SceneBuilder::new(PinholeCameraBuilder::new().build())
+            .surface(front.clone())
+            .surface(back)
+            .light_selection(LightSelection::Uniform)
+            .build();
+        let p = Vec3::zeros();
+        let dir = Vec3::new(0.1, 0.2, -1.0);
+
+        let hit = scene.intersect(&Ray::new(p, dir)).unwrap();
+        assert_eq!(hit.emitter, Some(0));
+        approx::assert_relative_eq!(
+            scene.light_pdf(&p, 0.0, &dir, Some(&hit)),
+            0.5 * front.pdf(&p, 0.0, &dir),
+            max_relative = 1e-5
+        );
+    }
+
+    #[test]
+    fn light_selection_matches_pdf() {
+        let bright = QuadBuilder::new(DiffuseLightBuilder::new().intensity(20.0).build())
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, -2.0)))
+            .build();
+        let dim = SphereBuilder::new(DiffuseLightBuilder::new().build())
+            .radius(0.5)
+            .transform(Transform::translate(&Vec3::new(3.0, 0.0, 0.0)))
+            .build();
+        let point = PointLightBuilder::new()
+            .transform(Transform::translate(&Vec3::new(0.0, 2.0, 0.0)))
+            .build();
+        let p = Vec3::new(0.2, 0.1, 0.3);
+        let rv = Vec2::new(0.4, 0.7);
+
+        for selection in [
+            LightSelection::Uniform,
+            LightSelection::Power,
+            LightSelection::Bvh,
+        ] {
+            let scene = SceneBuilder::new(PinholeCameraBuilder::new().build())
+                .surface(bright.clone())
+                .surface(dim.clone())
+                .light(point.clone())
+                .background(Vec3::new(0.1, 0.1, 0.1))
+                .light_selection(selection)
+                .build();
+            let mut total = 0.0;
+            for rv1 in stratified(64) {
+                let Some(sample) = scene.sample_light(&p, 0.0, rv, rv1) else {
+                    continue;
+                };
+                if sample.is_delta || !scene.light_visible(&p, 0.0, &sample) {
+                    continue;
+                }
+                // the pdf found again from the direction accounts for the choice of the emitter
+                let hit = scene.intersect(&Ray::new(p, sample.wi));
+                let pdf = scene.light_pdf(&p, 0.0, &sample.wi, hit.as_ref());
+                approx::assert_relative_eq!(pdf, sample.pdf, max_relative = 1e-3);
+                total += 1.0;
+            }
+            assert!(total > 0.0, "{selection:?}");
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use nalgebra_glm::{length2, normalize, Vec2, Vec3};
+use serde_json::Value;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::core::aabb::Aabb;
+use crate::core::assets::load_ies;
+use crate::core::ies::IesProfile;
+use crate::core::transform::Transform;
+use crate::core::utils::{luminance, read, read_v_or_f};
+use crate::lights::{Light, LightBounds, LightSample};
+
+/// Light emitted from a single point, the origin of its `transform`.
+///
+/// The light is emitted uniformly in all directions, unless an IES profile modulates it in the local frame.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PointLight {
+    transform: Transform,
+    /// Radiant intensity, the radiance reaching a point at distance d is `intensity` / d^2
+    intensity: Vec3,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl PointLight {
+    pub fn new(v: &Value) -> PointLight {
+        let mut builder = PointLightBuilder::new().transform(Transform::read(v));
+        if v.get("intensity").is_some() {
+            builder = builder.intensity(read_v_or_f(v, "intensity"));
+        }
+        if v.get("ies").is_some() {
+            let filename: String = read(v, "ies");
+            builder = builder.profile(load_ies(&filename));
+        }
+        builder.build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PointLightBuilder {
+    transform: Transform,
+    intensity: Vec3,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl PointLightBuilder {
+    pub fn new() -> PointLightBuilder {
+        PointLightBuilder {
+            transform: Transform::default(),
+            intensity: Vec3::new(1.0, 1.0, 1.0),
+            profile: None,
+        }
+    }
+
+    /// Placement of the light, which sits at the origin of its local frame
+    pub fn transform(mut self, transform: Transform) -> PointLightBuilder {
+        self.transform = transform;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: Vec3) -> PointLightBuilder {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Photometric profile scaling `intensity`, with its nadir along the local -z axis
+    pub fn profile(mut self, profile: impl Into<Arc<IesProfile>>) -> PointLightBuilder {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    pub fn build(self) -> PointLight {
+        PointLight {
+            transform: self.transform,
+            intensity: self.intensity,
+            profile: self.profile,
+        }
+    }
+}
+
+impl Default for PointLightBuilder {
+    fn default() -> PointLightBuilder {
+        PointLightBuilder::new()
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: &Vec3, _rv: Vec2) -> Option<LightSample> {
+        let to_light = self.transform.point(&Vec3::zeros()) - p;
+        let distance2 = length2(&to_light);
+        if distance2 <= 0.0 {
+            return None;
+        }
+        let wi = normalize(&to_light);
+        let falloff = match &self.profile {
+            Some(profile) => profile.value(&self.transform.inverse().vector(&-wi)),
+            None => 1.0,
+        };
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance: distance2.sqrt(),
+            radiance: self.intensity * falloff / distance2,
+            pdf: 1.0,
+            is_delta: true,
+        })
+    }
+
+    fn radiance(&self, _dir: &Vec3) -> Vec3 {
+        Vec3::zeros()
+    }
+
+    fn pdf(&self, _dir: &Vec3) -> f32 {
+        0.0
+    }
+
+    fn power(&self, _scene_radius: f32) -> f32 {
+        // an upper bound when a profile dims some directions
+        4.0 * PI * luminance(&self.intensity)
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        let p = self.transform.point(&Vec3::zeros());
+        Some(LightBounds {
+            bounds: Aabb { min: p, max: p },
+            w: Vec3::z(),
+            phi: self.power(1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+
+    use crate::core::transform::Transform;
+    use crate::lights::{create_light, Light, LightType, PointLightBuilder};
+
+    #[test]
+    fn inverse_square_falloff() {
+        let light = create_light(&json!({
+            "type": "point", "transform": {"translate": [1.0, 2.0, 3.0]}, "intensity": [8.0, 4.0, 2.0]
+        }));
+        let from_builder = PointLightBuilder::new()
+            .transform(Transform::translate(&Vec3::new(1.0, 2.0, 3.0)))
+            .intensity(Vec3::new(8.0, 4.0, 2.0))
+            .build();
+        assert_eq!(light, LightType::from(from_builder));
+
+        let sample = light
+            .sample(&Vec3::new(1.0, 0.0, 3.0), Vec2::new(0.3, 0.7))
+            .unwrap();
+        assert!(sample.is_delta);
+        approx::assert_abs_diff_eq!(sample.wi, Vec3::y());
+        approx::assert_abs_diff_eq!(sample.distance, 2.0);
+        approx::assert_abs_diff_eq!(sample.radiance, Vec3::new(2.0, 1.0, 0.5));
+        assert_eq!(light.radiance(&Vec3::y()), Vec3::zeros());
+    }
+
+    #[test]
+    fn ies_profile() {
+        // downlight fading to nothing at the horizon
+        let file = tempfile::Builder::new().suffix(".ies").tempfile().unwrap();
+        std::fs::write(
+            file.path(),
+            "IESNA:LM-63-2002\nTILT=NONE\n1 1000 1 3 1 1 2 0 0 0\n1 1 10\n0 45 90\n0\n200 100 0\n",
+        )
+        .unwrap();
+        // rotated so that the nadir of the profile points along +x
+        let light = create_light(&json!({
+            "type": "point",
+            "transform": {"axis": [0.0, 1.0, 0.0], "angle": -90.0},
+            "intensity": 2.0,
+            "ies": file.path().to_str().unwrap()
+        }));
+        let rv = Vec2::new(0.5, 0.5);
+
+        let nadir = light.sample(&Vec3::new(1.0, 0.0, 0.0), rv).unwrap();
+        approx::assert_abs_diff_eq!(nadir.radiance, Vec3::new(2.0, 2.0, 2.0), epsilon = 1e-5);
+        let side = light.sample(&Vec3::new(1.0, 1.0, 0.0), rv).unwrap();
+        approx::assert_abs_diff_eq!(side.radiance, Vec3::new(0.5, 0.5, 0.5), epsilon = 1e-4);
+        assert!(light.sample(&Vec3::new(-1.0, 0.0, 0.0), rv).is_none());
+    }
+}
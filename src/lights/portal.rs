@@ -0,0 +1,122 @@
+use nalgebra_glm::{length, normalize, Vec2, Vec3};
+use serde_json::Value;
+
+use crate::lights::{Background, BackgroundType, LightSample};
+use crate::materials::LambertianBuilder;
+use crate::surfaces::{Quad, QuadBuilder, Surface};
+
+/// Openings, like windows, through which the background lights an interior.
+///
+/// Without portals, the directions towards the background are sampled over the whole sphere, and most of them are
+/// blocked by the walls of an interior. With portals, a portal is chosen uniformly and a direction is sampled
+/// through it by solid angle, the background is only seen through the openings. Directions missing every portal
+/// have a zero density, the other sampling strategies still reach them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Portals {
+    quads: Vec<Quad>,
+}
+
+impl Portals {
+    pub fn new(quads: Vec<Quad>) -> Portals {
+        Portals { quads }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quads.is_empty()
+    }
+
+    /// Sample a direction from `p` towards `background`, through one of the portals if there are any
+    pub fn sample(
+        &self,
+        background: &BackgroundType,
+        p: &Vec3,
+        time: f32,
+        rv: Vec2,
+    ) -> Option<LightSample> {
+        if self.is_empty() {
+            return background.sample(rv);
+        }
+        // the random variable choosing the portal is reused to sample it
+        let scaled = rv.x * self.quads.len() as f32;
+        let index = (scaled as usize).min(self.quads.len() - 1);
+        let rv = Vec2::new((scaled - index as f32).min(0.999_999), rv.y);
+
+        let erec = self.quads[index].sample(p, time, rv)?;
+        if !(erec.pdf > 0.0 && erec.pdf.is_finite()) {
+            return None;
+        }
+        let wi = normalize(&erec.wi);
+        Some(LightSample {
+            wi,
+            distance: f32::INFINITY,
+            radiance: background.radiance(&wi),
+            pdf: self.pdf(background, p, time, &wi),
+            is_delta: false,
+        })
+    }
+
+    /// Solid angle density of `sample` for the direction `dir` from `p`
+    pub fn pdf(&self, background: &BackgroundType, p: &Vec3, time: f32, dir: &Vec3) -> f32 {
+        if self.is_empty() {
+            return background.pdf(dir);
+        }
+        // overlapping portals could each have sampled the direction
+        let dir = dir / length(dir);
+        self.quads
+            .iter()
+            .map(|quad| quad.pdf(p, time, &dir))
+            .sum::<f32>()
+            / self.quads.len() as f32
+    }
+}
+
+/// Read the portals of a scene, quads described by their `size` and `transform`
+pub fn read_portals(v: &Value) -> Vec<Quad> {
+    v.as_array()
+        .expect("Portals should be in an array")
+        .iter()
+        // portals are never hit by rays, their material doesn't matter
+        .map(|portal| QuadBuilder::read(portal, LambertianBuilder::new().build()).build())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use serde_json::json;
+
+    use crate::core::sampling::stratified_grid;
+    use crate::lights::{read_portals, BackgroundType, Portals};
+    use crate::tests::sample_test::DirectionTest;
+
+    #[test]
+    fn samples_go_through_portals() {
+        // a window in the ceiling and another one in a wall, overlapping as seen from the origin
+        let portals = Portals::new(read_portals(&json!([
+            {"size": 2.0, "transform": {"o": [0, 0, 2]}},
+            {"size": [2, 4], "transform": {"o": [1.5, 0, 2], "x": [0, 1, 0], "y": [0, 0, 1], "z": [1, 0, 0]}}
+        ])));
+        let background = BackgroundType::from(Vec3::new(1.0, 0.5, 0.25));
+        let o = Vec3::new(0.2, -0.1, 0.0);
+
+        for rv in stratified_grid(20) {
+            let sample = portals.sample(&background, &o, 0.0, rv).unwrap();
+            assert!(sample.distance.is_infinite());
+            assert_eq!(sample.radiance, Vec3::new(1.0, 0.5, 0.25));
+            assert!(sample.pdf > 0.0);
+        }
+
+        let (test, mut parameters) = DirectionTest::new(
+            &json!({"name": "portals"}),
+            |rv, _| {
+                let sample = portals.sample(&background, &o, 0.0, rv)?;
+                Some((sample.wi, sample.pdf))
+            },
+            |dir| portals.pdf(&background, &o, 0.0, dir),
+        );
+        parameters.run(&test, 1.0, 1e-2);
+
+        // directions missing both portals are never sampled
+        assert_eq!(portals.pdf(&background, &o, 0.0, &-Vec3::z()), 0.0);
+    }
+}
@@ -0,0 +1,501 @@
+use nalgebra_glm::{cross, dot, normalize, Vec2, Vec3};
+use serde_json::Value;
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use crate::core::distribution::Distribution2d;
+use crate::core::onb::Onb;
+use crate::core::sampling::{sample_sphere_cap, sample_sphere_cap_pdf};
+use crate::core::utils::{
+    deg2rad, luminance, read, read_or, read_v_or_f, spherical_uv_to_direction,
+};
+use crate::lights::environment_map::{lat_long_distribution, lat_long_pdf};
+use crate::lights::{Background, LightSample};
+
+/// Radiance units per kcd/m^2, so that a white surface lit by a high sun is about 1
+const RADIANCE_SCALE: f32 = 0.025;
+/// Illuminance of the sun at the top of the atmosphere, in klx
+const SUN_ILLUMINANCE: f32 = 128.0;
+/// Resolution of the table used to sample the sky and the ground
+const TABLE_WIDTH: usize = 128;
+const TABLE_HEIGHT: usize = 64;
+
+/// Daylight from the Preetham sky model and a sun disk, above a diffuse ground.
+///
+/// See "A Practical Analytic Model for Daylight", Preetham et al. 1999. The sun is attenuated through the
+/// atmosphere with the Rayleigh and aerosol optical depths of red, green and blue wavelengths, and lights the ground
+/// along with the sky.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Sky {
+    /// Frame with the zenith along z
+    frame: Onb,
+    sun_direction: Vec3,
+    cos_sun_radius: f32,
+    /// Zero when the sun is below the horizon
+    sun_radiance: Vec3,
+    /// Luminance and chromaticity of the zenith, in the xyY color space
+    zenith: [f32; 3],
+    /// Coefficients of the Perez distribution for Y, x and y
+    perez: [[f32; 5]; 3],
+    /// Cosine of the sun zenith angle, clamped to the horizon
+    cos_sun_zenith: f32,
+    ground_radiance: Vec3,
+    intensity: f32,
+    /// Probability of sampling the sun disk instead of the sky and ground table
+    sun_probability: f32,
+    distribution: Distribution2d,
+}
+
+impl Sky {
+    pub fn new(v: &Value) -> Sky {
+        let up: Vec3 = normalize(&read_or(v, "up", Vec3::new(0.0, 1.0, 0.0)));
+        let sun_direction = if v.get("latitude").is_some() {
+            // sun position from a place and a date
+            let north: Vec3 = read_or(v, "north", Vec3::new(0.0, 0.0, -1.0));
+            let north = normalize(&(north - dot(&north, &up) * up));
+            let west = cross(&up, &north);
+            let (theta, phi) = sun_position(
+                read(v, "latitude"),
+                read_or(v, "longitude", 0.0),
+                read_or(v, "timezone", 0.0),
+                read_or(v, "day", 80.0),
+                read(v, "time"),
+            );
+            // the azimuth goes from the south towards the west
+            f32::cos(theta) * up + f32::sin(theta) * (f32::sin(phi) * west - f32::cos(phi) * north)
+        } else {
+            read(v, "sun_direction")
+        };
+        let ground_albedo = if v.get("ground_albedo").is_some() {
+            read_v_or_f(v, "ground_albedo")
+        } else {
+            Vec3::new(0.3, 0.3, 0.3)
+        };
+
+        SkyBuilder::new()
+            .up(up)
+            .sun_direction(sun_direction)
+            .sun_radius(read_or(v, "sun_radius", 0.2665))
+            .turbidity(read_or(v, "turbidity", 3.0))
+            .ground_albedo(ground_albedo)
+            .intensity(read_or(v, "intensity", 1.0))
+            .build()
+    }
+
+    /// Radiance of the sky or the ground along the direction `local` of the zenith frame, without the sun disk
+    fn sky_radiance(&self, local: &Vec3) -> Vec3 {
+        if local.z <= 0.0 {
+            return self.ground_radiance;
+        }
+        let cos_theta = local.z.max(1e-3);
+        let cos_gamma = dot(&self.frame.local(local), &self.sun_direction).clamp(-1.0, 1.0);
+        let [luminance, x, y] = [0, 1, 2].map(|c| {
+            self.zenith[c] * perez(&self.perez[c], cos_theta, cos_gamma)
+                / perez(&self.perez[c], 1.0, self.cos_sun_zenith)
+        });
+        xyy_to_rgb(x, y, luminance) * RADIANCE_SCALE
+    }
+
+    fn in_sun_disk(&self, dir: &Vec3) -> bool {
+        dot(dir, &self.sun_direction) >= self.cos_sun_radius
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SkyBuilder {
+    up: Vec3,
+    sun_direction: Vec3,
+    sun_radius: f32,
+    turbidity: f32,
+    ground_albedo: Vec3,
+    intensity: f32,
+}
+
+impl SkyBuilder {
+    pub fn new() -> SkyBuilder {
+        SkyBuilder {
+            up: Vec3::new(0.0, 1.0, 0.0),
+            sun_direction: Vec3::new(0.0, 1.0, 1.0),
+            sun_radius: 0.2665,
+            turbidity: 3.0,
+            ground_albedo: Vec3::new(0.3, 0.3, 0.3),
+            intensity: 1.0,
+        }
+    }
+
+    /// Direction of the zenith
+    pub fn up(mut self, up: Vec3) -> SkyBuilder {
+        self.up = up;
+        self
+    }
+
+    /// Direction towards the center of the sun
+    pub fn sun_direction(mut self, sun_direction: Vec3) -> SkyBuilder {
+        self.sun_direction = sun_direction;
+        self
+    }
+
+    /// Angular radius of the sun disk, in degrees
+    pub fn sun_radius(mut self, sun_radius: f32) -> SkyBuilder {
+        self.sun_radius = sun_radius;
+        self
+    }
+
+    /// Haziness of the atmosphere, from 2 for a clear sky to about 10 for a hazy one
+    pub fn turbidity(mut self, turbidity: f32) -> SkyBuilder {
+        self.turbidity = turbidity;
+        self
+    }
+
+    pub fn ground_albedo(mut self, ground_albedo: Vec3) -> SkyBuilder {
+        self.ground_albedo = ground_albedo;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> SkyBuilder {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn build(self) -> Sky {
+        let t = self.turbidity;
+        let frame = Onb::build_from_w(&self.up);
+        let sun_direction = normalize(&self.sun_direction);
+        let cos_sun = frame.to_local(&sun_direction).z;
+        // the sky model only holds for a sun above the horizon
+        let theta_sun = f32::acos(cos_sun.clamp(0.0, 1.0));
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+        let zenith_luminance =
+            ((4.0453 * t - 4.9710) * f32::tan(chi) - 0.2155 * t + 2.4192).max(0.0);
+        let zenith_x = zenith_chromaticity(
+            &[
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+            t,
+            theta_sun,
+        );
+        let zenith_y = zenith_chromaticity(
+            &[
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+            t,
+            theta_sun,
+        );
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // the illuminance of the sun is kept when changing its size
+        let cos_sun_radius = f32::cos(deg2rad(self.sun_radius));
+        let sun_solid_angle = TAU * (1.0 - cos_sun_radius);
+        let sun_radiance = if cos_sun > 0.0 {
+            sun_transmittance(t, theta_sun) * SUN_ILLUMINANCE / sun_solid_angle * RADIANCE_SCALE
+        } else {
+            Vec3::zeros()
+        };
+
+        let mut sky = Sky {
+            frame,
+            sun_direction,
+            cos_sun_radius,
+            sun_radiance,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+            cos_sun_zenith: f32::cos(theta_sun),
+            ground_radiance: Vec3::zeros(),
+            intensity: self.intensity,
+            sun_probability: 0.0,
+            distribution: Distribution2d::new(&[1.0], 1, 1),
+        };
+
+        // tabulate the sky over the upper rows, and light the ground with it and the sun
+        let directions: Vec<Vec3> = (0..TABLE_HEIGHT)
+            .flat_map(|y| (0..TABLE_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                spherical_uv_to_direction(Vec2::new(
+                    (x as f32 + 0.5) / TABLE_WIDTH as f32,
+                    1.0 - (y as f32 + 0.5) / TABLE_HEIGHT as f32,
+                ))
+            })
+            .collect();
+        let cell_area = TAU / TABLE_WIDTH as f32 * PI / TABLE_HEIGHT as f32;
+        let sky_irradiance: Vec3 = directions
+            .iter()
+            .filter(|local| local.z > 0.0)
+            .map(|local| sky.sky_radiance(local) * local.z * f32::sqrt(1.0 - local.z * local.z))
+            .sum::<Vec3>()
+            * cell_area;
+        let sun_irradiance = sun_radiance * sun_solid_angle * cos_sun.max(0.0);
+        sky.ground_radiance = self
+            .ground_albedo
+            .component_mul(&(sky_irradiance + sun_irradiance))
+            / PI;
+
+        let weights: Vec<f32> = directions
+            .iter()
+            .map(|local| luminance(&sky.sky_radiance(local)))
+            .collect();
+        sky.distribution = lat_long_distribution(&weights, TABLE_WIDTH, TABLE_HEIGHT);
+
+        // split the samples between the sun and the rest by their power
+        let sky_power = sky.distribution.func_sum() * cell_area;
+        let sun_power = luminance(&sun_radiance) * sun_solid_angle;
+        if sun_power > 0.0 {
+            sky.sun_probability = sun_power / (sun_power + sky_power);
+        }
+        sky
+    }
+}
+
+impl Default for SkyBuilder {
+    fn default() -> SkyBuilder {
+        SkyBuilder::new()
+    }
+}
+
+impl Background for Sky {
+    fn radiance(&self, dir: &Vec3) -> Vec3 {
+        let dir = normalize(dir);
+        let mut radiance = self.sky_radiance(&self.frame.to_local(&dir));
+        if self.in_sun_disk(&dir) {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn sample(&self, rv: Vec2) -> Option<LightSample> {
+        let wi = if rv.x < self.sun_probability {
+            let rv = Vec2::new(rv.x / self.sun_probability, rv.y);
+            Onb::build_from_w(&self.sun_direction)
+                .local(&sample_sphere_cap(rv, self.cos_sun_radius))
+        } else {
+            let rv = Vec2::new(
+                (rv.x - self.sun_probability) / (1.0 - self.sun_probability),
+                rv.y,
+            );
+            let (p, _) = self.distribution.sample(rv);
+            self.frame
+                .local(&spherical_uv_to_direction(Vec2::new(p.x, 1.0 - p.y)))
+        };
+        let wi = normalize(&wi);
+
+        let pdf = self.pdf(&wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance: f32::INFINITY,
+            radiance: self.radiance(&wi),
+            pdf,
+            is_delta: false,
+        })
+    }
+
+    fn pdf(&self, dir: &Vec3) -> f32 {
+        let dir = normalize(dir);
+        let mut pdf = (1.0 - self.sun_probability)
+            * lat_long_pdf(&self.distribution, &self.frame.to_local(&dir));
+        if self.sun_probability > 0.0 && self.in_sun_disk(&dir) {
+            pdf += self.sun_probability * sample_sphere_cap_pdf(1.0, self.cos_sun_radius);
+        }
+        pdf
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.intensity > 0.0
+    }
+}
+
+/// Perez distribution of the sky, for a view direction at the zenith angle theta and the angle gamma to the sun
+fn perez(coefficients: &[f32; 5], cos_theta: f32, cos_gamma: f32) -> f32 {
+    let [a, b, c, d, e] = *coefficients;
+    let gamma = f32::acos(cos_gamma);
+    (1.0 + a * f32::exp(b / cos_theta))
+        * (1.0 + c * f32::exp(d * gamma) + e * cos_gamma * cos_gamma)
+}
+
+/// Chromaticity of the zenith, from the polynomial `coefficients` of the turbidity and the sun zenith angle
+fn zenith_chromaticity(coefficients: &[[f32; 4]; 3], turbidity: f32, theta_sun: f32) -> f32 {
+    let turbidities = [turbidity * turbidity, turbidity, 1.0];
+    let thetas = [theta_sun.powi(3), theta_sun * theta_sun, theta_sun, 1.0];
+    (0..3)
+        .map(|i| turbidities[i] * (0..4).map(|j| coefficients[i][j] * thetas[j]).sum::<f32>())
+        .sum()
+}
+
+/// Fraction of the sunlight crossing the atmosphere, for red, green and blue wavelengths
+fn sun_transmittance(turbidity: f32, theta_sun: f32) -> Vec3 {
+    // wavelengths in micrometers
+    const WAVELENGTHS: [f32; 3] = [0.68, 0.55, 0.44];
+    // relative optical mass of the atmosphere, from Kasten and Young
+    let air_mass = 1.0
+        / (f32::cos(theta_sun) + 0.50572 * f32::powf(96.07995 - theta_sun.to_degrees(), -1.6364));
+    // Angstrom turbidity coefficient
+    let beta = 0.04608 * turbidity - 0.04586;
+    Vec3::from_fn(|c, _| {
+        let rayleigh = 0.008735 * WAVELENGTHS[c].powf(-4.08);
+        let aerosol = beta * WAVELENGTHS[c].powf(-1.3);
+        f32::exp(-air_mass * (rayleigh + aerosol))
+    })
+}
+
+/// Linear sRGB color of a xyY color
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::zeros();
+    }
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vec3::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+    .map(|c| c.max(0.0))
+}
+
+/// Position of the sun in the sky, as its zenith angle and its azimuth from the south towards the west.
+///
+/// `latitude` and `longitude` are in degrees, positive towards the north and the east, `timezone` is the offset of
+/// the local standard time from UTC in hours, `day` is the day of the year starting at 1, and `time` is the local
+/// standard time in hours. See the appendix of Preetham et al.
+pub fn sun_position(
+    latitude: f32,
+    longitude: f32,
+    timezone: f32,
+    day: f32,
+    time: f32,
+) -> (f32, f32) {
+    let latitude = deg2rad(latitude);
+    // the standard meridian of the time zone
+    let meridian = deg2rad(15.0 * timezone);
+    let solar_time = time + 0.170 * f32::sin(4.0 * PI * (day - 80.0) / 373.0)
+        - 0.129 * f32::sin(TAU * (day - 8.0) / 355.0)
+        + 12.0 * (deg2rad(longitude) - meridian) / PI;
+    let declination = 0.4093 * f32::sin(TAU * (day - 81.0) / 368.0);
+
+    let hour_angle = PI * solar_time / 12.0;
+    let theta = FRAC_PI_2
+        - f32::asin(
+            f32::sin(latitude) * f32::sin(declination)
+                - f32::cos(latitude) * f32::cos(declination) * f32::cos(hour_angle),
+        );
+    let phi = f32::atan2(
+        -f32::cos(declination) * f32::sin(hour_angle),
+        f32::cos(latitude) * f32::sin(declination)
+            - f32::sin(latitude) * f32::cos(declination) * f32::cos(hour_angle),
+    );
+    (theta, phi)
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use serde_json::json;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    use crate::lights::sky::sun_position;
+    use crate::lights::{create_background, Background, BackgroundType, SkyBuilder};
+    use crate::tests::sample_test::DirectionTest;
+
+    #[test]
+    fn sun_positions() {
+        // noon at the equinox, on the equator then at 45 degrees north
+        let (theta, _) = sun_position(0.0, 0.0, 0.0, 80.0, 12.0);
+        assert!(theta < 0.05, "zenith angle {theta}");
+        let (theta, phi) = sun_position(45.0, 0.0, 0.0, 80.0, 12.0);
+        approx::assert_abs_diff_eq!(theta, FRAC_PI_4, epsilon = 0.05);
+        approx::assert_abs_diff_eq!(phi, 0.0, epsilon = 0.05);
+
+        // the sun rises in the east, and is below the horizon at midnight
+        let (theta, phi) = sun_position(45.0, 0.0, 0.0, 80.0, 7.0);
+        assert!(theta < FRAC_PI_2 && phi < -FRAC_PI_4, "({theta}, {phi})");
+        let (theta, _) = sun_position(45.0, 0.0, 0.0, 80.0, 0.0);
+        assert!(theta > FRAC_PI_2);
+    }
+
+    #[test]
+    fn daylight_colors() {
+        let background = create_background(&json!({"background": {
+            "type": "sky", "sun_direction": [0, 1, 1], "ground_albedo": [0.5, 0.3, 0.1]
+        }}));
+        let sky = SkyBuilder::new()
+            .sun_direction(Vec3::new(0.0, 1.0, 1.0))
+            .ground_albedo(Vec3::new(0.5, 0.3, 0.1))
+            .build();
+        assert_eq!(background, BackgroundType::Sky(sky.clone()));
+
+        // the sky is blue at the zenith, brighter around the sun, and the sun much brighter
+        let zenith = sky.radiance(&Vec3::y());
+        assert!(zenith.z > zenith.x, "zenith {zenith:?}");
+        let near_sun = sky.radiance(&Vec3::new(0.1, 1.0, 1.0));
+        let away_from_sun = sky.radiance(&Vec3::new(0.1, 1.0, -1.0));
+        assert!(near_sun.y > away_from_sun.y);
+        assert!(sky.radiance(&Vec3::new(0.0, 1.0, 1.0)).y > 1000.0 * near_sun.y);
+
+        // the ground is uniformly lit, with its albedo
+        let ground = sky.radiance(&-Vec3::y());
+        assert_eq!(ground, sky.radiance(&Vec3::new(0.3, -1.0, 0.5)));
+        let white = SkyBuilder::new()
+            .sun_direction(Vec3::new(0.0, 1.0, 1.0))
+            .ground_albedo(Vec3::new(1.0, 1.0, 1.0))
+            .build();
+        let white_ground = white.radiance(&-Vec3::y());
+        approx::assert_relative_eq!(
+            ground,
+            white_ground.component_mul(&Vec3::new(0.5, 0.3, 0.1))
+        );
+
+        // a sun below the horizon gives no disk
+        let night = SkyBuilder::new()
+            .sun_direction(Vec3::new(0.0, -1.0, 1.0))
+            .build();
+        assert!(night.radiance(&Vec3::new(0.0, -1.0, 1.0)).y < 1.0);
+    }
+
+    #[test]
+    fn samples_match_pdf() {
+        // a large sun so that the integral resolves it
+        let sky = SkyBuilder::new()
+            .sun_direction(Vec3::new(0.3, 0.5, 1.0))
+            .sun_radius(5.0)
+            .turbidity(5.0)
+            .build();
+
+        let (test, mut parameters) = DirectionTest::new(
+            &json!({"name": "sky"}),
+            |rv, _| {
+                let sample = sky.sample(rv)?;
+                assert_eq!(sample.radiance, sky.radiance(&sample.wi));
+                Some((sample.wi, sample.pdf))
+            },
+            |dir| sky.pdf(dir),
+        );
+        parameters.run(&test, 1.0, 2e-2);
+    }
+}
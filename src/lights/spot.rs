@@ -0,0 +1,216 @@
+use nalgebra_glm::{length2, normalize, Vec2, Vec3};
+use serde_json::Value;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::core::aabb::Aabb;
+use crate::core::assets::load_ies;
+use crate::core::ies::IesProfile;
+use crate::core::transform::Transform;
+use crate::core::utils::{deg2rad, luminance, read, read_or, read_v_or_f};
+use crate::lights::{Light, LightBounds, LightSample};
+
+/// Point light shining in a cone along the -z axis of its `transform`, like a camera with the "from" and "at" keys.
+///
+/// The intensity is full inside the beam, and fades smoothly to zero at the cutoff angle. An IES profile further
+/// modulates it inside the cone.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpotLight {
+    transform: Transform,
+    /// Radiant intensity inside the beam
+    intensity: Vec3,
+    cos_beam_width: f32,
+    cos_cutoff_angle: f32,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl SpotLight {
+    pub fn new(v: &Value) -> SpotLight {
+        let cutoff_angle = read_or(v, "cutoff_angle", 30.0);
+        let mut builder = SpotLightBuilder::new()
+            .transform(Transform::read(v))
+            .cutoff_angle(cutoff_angle)
+            .beam_width(read_or(v, "beam_width", 0.75 * cutoff_angle));
+        if v.get("intensity").is_some() {
+            builder = builder.intensity(read_v_or_f(v, "intensity"));
+        }
+        if v.get("ies").is_some() {
+            let filename: String = read(v, "ies");
+            builder = builder.profile(load_ies(&filename));
+        }
+        builder.build()
+    }
+
+    /// Fraction of the intensity emitted towards the direction `local` of the light frame
+    fn falloff(&self, local: &Vec3) -> f32 {
+        let cos_theta = -local.z;
+        if cos_theta <= self.cos_cutoff_angle {
+            return 0.0;
+        }
+        let profile = self
+            .profile
+            .as_ref()
+            .map_or(1.0, |profile| profile.value(local));
+        if cos_theta >= self.cos_beam_width {
+            return profile;
+        }
+        let t = (cos_theta - self.cos_cutoff_angle) / (self.cos_beam_width - self.cos_cutoff_angle);
+        profile * t * t * (3.0 - 2.0 * t)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SpotLightBuilder {
+    transform: Transform,
+    intensity: Vec3,
+    beam_width: f32,
+    cutoff_angle: f32,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl SpotLightBuilder {
+    pub fn new() -> SpotLightBuilder {
+        SpotLightBuilder {
+            transform: Transform::default(),
+            intensity: Vec3::new(1.0, 1.0, 1.0),
+            beam_width: 22.5,
+            cutoff_angle: 30.0,
+            profile: None,
+        }
+    }
+
+    /// Placement of the light, which sits at the origin of its local frame and points along -z
+    pub fn transform(mut self, transform: Transform) -> SpotLightBuilder {
+        self.transform = transform;
+        self
+    }
+
+    pub fn intensity(mut self, intensity: Vec3) -> SpotLightBuilder {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Angle from the axis of the light where the falloff starts, in degrees
+    pub fn beam_width(mut self, beam_width: f32) -> SpotLightBuilder {
+        self.beam_width = beam_width;
+        self
+    }
+
+    /// Angle from the axis of the light past which nothing is emitted, in degrees
+    pub fn cutoff_angle(mut self, cutoff_angle: f32) -> SpotLightBuilder {
+        self.cutoff_angle = cutoff_angle;
+        self
+    }
+
+    /// Photometric profile scaling `intensity`, with its nadir along the local -z axis
+    pub fn profile(mut self, profile: impl Into<Arc<IesProfile>>) -> SpotLightBuilder {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    pub fn build(self) -> SpotLight {
+        let cos_cutoff_angle = f32::cos(deg2rad(self.cutoff_angle));
+        SpotLight {
+            transform: self.transform,
+            intensity: self.intensity,
+            cos_beam_width: f32::cos(deg2rad(self.beam_width.min(self.cutoff_angle)))
+                .max(cos_cutoff_angle),
+            cos_cutoff_angle,
+            profile: self.profile,
+        }
+    }
+}
+
+impl Default for SpotLightBuilder {
+    fn default() -> SpotLightBuilder {
+        SpotLightBuilder::new()
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: &Vec3, _rv: Vec2) -> Option<LightSample> {
+        let to_light = self.transform.point(&Vec3::zeros()) - p;
+        let distance2 = length2(&to_light);
+        if distance2 <= 0.0 {
+            return None;
+        }
+        let wi = normalize(&to_light);
+        let local = normalize(&self.transform.inverse().vector(&-wi));
+        let falloff = self.falloff(&local);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance: distance2.sqrt(),
+            radiance: self.intensity * falloff / distance2,
+            pdf: 1.0,
+            is_delta: true,
+        })
+    }
+
+    fn radiance(&self, _dir: &Vec3) -> Vec3 {
+        Vec3::zeros()
+    }
+
+    fn pdf(&self, _dir: &Vec3) -> f32 {
+        0.0
+    }
+
+    fn power(&self, _scene_radius: f32) -> f32 {
+        // the falloff is about as bright as a beam through the middle of it
+        let solid_angle = 2.0 * PI * (1.0 - 0.5 * (self.cos_beam_width + self.cos_cutoff_angle));
+        solid_angle * luminance(&self.intensity)
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        let p = self.transform.point(&Vec3::zeros());
+        let theta_falloff = self.cos_cutoff_angle.acos() - self.cos_beam_width.acos();
+        Some(LightBounds {
+            bounds: Aabb { min: p, max: p },
+            w: normalize(&self.transform.vector(&-Vec3::z())),
+            // as bright as a point light inside the beam, the cone takes care of the rest
+            phi: 4.0 * PI * luminance(&self.intensity),
+            cos_theta_o: self.cos_beam_width,
+            cos_theta_e: theta_falloff.cos(),
+            two_sided: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+
+    use crate::lights::{create_light, Light};
+
+    #[test]
+    fn cone_falloff() {
+        // pointing down from (0, 2, 0)
+        let light = create_light(&json!({
+            "type": "spot",
+            "transform": {"from": [0.0, 2.0, 0.0], "at": [0.0, 0.0, 0.0], "up": [0.0, 0.0, 1.0]},
+            "intensity": 4.0,
+            "beam_width": 20.0,
+            "cutoff_angle": 40.0
+        }));
+        let rv = Vec2::new(0.5, 0.5);
+
+        let below = light.sample(&Vec3::zeros(), rv).unwrap();
+        assert!(below.is_delta);
+        approx::assert_abs_diff_eq!(below.wi, Vec3::y());
+        approx::assert_abs_diff_eq!(below.radiance, Vec3::new(1.0, 1.0, 1.0));
+
+        // 30 degrees off the axis is half way through the falloff
+        let side = 2.0 * f32::tan(30f32.to_radians());
+        let between = light.sample(&Vec3::new(side, 0.0, 0.0), rv).unwrap();
+        let distance2 = 4.0 + side * side;
+        let falloff = between.radiance.x * distance2 / 4.0;
+        assert!(falloff > 0.1 && falloff < 0.9, "falloff {falloff}");
+
+        // outside of the cone and behind the light
+        assert!(light.sample(&Vec3::new(3.0, 0.0, 0.0), rv).is_none());
+        assert!(light.sample(&Vec3::new(0.0, 3.0, 0.0), rv).is_none());
+    }
+}
@@ -1,25 +1,15 @@
-mod core;
-mod integrators;
-mod materials;
-mod samplers;
-mod surfaces;
-mod textures;
-
-#[cfg(test)]
-mod tests;
-
 mod example_scenes;
 
-use crate::core::scene::Scene;
 use crate::example_scenes::create_example_scene;
+use rustrt::{INTERSECTION_TEST, RAYS};
+use rustrt::{frame_filename, Animation};
+use rustrt::{read_scene_from_file, CancellationToken, ProgressBarProgress, Scene};
+use rustrt::{TileOrder, TileScheduler};
 
-use clap::Parser;
-use serde_json::Value;
+use clap::{Parser, ValueEnum};
 
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
 
 #[derive(Parser)]
 struct Cli {
@@ -34,22 +24,40 @@ struct Cli {
     /// Specify the output image filename (extension must be one accepted by -f)
     #[arg(short, long, default_value_t=String::from("test.png"))]
     outfile: String,
-}
 
-fn read_scene_from_file<P: AsRef<Path>>(path: P) -> Result<Value, Box<dyn Error>> {
-    // Open the file in read-only mode with buffer.
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    /// Size in pixels of the square tiles the image is split into
+    #[arg(long, default_value_t = 32)]
+    tile_size: usize,
 
-    // Read the JSON contents of the file
-    let j = serde_json::from_reader(reader)?;
+    /// Order in which the tiles are rendered
+    #[arg(long, value_enum, default_value_t = TileOrderArg::Spiral)]
+    tile_order: TileOrderArg,
 
-    Ok(j)
+    /// Render every frame of the "animation" section of the scene, to numbered images (outfile_0001.png, ...)
+    #[arg(long)]
+    animate: bool,
 }
 
-use crate::core::utils::INTERSECTION_TEST;
-use crate::core::utils::RAYS;
-use std::sync::atomic::Ordering;
+/// Command line names of the library's `TileOrder`
+#[derive(Clone, Copy, ValueEnum)]
+enum TileOrderArg {
+    /// Row by row, starting from the top-left corner
+    Scanline,
+    /// Outward from the center of the image
+    Spiral,
+    /// Along a Hilbert curve, which keeps consecutive tiles spatially coherent
+    Hilbert,
+}
+
+impl From<TileOrderArg> for TileOrder {
+    fn from(order: TileOrderArg) -> TileOrder {
+        match order {
+            TileOrderArg::Scanline => TileOrder::Scanline,
+            TileOrderArg::Spiral => TileOrder::Spiral,
+            TileOrderArg::Hilbert => TileOrder::Hilbert,
+        }
+    }
+}
 
 fn main() {
     let args = Cli::parse();
@@ -57,9 +65,29 @@ fn main() {
     println!("scene : {:?}", args.scene);
 
     let path = PathBuf::from(args.scene.clone());
-    let scene_json = if path.exists() {
+    let scheduler = TileScheduler::new(args.tile_size, args.tile_order.into());
+
+    if args.animate {
+        let scene_json = read_scene_from_file(path).unwrap();
+        let animation = Animation::read(&scene_json).expect("The scene has no animation");
+        for (frame, scene) in animation.scenes(&scene_json) {
+            println!("Frame {frame}/{}", animation.frame_end);
+            let image = scene.raytrace_tiles(
+                &scheduler,
+                &ProgressBarProgress::new(),
+                &CancellationToken::new(),
+            );
+            let outfile = frame_filename(&PathBuf::from(&args.outfile), frame);
+            println!("Writing rendered image to file {outfile:?}");
+            image.save(&outfile);
+        }
+        println!("Done");
+        return;
+    }
+
+    let scene = if path.exists() {
         println!("scene existing file");
-        read_scene_from_file(path).unwrap()
+        Scene::new(&read_scene_from_file(path).unwrap())
     } else if args.scene.parse::<i32>().is_ok() {
         let index = args.scene.parse::<i32>().unwrap();
         create_example_scene(index)
@@ -67,8 +95,11 @@ fn main() {
         panic!("I dont know how to parse {:?}", args.scene);
     };
 
-    let scene = Scene::new(&scene_json);
-    let image = scene.raytrace();
+    let image = scene.raytrace_tiles(
+        &scheduler,
+        &ProgressBarProgress::new(),
+        &CancellationToken::new(),
+    );
 
     println!("Number of intersection tests: {INTERSECTION_TEST:?}");
     println!("Number of rays traced: {RAYS:?}");
@@ -32,12 +32,12 @@ impl Material for BlinnPhong {
     }
 
     fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
-        let uvw = Onb::build_from_w(&hit.gn);
+        let uvw = Onb::build_from_w(&hit.sn);
         let normal = uvw.local(&sample_hemisphere_cosine_power(self.exponent, rv));
 
         let mirror_dir = normalize(&reflect(wi, &normal));
 
-        if dot(&mirror_dir, &hit.gn) >= 0.0 {
+        if dot(&mirror_dir, &hit.sn) >= 0.0 {
             let srec = ScatterRecord {
                 attenuation: self.albedo.value(hit)?,
                 wo: mirror_dir,
@@ -50,11 +50,11 @@ impl Material for BlinnPhong {
 
     fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
         let random_normal = normalize(&(-normalize(wi) + normalize(scattered)));
-        let cosine = f32::max(dot(&random_normal, &hit.gn), 0.0);
+        let cosine = f32::max(dot(&random_normal, &hit.sn), 0.0);
         let normal_pdf = sample_hemisphere_cosine_power_pdf(self.exponent, cosine);
         // account for a warping of the PDF by the reflection operation
         let final_pdf = normal_pdf / (4.0 * dot(&(-wi), &random_normal));
-        if dot(scattered, &hit.gn) >= 0.0 {
+        if dot(scattered, &hit.sn) >= 0.0 {
             final_pdf
         } else {
             0.0
@@ -64,9 +64,48 @@ impl Material for BlinnPhong {
 
 impl BlinnPhong {
     pub fn new(v: &Value) -> BlinnPhong {
-        let albedo = create_texture(v, "albedo");
-        let exponent = read_or(v, "exponent", 1.0);
-        BlinnPhong { albedo, exponent }
+        BlinnPhongBuilder::new()
+            .albedo(create_texture(v, "albedo"))
+            .exponent(read_or(v, "exponent", 1.0))
+            .build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlinnPhongBuilder {
+    albedo: TextureType,
+    exponent: f32,
+}
+
+impl BlinnPhongBuilder {
+    pub fn new() -> BlinnPhongBuilder {
+        BlinnPhongBuilder {
+            albedo: TextureType::from(0.5),
+            exponent: 1.0,
+        }
+    }
+
+    pub fn albedo(mut self, albedo: impl Into<TextureType>) -> BlinnPhongBuilder {
+        self.albedo = albedo.into();
+        self
+    }
+
+    pub fn exponent(mut self, exponent: f32) -> BlinnPhongBuilder {
+        self.exponent = exponent;
+        self
+    }
+
+    pub fn build(self) -> BlinnPhong {
+        BlinnPhong {
+            albedo: self.albedo,
+            exponent: self.exponent,
+        }
+    }
+}
+
+impl Default for BlinnPhongBuilder {
+    fn default() -> BlinnPhongBuilder {
+        BlinnPhongBuilder::new()
     }
 }
 
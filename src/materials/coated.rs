@@ -0,0 +1,482 @@
+use nalgebra_glm::{dot, exp, normalize, Vec2, Vec3};
+use rand::Rng;
+use serde_json::{from_value, Value};
+use std::sync::Arc;
+
+use crate::core::microfacet::{fresnel_dielectric, Ggx};
+use crate::core::onb::Onb;
+use crate::core::ray::Ray;
+use crate::core::utils::{luminance, read_or, reflect};
+use crate::materials::{Material, MaterialFactory, MaterialType};
+use crate::surfaces::{HitInfo, ScatterRecord};
+use crate::textures::{create_linear_texture, Texture, TextureType};
+
+/// Dielectric coating over any other material, like varnished wood or glossy plastic.
+///
+/// The coat reflects with a GGX microfacet lobe. The rest of the light refracts through a smooth
+/// interface, is attenuated by the absorption of the coat, scatters on the base and refracts back out.
+/// Light reflected back down by the coat bounces on the base again, which saturates the colors.
+/// The base should not be specular for `eval` and `pdf` to be meaningful.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Coated {
+    base: Arc<MaterialType>,
+    ior: TextureType,
+    roughness: TextureType,
+    absorption: TextureType,
+    thickness: f32,
+}
+
+/// Quantities shared by `eval`, `sample` and `pdf`
+struct CoatFrame {
+    uvw: Onb,
+    /// Direction towards the viewer, always in the upper hemisphere
+    wo: Vec3,
+    /// Same direction once refracted inside the coat
+    wo_inside: Vec3,
+    /// Hitpoint with the normals facing the viewer, handed to the base
+    base_hit: HitInfo,
+    eta: f32,
+    ggx: Ggx,
+    absorption: Vec3,
+    /// Fresnel reflectance of the coat seen from the viewer
+    fresnel: f32,
+    /// Scale for the light bouncing between the base and the inside of the coat
+    interreflection: Vec3,
+    /// Probability of sampling the coat reflection instead of the base
+    coat_probability: f32,
+}
+
+impl Coated {
+    pub fn new(v: &Value, mf: &MaterialFactory) -> Coated {
+        let base_v = v
+            .get("base")
+            .expect("coated material should have a base")
+            .clone();
+        let base = if base_v.is_string() {
+            let base_name: String = from_value(base_v).unwrap();
+            (*mf.materials
+                .get(&base_name)
+                .unwrap_or_else(|| panic!("unknown base material '{base_name}'")))
+            .clone()
+        } else {
+            mf.create_material(&base_v)
+        };
+
+        let m = v.as_object().unwrap();
+        let mut builder = CoatedBuilder::new(base)
+            .ior(create_linear_texture(v, "ior"))
+            .roughness(create_linear_texture(v, "roughness"))
+            .thickness(read_or(v, "thickness", 1.0));
+        if m.contains_key("absorption") {
+            builder = builder.absorption(create_linear_texture(v, "absorption"));
+        }
+        builder.build()
+    }
+
+    fn frame(&self, wi: &Vec3, hit: &HitInfo) -> Option<CoatFrame> {
+        let (gn, sn) = if dot(&hit.gn, wi) < 0.0 {
+            (hit.gn, hit.sn)
+        } else {
+            (-hit.gn, -hit.sn)
+        };
+        let uvw = Onb::build_from_w(&sn);
+        let wo = uvw.to_local(&normalize(&-wi));
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let eta = luminance(&self.ior.value(hit).unwrap());
+        let wo_inside = refract_into(&wo, eta);
+        let base_hit = HitInfo {
+            t: hit.t,
+            p: hit.p,
+            gn,
+            sn,
+            dpdu: hit.dpdu,
+            dpdv: hit.dpdv,
+            uv: hit.uv,
+            duvdx: hit.duvdx,
+            duvdy: hit.duvdy,
+            mat: Arc::clone(&hit.mat),
+            emitter: hit.emitter,
+        };
+
+        // hemispherical albedo of the base, estimated at normal incidence with a few fixed samples
+        let mut base_albedo = Vec3::zeros();
+        for rv in [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)] {
+            let rv = Vec2::new(rv.0, rv.1);
+            if let Some(srec) = self.base.sample(&-sn, &base_hit, rv) {
+                base_albedo += srec.attenuation / 4.0;
+            }
+        }
+        let internal = internal_diffuse_reflectance(eta);
+        let interreflection = Vec3::from_fn(|c, _| {
+            1.0 / f32::max(1.0 - internal * base_albedo[c].clamp(0.0, 1.0), 1e-3)
+        });
+
+        let fresnel = fresnel_dielectric(wo.z, eta);
+        let base_weight = (1.0 - fresnel) * luminance(&base_albedo).clamp(0.0, 1.0);
+        let coat_probability = if fresnel + base_weight > 0.0 {
+            fresnel / (fresnel + base_weight)
+        } else {
+            1.0
+        };
+
+        let roughness = luminance(&self.roughness.value(hit).unwrap());
+        Some(CoatFrame {
+            uvw,
+            wo,
+            wo_inside,
+            base_hit,
+            eta,
+            ggx: Ggx::from_roughness(roughness, 0.0),
+            absorption: self.absorption.value(hit).unwrap() * self.thickness,
+            fresnel,
+            interreflection,
+            coat_probability,
+        })
+    }
+
+    /// Reflection on the coat, times the cosine term
+    fn eval_coat(frame: &CoatFrame, wl: &Vec3) -> f32 {
+        let wo = &frame.wo;
+        let h = wo + wl;
+        if wl.z <= 0.0 || h.norm_squared() == 0.0 {
+            return 0.0;
+        }
+        let h = normalize(&h);
+        let fresnel = fresnel_dielectric(dot(wo, &h), frame.eta);
+        fresnel * frame.ggx.d(&h) * frame.ggx.g2(wo, wl) / (4.0 * wo.z)
+    }
+
+    fn pdf_coat(frame: &CoatFrame, wl: &Vec3) -> f32 {
+        let wo = &frame.wo;
+        let h = wo + wl;
+        if wl.z <= 0.0 || h.norm_squared() == 0.0 {
+            return 0.0;
+        }
+        let h = normalize(&h);
+        frame.ggx.d_visible(wo, &h) / (4.0 * dot(wo, &h))
+    }
+
+    /// Light going through the coat and scattered by the base, times the cosine term
+    fn eval_base(&self, frame: &CoatFrame, wl: &Vec3) -> Vec3 {
+        if wl.z <= 0.0 {
+            return Vec3::zeros();
+        }
+        let wl_inside = refract_into(wl, frame.eta);
+        let base = self.base.eval(
+            &-frame.uvw.local(&frame.wo_inside),
+            &frame.uvw.local(&wl_inside),
+            &frame.base_hit,
+        );
+        let transmittance = (1.0 - frame.fresnel) * (1.0 - fresnel_dielectric(wl.z, frame.eta));
+        let path_length = 1.0 / frame.wo_inside.z + 1.0 / wl_inside.z;
+        let absorption = exp(&(-frame.absorption * path_length));
+
+        // the base divided by its own cosine gives the BRDF, the solid angle shrinks by eta² inside
+        base.component_mul(&absorption)
+            .component_mul(&frame.interreflection)
+            * transmittance
+            * wl.z
+            / (wl_inside.z * frame.eta * frame.eta)
+    }
+
+    fn pdf_base(&self, frame: &CoatFrame, wl: &Vec3) -> f32 {
+        if wl.z <= 0.0 {
+            return 0.0;
+        }
+        let wl_inside = refract_into(wl, frame.eta);
+        let pdf = self.base.pdf(
+            &-frame.uvw.local(&frame.wo_inside),
+            &frame.uvw.local(&wl_inside),
+            &frame.base_hit,
+        );
+        // jacobian of the refraction out of the coat
+        pdf * wl.z / (wl_inside.z * frame.eta * frame.eta)
+    }
+}
+
+/// Refract a direction of the upper hemisphere through a smooth interface with relative index of
+/// refraction `eta`, and mirror it back up so that it faces away from the base
+fn refract_into(w: &Vec3, eta: f32) -> Vec3 {
+    let sin2_t = (1.0 - w.z * w.z) / (eta * eta);
+    let cos_t = f32::sqrt(f32::max(0.0, 1.0 - sin2_t));
+    normalize(&Vec3::new(w.x / eta, w.y / eta, cos_t))
+}
+
+/// Fraction of the light hitting the inside of the coat uniformly that gets reflected back down
+fn internal_diffuse_reflectance(eta: f32) -> f32 {
+    // polynomial fit by Egan and Hilgeman
+    -1.440 / (eta * eta) + 0.710 / eta + 0.668 + 0.0636 * eta
+}
+
+#[derive(Debug, Clone)]
+pub struct CoatedBuilder {
+    base: Arc<MaterialType>,
+    ior: TextureType,
+    roughness: TextureType,
+    absorption: TextureType,
+    thickness: f32,
+}
+
+impl CoatedBuilder {
+    pub fn new(base: impl Into<Arc<MaterialType>>) -> CoatedBuilder {
+        CoatedBuilder {
+            base: base.into(),
+            ior: TextureType::from(1.5),
+            roughness: TextureType::from(0.1),
+            absorption: TextureType::from(0.0),
+            thickness: 1.0,
+        }
+    }
+
+    /// Index of refraction of the coat
+    pub fn ior(mut self, ior: impl Into<TextureType>) -> CoatedBuilder {
+        self.ior = ior.into();
+        self
+    }
+
+    /// Perceptual roughness of the coat reflection
+    pub fn roughness(mut self, roughness: impl Into<TextureType>) -> CoatedBuilder {
+        self.roughness = roughness.into();
+        self
+    }
+
+    /// Absorption coefficient of the coat per unit length, per color channel
+    pub fn absorption(mut self, absorption: impl Into<TextureType>) -> CoatedBuilder {
+        self.absorption = absorption.into();
+        self
+    }
+
+    pub fn thickness(mut self, thickness: f32) -> CoatedBuilder {
+        self.thickness = thickness;
+        self
+    }
+
+    pub fn build(self) -> Coated {
+        Coated {
+            base: self.base,
+            ior: self.ior,
+            roughness: self.roughness,
+            absorption: self.absorption,
+            thickness: self.thickness,
+        }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        let mut rng = rand::thread_rng();
+        let rv = Vec2::new(rng.gen(), rng.gen());
+        let srec = self.sample(&r_in.direction, hit, rv)?;
+        let ray_out = Ray::new(hit.p, srec.wo).with_time(r_in.time);
+        Some((srec.attenuation, ray_out))
+    }
+
+    fn emmitted(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Vec3> {
+        None
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        let Some(frame) = self.frame(wi, hit) else {
+            return Vec3::zeros();
+        };
+        let wl = frame.uvw.to_local(&normalize(scattered));
+        let coat = Coated::eval_coat(&frame, &wl);
+        self.eval_base(&frame, &wl) + Vec3::new(coat, coat, coat)
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        let frame = self.frame(wi, hit)?;
+        let wo = &frame.wo;
+
+        // choose between the coat and the base, and reuse the random variable for the lobe
+        let p = frame.coat_probability;
+        if rv.x < p {
+            let rv = Vec2::new(rv.x / p, rv.y);
+            let wl = reflect(&-wo, &frame.ggx.sample_visible(wo, rv));
+            if wl.z <= 0.0 {
+                return None;
+            }
+            let scattered = frame.uvw.local(&wl);
+            let pdf = self.pdf(wi, &scattered, hit);
+            return Some(ScatterRecord {
+                attenuation: self.eval(wi, &scattered, hit) / pdf,
+                wo: scattered,
+                is_specular: false,
+            });
+        }
+
+        let rv = Vec2::new((rv.x - p) / (1.0 - p), rv.y);
+        let base_wi = -frame.uvw.local(&frame.wo_inside);
+        let base_srec = self.base.sample(&base_wi, &frame.base_hit, rv)?;
+        let wl_inside = frame.uvw.to_local(&normalize(&base_srec.wo));
+        if wl_inside.z <= 0.0 {
+            return None;
+        }
+
+        // refract back out of the coat, light past the critical angle stays inside
+        let sin2_out = (1.0 - wl_inside.z * wl_inside.z) * frame.eta * frame.eta;
+        if sin2_out >= 1.0 {
+            return None;
+        }
+        let wl = Vec3::new(
+            wl_inside.x * frame.eta,
+            wl_inside.y * frame.eta,
+            f32::sqrt(1.0 - sin2_out),
+        );
+        let scattered = normalize(&frame.uvw.local(&wl));
+
+        if base_srec.is_specular {
+            // no density to weigh against, the solid angle factors cancel out through the interface
+            let transmittance = (1.0 - frame.fresnel) * (1.0 - fresnel_dielectric(wl.z, frame.eta));
+            let path_length = 1.0 / frame.wo_inside.z + 1.0 / wl_inside.z;
+            let absorption = exp(&(-frame.absorption * path_length));
+            return Some(ScatterRecord {
+                attenuation: base_srec
+                    .attenuation
+                    .component_mul(&absorption)
+                    .component_mul(&frame.interreflection)
+                    * transmittance
+                    / (1.0 - p),
+                wo: scattered,
+                is_specular: true,
+            });
+        }
+
+        let pdf = self.pdf(wi, &scattered, hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(ScatterRecord {
+            attenuation: self.eval(wi, &scattered, hit) / pdf,
+            wo: scattered,
+            is_specular: false,
+        })
+    }
+
+    fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        let Some(frame) = self.frame(wi, hit) else {
+            return 0.0;
+        };
+        let wl = frame.uvw.to_local(&normalize(scattered));
+        let p = frame.coat_probability;
+        p * Coated::pdf_coat(&frame, &wl) + (1.0 - p) * self.pdf_base(&frame, &wl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{dot, Vec2, Vec3};
+    use serde_json::json;
+
+    use crate::core::microfacet::{fresnel_dielectric, Ggx};
+    use crate::core::sampling::stratified_grid;
+    use crate::core::utils::Factory;
+    use crate::materials::{Coated, CoatedBuilder, LambertianBuilder, Material, MaterialFactory};
+    use crate::surfaces::HitInfo;
+    use crate::tests::sample_test::{ggx_reflection_above, MaterialTest};
+
+    #[test]
+    fn builder_matches_json() {
+        let mut mf = MaterialFactory::new();
+        mf.make(&json!({"type": "lambertian", "name": "wood", "albedo": [0.6, 0.4, 0.2]}));
+        let v = json!({
+            "type": "coated",
+            "base": "wood",
+            "ior": 1.5,
+            "roughness": 0.05,
+            "absorption": [0.1, 0.2, 0.3],
+            "thickness": 0.5
+        });
+        let base = LambertianBuilder::new()
+            .albedo(Vec3::new(0.6, 0.4, 0.2))
+            .build();
+        let from_builder = CoatedBuilder::new(base)
+            .ior(1.5)
+            .roughness(0.05)
+            .absorption(Vec3::new(0.1, 0.2, 0.3))
+            .thickness(0.5)
+            .build();
+        assert_eq!(Coated::new(&v, &mf), from_builder);
+    }
+
+    #[test]
+    fn coat_conserves_energy() {
+        let mf = MaterialFactory::new();
+        let material = mf.create_material(&json!({
+            "type": "coated",
+            "base": {"type": "lambertian", "albedo": 1.0},
+            "ior": 1.5,
+            "roughness": 0.3
+        }));
+        let normal = Vec3::z();
+        let hit = HitInfo {
+            t: 1.0,
+            p: Vec3::zeros(),
+            gn: normal,
+            sn: normal,
+            dpdu: Vec3::x(),
+            dpdv: Vec3::y(),
+            uv: Vec2::new(0.5, 0.5),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
+            mat: material.clone(),
+            emitter: None,
+        };
+
+        // the directional albedo of a white base under a clear coat can't exceed one
+        for wi in [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(2.0, 0.0, -1.0).normalize(),
+        ] {
+            let n = 64;
+            let mut albedo = Vec3::zeros();
+            for rv in stratified_grid(n) {
+                if let Some(srec) = material.sample(&wi, &hit, rv) {
+                    albedo += srec.attenuation / (n * n) as f32;
+                }
+            }
+            assert!(albedo.max() <= 1.02 && albedo.min() > 0.6, "{albedo}");
+        }
+    }
+
+    #[test]
+    fn coated_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "coated",
+                "base": {"type": "lambertian", "albedo": 0.5},
+                "ior": 1.5,
+                "roughness": 0.3,
+                "absorption": [0.5, 0.2, 0.1]
+            },
+            "normal": [
+                0.25, 0.5, 1.0
+            ],
+            "name": "coated"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        // the coat is chosen in proportion to its reflectance against the light reaching the base
+        let cos_theta = dot(
+            &Vec3::new(-0.25, 0.0, 1.0).normalize(),
+            &Vec3::new(0.25, 0.5, 1.0).normalize(),
+        );
+        let fresnel = fresnel_dielectric(cos_theta, 1.5);
+        let coat = fresnel / (fresnel + (1.0 - fresnel) * 0.5);
+        // coat reflections below the surface are rejected
+        let wo = Vec3::new(f32::sqrt(1.0 - cos_theta * cos_theta), 0.0, cos_theta);
+        let coat_above = ggx_reflection_above(&Ggx::from_roughness(0.3, 0.0), &wo);
+        // cosine distributed base samples leave the coat when their sine is below 1 / ior, which happens with a
+        // probability of 1 / ior²
+        let base_out = 1.0 / (1.5 * 1.5);
+        parameters.run(&test, coat * coat_above + (1.0 - coat) * base_out, 1e-2);
+    }
+}
@@ -0,0 +1,324 @@
+use nalgebra_glm::{dot, normalize, Vec2, Vec3};
+use rand::Rng;
+use serde_json::Value;
+
+use crate::core::microfacet::{conductor_ior, fresnel_conductor, Ggx};
+use crate::core::onb::Onb;
+use crate::core::ray::Ray;
+use crate::core::utils::{luminance, read, read_or, reflect};
+use crate::materials::Material;
+use crate::surfaces::{HitInfo, ScatterRecord};
+use crate::textures::{create_linear_texture, Texture, TextureType};
+
+/// Rough metal using the GGX microfacet distribution and the exact conductor Fresnel equations
+#[derive(Debug, PartialEq, Clone)]
+pub struct Conductor {
+    eta: Vec3,
+    k: Vec3,
+    roughness: TextureType,
+    anisotropy: f32,
+}
+
+impl Conductor {
+    pub fn new(v: &Value) -> Conductor {
+        let m = v.as_object().unwrap();
+        let mut builder = ConductorBuilder::new()
+            .roughness(create_linear_texture(v, "roughness"))
+            .anisotropy(read_or(v, "anisotropy", 0.0));
+        if m.contains_key("metal") {
+            builder = builder.preset(&read::<String>(v, "metal"));
+        }
+        if m.contains_key("eta") || m.contains_key("k") {
+            builder = builder.ior(read(v, "eta"), read(v, "k"));
+        }
+        builder.build()
+    }
+
+    fn distribution(&self, hit: &HitInfo) -> Ggx {
+        let roughness = luminance(&self.roughness.value(hit).unwrap());
+        Ggx::from_roughness(roughness, self.anisotropy)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConductorBuilder {
+    eta: Vec3,
+    k: Vec3,
+    roughness: TextureType,
+    anisotropy: f32,
+}
+
+impl ConductorBuilder {
+    pub fn new() -> ConductorBuilder {
+        let (eta, k) = conductor_ior("Al").unwrap();
+        ConductorBuilder {
+            eta,
+            k,
+            roughness: TextureType::from(0.1),
+            anisotropy: 0.0,
+        }
+    }
+
+    /// Complex index of refraction `eta + i k`, per color channel
+    pub fn ior(mut self, eta: Vec3, k: Vec3) -> ConductorBuilder {
+        self.eta = eta;
+        self.k = k;
+        self
+    }
+
+    /// Use the index of refraction of a known metal: "Au", "Ag", "Cu" or "Al"
+    pub fn preset(self, metal: &str) -> ConductorBuilder {
+        let (eta, k) =
+            conductor_ior(metal).unwrap_or_else(|| unimplemented!("The conductor '{}' ", metal));
+        self.ior(eta, k)
+    }
+
+    /// Perceptual roughness in [0, 1]
+    pub fn roughness(mut self, roughness: impl Into<TextureType>) -> ConductorBuilder {
+        self.roughness = roughness.into();
+        self
+    }
+
+    /// Stretch of the highlight along the surface tangent, in [0, 1)
+    pub fn anisotropy(mut self, anisotropy: f32) -> ConductorBuilder {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub fn build(self) -> Conductor {
+        Conductor {
+            eta: self.eta,
+            k: self.k,
+            roughness: self.roughness,
+            anisotropy: self.anisotropy,
+        }
+    }
+}
+
+impl Default for ConductorBuilder {
+    fn default() -> ConductorBuilder {
+        ConductorBuilder::new()
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        let mut rng = rand::thread_rng();
+        let rv = Vec2::new(rng.gen(), rng.gen());
+        let srec = self.sample(&r_in.direction, hit, rv)?;
+        let ray_out = Ray::new(hit.p, srec.wo).with_time(r_in.time);
+        Some((srec.attenuation, ray_out))
+    }
+
+    fn emmitted(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Vec3> {
+        None
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        // the anisotropic lobe is stretched along the shading tangent
+        let uvw = Onb::build_from_w_u(&hit.sn, &hit.dpdu);
+        let wo = uvw.to_local(&normalize(&-wi));
+        let wl = uvw.to_local(&normalize(scattered));
+        if wo.z <= 0.0 || wl.z <= 0.0 {
+            return Vec3::zeros();
+        }
+
+        let ggx = self.distribution(hit);
+        let m = normalize(&(wo + wl));
+        let fresnel = fresnel_conductor(dot(&wo, &m), &self.eta, &self.k);
+        // the cosine term cancels with the one of the BRDF denominator
+        fresnel * ggx.d(&m) * ggx.g2(&wo, &wl) / (4.0 * wo.z)
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w_u(&hit.sn, &hit.dpdu);
+        let wo = uvw.to_local(&normalize(&-wi));
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let m = self.distribution(hit).sample_visible(&wo, rv);
+        let wl = reflect(&-wo, &m);
+        if wl.z <= 0.0 {
+            return None;
+        }
+
+        let scattered = uvw.local(&wl);
+        let pdf = self.pdf(wi, &scattered, hit);
+        let srec = ScatterRecord {
+            attenuation: self.eval(wi, &scattered, hit) / pdf,
+            wo: scattered,
+            is_specular: false,
+        };
+        Some(srec)
+    }
+
+    fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        let uvw = Onb::build_from_w_u(&hit.sn, &hit.dpdu);
+        let wo = uvw.to_local(&normalize(&-wi));
+        let wl = uvw.to_local(&normalize(scattered));
+        if wo.z <= 0.0 || wl.z <= 0.0 {
+            return 0.0;
+        }
+
+        let m = normalize(&(wo + wl));
+        self.distribution(hit).d_visible(&wo, &m) / (4.0 * dot(&wo, &m))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    use crate::core::microfacet::{conductor_ior, Ggx};
+    use crate::core::onb::Onb;
+    use crate::core::sampling::stratified_grid;
+    use crate::materials::{Conductor, ConductorBuilder, Material, MaterialFactory, MaterialType};
+    use crate::surfaces::HitInfo;
+    use crate::tests::sample_test::{ggx_reflection_above, MaterialTest};
+
+    #[test]
+    fn builder_matches_json() {
+        let v = json!({
+            "type": "conductor",
+            "metal": "Cu",
+            "roughness": 0.4,
+            "anisotropy": 0.5
+        });
+        let from_builder = ConductorBuilder::new()
+            .preset("Cu")
+            .roughness(0.4)
+            .anisotropy(0.5)
+            .build();
+        assert_eq!(Conductor::new(&v), from_builder);
+
+        let (eta, k) = conductor_ior("Cu").unwrap();
+        let v =
+            json!({"type": "conductor", "eta": eta, "k": k, "roughness": 0.4, "anisotropy": 0.5});
+        assert_eq!(Conductor::new(&v), from_builder);
+    }
+
+    #[test]
+    fn conductor_energy_is_bounded() {
+        let mf = MaterialFactory::new();
+        let material =
+            mf.create_material(&json!({"type": "conductor", "metal": "Ag", "roughness": 0.5}));
+        let normal = Vec3::z();
+        let hit = HitInfo {
+            t: 1.0,
+            p: Vec3::zeros(),
+            gn: normal,
+            sn: normal,
+            dpdu: Vec3::x(),
+            dpdv: Vec3::y(),
+            uv: Vec2::new(0.5, 0.5),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
+            mat: material.clone(),
+            emitter: None,
+        };
+        let wi = Vec3::new(0.5, 0.0, -1.0).normalize();
+
+        // estimate the directional albedo, it can't exceed one
+        let n = 64;
+        let mut albedo = Vec3::zeros();
+        for rv in stratified_grid(n) {
+            if let Some(srec) = material.sample(&wi, &hit, rv) {
+                albedo += srec.attenuation / (n * n) as f32;
+            }
+        }
+        assert!(albedo.max() <= 1.0 && albedo.min() > 0.5);
+    }
+
+    #[test]
+    fn tangent_orients_anisotropy() {
+        let material = ConductorBuilder::new()
+            .roughness(0.5)
+            .anisotropy(0.8)
+            .build();
+        let hit = |dpdu: Vec3| HitInfo {
+            t: 1.0,
+            p: Vec3::zeros(),
+            gn: Vec3::z(),
+            sn: Vec3::z(),
+            dpdu,
+            dpdv: Vec3::z().cross(&dpdu),
+            uv: Vec2::new(0.5, 0.5),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
+            mat: Arc::new(MaterialType::from(material.clone())),
+            emitter: None,
+        };
+        let quarter_turn = |v: Vec3| Vec3::new(-v.y, v.x, v.z);
+        let wi = Vec3::new(0.3, 0.1, -1.0).normalize();
+        let wo = Vec3::new(0.4, 0.5, 1.0).normalize();
+
+        // turning the tangent and the directions together leaves the reflection unchanged
+        let along_x = material.eval(&wi, &wo, &hit(Vec3::x()));
+        let along_y = material.eval(&quarter_turn(wi), &quarter_turn(wo), &hit(Vec3::y()));
+        approx::assert_relative_eq!(along_x, along_y, max_relative = 1e-4);
+        approx::assert_relative_eq!(
+            material.pdf(&wi, &wo, &hit(Vec3::x())),
+            material.pdf(&quarter_turn(wi), &quarter_turn(wo), &hit(Vec3::y())),
+            max_relative = 1e-4
+        );
+        // while turning only the tangent turns the highlight
+        let turned = material.eval(&wi, &wo, &hit(Vec3::y()));
+        assert!((turned - along_x).norm() > 0.1 * along_x.norm());
+        // only the part of the tangent in the surface matters
+        let tilted = material.eval(&wi, &wo, &hit(Vec3::new(1.0, 0.0, 0.7)));
+        approx::assert_relative_eq!(tilted, along_x, max_relative = 1e-4);
+    }
+
+    #[test]
+    fn conductor_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "conductor",
+                "metal": "Au",
+                "roughness": 0.5
+            },
+            "normal": [
+                0, 0, 1
+            ],
+            "name": "conductor"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        // the reflections of the visible normals below the surface are rejected
+        let wo = Vec3::new(-0.25, 0.0, 1.0).normalize();
+        let expected = ggx_reflection_above(&Ggx::from_roughness(0.5, 0.0), &wo);
+        parameters.run(&test, expected, 1e-2);
+    }
+
+    #[test]
+    fn conductor_anisotropic_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "conductor",
+                "metal": "Al",
+                "roughness": 0.6,
+                "anisotropy": 0.8
+            },
+            "normal": [
+                0.25, 0.5, 1.0
+            ],
+            "name": "conductor-anisotropic"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        // the tangent of the test hit is the one `Onb` picks, the anisotropic lobe follows it
+        let uvw = Onb::build_from_w(&Vec3::new(0.25, 0.5, 1.0));
+        let wo = uvw.to_local(&Vec3::new(-0.25, 0.0, 1.0).normalize());
+        let expected = ggx_reflection_above(&Ggx::from_roughness(0.6, 0.8), &wo);
+        parameters.run(&test, expected, 1e-2);
+    }
+}
@@ -7,7 +7,7 @@ use crate::core::utils::{luminance, reflect, reflectance, refract};
 use crate::materials::Material;
 use crate::surfaces::HitInfo;
 use crate::surfaces::ScatterRecord;
-use crate::textures::{create_texture, Texture, TextureType};
+use crate::textures::{create_linear_texture, Texture, TextureType};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Dielectric {
@@ -16,8 +16,9 @@ pub struct Dielectric {
 
 impl Dielectric {
     pub fn new(v: &Value) -> Dielectric {
-        let ior = create_texture(v, "ior");
-        Dielectric { ior }
+        DielectricBuilder::new()
+            .ior(create_linear_texture(v, "ior"))
+            .build()
     }
 
     fn _scatter(&self, ray: &Ray, hit: &HitInfo, rv: f32) -> (Vec3, Ray) {
@@ -44,12 +45,40 @@ impl Dielectric {
             refract(&unit_direction, &normal, ratio_index_of_refraction)
         };
 
-        let scattered = Ray::new(hit.p, direction);
+        let scattered = Ray::new(hit.p, direction).with_time(ray.time);
 
         (Vec3::new(1.0, 1.0, 1.0), scattered)
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct DielectricBuilder {
+    ior: TextureType,
+}
+
+impl DielectricBuilder {
+    pub fn new() -> DielectricBuilder {
+        DielectricBuilder {
+            ior: TextureType::from(1.5),
+        }
+    }
+
+    pub fn ior(mut self, ior: impl Into<TextureType>) -> DielectricBuilder {
+        self.ior = ior.into();
+        self
+    }
+
+    pub fn build(self) -> Dielectric {
+        Dielectric { ior: self.ior }
+    }
+}
+
+impl Default for DielectricBuilder {
+    fn default() -> DielectricBuilder {
+        DielectricBuilder::new()
+    }
+}
+
 impl Material for Dielectric {
     fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
         let mut rng = rand::thread_rng();
@@ -1,22 +1,150 @@
-use nalgebra_glm::{dot, Vec2, Vec3};
+use nalgebra_glm::{cross, dot, length2, normalize, Vec2, Vec3};
 use serde_json::Value;
+use std::sync::Arc;
 
+use crate::core::assets::load_ies;
+use crate::core::ies::IesProfile;
 use crate::core::onb::Onb;
 use crate::core::ray::Ray;
 use crate::core::sampling::{sample_hemisphere, sample_hemisphere_pdf};
-use crate::core::utils::read_v_or_f;
+use crate::core::utils::{read, read_or};
 use crate::materials::Material;
 use crate::surfaces::{HitInfo, ScatterRecord};
+use crate::textures::{create_texture, Texture, TextureType};
 
+/// Emitter with a radiance given by a texture, scaled by `intensity`.
+///
+/// An IES profile can modulate the radiance by direction, its nadir being the normal of the surface and its
+/// horizontal angle 0° the u tangent, so that the frame follows the `transform` of a quad.
+///
+/// The emission texture is point sampled, without the footprint of camera rays: light samples have no footprint, and
+/// both estimates of multiple importance sampling must see the same radiance for a point.
 #[derive(Debug, PartialEq, Clone)]
 pub struct DiffuseLight {
-    emit: Vec3,
+    emit: TextureType,
+    intensity: f32,
+    two_sided: bool,
+    profile: Option<Arc<IesProfile>>,
 }
 
 impl DiffuseLight {
     pub fn new(v: &Value) -> DiffuseLight {
-        let emit = read_v_or_f(v, "emit");
-        DiffuseLight { emit }
+        // "power" is accepted as an alias of "intensity"
+        let intensity = read_or(v, "intensity", read_or(v, "power", 1.0));
+        let mut builder = DiffuseLightBuilder::new()
+            .emit(create_texture(v, "emit"))
+            .intensity(intensity)
+            .two_sided(read_or(v, "two_sided", false));
+        if v.get("ies").is_some() {
+            let filename: String = read(v, "ies");
+            builder = builder.profile(load_ies(&filename));
+        }
+        builder.build()
+    }
+
+    /// Radiance leaving the surface at `hit` towards the origin of a ray travelling along `direction`
+    fn radiance(&self, direction: &Vec3, hit: &HitInfo) -> Vec3 {
+        // only emit from the normal-facing side, unless two sided
+        if !self.two_sided && dot(direction, &hit.sn) > 0.0 {
+            Vec3::zeros()
+        } else {
+            let point = HitInfo {
+                t: hit.t,
+                p: hit.p,
+                gn: hit.gn,
+                sn: hit.sn,
+                dpdu: hit.dpdu,
+                dpdv: hit.dpdv,
+                uv: hit.uv,
+                duvdx: Vec2::zeros(),
+                duvdy: Vec2::zeros(),
+                mat: Arc::clone(&hit.mat),
+                emitter: hit.emitter,
+            };
+            self.emit.value(&point).unwrap_or_default()
+                * self.intensity
+                * self.falloff(direction, hit)
+        }
+    }
+
+    /// Profile value for the light leaving `hit` towards the origin of a ray travelling along `direction`
+    fn falloff(&self, direction: &Vec3, hit: &HitInfo) -> f32 {
+        let Some(profile) = &self.profile else {
+            return 1.0;
+        };
+        // luminaire frame with the nadir along the normal, on the side the light leaves
+        let n = if dot(direction, &hit.sn) > 0.0 {
+            -hit.sn
+        } else {
+            hit.sn
+        };
+        let tangent = hit.dpdu - n * dot(&hit.dpdu, &n);
+        let (x, y) = if length2(&tangent) > 0.0 {
+            let x = normalize(&tangent);
+            (x, cross(&-n, &x))
+        } else {
+            let onb = Onb::build_from_w(&-n);
+            (onb.local(&Vec3::x()), onb.local(&Vec3::y()))
+        };
+        let out = -direction;
+        profile.value(&Vec3::new(dot(&out, &x), dot(&out, &y), -dot(&out, &n)))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffuseLightBuilder {
+    emit: TextureType,
+    intensity: f32,
+    two_sided: bool,
+    profile: Option<Arc<IesProfile>>,
+}
+
+impl DiffuseLightBuilder {
+    pub fn new() -> DiffuseLightBuilder {
+        DiffuseLightBuilder {
+            emit: TextureType::from(Vec3::new(1.0, 1.0, 1.0)),
+            intensity: 1.0,
+            two_sided: false,
+            profile: None,
+        }
+    }
+
+    /// Emitted radiance, before the `intensity` scaling
+    pub fn emit(mut self, emit: impl Into<TextureType>) -> DiffuseLightBuilder {
+        self.emit = emit.into();
+        self
+    }
+
+    pub fn intensity(mut self, intensity: f32) -> DiffuseLightBuilder {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Emit from both sides of the surface instead of the normal-facing side only
+    pub fn two_sided(mut self, two_sided: bool) -> DiffuseLightBuilder {
+        self.two_sided = two_sided;
+        self
+    }
+
+    /// Photometric profile scaling the radiance by direction
+    pub fn profile(mut self, profile: impl Into<Arc<IesProfile>>) -> DiffuseLightBuilder {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    pub fn build(self) -> DiffuseLight {
+        DiffuseLight {
+            emit: self.emit,
+            intensity: self.intensity,
+            two_sided: self.two_sided,
+            profile: self.profile,
+        }
+    }
+}
+
+impl Default for DiffuseLightBuilder {
+    fn default() -> DiffuseLightBuilder {
+        DiffuseLightBuilder::new()
     }
 }
 
@@ -26,12 +154,7 @@ impl Material for DiffuseLight {
     }
 
     fn emmitted(&self, ray: &Ray, hit: &HitInfo) -> Option<Vec3> {
-        // only emit from the normal-facing side
-        if dot(&ray.direction, &hit.sn) > 0.0 {
-            Some(Vec3::zeros())
-        } else {
-            Some(self.emit)
-        }
+        Some(self.radiance(&ray.direction, hit))
     }
 
     fn is_emissive(&self) -> bool {
@@ -39,11 +162,7 @@ impl Material for DiffuseLight {
     }
 
     fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
-        let emited_color = if dot(wi, &hit.sn) > 0.0 {
-            Vec3::zeros()
-        } else {
-            self.emit
-        };
+        let emited_color = self.radiance(wi, hit);
         // emited_color
         emited_color * self.pdf(wi, scattered, hit)
     }
@@ -62,3 +181,137 @@ impl Material for DiffuseLight {
         sample_hemisphere_pdf(wi)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma};
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+
+    use crate::core::ies::IesProfile;
+    use crate::core::ray::Ray;
+    use crate::core::sampling::stratified_grid;
+    use crate::core::transform::Transform;
+    use crate::materials::{DiffuseLight, DiffuseLightBuilder, Material, MaterialFactory};
+    use crate::surfaces::{QuadBuilder, Surface, TriangleBuilder};
+
+    #[test]
+    fn builder_matches_json() {
+        let v = json!({"type": "diffuse_light", "emit": [1.0, 0.5, 0.2], "power": 4.0, "two_sided": true});
+        let from_builder = DiffuseLightBuilder::new()
+            .emit(Vec3::new(1.0, 0.5, 0.2))
+            .intensity(4.0)
+            .two_sided(true)
+            .build();
+        assert_eq!(DiffuseLight::new(&v), from_builder);
+
+        let v = json!({"type": "diffuse_light", "emit": 2.0});
+        assert_eq!(
+            DiffuseLight::new(&v),
+            DiffuseLightBuilder::new().emit(2.0).build()
+        );
+    }
+
+    #[test]
+    fn emission_sides() {
+        let quad = QuadBuilder::new(DiffuseLightBuilder::new().intensity(3.0).build()).build();
+        let front = Ray::new(Vec3::new(0.1, 0.2, 1.0), -Vec3::z());
+        let back = Ray::new(Vec3::new(0.1, 0.2, -1.0), Vec3::z());
+        let hit = quad.intersect(&front).unwrap();
+        assert_eq!(
+            hit.mat.emmitted(&front, &hit),
+            Some(Vec3::new(3.0, 3.0, 3.0))
+        );
+        let hit = quad.intersect(&back).unwrap();
+        assert_eq!(hit.mat.emmitted(&back, &hit), Some(Vec3::zeros()));
+
+        let quad = QuadBuilder::new(DiffuseLightBuilder::new().two_sided(true).build()).build();
+        let hit = quad.intersect(&back).unwrap();
+        assert_eq!(
+            hit.mat.emmitted(&back, &hit),
+            Some(Vec3::new(1.0, 1.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn ies_profile() {
+        // profile brighter along the u tangent of the quad than along v, fading to nothing at the horizon
+        let profile =
+            IesProfile::new(vec![0.0, 90.0], vec![0.0, 90.0], vec![4.0, 0.0, 4.0, 2.0]).unwrap();
+        let quad = QuadBuilder::new(DiffuseLightBuilder::new().profile(profile).build()).build();
+        let emitted = |origin: Vec3| {
+            let ray = Ray::new(origin, -origin);
+            let hit = quad.intersect(&ray).unwrap();
+            hit.mat.emmitted(&ray, &hit).unwrap()
+        };
+        approx::assert_abs_diff_eq!(emitted(Vec3::z()), Vec3::new(1.0, 1.0, 1.0), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(
+            emitted(Vec3::new(1.0, 0.0, 1.0)),
+            Vec3::new(0.5, 0.5, 0.5),
+            epsilon = 1e-4
+        );
+        approx::assert_abs_diff_eq!(
+            emitted(Vec3::new(0.0, 1.0, 1.0)),
+            Vec3::new(0.75, 0.75, 0.75),
+            epsilon = 1e-4
+        );
+    }
+
+    /// Emitter samples should see the same texture value as rays hitting the same point
+    #[test]
+    fn sampled_emission_matches_hits() {
+        let mf = MaterialFactory::new();
+        let light = mf.create_material(&json!({
+            "type": "diffuse_light",
+            "emit": {"type": "checker", "even": [1, 0, 0], "odd": [0, 0, 1], "scale": 5.0},
+            "intensity": 2.0
+        }));
+        let quad = QuadBuilder::new(light.clone())
+            .size(Vec2::new(2.0, 1.0))
+            .transform(Transform::translate(&Vec3::new(0.0, 0.0, -1.0)))
+            .build();
+        let triangle = TriangleBuilder::new(
+            [
+                Vec3::new(-1.0, -1.0, -2.0),
+                Vec3::new(1.0, -1.0, -2.0),
+                Vec3::new(0.0, 1.0, -2.0),
+            ],
+            light,
+        )
+        .build();
+        let surfaces: [&dyn Surface; 2] = [&quad, &triangle];
+
+        let origin = Vec3::new(0.1, 0.3, 2.0);
+        for surface in surfaces {
+            for rv in stratified_grid(4) {
+                let erec = surface.sample(&origin, 0.0, rv).unwrap();
+                let ray = Ray::new(origin, erec.wi);
+                let hit = surface.intersect(&ray).unwrap();
+                approx::assert_abs_diff_eq!(hit.t, erec.hit.t, epsilon = 1e-4);
+                approx::assert_abs_diff_eq!(hit.mat.emmitted(&ray, &hit).unwrap(), erec.emitted);
+                approx::assert_abs_diff_eq!(hit.uv, erec.hit.uv, epsilon = 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn emission_ignores_the_footprint() {
+        // the same radiance whether the light was hit by a camera ray or sampled, which has no footprint
+        let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        ImageBuffer::from_fn(64, 64, |x, _| Luma([if x < 32 { 255u8 } else { 0 }]))
+            .save(file.path())
+            .unwrap();
+        let light = MaterialFactory::new().create_material(&json!({
+            "type": "diffuse_light",
+            "emit": {"type": "image", "filename": file.path().to_str().unwrap()}
+        }));
+        let quad = QuadBuilder::new(light).build();
+        let ray = Ray::new(Vec3::new(-0.25, 0.0, 1.0), -Vec3::z());
+        let mut hit = quad.intersect(&ray).unwrap();
+        let point = hit.mat.emmitted(&ray, &hit).unwrap();
+        hit.duvdx = Vec2::new(1.0, 0.0);
+        hit.duvdy = Vec2::new(0.0, 1.0);
+        assert_eq!(hit.mat.emmitted(&ray, &hit).unwrap(), point);
+        assert!(point.x < 0.05 || point.x > 0.95, "{point}");
+    }
+}
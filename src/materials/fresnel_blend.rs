@@ -7,7 +7,7 @@ use crate::core::ray::Ray;
 use crate::core::utils::{luminance, reflectance};
 use crate::materials::{Material, MaterialFactory, MaterialType};
 use crate::surfaces::{HitInfo, ScatterRecord};
-use crate::textures::{create_texture, Texture, TextureType};
+use crate::textures::{create_linear_texture, Texture, TextureType};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FresnelBlend {
@@ -18,7 +18,7 @@ pub struct FresnelBlend {
 
 impl FresnelBlend {
     pub fn new(v: &Value, mf: &MaterialFactory) -> FresnelBlend {
-        let ior = create_texture(v, "ior");
+        let ior = create_linear_texture(v, "ior");
         let refracted_v = v.get("refr").unwrap().clone();
         let refracted = if refracted_v.is_string() {
             let refracted_name: String = from_value(refracted_v).unwrap();
@@ -44,11 +44,9 @@ impl FresnelBlend {
         } else {
             panic!("NOOOOOO refl : {reflected_v}");
         };
-        FresnelBlend {
-            ior,
-            refracted,
-            reflected,
-        }
+        FresnelBlendBuilder::new(reflected, refracted)
+            .ior(ior)
+            .build()
     }
 
     fn _scatter(&self, ray: &Ray, hit: &HitInfo, rv: f32) -> Option<(Vec3, Ray)> {
@@ -70,6 +68,37 @@ impl FresnelBlend {
     }
 }
 
+/// Blend between a `reflected` and a `refracted` material, weighted by the Fresnel reflectance
+#[derive(Debug, Clone)]
+pub struct FresnelBlendBuilder {
+    ior: TextureType,
+    refracted: Arc<MaterialType>,
+    reflected: Arc<MaterialType>,
+}
+
+impl FresnelBlendBuilder {
+    pub fn new(reflected: Arc<MaterialType>, refracted: Arc<MaterialType>) -> FresnelBlendBuilder {
+        FresnelBlendBuilder {
+            ior: TextureType::from(1.5),
+            refracted,
+            reflected,
+        }
+    }
+
+    pub fn ior(mut self, ior: impl Into<TextureType>) -> FresnelBlendBuilder {
+        self.ior = ior.into();
+        self
+    }
+
+    pub fn build(self) -> FresnelBlend {
+        FresnelBlend {
+            ior: self.ior,
+            refracted: self.refracted,
+            reflected: self.reflected,
+        }
+    }
+}
+
 impl Material for FresnelBlend {
     fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
         let mut rng = rand::thread_rng();
@@ -15,13 +15,44 @@ pub struct Lambertian {
 
 impl Lambertian {
     pub fn new(v: &Value) -> Lambertian {
-        let albedo = create_texture(v, "albedo");
-        Lambertian { albedo }
+        LambertianBuilder::new()
+            .albedo(create_texture(v, "albedo"))
+            .build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LambertianBuilder {
+    albedo: TextureType,
+}
+
+impl LambertianBuilder {
+    pub fn new() -> LambertianBuilder {
+        LambertianBuilder {
+            albedo: TextureType::from(0.5),
+        }
+    }
+
+    pub fn albedo(mut self, albedo: impl Into<TextureType>) -> LambertianBuilder {
+        self.albedo = albedo.into();
+        self
+    }
+
+    pub fn build(self) -> Lambertian {
+        Lambertian {
+            albedo: self.albedo,
+        }
+    }
+}
+
+impl Default for LambertianBuilder {
+    fn default() -> LambertianBuilder {
+        LambertianBuilder::new()
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
         let mut rng = rand::thread_rng();
         let mut scatter_direction = hit.sn + normalize(&random_in_unit_sphere(&mut rng));
 
@@ -31,7 +62,7 @@ impl Material for Lambertian {
         }
 
         let attenuation = self.albedo.value(hit).unwrap();
-        let ray_out = Ray::new(hit.p, normalize(&scatter_direction));
+        let ray_out = Ray::new(hit.p, normalize(&scatter_direction)).with_time(ray.time);
 
         Some((attenuation, ray_out))
     }
@@ -48,7 +79,7 @@ impl Material for Lambertian {
     }
 
     fn sample(&self, _wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
-        let uvw = Onb::build_from_w(&hit.gn);
+        let uvw = Onb::build_from_w(&hit.sn);
         let srec = ScatterRecord {
             attenuation: self.albedo.value(hit).unwrap(),
             wo: uvw.local(&sample_hemisphere_cosine(rv)),
@@ -58,7 +89,7 @@ impl Material for Lambertian {
     }
 
     fn pdf(&self, _wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
-        f32::max(0.0, dot(scattered, &hit.gn)) * std::f32::consts::FRAC_1_PI
+        f32::max(0.0, dot(scattered, &hit.sn)) * std::f32::consts::FRAC_1_PI
     }
 }
 
@@ -67,6 +98,7 @@ mod tests {
     use nalgebra_glm::{Vec2, Vec3};
     use serde_json::json;
 
+    use crate::core::onb::Onb;
     use crate::core::ray::Ray;
     use crate::materials::{Material, MaterialFactory, MaterialType};
     use crate::surfaces::HitInfo;
@@ -89,13 +121,19 @@ mod tests {
         // Let's create a fictitious hitpoint
         let surface_point = Vec3::new(1.0, 2.0, 0.0);
         let normal = Vec3::new(1.0, 2.0, -1.0).normalize();
+        let uvw = Onb::build_from_w(&normal);
         let hit = HitInfo {
             t: 0.0,
             p: surface_point,
             uv: Vec2::new(0.0, 0.0),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
             gn: normal,
             sn: normal,
+            dpdu: uvw.local(&Vec3::x()),
+            dpdv: uvw.local(&Vec3::y()),
             mat: lambert_material.clone(),
+            emitter: None,
         };
 
         // And a fictitious ray
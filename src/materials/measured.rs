@@ -0,0 +1,306 @@
+use nalgebra_glm::{dot, normalize, Vec2, Vec3};
+use rand::Rng;
+use serde_json::Value;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::sync::Arc;
+
+use crate::core::assets::load_merl;
+use crate::core::distribution::Distribution1d;
+use crate::core::merl::{theta_h_index, MerlBrdf, RES_THETA_H};
+use crate::core::onb::Onb;
+use crate::core::ray::Ray;
+use crate::core::sampling::{sample_hemisphere_cosine, sample_hemisphere_cosine_pdf};
+use crate::core::utils::{luminance, modulo, read, reflect};
+use crate::materials::Material;
+use crate::surfaces::{HitInfo, ScatterRecord};
+
+/// Number of viewer elevations the half vector distribution is tabulated for
+const THETA_O_BINS: usize = 32;
+/// Number of half vector azimuths, relative to the viewer azimuth
+const PHI_H_BINS: usize = 16;
+/// Probability of sampling the cosine lobe, which covers the directions the table misses
+const COSINE_PROBABILITY: f32 = 0.2;
+
+/// Material evaluating a measured BRDF from the MERL 100 database.
+///
+/// Directions are sampled by picking the half vector from a distribution tabulated for each viewer elevation, mixed
+/// with cosine sampling.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Measured {
+    brdf: Arc<MerlBrdf>,
+    tables: Vec<HalfVectorTable>,
+}
+
+/// Distribution of the half vector for one viewer elevation.
+///
+/// The elevation index follows the square root spacing of the MERL table, and the azimuth is relative to the viewer.
+#[derive(Debug, PartialEq, Clone)]
+struct HalfVectorTable {
+    marginal: Distribution1d,
+    /// Distribution of the azimuth for each elevation
+    conditionals: Vec<Distribution1d>,
+}
+
+impl HalfVectorTable {
+    /// Tabulate the half vectors proportionally to the reflected luminance
+    fn new(brdf: &MerlBrdf, theta_o: f32) -> HalfVectorTable {
+        let wo = Vec3::new(theta_o.sin(), 0.0, theta_o.cos());
+        let conditionals: Vec<Distribution1d> = (0..RES_THETA_H)
+            .map(|j| {
+                let t = (j as f32 + 0.5) / RES_THETA_H as f32;
+                let theta_h = t * t * FRAC_PI_2;
+                let weights: Vec<f32> = (0..PHI_H_BINS)
+                    .map(|k| {
+                        let phi_h = (k as f32 + 0.5) / PHI_H_BINS as f32 * 2.0 * PI;
+                        let h = spherical_direction(theta_h, phi_h);
+                        let cos_oh = dot(&wo, &h);
+                        let wl = reflect(&-wo, &h);
+                        if cos_oh <= 0.0 || wl.z <= 0.0 {
+                            return 0.0;
+                        }
+                        // density of the half vector making wl proportional to the BRDF times the cosine
+                        luminance(&brdf.eval(&wl, &wo)) * wl.z * 4.0 * cos_oh
+                    })
+                    .collect();
+                Distribution1d::new(&weights)
+            })
+            .collect();
+
+        // solid angle of the elevation bins, which grow quadratically with the index
+        let marginal: Vec<f32> = conditionals
+            .iter()
+            .enumerate()
+            .map(|(j, conditional)| {
+                let t = (j as f32 + 0.5) / RES_THETA_H as f32;
+                let theta_h = t * t * FRAC_PI_2;
+                conditional.func_sum() * theta_h.sin() * t
+            })
+            .collect();
+        HalfVectorTable {
+            marginal: Distribution1d::new(&marginal),
+            conditionals,
+        }
+    }
+
+    /// Sample the elevation and relative azimuth of a half vector
+    fn sample(&self, rv: Vec2) -> (f32, f32) {
+        let (j, _, remapped) = self.marginal.sample(rv.x);
+        let t = (j as f32 + remapped) / RES_THETA_H as f32;
+        let (k, _, remapped) = self.conditionals[j].sample(rv.y);
+        let phi_h = (k as f32 + remapped) / PHI_H_BINS as f32 * 2.0 * PI;
+        (t * t * FRAC_PI_2, phi_h)
+    }
+
+    /// Density of a half vector over the solid angle
+    fn pdf(&self, theta_h: f32, phi_h: f32) -> f32 {
+        let sin_theta_h = theta_h.sin();
+        if sin_theta_h <= 0.0 {
+            return 0.0;
+        }
+        let j = theta_h_index(theta_h);
+        let k = ((phi_h / (2.0 * PI) * PHI_H_BINS as f32) as usize).min(PHI_H_BINS - 1);
+        let pmf = self.marginal.pmf(j) * self.conditionals[j].pmf(k);
+
+        // from the bin indices to the angles, then to the solid angle
+        let t = f32::sqrt(theta_h / FRAC_PI_2);
+        let d_theta_h = PI * t / RES_THETA_H as f32;
+        let d_phi_h = 2.0 * PI / PHI_H_BINS as f32;
+        pmf / (d_theta_h * d_phi_h * sin_theta_h)
+    }
+}
+
+fn spherical_direction(theta: f32, phi: f32) -> Vec3 {
+    Vec3::new(
+        theta.sin() * phi.cos(),
+        theta.sin() * phi.sin(),
+        theta.cos(),
+    )
+}
+
+impl Measured {
+    pub fn new(v: &Value) -> Measured {
+        let filename: String = read(v, "filename");
+        MeasuredBuilder::new(load_merl(&filename)).build()
+    }
+
+    fn table(&self, cos_theta_o: f32) -> &HalfVectorTable {
+        let theta_o = cos_theta_o.clamp(0.0, 1.0).acos();
+        let bin = ((theta_o / FRAC_PI_2 * THETA_O_BINS as f32) as usize).min(THETA_O_BINS - 1);
+        &self.tables[bin]
+    }
+
+    /// Density of the tabulated half vector sampling, over the solid angle of `wl`
+    fn tabulated_pdf(&self, wo: &Vec3, wl: &Vec3) -> f32 {
+        let h = normalize(&(wo + wl));
+        let cos_oh = dot(wo, &h);
+        if cos_oh <= 0.0 {
+            return 0.0;
+        }
+        let theta_h = h.z.clamp(-1.0, 1.0).acos();
+        let phi_h = modulo(h.y.atan2(h.x) - wo.y.atan2(wo.x), 2.0 * PI);
+        self.table(wo.z).pdf(theta_h, phi_h) / (4.0 * cos_oh)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MeasuredBuilder {
+    brdf: Arc<MerlBrdf>,
+}
+
+impl MeasuredBuilder {
+    pub fn new(brdf: impl Into<Arc<MerlBrdf>>) -> MeasuredBuilder {
+        MeasuredBuilder { brdf: brdf.into() }
+    }
+
+    pub fn build(self) -> Measured {
+        let tables = (0..THETA_O_BINS)
+            .map(|i| {
+                let theta_o = (i as f32 + 0.5) / THETA_O_BINS as f32 * FRAC_PI_2;
+                HalfVectorTable::new(&self.brdf, theta_o)
+            })
+            .collect();
+        Measured {
+            brdf: self.brdf,
+            tables,
+        }
+    }
+}
+
+impl Material for Measured {
+    fn scatter(&self, r_in: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        let mut rng = rand::thread_rng();
+        let rv = Vec2::new(rng.gen(), rng.gen());
+        let srec = self.sample(&r_in.direction, hit, rv)?;
+        let ray_out = Ray::new(hit.p, srec.wo).with_time(r_in.time);
+        Some((srec.attenuation, ray_out))
+    }
+
+    fn emmitted(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Vec3> {
+        None
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        let uvw = Onb::build_from_w(&hit.sn);
+        let wo = uvw.to_local(&normalize(&-wi));
+        let wl = uvw.to_local(&normalize(scattered));
+        self.brdf.eval(&wl, &wo) * wl.z.max(0.0)
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(&hit.sn);
+        let wo = uvw.to_local(&normalize(&-wi));
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let wl = if rv.x < COSINE_PROBABILITY {
+            sample_hemisphere_cosine(Vec2::new(rv.x / COSINE_PROBABILITY, rv.y))
+        } else {
+            let u = (rv.x - COSINE_PROBABILITY) / (1.0 - COSINE_PROBABILITY);
+            let (theta_h, phi_h) = self.table(wo.z).sample(Vec2::new(u, rv.y));
+            let h = spherical_direction(theta_h, phi_h + wo.y.atan2(wo.x));
+            reflect(&-wo, &h)
+        };
+        if wl.z <= 0.0 {
+            return None;
+        }
+
+        let scattered = uvw.local(&wl);
+        let pdf = self.pdf(wi, &scattered, hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let srec = ScatterRecord {
+            attenuation: self.eval(wi, &scattered, hit) / pdf,
+            wo: scattered,
+            is_specular: false,
+        };
+        Some(srec)
+    }
+
+    fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        let uvw = Onb::build_from_w(&hit.sn);
+        let wo = uvw.to_local(&normalize(&-wi));
+        let wl = uvw.to_local(&normalize(scattered));
+        if wo.z <= 0.0 || wl.z <= 0.0 {
+            return 0.0;
+        }
+        COSINE_PROBABILITY * sample_hemisphere_cosine_pdf(&wl)
+            + (1.0 - COSINE_PROBABILITY) * self.tabulated_pdf(&wo, &wl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use serde_json::json;
+    use std::f32::consts::FRAC_PI_2;
+    use std::sync::Arc;
+    use tempfile::NamedTempFile;
+
+    use crate::core::merl::{MerlBrdf, RES_PHI_D, RES_THETA_D, RES_THETA_H, SAMPLE_COUNT};
+    use crate::materials::{Measured, MeasuredBuilder};
+    use crate::tests::sample_test::MaterialTest;
+
+    /// Glossy BRDF over a diffuse base, written to a temporary MERL file
+    fn write_glossy_merl() -> (NamedTempFile, MerlBrdf) {
+        let mut bytes = Vec::new();
+        for dim in [RES_THETA_H as i32, RES_THETA_D as i32, RES_PHI_D as i32] {
+            bytes.extend_from_slice(&dim.to_le_bytes());
+        }
+        let mut data = Vec::with_capacity(3 * SAMPLE_COUNT);
+        for scale in [1.0, 1.15, 1.66] {
+            for index in 0..SAMPLE_COUNT {
+                let t = (index / (RES_PHI_D * RES_THETA_D)) as f64 / RES_THETA_H as f64;
+                let theta_h = t * t * FRAC_PI_2 as f64;
+                let value = 0.1 + 2.0 * f64::exp(-theta_h * theta_h / 0.02);
+                bytes.extend_from_slice(&(value * 1500.0 / scale).to_le_bytes());
+                data.push(value as f32);
+            }
+        }
+        let file = tempfile::Builder::new().suffix(".binary").tempfile().unwrap();
+        std::fs::write(file.path(), bytes).unwrap();
+        (file, MerlBrdf::new(data))
+    }
+
+    #[test]
+    fn builder_matches_json() {
+        let (file, brdf) = write_glossy_merl();
+        let v = json!({"type": "measured", "filename": file.path()});
+        let from_json = Measured::new(&v);
+        let from_builder = MeasuredBuilder::new(Arc::new(brdf)).build();
+        approx::assert_abs_diff_eq!(
+            from_json
+                .brdf
+                .eval(&Vec3::new(0.3, 0.2, 0.9).normalize(), &Vec3::z()),
+            from_builder
+                .brdf
+                .eval(&Vec3::new(0.3, 0.2, 0.9).normalize(), &Vec3::z()),
+            epsilon = 1e-5
+        );
+        assert_eq!(from_json.tables.len(), from_builder.tables.len());
+    }
+
+    #[test]
+    fn measured_monte_carlo() {
+        let (file, _) = write_glossy_merl();
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "measured",
+                "filename": file.path()
+            },
+            "normal": [
+                0.25, 0.5, 1.0
+            ],
+            "name": "measured"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        parameters.run(&test, 1.0, 1e-2);
+    }
+}
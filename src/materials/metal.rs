@@ -8,7 +8,7 @@ use crate::core::utils::{luminance, reflect};
 use crate::materials::Material;
 use crate::surfaces::HitInfo;
 use crate::surfaces::ScatterRecord;
-use crate::textures::{create_texture, Texture, TextureType};
+use crate::textures::{create_linear_texture, create_texture, Texture, TextureType};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Metal {
@@ -18,9 +18,10 @@ pub struct Metal {
 
 impl Metal {
     pub fn new(v: &Value) -> Metal {
-        let albedo = create_texture(v, "albedo");
-        let roughness = create_texture(v, "roughness");
-        Metal { albedo, roughness }
+        MetalBuilder::new()
+            .albedo(create_texture(v, "albedo"))
+            .roughness(create_linear_texture(v, "roughness"))
+            .build()
     }
 
     fn _scatter(&self, r_in: &Ray, hit: &HitInfo, rv: Vec2) -> Option<(Vec3, Ray)> {
@@ -32,12 +33,50 @@ impl Metal {
             return None;
         }
         let attenuation = self.albedo.value(hit).unwrap();
-        let ray_out = Ray::new(hit.p, scatter_direction.normalize());
+        let ray_out = Ray::new(hit.p, scatter_direction.normalize()).with_time(r_in.time);
 
         Some((attenuation, ray_out))
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct MetalBuilder {
+    albedo: TextureType,
+    roughness: TextureType,
+}
+
+impl MetalBuilder {
+    pub fn new() -> MetalBuilder {
+        MetalBuilder {
+            albedo: TextureType::from(1.0),
+            roughness: TextureType::from(0.0),
+        }
+    }
+
+    pub fn albedo(mut self, albedo: impl Into<TextureType>) -> MetalBuilder {
+        self.albedo = albedo.into();
+        self
+    }
+
+    pub fn roughness(mut self, roughness: impl Into<TextureType>) -> MetalBuilder {
+        self.roughness = roughness.into();
+        self
+    }
+
+    pub fn build(self) -> Metal {
+        Metal {
+            albedo: self.albedo,
+            roughness: self.roughness,
+        }
+    }
+}
+
+impl Default for MetalBuilder {
+    fn default() -> MetalBuilder {
+        MetalBuilder::new()
+    }
+}
+
 impl Material for Metal {
     fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
         let mut rng = rand::thread_rng();
@@ -78,6 +117,7 @@ mod tests {
     use nalgebra_glm::{Vec2, Vec3};
     use serde_json::json;
 
+    use crate::core::onb::Onb;
     use crate::core::ray::Ray;
     use crate::materials::{Material, MaterialFactory};
     use crate::surfaces::HitInfo;
@@ -98,13 +138,19 @@ mod tests {
         // Let's create a fictitious hitpoint
         let surface_point = Vec3::new(1.0, 2.0, 0.0);
         let normal = Vec3::new(1.0, 2.0, -1.0).normalize();
+        let uvw = Onb::build_from_w(&normal);
         let hit = HitInfo {
             t: 0.0,
             p: surface_point,
             uv: Vec2::new(0.0, 0.0),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
             gn: normal,
             sn: normal,
+            dpdu: uvw.local(&Vec3::x()),
+            dpdv: uvw.local(&Vec3::y()),
             mat: metal_material.clone(),
+            emitter: None,
         };
 
         // And a fictitious ray
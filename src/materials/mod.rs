@@ -1,10 +1,17 @@
 mod blinn_phong;
+mod coated;
+mod conductor;
 mod dielectric;
 mod diffuse_light;
 mod fresnel_blend;
 mod lambertian;
+mod measured;
 mod metal;
+mod normal_mapped;
+mod oren_nayar;
 mod phong;
+mod principled;
+mod rough_dielectric;
 
 use enum_dispatch::enum_dispatch;
 use nalgebra_glm::{Vec2, Vec3};
@@ -54,14 +61,23 @@ pub trait Material {
     fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32;
 }
 
-use crate::materials::blinn_phong::BlinnPhong;
-use crate::materials::dielectric::Dielectric;
-use crate::materials::diffuse_light::DiffuseLight;
-use crate::materials::fresnel_blend::FresnelBlend;
-use crate::materials::lambertian::Lambertian;
-use crate::materials::metal::Metal;
-use crate::materials::phong::Phong;
+pub use crate::materials::blinn_phong::{BlinnPhong, BlinnPhongBuilder};
+pub use crate::materials::coated::{Coated, CoatedBuilder};
+pub use crate::materials::conductor::{Conductor, ConductorBuilder};
+pub use crate::materials::dielectric::{Dielectric, DielectricBuilder};
+pub use crate::materials::diffuse_light::{DiffuseLight, DiffuseLightBuilder};
+pub use crate::materials::fresnel_blend::{FresnelBlend, FresnelBlendBuilder};
+pub use crate::materials::lambertian::{Lambertian, LambertianBuilder};
+pub use crate::materials::measured::{Measured, MeasuredBuilder};
+pub use crate::materials::metal::{Metal, MetalBuilder};
+pub use crate::materials::normal_mapped::{NormalMapped, NormalMappedBuilder};
+pub use crate::materials::oren_nayar::{OrenNayar, OrenNayarBuilder};
+pub use crate::materials::phong::{Phong, PhongBuilder};
+pub use crate::materials::principled::{Principled, PrincipledBuilder};
+pub use crate::materials::rough_dielectric::{RoughDielectric, RoughDielectricBuilder};
 
+// materials are always shared behind an Arc, so the size of the largest variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[enum_dispatch(Material)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum MaterialType {
@@ -72,6 +88,89 @@ pub enum MaterialType {
     FresnelBlend(FresnelBlend),
     Phong(Phong),
     BlinnPhong(BlinnPhong),
+    Conductor(Conductor),
+    RoughDielectric(RoughDielectric),
+    Principled(Principled),
+    OrenNayar(OrenNayar),
+    Coated(Coated),
+    NormalMapped(NormalMapped),
+    Measured(Measured),
+    Custom(CustomMaterial),
+}
+
+/// Lets the surface builders take a material by value as well as a shared one
+macro_rules! impl_into_shared_material {
+    ($($material:ty),*) => {
+        $(
+            impl From<$material> for Arc<MaterialType> {
+                fn from(material: $material) -> Arc<MaterialType> {
+                    Arc::new(MaterialType::from(material))
+                }
+            }
+        )*
+    };
+}
+
+impl_into_shared_material!(
+    Lambertian,
+    Dielectric,
+    Metal,
+    DiffuseLight,
+    FresnelBlend,
+    Phong,
+    BlinnPhong,
+    Conductor,
+    RoughDielectric,
+    Principled,
+    OrenNayar,
+    Coated,
+    NormalMapped,
+    Measured,
+    CustomMaterial
+);
+
+/// Wrapper for materials defined outside of this crate.
+///
+/// Register them by name in `MaterialFactory::materials` to reference them from a scene file.
+#[derive(Clone)]
+pub struct CustomMaterial(pub Arc<dyn Material + Send + Sync>);
+
+impl std::fmt::Debug for CustomMaterial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CustomMaterial")
+    }
+}
+
+impl PartialEq for CustomMaterial {
+    fn eq(&self, other: &CustomMaterial) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Material for CustomMaterial {
+    fn scatter(&self, r_in: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        self.0.scatter(r_in, hit)
+    }
+
+    fn emmitted(&self, ray: &Ray, hit: &HitInfo) -> Option<Vec3> {
+        self.0.emmitted(ray, hit)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.0.is_emissive()
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        self.0.eval(wi, scattered, hit)
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        self.0.sample(wi, hit, rv)
+    }
+
+    fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        self.0.pdf(wi, scattered, hit)
+    }
 }
 
 pub struct MaterialFactory {
@@ -100,13 +199,30 @@ impl MaterialFactory {
             "fresnel_blend" => MaterialType::FresnelBlend(FresnelBlend::new(v, self)),
             "phong" => MaterialType::Phong(Phong::new(v)),
             "blinn_phong" => MaterialType::BlinnPhong(BlinnPhong::new(v)),
+            "conductor" => MaterialType::Conductor(Conductor::new(v)),
+            "rough_dielectric" => MaterialType::RoughDielectric(RoughDielectric::new(v)),
+            "principled" => MaterialType::Principled(Principled::new(v)),
+            "oren_nayar" => MaterialType::OrenNayar(OrenNayar::new(v)),
+            "coated" => MaterialType::Coated(Coated::new(v, self)),
+            "measured" => MaterialType::Measured(Measured::new(v)),
             _ => unimplemented!("The material type '{}' ", type_material),
         };
 
+        // any material can have its shading normal perturbed
+        if v.get("normal_map").is_some() || v.get("bump_map").is_some() {
+            let mapped = NormalMapped::new(v, Arc::new(material));
+            return Arc::new(MaterialType::NormalMapped(mapped));
+        }
         Arc::new(material)
     }
 }
 
+impl Default for MaterialFactory {
+    fn default() -> MaterialFactory {
+        MaterialFactory::new()
+    }
+}
+
 impl Factory<Arc<MaterialType>> for MaterialFactory {
     fn make(&mut self, v: &Value) -> Option<Vec<Arc<MaterialType>>> {
         let m = v.as_object().unwrap();
@@ -121,3 +237,59 @@ impl Factory<Arc<MaterialType>> for MaterialFactory {
         Some(vec![material])
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    use crate::core::ray::Ray;
+    use crate::core::utils::Factory;
+    use crate::materials::{CustomMaterial, Material, MaterialFactory, MaterialType};
+    use crate::surfaces::{HitInfo, ScatterRecord, Surface, SurfaceFactory};
+
+    /// Material returning a constant value everywhere, as a downstream crate would write it
+    struct Grey;
+
+    impl Material for Grey {
+        fn scatter(&self, _r_in: &Ray, _hit: &HitInfo) -> Option<(Vec3, Ray)> {
+            None
+        }
+        fn emmitted(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Vec3> {
+            None
+        }
+        fn is_emissive(&self) -> bool {
+            false
+        }
+        fn eval(&self, _wi: &Vec3, _scattered: &Vec3, _hit: &HitInfo) -> Vec3 {
+            Vec3::new(0.5, 0.5, 0.5)
+        }
+        fn sample(&self, _wi: &Vec3, _hit: &HitInfo, _rv: Vec2) -> Option<ScatterRecord> {
+            None
+        }
+        fn pdf(&self, _wi: &Vec3, _scattered: &Vec3, _hit: &HitInfo) -> f32 {
+            1.0
+        }
+    }
+
+    #[test]
+    fn custom_material_by_name() {
+        let mut sf = SurfaceFactory {
+            material_factory: MaterialFactory::new(),
+        };
+        let grey = Arc::new(MaterialType::Custom(CustomMaterial(Arc::new(Grey))));
+        sf.material_factory
+            .materials
+            .insert("grey".to_string(), grey.clone());
+
+        let surfaces = sf.make(&json!({"type": "sphere", "material": "grey"}));
+        let sphere = &surfaces.unwrap()[0];
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 4.0), Vec3::new(0.0, 0.0, -1.0));
+        let hit = sphere.intersect(&ray).expect("should hit the sphere");
+
+        assert_eq!(hit.mat, grey);
+        let value = hit.mat.eval(&ray.direction, &hit.sn, &hit);
+        approx::assert_abs_diff_eq!(value, Vec3::new(0.5, 0.5, 0.5));
+    }
+}
@@ -0,0 +1,318 @@
+use nalgebra_glm::{cross, dot, normalize, Vec2, Vec3};
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::core::onb::Onb;
+use crate::core::ray::Ray;
+use crate::core::utils::{luminance, read_or};
+use crate::materials::{Material, MaterialType};
+use crate::surfaces::{HitInfo, ScatterRecord};
+use crate::textures::{create_linear_texture, Texture, TextureType};
+
+/// Offset in texture space used to differentiate the bump map
+const BUMP_DELTA: f32 = 0.0005;
+
+/// Perturbs the shading normal of another material with a normal map or a bump map.
+///
+/// The normal map is expressed in the tangent frame built from `dpdu` and the shading normal, with
+/// each component encoded in [0, 1] like the usual RGB normal maps. Its values are decoded as they
+/// are, so image normal maps must be stored linearly. The bump map is a scalar height field along
+/// the shading normal, scaled by `bump_scale`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NormalMapped {
+    material: Arc<MaterialType>,
+    normal_map: Option<TextureType>,
+    bump_map: Option<TextureType>,
+    bump_scale: f32,
+}
+
+impl NormalMapped {
+    /// Wrap `material` with the maps found in the material description `v`
+    pub fn new(v: &Value, material: Arc<MaterialType>) -> NormalMapped {
+        let mut builder =
+            NormalMappedBuilder::new(material).bump_scale(read_or(v, "bump_scale", 1.0));
+        if v.get("normal_map").is_some() {
+            builder = builder.normal_map(create_linear_texture(v, "normal_map"));
+        }
+        if v.get("bump_map").is_some() {
+            builder = builder.bump_map(create_linear_texture(v, "bump_map"));
+        }
+        builder.build()
+    }
+
+    /// Copy of the hit record with the perturbed shading normal and tangents
+    fn perturb(&self, hit: &HitInfo) -> HitInfo {
+        let mut sn = hit.sn;
+        if let Some(bump_map) = &self.bump_map {
+            sn = self.bump(bump_map, hit);
+        }
+        if let Some(normal_map) = &self.normal_map {
+            let (tangent, bitangent) = tangent_frame(&sn, &hit.dpdu, &hit.dpdv);
+            let encoded = normal_map.value(hit).unwrap();
+            let local = 2.0 * encoded - Vec3::new(1.0, 1.0, 1.0);
+            sn = normalize(&(local.x * tangent + local.y * bitangent + local.z * sn));
+        }
+        // a perturbed normal pointing through the surface would leak light, keep the original one
+        if !sn.iter().all(|c| c.is_finite()) || dot(&sn, &hit.gn) * dot(&hit.sn, &hit.gn) <= 0.0 {
+            sn = hit.sn;
+        }
+
+        HitInfo {
+            t: hit.t,
+            p: hit.p,
+            gn: hit.gn,
+            sn,
+            dpdu: hit.dpdu - dot(&hit.dpdu, &sn) * sn,
+            dpdv: hit.dpdv - dot(&hit.dpdv, &sn) * sn,
+            uv: hit.uv,
+            duvdx: hit.duvdx,
+            duvdy: hit.duvdy,
+            mat: Arc::clone(&hit.mat),
+            emitter: hit.emitter,
+        }
+    }
+
+    /// Shading normal of the surface displaced by the bump map, from forward differences
+    fn bump(&self, bump_map: &TextureType, hit: &HitInfo) -> Vec3 {
+        let height = |uv: Vec2, p: Vec3| {
+            let shifted = HitInfo {
+                t: hit.t,
+                p,
+                gn: hit.gn,
+                sn: hit.sn,
+                dpdu: hit.dpdu,
+                dpdv: hit.dpdv,
+                uv,
+                duvdx: hit.duvdx,
+                duvdy: hit.duvdy,
+                mat: Arc::clone(&hit.mat),
+                emitter: hit.emitter,
+            };
+            self.bump_scale * luminance(&bump_map.value(&shifted).unwrap())
+        };
+        let displacement = height(hit.uv, hit.p);
+        let displacement_u = height(
+            hit.uv + Vec2::new(BUMP_DELTA, 0.0),
+            hit.p + BUMP_DELTA * hit.dpdu,
+        );
+        let displacement_v = height(
+            hit.uv + Vec2::new(0.0, BUMP_DELTA),
+            hit.p + BUMP_DELTA * hit.dpdv,
+        );
+
+        let dpdu = hit.dpdu + (displacement_u - displacement) / BUMP_DELTA * hit.sn;
+        let dpdv = hit.dpdv + (displacement_v - displacement) / BUMP_DELTA * hit.sn;
+        let n = normalize(&cross(&dpdu, &dpdv));
+        if dot(&n, &hit.sn) < 0.0 {
+            -n
+        } else {
+            n
+        }
+    }
+}
+
+/// Orthonormal tangent and bitangent around `n`, following the direction of the surface derivatives
+fn tangent_frame(n: &Vec3, dpdu: &Vec3, dpdv: &Vec3) -> (Vec3, Vec3) {
+    let tangent = dpdu - dot(dpdu, n) * n;
+    if tangent.norm_squared() < 1e-12 {
+        let uvw = Onb::build_from_w(n);
+        return (uvw.local(&Vec3::x()), uvw.local(&Vec3::y()));
+    }
+    let tangent = normalize(&tangent);
+    let bitangent = cross(n, &tangent);
+    // mirrored texture coordinates flip the bitangent
+    if dot(&bitangent, dpdv) < 0.0 {
+        (tangent, -bitangent)
+    } else {
+        (tangent, bitangent)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NormalMappedBuilder {
+    material: Arc<MaterialType>,
+    normal_map: Option<TextureType>,
+    bump_map: Option<TextureType>,
+    bump_scale: f32,
+}
+
+impl NormalMappedBuilder {
+    pub fn new(material: impl Into<Arc<MaterialType>>) -> NormalMappedBuilder {
+        NormalMappedBuilder {
+            material: material.into(),
+            normal_map: None,
+            bump_map: None,
+            bump_scale: 1.0,
+        }
+    }
+
+    /// Tangent space normals, encoded in [0, 1]
+    pub fn normal_map(mut self, normal_map: impl Into<TextureType>) -> NormalMappedBuilder {
+        self.normal_map = Some(normal_map.into());
+        self
+    }
+
+    /// Height field along the shading normal, its luminance is used for colored textures
+    pub fn bump_map(mut self, bump_map: impl Into<TextureType>) -> NormalMappedBuilder {
+        self.bump_map = Some(bump_map.into());
+        self
+    }
+
+    pub fn bump_scale(mut self, bump_scale: f32) -> NormalMappedBuilder {
+        self.bump_scale = bump_scale;
+        self
+    }
+
+    pub fn build(self) -> NormalMapped {
+        NormalMapped {
+            material: self.material,
+            normal_map: self.normal_map,
+            bump_map: self.bump_map,
+            bump_scale: self.bump_scale,
+        }
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, r_in: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        self.material.scatter(r_in, &self.perturb(hit))
+    }
+
+    fn emmitted(&self, ray: &Ray, hit: &HitInfo) -> Option<Vec3> {
+        self.material.emmitted(ray, &self.perturb(hit))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        self.material.eval(wi, scattered, &self.perturb(hit))
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        self.material.sample(wi, &self.perturb(hit), rv)
+    }
+
+    fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        self.material.pdf(wi, scattered, &self.perturb(hit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    use crate::materials::{
+        LambertianBuilder, MaterialFactory, MaterialType, NormalMapped, NormalMappedBuilder,
+    };
+    use crate::surfaces::HitInfo;
+    use crate::tests::sample_test::MaterialTest;
+
+    fn flat_hit(material: Arc<MaterialType>) -> HitInfo {
+        HitInfo {
+            t: 1.0,
+            p: Vec3::zeros(),
+            gn: Vec3::z(),
+            sn: Vec3::z(),
+            dpdu: Vec3::x(),
+            dpdv: Vec3::y(),
+            uv: Vec2::new(0.5, 0.5),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
+            mat: material,
+            emitter: None,
+        }
+    }
+
+    #[test]
+    fn factory_wraps_mapped_materials() {
+        let mf = MaterialFactory::new();
+        let v = json!({
+            "type": "lambertian",
+            "albedo": 0.5,
+            "normal_map": [0.5, 0.5, 1.0],
+            "bump_scale": 0.1
+        });
+        let from_builder = NormalMappedBuilder::new(LambertianBuilder::new().albedo(0.5).build())
+            .normal_map(Vec3::new(0.5, 0.5, 1.0))
+            .bump_scale(0.1)
+            .build();
+        assert_eq!(*mf.create_material(&v), MaterialType::from(from_builder));
+
+        let plain = mf.create_material(&json!({"type": "lambertian", "albedo": 0.5}));
+        assert!(matches!(*plain, MaterialType::Lambertian(_)));
+    }
+
+    #[test]
+    fn flat_normal_map_keeps_the_normal() {
+        let material = NormalMappedBuilder::new(LambertianBuilder::new().build())
+            .normal_map(Vec3::new(0.5, 0.5, 1.0))
+            .build();
+        let hit = flat_hit(Arc::new(material.clone().into()));
+        approx::assert_abs_diff_eq!(material.perturb(&hit).sn, Vec3::z(), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn normal_map_follows_the_tangents() {
+        // tilted towards +u in tangent space
+        let material = NormalMappedBuilder::new(LambertianBuilder::new().build())
+            .normal_map(Vec3::new(1.0, 0.5, 1.0))
+            .build();
+        let mut hit = flat_hit(Arc::new(material.clone().into()));
+        hit.dpdu = Vec3::y();
+        hit.dpdv = -Vec3::x();
+        let expected = Vec3::new(0.0, 1.0, 1.0).normalize();
+        approx::assert_abs_diff_eq!(material.perturb(&hit).sn, expected, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn bump_map_tilts_the_normal() {
+        // the checker edge at x = 1 is a step down in height along u
+        let v = json!({
+            "type": "lambertian",
+            "albedo": 0.5,
+            "bump_map": {
+                "type": "checker",
+                "even": 1.0,
+                "odd": 0.0,
+                "scale": std::f32::consts::PI
+            },
+            "bump_scale": 1e-4
+        });
+        let mf = MaterialFactory::new();
+        let material = mf.create_material(&v);
+        let MaterialType::NormalMapped(mapped) = &*material else {
+            panic!("a bump mapped material should be wrapped");
+        };
+        let mut hit = flat_hit(material.clone());
+        hit.p = Vec3::new(0.9999, 0.5, 0.5);
+        let expected = Vec3::new(0.2, 0.0, 1.0).normalize();
+        approx::assert_abs_diff_eq!(mapped.perturb(&hit).sn, expected, epsilon = 1e-4);
+
+        // a constant bump map leaves the surface flat
+        let flat = NormalMapped::new(&json!({"bump_map": 0.3}), mapped.material.clone());
+        approx::assert_abs_diff_eq!(flat.perturb(&hit).sn, Vec3::z(), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn normal_mapped_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "lambertian",
+                "albedo": 1.0,
+                "normal_map": [0.55, 0.45, 1.0]
+            },
+            "normal": [
+                0.25, 0.5, 1.0
+            ],
+            "name": "normal-mapped"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        parameters.run(&test, 1.0, 1e-2);
+    }
+}
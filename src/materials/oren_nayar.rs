@@ -0,0 +1,206 @@
+use nalgebra_glm::{dot, normalize, Vec2, Vec3};
+use rand::Rng;
+use serde_json::Value;
+use std::f32::consts::{FRAC_1_PI, FRAC_PI_2};
+
+use crate::core::onb::Onb;
+use crate::core::ray::Ray;
+use crate::core::sampling::{sample_hemisphere_cosine, sample_hemisphere_cosine_pdf};
+use crate::core::utils::luminance;
+use crate::materials::Material;
+use crate::surfaces::{HitInfo, ScatterRecord};
+use crate::textures::{create_linear_texture, create_texture, Texture, TextureType};
+
+/// Rough diffuse surface made of V-shaped lambertian facets.
+///
+/// Uses Fujii's improved Oren-Nayar model, which keeps the reflectance below the albedo for every
+/// roughness `sigma` in [0, 1]. A `sigma` of zero gives back the lambertian model.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OrenNayar {
+    albedo: TextureType,
+    sigma: TextureType,
+}
+
+impl OrenNayar {
+    pub fn new(v: &Value) -> OrenNayar {
+        OrenNayarBuilder::new()
+            .albedo(create_texture(v, "albedo"))
+            .sigma(create_linear_texture(v, "sigma"))
+            .build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OrenNayarBuilder {
+    albedo: TextureType,
+    sigma: TextureType,
+}
+
+impl OrenNayarBuilder {
+    pub fn new() -> OrenNayarBuilder {
+        OrenNayarBuilder {
+            albedo: TextureType::from(0.5),
+            sigma: TextureType::from(0.5),
+        }
+    }
+
+    pub fn albedo(mut self, albedo: impl Into<TextureType>) -> OrenNayarBuilder {
+        self.albedo = albedo.into();
+        self
+    }
+
+    /// Roughness of the surface in [0, 1]
+    pub fn sigma(mut self, sigma: impl Into<TextureType>) -> OrenNayarBuilder {
+        self.sigma = sigma.into();
+        self
+    }
+
+    pub fn build(self) -> OrenNayar {
+        OrenNayar {
+            albedo: self.albedo,
+            sigma: self.sigma,
+        }
+    }
+}
+
+impl Default for OrenNayarBuilder {
+    fn default() -> OrenNayarBuilder {
+        OrenNayarBuilder::new()
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, ray: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        let mut rng = rand::thread_rng();
+        let rv = Vec2::new(rng.gen(), rng.gen());
+        let srec = self.sample(&ray.direction, hit, rv)?;
+        let ray_out = Ray::new(hit.p, srec.wo).with_time(ray.time);
+        Some((srec.attenuation, ray_out))
+    }
+
+    fn emmitted(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Vec3> {
+        None
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        let wo = -normalize(wi);
+        let wl = normalize(scattered);
+        let cos_o = dot(&wo, &hit.sn);
+        let cos_l = dot(&wl, &hit.sn);
+        if cos_l <= 0.0 {
+            return Vec3::zeros();
+        }
+
+        let sigma = luminance(&self.sigma.value(hit).unwrap()).max(0.0);
+        let a = 1.0 / (1.0 + (FRAC_PI_2 - 2.0 / 3.0) * sigma);
+        let b = sigma * a;
+
+        // s / t grows when the two directions are on the same side, like retro-reflection
+        let s = dot(&wo, &wl) - cos_o * cos_l;
+        let t = if s > 0.0 { f32::max(cos_o, cos_l) } else { 1.0 };
+        let factor = a + b * s / t;
+
+        self.albedo.value(hit).unwrap() * FRAC_1_PI * factor * cos_l
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        let uvw = Onb::build_from_w(&hit.sn);
+        let scattered = uvw.local(&sample_hemisphere_cosine(rv));
+        let pdf = self.pdf(wi, &scattered, hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let srec = ScatterRecord {
+            attenuation: self.eval(wi, &scattered, hit) / pdf,
+            wo: scattered,
+            is_specular: false,
+        };
+        Some(srec)
+    }
+
+    fn pdf(&self, _wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        let uvw = Onb::build_from_w(&hit.sn);
+        f32::max(
+            0.0,
+            sample_hemisphere_cosine_pdf(&uvw.to_local(&normalize(scattered))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+
+    use crate::core::onb::Onb;
+    use crate::materials::{LambertianBuilder, Material, OrenNayar, OrenNayarBuilder};
+    use crate::surfaces::HitInfo;
+    use crate::tests::sample_test::MaterialTest;
+
+    #[test]
+    fn builder_matches_json() {
+        let v = json!({"type": "oren_nayar", "albedo": [0.7, 0.6, 0.5], "sigma": 0.8});
+        let from_builder = OrenNayarBuilder::new()
+            .albedo(Vec3::new(0.7, 0.6, 0.5))
+            .sigma(0.8)
+            .build();
+        assert_eq!(OrenNayar::new(&v), from_builder);
+    }
+
+    #[test]
+    fn smooth_oren_nayar_is_lambertian() {
+        let oren_nayar = OrenNayarBuilder::new().albedo(0.8).sigma(0.0).build();
+        let lambertian = LambertianBuilder::new().albedo(0.8).build();
+        let normal = Vec3::new(0.3, -0.2, 1.0).normalize();
+        let uvw = Onb::build_from_w(&normal);
+        let hit = HitInfo {
+            t: 1.0,
+            p: Vec3::zeros(),
+            gn: normal,
+            sn: normal,
+            dpdu: uvw.local(&Vec3::x()),
+            dpdv: uvw.local(&Vec3::y()),
+            uv: Vec2::new(0.5, 0.5),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
+            mat: std::sync::Arc::new(oren_nayar.clone().into()),
+            emitter: None,
+        };
+        let wi = Vec3::new(0.4, 0.1, -1.0).normalize();
+        for scattered in [
+            Vec3::z(),
+            Vec3::new(0.5, 0.5, 0.3),
+            Vec3::new(-0.9, 0.1, 0.2),
+        ] {
+            let scattered = scattered.normalize();
+            approx::assert_abs_diff_eq!(
+                oren_nayar.eval(&wi, &scattered, &hit),
+                lambertian.eval(&wi, &scattered, &hit),
+                epsilon = 1e-5
+            );
+        }
+    }
+
+    #[test]
+    fn oren_nayar_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "oren_nayar",
+                "albedo": 1.0,
+                "sigma": 1.0
+            },
+            "normal": [
+                0.25, 0.5, 1.0
+            ],
+            "name": "oren-nayar"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        parameters.run(&test, 1.0, 1e-2);
+    }
+}
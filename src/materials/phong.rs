@@ -17,9 +17,48 @@ pub struct Phong {
 
 impl Phong {
     pub fn new(v: &Value) -> Phong {
-        let albedo = create_texture(v, "albedo");
-        let exponent = read_or(v, "exponent", 1.0);
-        Phong { albedo, exponent }
+        PhongBuilder::new()
+            .albedo(create_texture(v, "albedo"))
+            .exponent(read_or(v, "exponent", 1.0))
+            .build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PhongBuilder {
+    albedo: TextureType,
+    exponent: f32,
+}
+
+impl PhongBuilder {
+    pub fn new() -> PhongBuilder {
+        PhongBuilder {
+            albedo: TextureType::from(0.5),
+            exponent: 1.0,
+        }
+    }
+
+    pub fn albedo(mut self, albedo: impl Into<TextureType>) -> PhongBuilder {
+        self.albedo = albedo.into();
+        self
+    }
+
+    pub fn exponent(mut self, exponent: f32) -> PhongBuilder {
+        self.exponent = exponent;
+        self
+    }
+
+    pub fn build(self) -> Phong {
+        Phong {
+            albedo: self.albedo,
+            exponent: self.exponent,
+        }
+    }
+}
+
+impl Default for PhongBuilder {
+    fn default() -> PhongBuilder {
+        PhongBuilder::new()
     }
 }
 
@@ -40,24 +79,24 @@ impl Material for Phong {
     }
 
     fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
-        let mirror_dir = normalize(&reflect(wi, &hit.gn));
+        let mirror_dir = normalize(&reflect(wi, &hit.sn));
         let uvw = Onb::build_from_w(&mirror_dir);
         let srec = ScatterRecord {
             attenuation: self.albedo.value(hit).unwrap(),
             wo: uvw.local(&sample_hemisphere_cosine_power(self.exponent, rv)),
             is_specular: false,
         };
-        if dot(&srec.wo, &hit.gn) >= 0.0 {
+        if dot(&srec.wo, &hit.sn) >= 0.0 {
             return Some(srec);
         }
         None
     }
 
     fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
-        let mirror_dir = normalize(&reflect(wi, &hit.gn));
+        let mirror_dir = normalize(&reflect(wi, &hit.sn));
         let cosine = f32::max(dot(&normalize(scattered), &mirror_dir), 0.0);
         let pdf = sample_hemisphere_cosine_power_pdf(self.exponent, cosine);
-        if dot(scattered, &hit.gn) >= 0.0 {
+        if dot(scattered, &hit.sn) >= 0.0 {
             pdf
         } else {
             0.0
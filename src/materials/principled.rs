@@ -0,0 +1,577 @@
+use nalgebra_glm::{dot, lerp, normalize, Vec2, Vec3};
+use rand::Rng;
+use serde_json::Value;
+use std::f32::consts::FRAC_1_PI;
+
+use crate::core::microfacet::{
+    fresnel_dielectric, fresnel_schlick, half_vector_transmission, refract, transmission_jacobian,
+    Ggx,
+};
+use crate::core::onb::Onb;
+use crate::core::ray::Ray;
+use crate::core::sampling::sample_hemisphere_cosine;
+use crate::core::utils::{luminance, reflect};
+use crate::materials::Material;
+use crate::surfaces::{HitInfo, ScatterRecord};
+use crate::textures::{create_linear_texture, create_texture, Texture, TextureType};
+
+/// Disney-style uber material: a diffuse base with sheen, a specular layer that turns into a metal,
+/// a clearcoat on top, and rough transmission for glass-like surfaces.
+///
+/// All the parameters are in [0, 1], except the index of refraction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Principled {
+    base_color: TextureType,
+    metallic: TextureType,
+    roughness: TextureType,
+    specular: TextureType,
+    specular_tint: TextureType,
+    sheen: TextureType,
+    sheen_tint: TextureType,
+    clearcoat: TextureType,
+    clearcoat_gloss: TextureType,
+    transmission: TextureType,
+    anisotropic: TextureType,
+    ior: TextureType,
+}
+
+/// The parameters evaluated at a hitpoint, in the shading frame of the viewer
+struct Lobes {
+    uvw: Onb,
+    /// Direction towards the viewer, always in the upper hemisphere
+    wo: Vec3,
+    /// Index of refraction of the side opposite to the viewer over the viewer side
+    eta: f32,
+    base_color: Vec3,
+    metallic: f32,
+    roughness: f32,
+    /// Weight of the diffuse and sheen lobes, zero on the inside of transmissive surfaces
+    diffuse: f32,
+    /// Tint of the dielectric specular reflection
+    specular_color: Vec3,
+    sheen_color: Vec3,
+    clearcoat: f32,
+    transmission: f32,
+    ggx: Ggx,
+    ggx_clearcoat: Ggx,
+    /// Probabilities of sampling the diffuse, specular, clearcoat and transmission lobes
+    weights: [f32; 4],
+}
+
+impl Principled {
+    pub fn new(v: &Value) -> Principled {
+        let mut builder = PrincipledBuilder::new();
+        // every parameter is optional and defaults to the builder's value, only the base color is a color
+        let texture = |name: &str| v.get(name).map(|_| create_linear_texture(v, name));
+        if let Some(t) = v.get("base_color").map(|_| create_texture(v, "base_color")) {
+            builder = builder.base_color(t);
+        }
+        if let Some(t) = texture("metallic") {
+            builder = builder.metallic(t);
+        }
+        if let Some(t) = texture("roughness") {
+            builder = builder.roughness(t);
+        }
+        if let Some(t) = texture("specular") {
+            builder = builder.specular(t);
+        }
+        if let Some(t) = texture("specular_tint") {
+            builder = builder.specular_tint(t);
+        }
+        if let Some(t) = texture("sheen") {
+            builder = builder.sheen(t);
+        }
+        if let Some(t) = texture("sheen_tint") {
+            builder = builder.sheen_tint(t);
+        }
+        if let Some(t) = texture("clearcoat") {
+            builder = builder.clearcoat(t);
+        }
+        if let Some(t) = texture("clearcoat_gloss") {
+            builder = builder.clearcoat_gloss(t);
+        }
+        if let Some(t) = texture("transmission") {
+            builder = builder.transmission(t);
+        }
+        if let Some(t) = texture("anisotropic") {
+            builder = builder.anisotropic(t);
+        }
+        if let Some(t) = texture("ior") {
+            builder = builder.ior(t);
+        }
+        builder.build()
+    }
+
+    fn scalar(texture: &TextureType, hit: &HitInfo) -> f32 {
+        luminance(&texture.value(hit).unwrap())
+    }
+
+    /// Evaluate the parameters at the hitpoint, or None at grazing angles
+    fn lobes(&self, wi: &Vec3, hit: &HitInfo) -> Option<Lobes> {
+        let base_color = self.base_color.value(hit).unwrap();
+        let metallic = Principled::scalar(&self.metallic, hit).clamp(0.0, 1.0);
+        let roughness = Principled::scalar(&self.roughness, hit).clamp(0.0, 1.0);
+        let transmission = Principled::scalar(&self.transmission, hit).clamp(0.0, 1.0);
+        let ior = Principled::scalar(&self.ior, hit);
+
+        // opaque surfaces are two-sided, transmissive ones have an inside
+        let front_face = dot(&hit.gn, wi) < 0.0;
+        let inside = !front_face && (1.0 - metallic) * transmission > 0.0;
+        let normal = if front_face { hit.sn } else { -hit.sn };
+        let eta = if inside { 1.0 / ior } else { ior };
+
+        // the anisotropic specular lobe is stretched along the shading tangent
+        let uvw = Onb::build_from_w_u(&normal, &hit.dpdu);
+        let wo = uvw.to_local(&normalize(&-wi));
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let tint = if luminance(&base_color) > 0.0 {
+            base_color / luminance(&base_color)
+        } else {
+            Vec3::new(1.0, 1.0, 1.0)
+        };
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let specular = Principled::scalar(&self.specular, hit);
+        let specular_tint = Principled::scalar(&self.specular_tint, hit);
+        let sheen = Principled::scalar(&self.sheen, hit);
+        let sheen_tint = Principled::scalar(&self.sheen_tint, hit);
+        // the default specular of 0.5 gives the reflectance of the index of refraction
+        let specular_color = 2.0 * specular * lerp(&white, &tint, specular_tint);
+        let sheen_color = sheen * lerp(&white, &tint, sheen_tint);
+
+        let anisotropic = Principled::scalar(&self.anisotropic, hit);
+        let clearcoat = if inside {
+            0.0
+        } else {
+            Principled::scalar(&self.clearcoat, hit)
+        };
+        let clearcoat_gloss = Principled::scalar(&self.clearcoat_gloss, hit);
+        let clearcoat_alpha = 0.1 + (0.001 - 0.1) * clearcoat_gloss;
+
+        let diffuse_weight = if inside {
+            0.0
+        } else {
+            (1.0 - metallic) * (1.0 - transmission)
+        };
+        let fresnel = fresnel_dielectric(wo.z, eta);
+        let specular_weight =
+            metallic + (1.0 - metallic) * luminance(&specular_color).min(1.0) * fresnel;
+        let transmission_weight = (1.0 - metallic) * transmission * (1.0 - fresnel);
+        let clearcoat_weight = 0.25 * clearcoat;
+
+        Some(Lobes {
+            uvw,
+            wo,
+            eta,
+            base_color,
+            metallic,
+            roughness,
+            diffuse: diffuse_weight,
+            specular_color,
+            sheen_color,
+            clearcoat,
+            transmission,
+            ggx: Ggx::from_roughness(roughness, anisotropic),
+            ggx_clearcoat: Ggx::new(clearcoat_alpha, clearcoat_alpha),
+            weights: [
+                diffuse_weight,
+                specular_weight,
+                clearcoat_weight,
+                transmission_weight,
+            ],
+        })
+    }
+}
+
+impl Lobes {
+    /// BSDF times the cosine term, for a local direction `wl`
+    fn eval(&self, wl: &Vec3) -> Vec3 {
+        let wo = &self.wo;
+        if wl.z < 0.0 {
+            return self.eval_transmission(wl);
+        }
+        let h = wo + wl;
+        if wl.z == 0.0 || h.norm_squared() == 0.0 {
+            return Vec3::zeros();
+        }
+        let h = normalize(&h);
+        let cos_d = dot(wl, &h);
+
+        // Burley diffuse with retro-reflection, and sheen at grazing angles
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let fl = (1.0 - wl.z).powi(5);
+        let fo = (1.0 - wo.z).powi(5);
+        let fd = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fo);
+        let diffuse = self.base_color * FRAC_1_PI * fd + self.sheen_color * (1.0 - cos_d).powi(5);
+        let diffuse = self.diffuse * diffuse * wl.z;
+
+        // the cosine term of the microfacet lobes cancels with the one of the BRDF denominator
+        let dielectric = self.specular_color * fresnel_dielectric(dot(wo, &h), self.eta);
+        let metal = fresnel_schlick(dot(wo, &h), &self.base_color);
+        let fresnel = lerp(&dielectric, &metal, self.metallic);
+        let specular = fresnel * self.ggx.d(&h) * self.ggx.g2(wo, wl) / (4.0 * wo.z);
+
+        let fresnel_clearcoat = fresnel_schlick(dot(wo, &h), &Vec3::new(0.04, 0.04, 0.04)).x;
+        let clearcoat = 0.25
+            * self.clearcoat
+            * fresnel_clearcoat
+            * self.ggx_clearcoat.d(&h)
+            * self.ggx_clearcoat.g2(wo, wl)
+            / (4.0 * wo.z);
+
+        diffuse + specular + Vec3::new(clearcoat, clearcoat, clearcoat)
+    }
+
+    fn eval_transmission(&self, wl: &Vec3) -> Vec3 {
+        let wo = &self.wo;
+        let Some(m) = half_vector_transmission(wo, wl, self.eta) else {
+            return Vec3::zeros();
+        };
+        let fresnel = fresnel_dielectric(dot(wo, &m), self.eta);
+        let btdf = (1.0 - fresnel)
+            * self.ggx.d(&m)
+            * self.ggx.g2(wo, wl)
+            * transmission_jacobian(wo, wl, &m, self.eta)
+            * f32::abs(dot(wo, &m) / wo.z)
+            / (self.eta * self.eta);
+        (1.0 - self.metallic) * self.transmission * btdf * self.base_color
+    }
+
+    /// Sample a local direction, picking the lobe with the first random variable
+    fn sample(&self, rv: Vec2) -> Option<Vec3> {
+        let total: f32 = self.weights.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+        let mut u = rv.x * total;
+        let mut lobe = self.weights.len() - 1;
+        for (i, weight) in self.weights.iter().enumerate() {
+            if u < *weight {
+                lobe = i;
+                break;
+            }
+            u -= weight;
+        }
+        let rv = Vec2::new(
+            (u / self.weights[lobe]).clamp(0.0, 1.0 - f32::EPSILON),
+            rv.y,
+        );
+
+        let wo = &self.wo;
+        let wl = match lobe {
+            0 => sample_hemisphere_cosine(rv),
+            1 => reflect(&-wo, &self.ggx.sample_visible(wo, rv)),
+            2 => reflect(&-wo, &self.ggx_clearcoat.sample_visible(wo, rv)),
+            _ => refract(wo, &self.ggx.sample_visible(wo, rv), self.eta)?,
+        };
+        (wl.z != 0.0).then_some(wl)
+    }
+
+    fn pdf(&self, wl: &Vec3) -> f32 {
+        let total: f32 = self.weights.iter().sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        let wo = &self.wo;
+        let [diffuse, specular, clearcoat, transmission] = self.weights;
+
+        if wl.z < 0.0 {
+            let Some(m) = half_vector_transmission(wo, wl, self.eta) else {
+                return 0.0;
+            };
+            let pdf = self.ggx.d_visible(wo, &m) * transmission_jacobian(wo, wl, &m, self.eta);
+            return transmission * pdf / total;
+        }
+        let h = wo + wl;
+        if wl.z == 0.0 || h.norm_squared() == 0.0 {
+            return 0.0;
+        }
+        let h = normalize(&h);
+        let jacobian = 1.0 / (4.0 * dot(wo, &h));
+        let pdf = diffuse * wl.z * FRAC_1_PI
+            + specular * self.ggx.d_visible(wo, &h) * jacobian
+            + clearcoat * self.ggx_clearcoat.d_visible(wo, &h) * jacobian;
+        pdf / total
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PrincipledBuilder {
+    base_color: TextureType,
+    metallic: TextureType,
+    roughness: TextureType,
+    specular: TextureType,
+    specular_tint: TextureType,
+    sheen: TextureType,
+    sheen_tint: TextureType,
+    clearcoat: TextureType,
+    clearcoat_gloss: TextureType,
+    transmission: TextureType,
+    anisotropic: TextureType,
+    ior: TextureType,
+}
+
+impl PrincipledBuilder {
+    pub fn new() -> PrincipledBuilder {
+        PrincipledBuilder {
+            base_color: TextureType::from(0.8),
+            metallic: TextureType::from(0.0),
+            roughness: TextureType::from(0.5),
+            specular: TextureType::from(0.5),
+            specular_tint: TextureType::from(0.0),
+            sheen: TextureType::from(0.0),
+            sheen_tint: TextureType::from(0.5),
+            clearcoat: TextureType::from(0.0),
+            clearcoat_gloss: TextureType::from(1.0),
+            transmission: TextureType::from(0.0),
+            anisotropic: TextureType::from(0.0),
+            ior: TextureType::from(1.5),
+        }
+    }
+
+    pub fn base_color(mut self, base_color: impl Into<TextureType>) -> PrincipledBuilder {
+        self.base_color = base_color.into();
+        self
+    }
+
+    /// Blend from a dielectric to a metal whose reflectance is the base color
+    pub fn metallic(mut self, metallic: impl Into<TextureType>) -> PrincipledBuilder {
+        self.metallic = metallic.into();
+        self
+    }
+
+    /// Perceptual roughness of the diffuse, specular and transmission lobes
+    pub fn roughness(mut self, roughness: impl Into<TextureType>) -> PrincipledBuilder {
+        self.roughness = roughness.into();
+        self
+    }
+
+    /// Strength of the dielectric specular reflection, 0.5 matches the index of refraction
+    pub fn specular(mut self, specular: impl Into<TextureType>) -> PrincipledBuilder {
+        self.specular = specular.into();
+        self
+    }
+
+    /// Tint the dielectric specular reflection towards the hue of the base color
+    pub fn specular_tint(mut self, specular_tint: impl Into<TextureType>) -> PrincipledBuilder {
+        self.specular_tint = specular_tint.into();
+        self
+    }
+
+    /// Strength of the grazing retro-reflection of cloth
+    pub fn sheen(mut self, sheen: impl Into<TextureType>) -> PrincipledBuilder {
+        self.sheen = sheen.into();
+        self
+    }
+
+    pub fn sheen_tint(mut self, sheen_tint: impl Into<TextureType>) -> PrincipledBuilder {
+        self.sheen_tint = sheen_tint.into();
+        self
+    }
+
+    /// Strength of a second, colorless specular layer
+    pub fn clearcoat(mut self, clearcoat: impl Into<TextureType>) -> PrincipledBuilder {
+        self.clearcoat = clearcoat.into();
+        self
+    }
+
+    /// Glossiness of the clearcoat, 1 is a sharp reflection
+    pub fn clearcoat_gloss(mut self, clearcoat_gloss: impl Into<TextureType>) -> PrincipledBuilder {
+        self.clearcoat_gloss = clearcoat_gloss.into();
+        self
+    }
+
+    /// Blend from an opaque diffuse base to rough glass tinted by the base color
+    pub fn transmission(mut self, transmission: impl Into<TextureType>) -> PrincipledBuilder {
+        self.transmission = transmission.into();
+        self
+    }
+
+    /// Stretch of the specular highlights along the surface tangent
+    pub fn anisotropic(mut self, anisotropic: impl Into<TextureType>) -> PrincipledBuilder {
+        self.anisotropic = anisotropic.into();
+        self
+    }
+
+    pub fn ior(mut self, ior: impl Into<TextureType>) -> PrincipledBuilder {
+        self.ior = ior.into();
+        self
+    }
+
+    pub fn build(self) -> Principled {
+        Principled {
+            base_color: self.base_color,
+            metallic: self.metallic,
+            roughness: self.roughness,
+            specular: self.specular,
+            specular_tint: self.specular_tint,
+            sheen: self.sheen,
+            sheen_tint: self.sheen_tint,
+            clearcoat: self.clearcoat,
+            clearcoat_gloss: self.clearcoat_gloss,
+            transmission: self.transmission,
+            anisotropic: self.anisotropic,
+            ior: self.ior,
+        }
+    }
+}
+
+impl Default for PrincipledBuilder {
+    fn default() -> PrincipledBuilder {
+        PrincipledBuilder::new()
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        let mut rng = rand::thread_rng();
+        let rv = Vec2::new(rng.gen(), rng.gen());
+        let srec = self.sample(&r_in.direction, hit, rv)?;
+        let ray_out = Ray::new(hit.p, srec.wo).with_time(r_in.time);
+        Some((srec.attenuation, ray_out))
+    }
+
+    fn emmitted(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Vec3> {
+        None
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        let Some(lobes) = self.lobes(wi, hit) else {
+            return Vec3::zeros();
+        };
+        lobes.eval(&lobes.uvw.to_local(&normalize(scattered)))
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        let lobes = self.lobes(wi, hit)?;
+        let wl = lobes.sample(rv)?;
+        let pdf = lobes.pdf(&wl);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let srec = ScatterRecord {
+            attenuation: lobes.eval(&wl) / pdf,
+            wo: normalize(&lobes.uvw.local(&wl)),
+            is_specular: false,
+        };
+        Some(srec)
+    }
+
+    fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        let Some(lobes) = self.lobes(wi, hit) else {
+            return 0.0;
+        };
+        lobes.pdf(&lobes.uvw.to_local(&normalize(scattered)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    use crate::materials::{Material, MaterialType, Principled, PrincipledBuilder};
+    use crate::surfaces::HitInfo;
+    use crate::tests::sample_test::MaterialTest;
+
+    #[test]
+    fn builder_matches_json() {
+        let v = json!({
+            "type": "principled",
+            "base_color": [0.9, 0.6, 0.2],
+            "metallic": 1.0,
+            "roughness": 0.3,
+            "anisotropic": 0.5
+        });
+        let from_builder = PrincipledBuilder::new()
+            .base_color(Vec3::new(0.9, 0.6, 0.2))
+            .metallic(1.0)
+            .roughness(0.3)
+            .anisotropic(0.5)
+            .build();
+        assert_eq!(Principled::new(&v), from_builder);
+    }
+
+    #[test]
+    fn tangent_orients_anisotropy() {
+        let material = PrincipledBuilder::new()
+            .metallic(1.0)
+            .roughness(0.5)
+            .anisotropic(0.8)
+            .build();
+        let hit = |dpdu: Vec3| HitInfo {
+            t: 1.0,
+            p: Vec3::zeros(),
+            gn: Vec3::z(),
+            sn: Vec3::z(),
+            dpdu,
+            dpdv: Vec3::z().cross(&dpdu),
+            uv: Vec2::new(0.5, 0.5),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
+            mat: Arc::new(MaterialType::from(material.clone())),
+            emitter: None,
+        };
+        let quarter_turn = |v: Vec3| Vec3::new(-v.y, v.x, v.z);
+        let wi = Vec3::new(0.3, 0.1, -1.0).normalize();
+        let wo = Vec3::new(0.4, 0.5, 1.0).normalize();
+
+        let along_x = material.eval(&wi, &wo, &hit(Vec3::x()));
+        let along_y = material.eval(&quarter_turn(wi), &quarter_turn(wo), &hit(Vec3::y()));
+        approx::assert_relative_eq!(along_x, along_y, max_relative = 1e-4);
+        let turned = material.eval(&wi, &wo, &hit(Vec3::y()));
+        assert!((turned - along_x).norm() > 0.1 * along_x.norm());
+    }
+
+    #[test]
+    fn principled_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "principled",
+                "base_color": [0.8, 0.3, 0.2],
+                "roughness": 0.4,
+                "sheen": 0.5,
+                "clearcoat": 1.0,
+                "clearcoat_gloss": 0.5
+            },
+            "normal": [
+                0.25, 0.5, 1.0
+            ],
+            "name": "principled"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        parameters.run(&test, 1.0, 2e-2);
+    }
+
+    #[test]
+    fn principled_transmission_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "principled",
+                "base_color": 1.0,
+                "metallic": 0.2,
+                "roughness": 0.5,
+                "transmission": 0.8,
+                "anisotropic": 0.6
+            },
+            "normal": [
+                0, 0, 1
+            ],
+            "transmission": true,
+            "name": "principled-transmission"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        parameters.run(&test, 1.0, 2e-2);
+    }
+}
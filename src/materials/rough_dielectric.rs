@@ -0,0 +1,284 @@
+use nalgebra_glm::{dot, normalize, Vec2, Vec3};
+use rand::Rng;
+use serde_json::Value;
+
+use crate::core::microfacet::{
+    fresnel_dielectric, half_vector_transmission, refract, transmission_jacobian, Ggx,
+};
+use crate::core::onb::Onb;
+use crate::core::ray::Ray;
+use crate::core::utils::{luminance, reflect};
+use crate::materials::Material;
+use crate::surfaces::{HitInfo, ScatterRecord};
+use crate::textures::{create_linear_texture, Texture, TextureType};
+
+/// Frosted glass: GGX microfacets on a dielectric interface, reflecting and transmitting light
+#[derive(Debug, PartialEq, Clone)]
+pub struct RoughDielectric {
+    ior: TextureType,
+    roughness: TextureType,
+}
+
+/// Local quantities shared by `eval`, `sample` and `pdf`
+struct LocalFrame {
+    uvw: Onb,
+    /// Direction towards the viewer, always in the upper hemisphere
+    wo: Vec3,
+    /// Index of refraction of the side opposite to the viewer over the viewer side
+    eta: f32,
+    ggx: Ggx,
+}
+
+impl RoughDielectric {
+    pub fn new(v: &Value) -> RoughDielectric {
+        RoughDielectricBuilder::new()
+            .ior(create_linear_texture(v, "ior"))
+            .roughness(create_linear_texture(v, "roughness"))
+            .build()
+    }
+
+    /// Build the shading frame on the side of the incoming ray, or None at grazing angles
+    fn frame(&self, wi: &Vec3, hit: &HitInfo) -> Option<LocalFrame> {
+        let ior = luminance(&self.ior.value(hit).unwrap());
+        let (normal, eta) = if dot(&hit.gn, wi) < 0.0 {
+            (hit.sn, ior)
+        } else {
+            (-hit.sn, 1.0 / ior)
+        };
+
+        let uvw = Onb::build_from_w(&normal);
+        let wo = uvw.to_local(&normalize(&-wi));
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let roughness = luminance(&self.roughness.value(hit).unwrap());
+        Some(LocalFrame {
+            uvw,
+            wo,
+            eta,
+            ggx: Ggx::from_roughness(roughness, 0.0),
+        })
+    }
+
+    /// Microfacet normal linking `wo` to `wl`, in the upper hemisphere.
+    ///
+    /// Returns None for configurations no microfacet can produce.
+    fn half_vector(frame: &LocalFrame, wl: &Vec3) -> Option<Vec3> {
+        let wo = &frame.wo;
+        if wl.z < 0.0 {
+            return half_vector_transmission(wo, wl, frame.eta);
+        }
+        let m = wo + wl;
+        if m.norm_squared() == 0.0 || wl.z == 0.0 {
+            return None;
+        }
+        let m = normalize(&m);
+        (dot(wo, &m) > 0.0 && dot(wl, &m) > 0.0).then_some(m)
+    }
+
+    /// Probability of choosing the reflection lobe, from the Fresnel term of the macro surface
+    fn reflect_probability(frame: &LocalFrame) -> f32 {
+        fresnel_dielectric(frame.wo.z, frame.eta)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RoughDielectricBuilder {
+    ior: TextureType,
+    roughness: TextureType,
+}
+
+impl RoughDielectricBuilder {
+    pub fn new() -> RoughDielectricBuilder {
+        RoughDielectricBuilder {
+            ior: TextureType::from(1.5),
+            roughness: TextureType::from(0.1),
+        }
+    }
+
+    pub fn ior(mut self, ior: impl Into<TextureType>) -> RoughDielectricBuilder {
+        self.ior = ior.into();
+        self
+    }
+
+    /// Perceptual roughness in [0, 1]
+    pub fn roughness(mut self, roughness: impl Into<TextureType>) -> RoughDielectricBuilder {
+        self.roughness = roughness.into();
+        self
+    }
+
+    pub fn build(self) -> RoughDielectric {
+        RoughDielectric {
+            ior: self.ior,
+            roughness: self.roughness,
+        }
+    }
+}
+
+impl Default for RoughDielectricBuilder {
+    fn default() -> RoughDielectricBuilder {
+        RoughDielectricBuilder::new()
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, hit: &HitInfo) -> Option<(Vec3, Ray)> {
+        let mut rng = rand::thread_rng();
+        let rv = Vec2::new(rng.gen(), rng.gen());
+        let srec = self.sample(&r_in.direction, hit, rv)?;
+        let ray_out = Ray::new(hit.p, srec.wo).with_time(r_in.time);
+        Some((srec.attenuation, ray_out))
+    }
+
+    fn emmitted(&self, _ray: &Ray, _hit: &HitInfo) -> Option<Vec3> {
+        None
+    }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    fn eval(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> Vec3 {
+        let Some(frame) = self.frame(wi, hit) else {
+            return Vec3::zeros();
+        };
+        let wo = &frame.wo;
+        let wl = frame.uvw.to_local(&normalize(scattered));
+        let Some(m) = RoughDielectric::half_vector(&frame, &wl) else {
+            return Vec3::zeros();
+        };
+
+        let ggx = &frame.ggx;
+        let fresnel = fresnel_dielectric(dot(wo, &m), frame.eta);
+        let value = if wl.z > 0.0 {
+            // the cosine term cancels with the one of the BRDF denominator
+            fresnel * ggx.d(&m) * ggx.g2(wo, &wl) / (4.0 * wo.z)
+        } else {
+            let denom = dot(&wl, &m) + dot(wo, &m) / frame.eta;
+            let btdf = (1.0 - fresnel)
+                * ggx.d(&m)
+                * ggx.g2(wo, &wl)
+                * f32::abs(dot(&wl, &m) * dot(wo, &m) / (denom * denom * wo.z));
+            // radiance is compressed into the smaller solid angle on the denser side
+            btdf / (frame.eta * frame.eta)
+        };
+        Vec3::new(value, value, value)
+    }
+
+    fn sample(&self, wi: &Vec3, hit: &HitInfo, rv: Vec2) -> Option<ScatterRecord> {
+        let frame = self.frame(wi, hit)?;
+        let wo = &frame.wo;
+
+        // choose the lobe first, and reuse the random variable for the microfacet normal
+        let reflect_probability = RoughDielectric::reflect_probability(&frame);
+        let (reflection, rv) = if rv.x < reflect_probability {
+            (true, Vec2::new(rv.x / reflect_probability, rv.y))
+        } else {
+            let u = (rv.x - reflect_probability) / (1.0 - reflect_probability);
+            (false, Vec2::new(u, rv.y))
+        };
+
+        let m = frame.ggx.sample_visible(wo, rv);
+        let wl = if reflection {
+            reflect(&-wo, &m)
+        } else {
+            refract(wo, &m, frame.eta)?
+        };
+        if reflection != (wl.z > 0.0) {
+            return None;
+        }
+
+        let scattered = normalize(&frame.uvw.local(&wl));
+        let pdf = self.pdf(wi, &scattered, hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let srec = ScatterRecord {
+            attenuation: self.eval(wi, &scattered, hit) / pdf,
+            wo: scattered,
+            is_specular: false,
+        };
+        Some(srec)
+    }
+
+    fn pdf(&self, wi: &Vec3, scattered: &Vec3, hit: &HitInfo) -> f32 {
+        let Some(frame) = self.frame(wi, hit) else {
+            return 0.0;
+        };
+        let wo = &frame.wo;
+        let wl = frame.uvw.to_local(&normalize(scattered));
+        let Some(m) = RoughDielectric::half_vector(&frame, &wl) else {
+            return 0.0;
+        };
+
+        let reflect_probability = RoughDielectric::reflect_probability(&frame);
+        let d_visible = frame.ggx.d_visible(wo, &m);
+        if wl.z > 0.0 {
+            reflect_probability * d_visible / (4.0 * dot(wo, &m))
+        } else {
+            let jacobian = transmission_jacobian(wo, &wl, &m, frame.eta);
+            (1.0 - reflect_probability) * d_visible * jacobian
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::materials::{RoughDielectric, RoughDielectricBuilder};
+    use crate::tests::sample_test::MaterialTest;
+
+    #[test]
+    fn builder_matches_json() {
+        let v = json!({"type": "rough_dielectric", "ior": 1.33, "roughness": 0.2});
+        let from_builder = RoughDielectricBuilder::new()
+            .ior(1.33)
+            .roughness(0.2)
+            .build();
+        assert_eq!(RoughDielectric::new(&v), from_builder);
+    }
+
+    #[test]
+    fn rough_dielectric_monte_carlo() {
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "rough_dielectric",
+                "ior": 1.5,
+                "roughness": 0.5
+            },
+            "normal": [
+                0, 0, 1
+            ],
+            "transmission": true,
+            "name": "rough-dielectric"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        parameters.run(&test, 1.0, 2e-2);
+    }
+
+    #[test]
+    fn rough_dielectric_inside_monte_carlo() {
+        // hitting the interface from inside, total internal reflection kicks in
+        let v = json!({
+            "type": "sample_material",
+            "material": {
+                "type": "rough_dielectric",
+                "ior": 1.5,
+                "roughness": 0.5
+            },
+            "normal": [
+                0, 0, -1
+            ],
+            "incoming": [0.6, 0.0, -1.0],
+            "transmission": true,
+            "name": "rough-dielectric-inside"
+        });
+
+        let (test, mut parameters) = MaterialTest::new(&v);
+        // transmitted samples past the critical angle are lost
+        parameters.run(&test, 0.8, 1e-2);
+    }
+}
@@ -36,7 +36,7 @@ pub trait Sampler {
     fn seed(&self) -> u64;
 }
 
-use crate::samplers::independent::IndependentSampler;
+pub use crate::samplers::independent::IndependentSampler;
 
 #[enum_dispatch(Sampler)]
 #[derive(Debug, Clone)]
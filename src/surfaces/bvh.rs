@@ -8,7 +8,7 @@ use crate::core::ray::Ray;
 use crate::core::utils::get_progress_bar;
 use crate::surfaces::{EmitterRecord, HitInfo, Surface, SurfaceType};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SplitMethod {
     Equal,
     Middle,
@@ -55,10 +55,10 @@ impl Surface for Bvh {
         self.bbox.clone()
     }
 
-    fn sample(&self, _o: &Vec3, _rv: Vec2) -> Option<EmitterRecord> {
+    fn sample(&self, _o: &Vec3, _time: f32, _rv: Vec2) -> Option<EmitterRecord> {
         unimplemented!()
     }
-    fn pdf(&self, _o: &Vec3, _dir: &Vec3) -> f32 {
+    fn pdf(&self, _o: &Vec3, _time: f32, _dir: &Vec3) -> f32 {
         unimplemented!()
     }
     fn is_emissive(&self) -> bool {
@@ -0,0 +1,193 @@
+use nalgebra_glm::{dot, length, Vec2, Vec3};
+use std::collections::HashMap;
+
+use crate::core::aabb::Aabb;
+use crate::core::distribution::Distribution1d;
+use crate::core::ray::Ray;
+use crate::surfaces::{Bvh, EmitterRecord, HitInfo, SplitMethod, Surface, SurfaceType, Triangle};
+
+/// The emissive triangles of a mesh sampled as a single emitter.
+///
+/// A triangle is chosen proportionally to its area and then sampled uniformly by area, not by solid angle, so the
+/// points are uniform over the whole mesh. Rays are intersected with a BVH of the triangles to find the density of a direction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EmissiveMesh {
+    triangles: Vec<Triangle>,
+    bvh: Bvh,
+    /// Distribution of the triangles, proportional to their area
+    distribution: Distribution1d,
+    area: f32,
+}
+
+impl EmissiveMesh {
+    pub fn new(triangles: Vec<Triangle>) -> EmissiveMesh {
+        let areas: Vec<f32> = triangles.iter().map(Surface::area).collect();
+        let distribution = Distribution1d::new(&areas);
+        let mut bvh_triangles = triangles
+            .iter()
+            .cloned()
+            .map(SurfaceType::Triangle)
+            .collect();
+        EmissiveMesh {
+            triangles,
+            bvh: Bvh::new(&mut bvh_triangles, &SplitMethod::Equal),
+            area: distribution.func_sum(),
+            distribution,
+        }
+    }
+}
+
+impl Surface for EmissiveMesh {
+    fn intersect(&self, ray: &Ray) -> Option<HitInfo> {
+        self.bvh.intersect(ray)
+    }
+
+    fn bounds(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+
+    fn area(&self) -> f32 {
+        self.area
+    }
+
+    fn sample(&self, o: &Vec3, time: f32, rv: Vec2) -> Option<EmitterRecord> {
+        // the random variable choosing the triangle is reused to sample it
+        let (index, pmf, remapped) = self.distribution.sample(rv.x);
+        let mut erec = self.triangles[index].sample_area(o, time, Vec2::new(remapped, rv.y))?;
+        erec.pdf *= pmf;
+        Some(erec)
+    }
+
+    fn pdf(&self, o: &Vec3, time: f32, dir: &Vec3) -> f32 {
+        match self.intersect(&Ray::new(*o, *dir).with_time(time)) {
+            Some(hit) => self.pdf_hit(o, time, dir, &hit),
+            None => 0.0,
+        }
+    }
+
+    fn pdf_hit(&self, _o: &Vec3, _time: f32, dir: &Vec3, hit: &HitInfo) -> f32 {
+        // every point of the mesh has the same area density, whichever triangle it is on
+        let distance = hit.t * length(dir);
+        let cosine = f32::abs(dot(dir, &hit.gn) / length(dir));
+        distance * distance / (cosine * self.area)
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
+
+/// Gather the emissive surfaces of `surfaces`, the triangles of each mesh becoming a single `EmissiveMesh`.
+///
+/// Each emissive surface remembers the index of its emitter in the returned list, so that its hits can find it.
+pub fn collect_emitters(surfaces: &mut [SurfaceType]) -> Vec<SurfaceType> {
+    // the meshes come after the other emitters
+    let singles = surfaces
+        .iter()
+        .filter(|surface| surface.is_emissive() && !matches!(surface, SurfaceType::Triangle(_)))
+        .count();
+    let mut emitters = Vec::new();
+    let mut meshes: HashMap<usize, (usize, Vec<Triangle>)> = HashMap::new();
+    for surface in surfaces.iter_mut().filter(|surface| surface.is_emissive()) {
+        match surface {
+            SurfaceType::Triangle(triangle) => {
+                let count = meshes.len();
+                let (order, triangles) = meshes
+                    .entry(triangle.mesh_key())
+                    .or_insert_with(|| (count, Vec::new()));
+                triangle.set_emitter(singles + *order);
+                triangles.push(triangle.clone());
+            }
+            _ => {
+                surface.set_emitter(emitters.len());
+                emitters.push(surface.clone());
+            }
+        }
+    }
+    // keep the meshes in the order of the scene
+    let mut meshes: Vec<(usize, Vec<Triangle>)> = meshes.into_values().collect();
+    meshes.sort_by_key(|(order, _)| *order);
+    for (_, mut triangles) in meshes {
+        if triangles.len() == 1 {
+            emitters.push(SurfaceType::Triangle(triangles.remove(0)));
+        } else {
+            emitters.push(SurfaceType::EmissiveMesh(EmissiveMesh::new(triangles)));
+        }
+    }
+    emitters
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra_glm::Vec3;
+    use serde_json::json;
+
+    use crate::core::ray::Ray;
+    use crate::core::sampling::stratified_grid;
+    use crate::materials::DiffuseLightBuilder;
+    use crate::surfaces::{collect_emitters, MeshBuilder, Surface, SurfaceType, TriangleBuilder};
+    use crate::tests::sample_test::DirectionTest;
+
+    #[test]
+    fn samples_match_pdf() {
+        // a small and a large triangle of the same mesh, and a triangle on its own
+        let file = tempfile::Builder::new().suffix(".obj").tempfile().unwrap();
+        std::fs::write(
+            file.path(),
+            "v 0 0 -1\nv 1 0 -1\nv 0 1 -1\nv -1 -1 -2\nv -4 -1 -2\nv -1 -4 -2\nf 1 2 3\nf 4 5 6\n",
+        )
+        .unwrap();
+        let light = DiffuseLightBuilder::new().build();
+        let mesh = MeshBuilder::new(file.path().to_str().unwrap(), light.clone()).build();
+        let alone = TriangleBuilder::new(
+            [
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.0, 1.0, 1.0),
+                Vec3::new(1.0, 0.0, 1.0),
+            ],
+            light,
+        )
+        .build();
+        let mut surfaces = mesh;
+        surfaces.push(SurfaceType::Triangle(alone));
+
+        let emitters = collect_emitters(&mut surfaces);
+        assert_eq!(emitters.len(), 2);
+        // the hits of each triangle lead to its emitter
+        let down = |x, y, z| Ray::new(Vec3::new(x, y, z), -Vec3::z());
+        assert_eq!(
+            surfaces[0].intersect(&down(0.2, 0.2, 0.0)).unwrap().emitter,
+            Some(0)
+        );
+        assert_eq!(
+            surfaces[2].intersect(&down(0.2, 0.2, 2.0)).unwrap().emitter,
+            Some(1)
+        );
+        let mesh = emitters
+            .iter()
+            .find_map(|emitter| match emitter {
+                SurfaceType::EmissiveMesh(mesh) => Some(mesh),
+                _ => None,
+            })
+            .expect("the mesh should be a single emitter");
+        approx::assert_abs_diff_eq!(mesh.area(), 5.0, epsilon = 1e-5);
+
+        let o = Vec3::new(0.3, 0.2, 0.5);
+        let (test, mut parameters) = DirectionTest::new(
+            &json!({"name": "emissive_mesh"}),
+            |rv, _| mesh.sample(&o, 0.0, rv).map(|erec| (erec.wi, erec.pdf)),
+            |dir| mesh.pdf(&o, 0.0, dir),
+        );
+        parameters.run(&test, 1.0, 1e-2);
+
+        let n = 30;
+        let large = stratified_grid(n)
+            .filter(|rv| {
+                let erec = mesh.sample(&o, 0.0, *rv).unwrap();
+                mesh.intersect(&Ray::new(o, erec.wi)).unwrap().p.z < -1.5
+            })
+            .count();
+        // the large triangle has nine times the area of the small one
+        assert_eq!(large, 9 * n * n / 10);
+    }
+}
@@ -1,11 +1,12 @@
 mod bvh;
+mod emissive_mesh;
 mod quad;
 mod sphere;
 mod surface_group;
 mod triangle;
 
 use enum_dispatch::enum_dispatch;
-use nalgebra_glm::{Vec2, Vec3};
+use nalgebra_glm::{dot, Vec2, Vec3};
 use serde_json::{Map, Value};
 use std::sync::Arc;
 
@@ -28,10 +29,54 @@ pub struct HitInfo {
     pub gn: Vec3,
     /// Interpolated shading normal
     pub sn: Vec3,
+    /// Partial derivative of the position with respect to the u texture coordinate
+    pub dpdu: Vec3,
+    /// Partial derivative of the position with respect to the v texture coordinate
+    pub dpdv: Vec3,
     /// UV texture coordinates
     pub uv: Vec2,
+    /// Change of the uv coordinates to the hit of the ray through the next pixel along x, zero without differentials
+    pub duvdx: Vec2,
+    /// Change of the uv coordinates to the hit of the ray through the next pixel along y, zero without differentials
+    pub duvdy: Vec2,
     /// Material at the hit point
     pub mat: Arc<MaterialType>,
+    /// Index of the scene emitter sampling the hit surface, `None` if it is not emissive
+    pub emitter: Option<usize>,
+}
+
+impl HitInfo {
+    /// Estimate the footprint of `ray` in texture space, by intersecting its differentials with the tangent plane
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        let Some(d) = &ray.differentials else {
+            return;
+        };
+        let plane = dot(&self.gn, &self.p);
+        let tx = (plane - dot(&self.gn, &d.rx_origin)) / dot(&self.gn, &d.rx_direction);
+        let ty = (plane - dot(&self.gn, &d.ry_origin)) / dot(&self.gn, &d.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return;
+        }
+        let dpdx = d.rx_origin + tx * d.rx_direction - self.p;
+        let dpdy = d.ry_origin + ty * d.ry_direction - self.p;
+
+        // least squares solution of dpdu * du + dpdv * dv = dp
+        let ata00 = dot(&self.dpdu, &self.dpdu);
+        let ata01 = dot(&self.dpdu, &self.dpdv);
+        let ata11 = dot(&self.dpdv, &self.dpdv);
+        let inv_det = 1.0 / (ata00 * ata11 - ata01 * ata01);
+        if !inv_det.is_finite() {
+            return;
+        }
+        let solve = |dp: &Vec3| {
+            let atb0 = dot(&self.dpdu, dp);
+            let atb1 = dot(&self.dpdv, dp);
+            let duv = Vec2::new(ata11 * atb0 - ata01 * atb1, ata00 * atb1 - ata01 * atb0) * inv_det;
+            duv.map(|x| x.clamp(-1e8, 1e8))
+        };
+        self.duvdx = solve(&dpdx);
+        self.duvdy = solve(&dpdy);
+    }
 }
 
 /// Data record for conveniently querying and sampling emitters
@@ -75,33 +120,60 @@ pub trait Surface {
         unimplemented!();
     }
 
-    /// Sample a direction from `rec.o` towards this surface.
+    /// Return the surface's world-space area, at the start of the exposure.
+    fn area(&self) -> f32 {
+        unimplemented!();
+    }
+
+    /// Bound the directions of the geometric normals, as a cone given by its axis and the cosine of its half angle.
+    ///
+    /// The default cone covers every direction.
+    fn normal_bounds(&self) -> (Vec3, f32) {
+        (Vec3::z(), -1.0)
+    }
+
+    /// Sample a direction from `rec.o` towards this surface, as it is positioned at `time`.
     ///
     /// Store result in `rec`, and return important weight (i.e. the color of the Surface divided by the probability
     /// density of the sample with respect to solid angle).
-    fn sample(&self, o: &Vec3, rv: Vec2) -> Option<EmitterRecord>;
+    fn sample(&self, o: &Vec3, time: f32, rv: Vec2) -> Option<EmitterRecord>;
 
     /// TODO
-    fn sample_from_group(&self, _o: &Vec3, _rv: Vec2, _rv1: f32) -> Option<EmitterRecord> {
+    fn sample_from_group(
+        &self,
+        _o: &Vec3,
+        _time: f32,
+        _rv: Vec2,
+        _rv1: f32,
+    ) -> Option<EmitterRecord> {
         unimplemented!()
     }
 
     /// TODO
-    fn pdf_child(&self, _o: &Vec3, _dir: &Vec3, _rv: f32) -> f32 {
+    fn pdf_child(&self, _o: &Vec3, _time: f32, _dir: &Vec3, _rv: f32) -> f32 {
         unimplemented!()
     }
 
     /// Return the probability density of the sample generated by #sample
-    fn pdf(&self, o: &Vec3, dir: &Vec3) -> f32;
+    fn pdf(&self, o: &Vec3, time: f32, dir: &Vec3) -> f32;
+
+    /// Same as `pdf`, for a direction already known to reach the surface at `hit`
+    fn pdf_hit(&self, o: &Vec3, time: f32, dir: &Vec3, _hit: &HitInfo) -> f32 {
+        self.pdf(o, time, dir)
+    }
 
     /// Return whether or not this Surface's Material is emissive.
     fn is_emissive(&self) -> bool;
+
+    /// Remember the index of the scene emitter sampling this surface, which its hits report
+    fn set_emitter(&mut self, _index: usize) {}
 }
 
-use crate::surfaces::bvh::{Bvh, SplitMethod};
-use crate::surfaces::quad::Quad;
-use crate::surfaces::sphere::Sphere;
-use crate::surfaces::triangle::{Mesh, Triangle};
+pub use crate::surfaces::bvh::{Bvh, SplitMethod};
+pub use crate::surfaces::emissive_mesh::{collect_emitters, EmissiveMesh};
+pub use crate::surfaces::quad::{Quad, QuadBuilder};
+pub use crate::surfaces::sphere::{Sphere, SphereBuilder};
+pub use crate::surfaces::triangle::{Mesh, MeshBuilder, Triangle, TriangleBuilder};
 
 #[enum_dispatch(Surface)]
 #[derive(Debug, PartialEq, Clone)]
@@ -110,6 +182,7 @@ pub enum SurfaceType {
     Quad(Quad),
     Triangle(Triangle),
     Bvh(Bvh),
+    EmissiveMesh(EmissiveMesh),
 }
 
 pub struct SurfaceFactory {
@@ -167,7 +240,7 @@ impl SurfaceFactory {
     }
 }
 
-use crate::surfaces::surface_group::LinearSurfaceGroup;
+pub use crate::surfaces::surface_group::LinearSurfaceGroup;
 
 #[enum_dispatch(Surface)]
 #[derive(Debug, PartialEq, Clone)]
@@ -180,17 +253,27 @@ pub fn create_surface_group(
     map: &Map<String, Value>,
     surfaces: &mut Vec<SurfaceType>,
 ) -> SurfaceGroupType {
-    if let Some(accel_value) = map.get("accelerator") {
-        let type_acceletator = accel_value.get("type").unwrap().as_str().unwrap();
-        match type_acceletator {
-            "bbh" => {
-                let split_method = read_or(accel_value, "split_method", SplitMethod::Middle);
-                SurfaceGroupType::Bvh(Bvh::new(surfaces, &split_method))
-            }
-            _ => panic!("Unusported accelerator {type_acceletator}"),
-        }
+    build_surface_group(read_split_method(map).as_ref(), surfaces)
+}
+
+/// Read the BVH split method of the "accelerator" field, if there is one
+pub fn read_split_method(map: &Map<String, Value>) -> Option<SplitMethod> {
+    let accel_value = map.get("accelerator")?;
+    let type_acceletator = accel_value.get("type").unwrap().as_str().unwrap();
+    match type_acceletator {
+        "bbh" => Some(read_or(accel_value, "split_method", SplitMethod::Middle)),
+        _ => panic!("Unusported accelerator {type_acceletator}"),
+    }
+}
+
+/// Group the surfaces in a BVH using `split_method`, or in a naive linear accelerator if there is none
+pub fn build_surface_group(
+    split_method: Option<&SplitMethod>,
+    surfaces: &mut Vec<SurfaceType>,
+) -> SurfaceGroupType {
+    if let Some(split_method) = split_method {
+        SurfaceGroupType::Bvh(Bvh::new(surfaces, split_method))
     } else {
-        // default to a naive linear accelerator
         SurfaceGroupType::LinearSurfaceGroup(LinearSurfaceGroup {
             surfaces: surfaces.clone(),
         })
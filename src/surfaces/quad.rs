@@ -5,7 +5,11 @@ use std::sync::Arc;
 
 use crate::core::aabb::Aabb;
 use crate::core::ray::Ray;
-use crate::core::transform::Transform;
+use crate::core::sampling::{
+    sample_spherical_rectangle, spherical_rectangle_area, MAX_SPHERICAL_SAMPLE_AREA,
+    MIN_SPHERICAL_SAMPLE_AREA,
+};
+use crate::core::transform::{AnimatedTransform, Transform};
 use crate::core::utils::{read, INTERSECTION_TEST};
 use crate::materials::{Material, MaterialType};
 use crate::surfaces::{EmitterRecord, HitInfo, Surface, SurfaceFactory};
@@ -13,8 +17,9 @@ use crate::surfaces::{EmitterRecord, HitInfo, Surface, SurfaceFactory};
 #[derive(Debug, PartialEq, Clone)]
 pub struct Quad {
     size: Vec2,
-    transform: Transform,
+    transform: AnimatedTransform,
     material: Arc<MaterialType>,
+    emitter: Option<usize>,
 }
 
 impl Surface for Quad {
@@ -22,7 +27,8 @@ impl Surface for Quad {
         INTERSECTION_TEST.fetch_add(1, Ordering::SeqCst);
         // compute ray intersection (and ray parameter), continue if not hit
         // put ray into sphere frame
-        let ray_transformed = self.transform.inverse().ray(ray);
+        let transform = self.transform.at(ray.time);
+        let ray_transformed = transform.inverse().ray(ray);
 
         if ray_transformed.direction.z == 0.0 {
             return None;
@@ -43,59 +49,81 @@ impl Surface for Quad {
         // project hitpoint onto plane to reduce floating-point error
         p.z = 0.0;
 
-        let n = normalize(&self.transform.normal(&Vec3::z()));
+        let n = normalize(&transform.normal(&Vec3::z()));
         let uv = 0.5 * p.xy().component_div(&self.size).add_scalar(1.0);
         let uv = clamp(&uv, 0.000_001, 0.999_999);
+        let (dpdu, dpdv) = self.tangents(&transform);
 
         // if hit, set intersection record values
         let hit = HitInfo {
             t,
-            p: self.transform.point(&p),
+            p: transform.point(&p),
             gn: n,
             sn: n,
+            dpdu,
+            dpdv,
             uv,
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
             mat: Arc::clone(&self.material),
+            emitter: self.emitter,
         };
         Some(hit)
     }
 
     fn bounds(&self) -> Aabb {
-        self.transform.aabb(&self.local_bounds())
+        self.transform.motion_bounds(&self.local_bounds())
     }
 
-    fn sample(&self, o: &Vec3, rv: Vec2) -> Option<EmitterRecord> {
-        let new_rv = (rv * 2.0).add_scalar(-1.0);
-        let temp = new_rv.component_mul(&self.size);
-        let raw_p = Vec3::new(temp.x, temp.y, 0.0);
+    fn sample(&self, o: &Vec3, time: f32, rv: Vec2) -> Option<EmitterRecord> {
+        let transform = self.transform.at(time);
+        let normal = transform.normal(&Vec3::z());
 
-        let p = self.transform.point(&raw_p);
-        let wi = p - o;
-        let distance2 = length2(&wi);
-        let t = f32::sqrt(distance2);
-        let normal = self.transform.normal(&Vec3::z());
-        let wi = wi / t;
+        let (raw_p, pdf) = if let Some(solid_angle) = self.solid_angle(&transform, o) {
+            // sample the spherical rectangle, then find the point in the frame of the quad
+            let (corner, ex, ey) = self.edges(&transform);
+            let p = sample_spherical_rectangle(o, &corner, &ex, &ey, rv);
+            let mut raw_p = transform.inverse().point(&p);
+            raw_p.x = raw_p.x.clamp(-self.size.x, self.size.x);
+            raw_p.y = raw_p.y.clamp(-self.size.y, self.size.y);
+            raw_p.z = 0.0;
+            (raw_p, 1.0 / solid_angle)
+        } else {
+            let new_rv = (rv * 2.0).add_scalar(-1.0);
+            let temp = new_rv.component_mul(&self.size);
+            let raw_p = Vec3::new(temp.x, temp.y, 0.0);
 
-        let v0 = self.transform.vector(&Vec3::new(self.size.x, 0.0, 0.0));
-        let v1 = self.transform.vector(&Vec3::new(0.0, self.size.y, 0.0));
+            let p = transform.point(&raw_p);
+            let distance2 = length2(&(p - o));
+            let cosine = f32::abs(dot(&normalize(&(p - o)), &normal));
+            let geometry_factor = distance2 / cosine;
+            (raw_p, 1.0 / self.area_at(&transform) * geometry_factor)
+        };
 
-        let area = 4.0 * length(&cross(&v0, &v1));
-        let cosine = f32::abs(dot(&wi, &normal));
-        let geometry_factor = distance2 / cosine;
-        let pdf = 1.0 / area * geometry_factor;
+        let p = transform.point(&raw_p);
+        let wi = p - o;
+        let t = length(&wi);
+        let wi = wi / t;
 
-        // println!("quad: pdf={}, distance2={}, area={}", pdf, distance2, area);
+        let (dpdu, dpdv) = self.tangents(&transform);
+        let uv = 0.5 * raw_p.xy().component_div(&self.size).add_scalar(1.0);
         let hit = HitInfo {
             t,
             p,
             mat: self.material.clone(),
             gn: normal,
             sn: normal,
-            uv: Vec2::zeros(),
+            dpdu,
+            dpdv,
+            uv: clamp(&uv, 0.000_001, 0.999_999),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
+            emitter: self.emitter,
         };
 
         let emitted = self
             .material
-            .emmitted(&Ray::new(*o, wi), &hit)
+            .emmitted(&Ray::new(*o, wi).with_time(time), &hit)
             .unwrap_or_default();
 
         let erec = EmitterRecord {
@@ -109,25 +137,42 @@ impl Surface for Quad {
         Some(erec)
     }
 
-    fn pdf(&self, o: &Vec3, dir: &Vec3) -> f32 {
-        if let Some(hit) = self.intersect(&Ray::new(*o, *dir)) {
-            let v0 = self.transform.vector(&Vec3::new(self.size.x, 0.0, 0.0));
-            let v1 = self.transform.vector(&Vec3::new(0.0, self.size.y, 0.0));
+    fn pdf(&self, o: &Vec3, time: f32, dir: &Vec3) -> f32 {
+        if let Some(hit) = self.intersect(&Ray::new(*o, *dir).with_time(time)) {
+            let transform = self.transform.at(time);
+            if let Some(solid_angle) = self.solid_angle(&transform, o) {
+                return 1.0 / solid_angle;
+            }
 
-            let area = 4.0 * length(&cross(&v0, &v1));
             let distance2 = hit.t * hit.t * length2(dir);
             let cosine = f32::abs(dot(dir, &hit.gn) / length(dir));
             let geometry_factor = distance2 / cosine;
-            let pdf = 1.0 / area;
+            let pdf = 1.0 / self.area_at(&transform);
 
             return geometry_factor * pdf;
         }
         0.0
     }
 
+    fn area(&self) -> f32 {
+        self.area_at(&self.transform.at(0.0))
+    }
+
+    fn normal_bounds(&self) -> (Vec3, f32) {
+        if self.transform.is_animated() {
+            return (Vec3::z(), -1.0);
+        }
+        let normal = self.transform.at(0.0).normal(&Vec3::z());
+        (normalize(&normal), 1.0)
+    }
+
     fn is_emissive(&self) -> bool {
         self.material.is_emissive()
     }
+
+    fn set_emitter(&mut self, index: usize) {
+        self.emitter = Some(index);
+    }
 }
 
 impl Quad {
@@ -137,7 +182,66 @@ impl Quad {
         Aabb { min: -v, max: v }
     }
 
+    fn area_at(&self, transform: &Transform) -> f32 {
+        let v0 = transform.vector(&Vec3::new(self.size.x, 0.0, 0.0));
+        let v1 = transform.vector(&Vec3::new(0.0, self.size.y, 0.0));
+        4.0 * length(&cross(&v0, &v1))
+    }
+
+    /// Corner of the quad and its two edges leaving it
+    fn edges(&self, transform: &Transform) -> (Vec3, Vec3, Vec3) {
+        let (ex, ey) = self.tangents(transform);
+        let corner = transform.point(&Vec3::new(-self.size.x, -self.size.y, 0.0));
+        (corner, ex, ey)
+    }
+
+    /// Solid angle of the quad seen from `o`, when it is large enough to be sampled directly.
+    ///
+    /// A sheared transform turns the quad into a parallelogram, which is always sampled by area.
+    fn solid_angle(&self, transform: &Transform, o: &Vec3) -> Option<f32> {
+        let (corner, ex, ey) = self.edges(transform);
+        if f32::abs(dot(&ex, &ey)) > 1e-4 * length(&ex) * length(&ey) {
+            return None;
+        }
+        let solid_angle = spherical_rectangle_area(o, &corner, &ex, &ey);
+        (MIN_SPHERICAL_SAMPLE_AREA..MAX_SPHERICAL_SAMPLE_AREA)
+            .contains(&solid_angle)
+            .then_some(solid_angle)
+    }
+
+    /// The uv coordinates span the quad along its local x and y axes
+    fn tangents(&self, transform: &Transform) -> (Vec3, Vec3) {
+        (
+            transform.vector(&Vec3::new(2.0 * self.size.x, 0.0, 0.0)),
+            transform.vector(&Vec3::new(0.0, 2.0 * self.size.y, 0.0)),
+        )
+    }
+
     pub fn new(v: &Value, sf: &SurfaceFactory) -> Quad {
+        let m = v.as_object().unwrap();
+        QuadBuilder::read(v, sf.get_material(m)).build()
+    }
+}
+
+/// Quad in the local xy plane, centered at the origin and facing +z
+#[derive(Debug, Clone)]
+pub struct QuadBuilder {
+    size: Vec2,
+    transform: AnimatedTransform,
+    material: Arc<MaterialType>,
+}
+
+impl QuadBuilder {
+    pub fn new(material: impl Into<Arc<MaterialType>>) -> QuadBuilder {
+        QuadBuilder {
+            size: Vec2::new(1.0, 1.0),
+            transform: AnimatedTransform::default(),
+            material: material.into(),
+        }
+    }
+
+    /// Read the size and the placement of the quad from its JSON description
+    pub fn read(v: &Value, material: impl Into<Arc<MaterialType>>) -> QuadBuilder {
         let m = v.as_object().unwrap();
         let size = if m.get("size").unwrap().is_number() {
             let s = read(v, "size");
@@ -145,24 +249,60 @@ impl Quad {
         } else {
             read::<Vec2>(v, "size")
         };
-        let size = size / 2.0;
 
-        let transform = Transform::read(v);
-        let material = sf.get_material(m);
+        QuadBuilder::new(material)
+            .size(size)
+            .transform(AnimatedTransform::read(v))
+    }
 
+    /// Full width and height of the quad
+    pub fn size(mut self, size: Vec2) -> QuadBuilder {
+        self.size = size;
+        self
+    }
+
+    /// Placement of the quad, either a `Transform` or an `AnimatedTransform` for a moving quad
+    pub fn transform(mut self, transform: impl Into<AnimatedTransform>) -> QuadBuilder {
+        self.transform = transform.into();
+        self
+    }
+
+    pub fn build(self) -> Quad {
         Quad {
-            size,
-            transform,
-            material,
+            size: self.size / 2.0,
+            transform: self.transform,
+            material: self.material,
+            emitter: None,
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use nalgebra_glm::{Vec2, Vec3};
+
+    use crate::core::ray::Ray;
+    use crate::core::sampling::stratified_grid;
+    use crate::core::transform::Transform;
+    use crate::materials::LambertianBuilder;
+    use crate::surfaces::{QuadBuilder, Surface};
     use crate::tests::sample_test::SurfaceTest;
     use serde_json::json;
 
+    #[test]
+    fn quad_tangents_follow_its_axes() {
+        let quad = QuadBuilder::new(LambertianBuilder::new().build())
+            .size(Vec2::new(2.0, 4.0))
+            .transform(Transform::rotate(&Vec3::x(), 90.0))
+            .build();
+        let ray = Ray::new(Vec3::new(0.2, -3.0, 0.5), Vec3::y());
+        let hit = quad.intersect(&ray).expect("should hit the quad");
+
+        approx::assert_abs_diff_eq!(hit.dpdu, Vec3::new(2.0, 0.0, 0.0), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(hit.dpdv.norm(), 4.0, epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(hit.dpdv.dot(&hit.sn), 0.0, epsilon = 1e-5);
+    }
+
     #[test]
     fn quad_monte_carlo() {
         let v = json!({
@@ -190,4 +330,49 @@ mod tests {
         let (test, mut parameters) = SurfaceTest::new(&v);
         parameters.run(&test, 1.0, 1e-2);
     }
+
+    #[test]
+    fn large_quad_monte_carlo() {
+        // a quad covering most of the hemisphere, sampled by solid angle
+        let v = json!({
+            "type": "sample_surface",
+            "name": "large_quad",
+            "surface": {
+                "type": "quad",
+                "size": [20, 10],
+                "transform": {
+                    "o": [
+                        1, 0, 0.2
+                    ],
+                    "x": [
+                        1, 1, 0
+                    ],
+                    "y": [-1, 1, 0]
+                },
+                "material": {
+                    "type": "diffuse_light",
+                    "emit": 1.0
+                }
+            }
+        });
+
+        let (test, mut parameters) = SurfaceTest::new(&v);
+        parameters.run(&test, 1.0, 1e-2);
+    }
+
+    #[test]
+    fn quad_samples_match_pdf() {
+        let quad = QuadBuilder::new(LambertianBuilder::new().build())
+            .size(Vec2::new(4.0, 2.0))
+            .transform(Transform::translate(&Vec3::new(0.5, 0.0, 0.3)))
+            .build();
+        let o = Vec3::zeros();
+        let solid_angle = 1.0 / quad.pdf(&o, 0.0, &Vec3::z());
+        for rv in stratified_grid(20) {
+            let erec = quad.sample(&o, 0.0, rv).unwrap();
+            approx::assert_relative_eq!(erec.pdf, 1.0 / solid_angle, max_relative = 1e-4);
+            approx::assert_abs_diff_eq!(erec.hit.p.z, 0.3, epsilon = 1e-5);
+            assert!(quad.intersect(&Ray::new(o, erec.wi)).is_some());
+        }
+    }
 }
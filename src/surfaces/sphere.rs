@@ -1,5 +1,6 @@
-use nalgebra_glm::{length, Vec2, Vec3};
+use nalgebra_glm::{length, normalize, Vec2, Vec3};
 use serde_json::Value;
+use std::f32::consts::PI;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
@@ -7,16 +8,17 @@ use crate::core::aabb::Aabb;
 use crate::core::onb::Onb;
 use crate::core::ray::Ray;
 use crate::core::sampling::{sample_sphere_cap, sample_sphere_cap_pdf};
-use crate::core::transform::Transform;
+use crate::core::transform::AnimatedTransform;
 use crate::core::utils::{direction_to_spherical_uv, read_or, INTERSECTION_TEST};
 use crate::materials::{Material, MaterialType};
 use crate::surfaces::{EmitterRecord, HitInfo, Surface, SurfaceFactory};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Sphere {
-    transform: Transform,
+    transform: AnimatedTransform,
     radius: f32,
     material: Arc<MaterialType>,
+    emitter: Option<usize>,
 }
 
 impl Sphere {
@@ -26,15 +28,61 @@ impl Sphere {
             max: Vec3::new(self.radius, self.radius, self.radius),
         }
     }
+    /// Derivatives of a point of the sphere with respect to its spherical texture coordinates, in the sphere frame
+    fn tangents(&self, p: &Vec3) -> (Vec3, Vec3) {
+        let rho = f32::sqrt(p.x * p.x + p.y * p.y);
+        if rho < 1e-6 * self.radius {
+            // the poles are singular, any frame of the tangent plane will do
+            let uvw = Onb::build_from_w(p);
+            return (uvw.local(&Vec3::x()), uvw.local(&Vec3::y()));
+        }
+        // u follows the longitude over a full turn, v the latitude from the south pole
+        let dpdu = 2.0 * PI * Vec3::new(-p.y, p.x, 0.0);
+        let dpdv = PI * Vec3::new(-p.x * p.z / rho, -p.y * p.z / rho, rho);
+        (dpdu, dpdv)
+    }
+
     pub fn new(v: &Value, sf: &SurfaceFactory) -> Sphere {
-        let radius = read_or(v, "radius", 1.0);
-        let transform = Transform::read(v);
-        let material = sf.get_material(v.as_object().unwrap());
+        SphereBuilder::new(sf.get_material(v.as_object().unwrap()))
+            .radius(read_or(v, "radius", 1.0))
+            .transform(AnimatedTransform::read(v))
+            .build()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SphereBuilder {
+    transform: AnimatedTransform,
+    radius: f32,
+    material: Arc<MaterialType>,
+}
+
+impl SphereBuilder {
+    pub fn new(material: impl Into<Arc<MaterialType>>) -> SphereBuilder {
+        SphereBuilder {
+            transform: AnimatedTransform::default(),
+            radius: 1.0,
+            material: material.into(),
+        }
+    }
+
+    pub fn radius(mut self, radius: f32) -> SphereBuilder {
+        self.radius = radius;
+        self
+    }
 
+    /// Placement of the sphere, either a `Transform` or an `AnimatedTransform` for a moving sphere
+    pub fn transform(mut self, transform: impl Into<AnimatedTransform>) -> SphereBuilder {
+        self.transform = transform.into();
+        self
+    }
+
+    pub fn build(self) -> Sphere {
         Sphere {
-            radius,
-            transform,
-            material,
+            transform: self.transform,
+            radius: self.radius,
+            material: self.material,
+            emitter: None,
         }
     }
 }
@@ -43,7 +91,8 @@ impl Surface for Sphere {
     fn intersect(&self, ray: &Ray) -> Option<HitInfo> {
         INTERSECTION_TEST.fetch_add(1, Ordering::SeqCst);
         // put ray into sphere frame
-        let ray_transformed = self.transform.inverse().ray(ray);
+        let transform = self.transform.at(ray.time);
+        let ray_transformed = transform.inverse().ray(ray);
 
         let oc = ray_transformed.origin;
 
@@ -67,31 +116,38 @@ impl Surface for Sphere {
 
         let p_sphere_frame = ray_transformed.at(root);
         // put point and normal back into the world frame
-        let p = self.transform.point(&p_sphere_frame);
-        let n = self.transform.normal(&(p_sphere_frame / self.radius));
-        let uv = direction_to_spherical_uv(&p_sphere_frame);
+        let p = transform.point(&p_sphere_frame);
+        let n = transform.normal(&(p_sphere_frame / self.radius));
+        let uv = direction_to_spherical_uv(&normalize(&p_sphere_frame));
+        let (dpdu, dpdv) = self.tangents(&p_sphere_frame);
 
         let hit = HitInfo {
             t: root,
             p,
             gn: n,
             sn: n,
+            dpdu: transform.vector(&dpdu),
+            dpdv: transform.vector(&dpdv),
             uv,
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
             mat: Arc::clone(&self.material),
+            emitter: self.emitter,
         };
         Some(hit)
     }
 
     fn bounds(&self) -> Aabb {
-        self.transform.aabb(&self.local_bounds())
+        self.transform.motion_bounds(&self.local_bounds())
     }
 
-    fn sample(&self, o: &Vec3, rv: Vec2) -> Option<EmitterRecord> {
-        let center = self.transform.point(&Vec3::zeros());
+    fn sample(&self, o: &Vec3, time: f32, rv: Vec2) -> Option<EmitterRecord> {
+        let transform = self.transform.at(time);
+        let center = transform.point(&Vec3::zeros());
         let direction_centre: Vec3 = center - o;
         let dist = length(&(o - center));
 
-        let radius = length(&self.transform.vector(&(Vec3::z()))) * self.radius;
+        let radius = length(&transform.vector(&(Vec3::z()))) * self.radius;
         let cos_theta_max_from_p = f32::sqrt(dist * dist - radius * radius) / dist;
 
         if radius > dist {
@@ -101,7 +157,7 @@ impl Surface for Sphere {
         let uvw = Onb::build_from_w(&direction_centre);
         let sample_direction = uvw.local(&sample_sphere_cap(rv, cos_theta_max_from_p));
 
-        let sample_ray = Ray::new(*o, sample_direction);
+        let sample_ray = Ray::new(*o, sample_direction).with_time(time);
 
         let hit = self.intersect(&sample_ray)?;
         let pdf = sample_sphere_cap_pdf(cos_theta_max_from_p, cos_theta_max_from_p);
@@ -121,10 +177,10 @@ impl Surface for Sphere {
         Some(erec)
     }
 
-    fn pdf(&self, o: &Vec3, dir: &Vec3) -> f32 {
-        let test_ray = Ray::new(*o, *dir);
+    fn pdf(&self, o: &Vec3, time: f32, dir: &Vec3) -> f32 {
+        let test_ray = Ray::new(*o, *dir).with_time(time);
         if let Some(_hit) = self.intersect(&test_ray) {
-            let center = self.transform.point(&Vec3::zeros());
+            let center = self.transform.at(time).point(&Vec3::zeros());
             // let direction = center - o;
             let dist = length(&(o - center));
             let cos_theta_max = f32::sqrt(dist * dist - self.radius * self.radius) / dist;
@@ -134,9 +190,18 @@ impl Surface for Sphere {
         0.0
     }
 
+    fn area(&self) -> f32 {
+        let radius = length(&self.transform.at(0.0).vector(&Vec3::z())) * self.radius;
+        4.0 * PI * radius * radius
+    }
+
     fn is_emissive(&self) -> bool {
         self.material.is_emissive()
     }
+
+    fn set_emitter(&mut self, index: usize) {
+        self.emitter = Some(index);
+    }
 }
 
 #[cfg(test)]
@@ -144,10 +209,9 @@ mod tests {
     use nalgebra_glm::Vec3;
 
     use crate::core::ray::Ray;
-    use crate::core::transform::Transform;
-    use crate::materials::MaterialFactory;
-    use crate::surfaces::Sphere;
-    use crate::surfaces::Surface;
+    use crate::core::transform::{AnimatedTransform, Transform};
+    use crate::materials::{LambertianBuilder, MaterialFactory};
+    use crate::surfaces::{Sphere, SphereBuilder, Surface, SurfaceFactory};
 
     use serde_json::json;
 
@@ -166,8 +230,9 @@ mod tests {
 
         let test_sphere = Sphere {
             radius: 1.0,
-            transform: Transform::default(),
+            transform: Transform::default().into(),
             material: material.clone(),
+            emitter: None,
         };
 
         println!("Testing untransformed sphere intersection");
@@ -194,8 +259,9 @@ mod tests {
         );
         let transformed_sphere = Sphere {
             radius: 1.0,
-            transform,
+            transform: transform.into(),
             material,
+            emitter: None,
         };
         let test_ray = Ray::new(Vec3::new(1.0, 0.5, 8.0), Vec3::new(0.0, 0.0, -1.0));
 
@@ -213,6 +279,76 @@ mod tests {
         }
     }
 
+    #[test]
+    fn sphere_tangents_follow_uvs() {
+        let mf = MaterialFactory::new();
+        let material = mf.create_material(&json!({"type": "lambertian", "albedo": 1.0}));
+        let sphere = Sphere {
+            radius: 2.0,
+            transform: Transform::default().into(),
+            material,
+            emitter: None,
+        };
+
+        // nearby rays hitting the sphere, the uv change should match the tangents
+        let ray = Ray::new(Vec3::new(0.3, -4.0, 0.7), Vec3::y());
+        let offset = Vec3::new(1e-3, 0.0, 1e-3);
+        let shifted = Ray::new(ray.origin + offset, Vec3::y());
+        let hit = sphere.intersect(&ray).expect("should hit the sphere");
+        let hit_shifted = sphere.intersect(&shifted).expect("should hit the sphere");
+
+        let duv = hit_shifted.uv - hit.uv;
+        let predicted = hit.p + duv.x * hit.dpdu + duv.y * hit.dpdv;
+        approx::assert_abs_diff_eq!(predicted, hit_shifted.p, epsilon = 1e-4);
+        approx::assert_abs_diff_eq!(hit.dpdu.dot(&hit.sn), 0.0, epsilon = 1e-4);
+        approx::assert_abs_diff_eq!(hit.dpdv.dot(&hit.sn), 0.0, epsilon = 1e-4);
+        assert!(hit.dpdu.cross(&hit.dpdv).dot(&hit.sn) > 0.0);
+    }
+
+    #[test]
+    fn builder_matches_json() {
+        let v = json!({
+            "type": "sphere",
+            "radius": 2.0,
+            "transform": {"translate": [1.0, 2.0, 3.0]},
+            "material": {"type": "lambertian", "albedo": [0.2, 0.4, 0.6]}
+        });
+        let sf = SurfaceFactory {
+            material_factory: MaterialFactory::new(),
+        };
+        let from_json = Sphere::new(&v, &sf);
+
+        let material = LambertianBuilder::new()
+            .albedo(Vec3::new(0.2, 0.4, 0.6))
+            .build();
+        let from_builder = SphereBuilder::new(material)
+            .radius(2.0)
+            .transform(Transform::translate(&Vec3::new(1.0, 2.0, 3.0)))
+            .build();
+
+        assert_eq!(from_json, from_builder);
+    }
+
+    #[test]
+    fn moving_sphere() {
+        let motion = AnimatedTransform::new(vec![
+            (0.0, Transform::default()),
+            (1.0, Transform::translate(&Vec3::new(4.0, 0.0, 0.0))),
+        ]);
+        let sphere = SphereBuilder::new(LambertianBuilder::new().build())
+            .transform(motion)
+            .build();
+
+        let ray = Ray::new(Vec3::new(4.0, 0.0, 5.0), -Vec3::z());
+        assert!(sphere.intersect(&ray.clone().with_time(0.0)).is_none());
+        let hit = sphere.intersect(&ray.with_time(1.0)).unwrap();
+        approx::assert_abs_diff_eq!(hit.p, Vec3::new(4.0, 0.0, 1.0), epsilon = 1e-5);
+
+        let bounds = sphere.bounds();
+        approx::assert_abs_diff_eq!(bounds.min, Vec3::new(-1.0, -1.0, -1.0), epsilon = 1e-5);
+        approx::assert_abs_diff_eq!(bounds.max, Vec3::new(5.0, 1.0, 1.0), epsilon = 1e-5);
+    }
+
     use crate::tests::sample_test::SurfaceTest;
 
     #[test]
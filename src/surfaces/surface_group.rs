@@ -22,22 +22,22 @@ impl Surface for LinearSurfaceGroup {
         option_hit
     }
 
-    fn sample(&self, origin: &Vec3, rv: Vec2) -> Option<EmitterRecord> {
+    fn sample(&self, origin: &Vec3, time: f32, rv: Vec2) -> Option<EmitterRecord> {
         let index = (rv.x * (self.surfaces.len() as f32)) as usize;
-        self.surfaces[index].sample(origin, rv)
+        self.surfaces[index].sample(origin, time, rv)
     }
 
-    fn sample_from_group(&self, o: &Vec3, rv: Vec2, rv1: f32) -> Option<EmitterRecord> {
+    fn sample_from_group(&self, o: &Vec3, time: f32, rv: Vec2, rv1: f32) -> Option<EmitterRecord> {
         let index = (rv1 * (self.surfaces.len() as f32)) as usize;
-        self.surfaces[index].sample(o, rv)
+        self.surfaces[index].sample(o, time, rv)
     }
 
-    fn pdf_child(&self, o: &Vec3, dir: &Vec3, rv: f32) -> f32 {
+    fn pdf_child(&self, o: &Vec3, time: f32, dir: &Vec3, rv: f32) -> f32 {
         let index = (rv * (self.surfaces.len() as f32)) as usize;
-        self.surfaces[index].pdf(o, dir)
+        self.surfaces[index].pdf(o, time, dir)
     }
 
-    fn pdf(&self, _o: &Vec3, _dir: &Vec3) -> f32 {
+    fn pdf(&self, _o: &Vec3, _time: f32, _dir: &Vec3) -> f32 {
         // must multiply this by the child pdf
         let n_sufaces = self.surfaces.len() as f32;
         1.0 / n_sufaces
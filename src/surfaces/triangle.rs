@@ -5,9 +5,14 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use crate::core::aabb::Aabb;
+use crate::core::assets::load_obj;
+use crate::core::onb::Onb;
 use crate::core::ray::Ray;
-use crate::core::sampling::{sample_triangle, sample_triangle_pdf};
-use crate::core::transform::Transform;
+use crate::core::sampling::{
+    sample_spherical_triangle, sample_triangle, sample_triangle_pdf, spherical_triangle_area,
+    MAX_SPHERICAL_SAMPLE_AREA, MIN_SPHERICAL_SAMPLE_AREA,
+};
+use crate::core::transform::{AnimatedTransform, Transform};
 use crate::core::utils::{read, INTERSECTION_TEST};
 use crate::materials::{Material, MaterialType};
 use crate::surfaces::{EmitterRecord, HitInfo, Surface};
@@ -43,19 +48,38 @@ pub struct Mesh {
     /// Transformation that the data has already been transformed by
     transform: Transform,
 
+    /// Motion during the exposure, the vertices are then kept in local space
+    motion: Option<AnimatedTransform>,
+
     /// The bounds, after transformation
     bbox: Aabb,
 }
 
 impl Mesh {
     pub fn read(v: &Value, sf: &SurfaceFactory) -> Vec<SurfaceType> {
-        let transform = Transform::read(v);
         let filename: String = read(v, "filename");
+        MeshBuilder::new(filename, sf.get_material(v.as_object().unwrap()))
+            .transform(AnimatedTransform::read(v))
+            .build()
+    }
+
+    /// Load all the models of an OBJ file and return one triangle per face
+    ///
+    /// Static meshes are transformed once at load time, moving meshes keep their vertices in local space.
+    fn load(
+        filename: &str,
+        transform: &AnimatedTransform,
+        material: &Arc<MaterialType>,
+    ) -> Vec<SurfaceType> {
+        let (motion, transform) = if transform.is_animated() {
+            (Some(transform.clone()), Transform::default())
+        } else {
+            (None, transform.at(0.0))
+        };
 
-        let obj = tobj::load_obj(filename, &tobj::OFFLINE_RENDERING_LOAD_OPTIONS);
-        let (models, _) = obj.expect("Failed to load OBJ file");
+        let models = load_obj(filename);
         let mut output = Vec::new();
-        for model in models {
+        for model in models.iter() {
             let mesh = &model.mesh;
             let vs: Vec<Vec3> = mesh
                 .positions
@@ -100,9 +124,6 @@ impl Mesh {
 
             assert_eq!(mesh.positions.len() % 3, 0);
 
-            let m = v.as_object().unwrap();
-            let material = sf.get_material(m);
-
             let n_triangles = vertex_indices.len();
             let my_mesh = Mesh {
                 vertex_positions: vs,
@@ -112,8 +133,9 @@ impl Mesh {
                 normal_indices,
                 texture_indices,
                 material_indices: Vec::new(),
-                materials: material,
+                materials: material.clone(),
                 transform: transform.clone(),
+                motion: motion.clone(),
                 bbox: aabb,
             };
 
@@ -124,6 +146,7 @@ impl Mesh {
                     SurfaceType::Triangle(Triangle {
                         mesh: rc_mesh.clone(),
                         face_idx: i,
+                        emitter: None,
                     })
                 })
                 .collect();
@@ -133,59 +156,140 @@ impl Mesh {
     }
 }
 
+/// Triangle mesh loaded from an OBJ file
+#[derive(Debug, Clone)]
+pub struct MeshBuilder {
+    filename: String,
+    transform: AnimatedTransform,
+    material: Arc<MaterialType>,
+}
+
+impl MeshBuilder {
+    pub fn new(filename: impl Into<String>, material: impl Into<Arc<MaterialType>>) -> MeshBuilder {
+        MeshBuilder {
+            filename: filename.into(),
+            transform: AnimatedTransform::default(),
+            material: material.into(),
+        }
+    }
+
+    /// Placement of the mesh, either a `Transform` or an `AnimatedTransform` for a moving mesh
+    pub fn transform(mut self, transform: impl Into<AnimatedTransform>) -> MeshBuilder {
+        self.transform = transform.into();
+        self
+    }
+
+    /// Return one surface per triangle of the mesh
+    pub fn build(self) -> Vec<SurfaceType> {
+        Mesh::load(&self.filename, &self.transform, &self.material)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Triangle {
     mesh: Arc<Mesh>,
     face_idx: usize,
+    emitter: Option<usize>,
 }
 
 impl Triangle {
     pub fn new(v: &Value, sf: &SurfaceFactory) -> Triangle {
         let m = v.as_object().unwrap();
-        let transform = Transform::read(v);
-        let material = sf.get_material(m);
 
         assert!(
             m.contains_key("positions"),
             "Triangle should have 'positions'"
         );
-        let pos = read::<Vec<Vec3>>(v, "positions");
+        let positions = read::<[Vec3; 3]>(v, "positions");
+        let mut builder =
+            TriangleBuilder::new(positions, sf.get_material(m)).transform(Transform::read(v));
+
+        if m.contains_key("normals") {
+            builder = builder.normals(read(v, "normals"));
+        } else {
+            println!("no normals in triangle");
+        }
+
+        if m.contains_key("uvs") {
+            builder = builder.uvs(read(v, "uvs"));
+        } else {
+            println!("no texture in triangle");
+        }
+
+        builder.build()
+    }
+}
+
+/// Single triangle, with optional per vertex normals and texture coordinates
+#[derive(Debug, Clone)]
+pub struct TriangleBuilder {
+    positions: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[Vec2; 3]>,
+    transform: Transform,
+    material: Arc<MaterialType>,
+}
+
+impl TriangleBuilder {
+    pub fn new(positions: [Vec3; 3], material: impl Into<Arc<MaterialType>>) -> TriangleBuilder {
+        TriangleBuilder {
+            positions,
+            normals: None,
+            uvs: None,
+            transform: Transform::default(),
+            material: material.into(),
+        }
+    }
+
+    pub fn normals(mut self, normals: [Vec3; 3]) -> TriangleBuilder {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn uvs(mut self, uvs: [Vec2; 3]) -> TriangleBuilder {
+        self.uvs = Some(uvs);
+        self
+    }
 
+    pub fn transform(mut self, transform: Transform) -> TriangleBuilder {
+        self.transform = transform;
+        self
+    }
+
+    pub fn build(self) -> Triangle {
         let mut aabb = Aabb::new();
-        for vertex in &pos {
+        for vertex in &self.positions {
             aabb.enclose_point(vertex);
         }
 
-        let (normals, normal_indices) = if m.contains_key("normals") {
-            (read::<Vec<Vec3>>(v, "normals"), vec![Vector3::new(0, 1, 2)])
-        } else {
-            println!("no normals in triangle");
-            (Vec::new(), Vec::new())
+        let (normals, normal_indices) = match self.normals {
+            Some(normals) => (normals.to_vec(), vec![Vector3::new(0, 1, 2)]),
+            None => (Vec::new(), Vec::new()),
         };
 
-        let (uvs, texture_indices) = if m.contains_key("uvs") {
-            (read::<Vec<Vec2>>(v, "uvs"), vec![Vector3::new(0, 1, 2)])
-        } else {
-            println!("no texture in triangle");
-            (Vec::new(), Vec::new())
+        let (uvs, texture_indices) = match self.uvs {
+            Some(uvs) => (uvs.to_vec(), vec![Vector3::new(0, 1, 2)]),
+            None => (Vec::new(), Vec::new()),
         };
 
         let mesh = Mesh {
-            vertex_positions: pos,
+            vertex_positions: self.positions.to_vec(),
             vertex_normals: normals,
             uvs,
             vertex_indices: vec![Vector3::new(0, 1, 2)],
             normal_indices,
             texture_indices,
             material_indices: Vec::new(),
-            materials: material,
-            transform,
+            materials: self.material,
+            transform: self.transform,
+            motion: None,
             bbox: aabb,
         };
 
         Triangle {
             mesh: Arc::new(mesh),
             face_idx: 0,
+            emitter: None,
         }
     }
 }
@@ -195,6 +299,79 @@ impl Triangle {
     fn vertex(&self, i: usize) -> Vec3 {
         self.mesh.vertex_positions[self.mesh.vertex_indices[self.face_idx][i]]
     }
+
+    /// Identify the mesh of the triangle, shared by all its faces
+    pub fn mesh_key(&self) -> usize {
+        Arc::as_ptr(&self.mesh) as usize
+    }
+
+    /// The three vertices in world space at a given time
+    fn world_vertices(&self, time: f32) -> [Vec3; 3] {
+        let vertices = [self.vertex(0), self.vertex(1), self.vertex(2)];
+        match &self.mesh.motion {
+            Some(motion) => {
+                let transform = motion.at(time);
+                vertices.map(|v| transform.point(&v))
+            }
+            None => vertices,
+        }
+    }
+
+    /// Sample a point uniformly over the area of the triangle, whatever solid angle it subtends
+    pub fn sample_area(&self, origin: &Vec3, time: f32, rv: Vec2) -> Option<EmitterRecord> {
+        let [v0, v1, v2] = self.world_vertices(time);
+
+        let p = sample_triangle(&v0, &v1, &v2, rv);
+        let wi = normalize(&(p - origin));
+
+        // look the point up like a ray reaching it, so that textured emission and the hit distance match
+        let hit = self.intersect(&Ray::new(*origin, wi).with_time(time))?;
+
+        let pdf = sample_triangle_pdf(&v0, &v1, &v2);
+        let cosine = f32::abs(dot(&wi, &hit.gn));
+        let geometry_factor = hit.t * hit.t / cosine;
+
+        Some(self.emitter_record(origin, time, wi, geometry_factor * pdf, hit))
+    }
+
+    /// Solid angle density of `sample_area`()
+    pub fn pdf_area(&self, o: &Vec3, time: f32, dir: &Vec3) -> f32 {
+        if let Some(hit) = self.intersect(&Ray::new(*o, *dir).with_time(time)) {
+            let [v0, v1, v2] = self.world_vertices(time);
+
+            let pdf = sample_triangle_pdf(&v0, &v1, &v2);
+
+            let distance2 = hit.t * hit.t * length2(dir);
+            let cosine = f32::abs(dot(dir, &hit.gn) / length(dir));
+            let geometry_factor = distance2 / cosine;
+
+            return geometry_factor * pdf;
+        }
+        0.0
+    }
+
+    fn emitter_record(
+        &self,
+        origin: &Vec3,
+        time: f32,
+        wi: Vec3,
+        pdf: f32,
+        hit: HitInfo,
+    ) -> EmitterRecord {
+        let emitted = self
+            .mesh
+            .materials
+            .emmitted(&Ray::new(*origin, wi).with_time(time), &hit)
+            .unwrap_or_default();
+
+        EmitterRecord {
+            o: *origin,
+            wi,
+            pdf,
+            hit,
+            emitted,
+        }
+    }
 }
 
 impl Surface for Triangle {
@@ -231,7 +408,29 @@ impl Surface for Triangle {
             t2.replace(self.mesh.uvs[it.z]);
         }
         let material = self.mesh.materials.clone();
-        single_triangle_intersect(ray, &v0, &v1, &v2, &n0, &n1, &n2, &t0, &t1, &t2, material)
+        let Some(motion) = &self.mesh.motion else {
+            let hit = single_triangle_intersect(
+                ray, &v0, &v1, &v2, &n0, &n1, &n2, &t0, &t1, &t2, material,
+            )?;
+            return Some(HitInfo {
+                emitter: self.emitter,
+                ..hit
+            });
+        };
+
+        // intersect in the local space of the moving mesh
+        let transform = motion.at(ray.time);
+        let local_ray = transform.inverse().ray(ray);
+        let mut hit = single_triangle_intersect(
+            &local_ray, &v0, &v1, &v2, &n0, &n1, &n2, &t0, &t1, &t2, material,
+        )?;
+        hit.p = transform.point(&hit.p);
+        hit.gn = transform.normal(&hit.gn);
+        hit.sn = transform.normal(&hit.sn);
+        hit.dpdu = transform.vector(&hit.dpdu);
+        hit.dpdv = transform.vector(&hit.dpdv);
+        hit.emitter = self.emitter;
+        Some(hit)
     }
 
     fn bounds(&self) -> Aabb {
@@ -250,73 +449,62 @@ impl Surface for Triangle {
                 aabb.max[i] += 5e-5;
             }
         }
-        aabb
-    }
-
-    fn sample(&self, origin: &Vec3, rv: Vec2) -> Option<EmitterRecord> {
-        let v0 = self.vertex(0);
-        let v1 = self.vertex(1);
-        let v2 = self.vertex(2);
 
-        let p = sample_triangle(&v0, &v1, &v2, rv);
-        let wi = p - origin;
-        let distance2 = length2(&wi);
-        let t = f32::sqrt(distance2);
-        let normal = self.mesh.transform.normal(&Vec3::z());
-
-        let pdf = sample_triangle_pdf(&v0, &v1, &v2);
-        let cosine = f32::abs(dot(&wi, &normal));
-        let geometry_factor = distance2 / cosine;
-
-        let pdf = geometry_factor * pdf;
-
-        let hit = HitInfo {
-            t,
-            p,
-            mat: self.mesh.materials.clone(),
-            gn: normal,
-            sn: normal,
-            uv: Vec2::zeros(),
-        };
-
-        let emitted = self
-            .mesh
-            .materials
-            .emmitted(&Ray::new(*origin, wi), &hit)
-            .unwrap_or_default();
+        match &self.mesh.motion {
+            Some(motion) => motion.motion_bounds(&aabb),
+            None => aabb,
+        }
+    }
 
-        let erec = EmitterRecord {
-            o: *origin,
-            wi,
-            pdf,
-            hit,
-            emitted,
-        };
+    fn sample(&self, origin: &Vec3, time: f32, rv: Vec2) -> Option<EmitterRecord> {
+        let [v0, v1, v2] = self.world_vertices(time);
+        let solid_angle = spherical_triangle_area(&v0, &v1, &v2, origin);
+        if !(MIN_SPHERICAL_SAMPLE_AREA..MAX_SPHERICAL_SAMPLE_AREA).contains(&solid_angle) {
+            return self.sample_area(origin, time, rv);
+        }
 
-        Some(erec)
+        let wi = sample_spherical_triangle(&v0, &v1, &v2, origin, rv);
+        let hit = self.intersect(&Ray::new(*origin, wi).with_time(time))?;
+        Some(self.emitter_record(origin, time, wi, 1.0 / solid_angle, hit))
     }
 
-    fn pdf(&self, o: &Vec3, dir: &Vec3) -> f32 {
-        if let Some(hit) = self.intersect(&Ray::new(*o, *dir)) {
-            let v0 = self.vertex(0);
-            let v1 = self.vertex(1);
-            let v2 = self.vertex(2);
-
-            let pdf = sample_triangle_pdf(&v0, &v1, &v2);
+    fn pdf(&self, o: &Vec3, time: f32, dir: &Vec3) -> f32 {
+        if self
+            .intersect(&Ray::new(*o, *dir).with_time(time))
+            .is_none()
+        {
+            return 0.0;
+        }
+        let [v0, v1, v2] = self.world_vertices(time);
+        let solid_angle = spherical_triangle_area(&v0, &v1, &v2, o);
+        if (MIN_SPHERICAL_SAMPLE_AREA..MAX_SPHERICAL_SAMPLE_AREA).contains(&solid_angle) {
+            1.0 / solid_angle
+        } else {
+            self.pdf_area(o, time, dir)
+        }
+    }
 
-            let distance2 = hit.t * hit.t * length2(dir);
-            let cosine = f32::abs(dot(dir, &hit.gn) / length(dir));
-            let geometry_factor = distance2 / cosine;
+    fn area(&self) -> f32 {
+        let [v0, v1, v2] = self.world_vertices(0.0);
+        0.5 * length(&cross(&(v1 - v0), &(v2 - v0)))
+    }
 
-            return geometry_factor * pdf;
+    fn normal_bounds(&self) -> (Vec3, f32) {
+        if self.mesh.motion.is_some() {
+            return (Vec3::z(), -1.0);
         }
-        0.0
+        let [v0, v1, v2] = self.world_vertices(0.0);
+        (normalize(&cross(&(v1 - v0), &(v2 - v0))), 1.0)
     }
 
     // TODO : change this if multiple materials !
     fn is_emissive(&self) -> bool {
         self.mesh.materials.is_emissive()
     }
+
+    fn set_emitter(&mut self, index: usize) {
+        self.emitter = Some(index);
+    }
 }
 
 /// Ray-Triangle intersection
@@ -394,13 +582,15 @@ pub fn single_triangle_intersect(
     };
 
     // vertex texture coordinates
-    let uv = if t0.is_some() && t1.is_some() && t2.is_some() {
+    let (uv, (dpdu, dpdv)) = if t0.is_some() && t1.is_some() && t2.is_some() {
         // Do we have per-vertex texture coordinates available?
         //  barycentric interpolation of the per-vertex texture coordinates
-        (1.0 - u - v) * (t0.unwrap()) + u * (t1.unwrap()) + v * (t2.unwrap())
+        let (t0, t1, t2) = (t0.unwrap(), t1.unwrap(), t2.unwrap());
+        let uv = (1.0 - u - v) * t0 + u * t1 + v * t2;
+        (uv, triangle_tangents(v0, v1, v2, &t0, &t1, &t2, &gn))
     } else {
         // We don't have per-vertex texture coordinates - just use the geometric normal
-        Vector2::new(u, v)
+        (Vector2::new(u, v), (edge1, edge2))
     };
 
     let hit = HitInfo {
@@ -408,12 +598,42 @@ pub fn single_triangle_intersect(
         p: ray.at(t),
         gn,
         sn,
+        dpdu,
+        dpdv,
         uv,
+        duvdx: Vec2::zeros(),
+        duvdy: Vec2::zeros(),
         mat: material,
+        emitter: None,
     };
     Some(hit)
 }
 
+/// Derivatives of the position with respect to the texture coordinates, constant over the triangle
+fn triangle_tangents(
+    v0: &Vec3,
+    v1: &Vec3,
+    v2: &Vec3,
+    t0: &Vec2,
+    t1: &Vec2,
+    t2: &Vec2,
+    gn: &Vec3,
+) -> (Vec3, Vec3) {
+    let duv02 = t0 - t2;
+    let duv12 = t1 - t2;
+    let dp02 = v0 - v2;
+    let dp12 = v1 - v2;
+    let det = duv02.x * duv12.y - duv02.y * duv12.x;
+    if det.abs() < 1e-12 {
+        // degenerate texture coordinates, any frame of the triangle plane will do
+        let uvw = Onb::build_from_w(gn);
+        return (uvw.local(&Vec3::x()), uvw.local(&Vec3::y()));
+    }
+    let dpdu = (duv12.y * dp02 - duv02.y * dp12) / det;
+    let dpdv = (duv02.x * dp12 - duv12.x * dp02) / det;
+    (dpdu, dpdv)
+}
+
 #[cfg(test)]
 mod tests {
     use approx::assert_abs_diff_eq;
@@ -468,6 +688,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn triangle_tangents_follow_uvs() {
+        let v0 = Vec3::new(0.0, 0.0, 0.0);
+        let v1 = Vec3::new(2.0, 0.0, 0.0);
+        let v2 = Vec3::new(0.0, 3.0, 0.0);
+        let t0 = Some(Vec2::new(0.0, 0.0));
+        let t1 = Some(Vec2::new(1.0, 0.0));
+        let t2 = Some(Vec2::new(0.0, 1.0));
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+
+        let mf = MaterialFactory::new();
+        let material = mf.create_material(&json!({"type": "lambertian", "albedo": 1.0}));
+
+        let hit = single_triangle_intersect(
+            &ray, &v0, &v1, &v2, &None, &None, &None, &t0, &t1, &t2, material,
+        )
+        .expect("did not hit");
+        assert_abs_diff_eq!(hit.dpdu, Vec3::new(2.0, 0.0, 0.0), epsilon = 1e-5);
+        assert_abs_diff_eq!(hit.dpdv, Vec3::new(0.0, 3.0, 0.0), epsilon = 1e-5);
+    }
+
     use crate::tests::sample_test::SurfaceTest;
     #[test]
     fn triangle_monte_carlo() {
@@ -497,4 +738,34 @@ mod tests {
         let (test, mut parameters) = SurfaceTest::new(&v);
         parameters.run(&test, 1.0, 1e-2);
     }
+
+    #[test]
+    fn large_triangle_monte_carlo() {
+        // a triangle close to the origin, sampled by solid angle
+        let v = json!({
+            "type": "sample_surface",
+            "name": "large_triangle",
+            "surface": {
+                "type": "triangle",
+                "positions": [
+                    [
+                        -5.0, -3.0, 0.1
+                    ],
+                    [
+                        6.0, -2.0, 0.1
+                    ],
+                    [
+                        0.0, 8.0, 0.4
+                    ]
+                ],
+                "material": {
+                    "type": "lambertian",
+                    "albedo": 1.0
+                }
+            }
+        });
+
+        let (test, mut parameters) = SurfaceTest::new(&v);
+        parameters.run(&test, 1.0, 1e-2);
+    }
 }
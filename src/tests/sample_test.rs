@@ -9,8 +9,11 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::core::image2d::{Array2d, Image2d};
+use crate::core::microfacet::Ggx;
+use crate::core::onb::Onb;
+use crate::core::sampling::stratified_grid;
 use crate::core::utils::{
-    direction_to_spherical_coordinates, inferno, read, read_or, spherical_coordinates_to_direction,
+    direction_to_spherical_coordinates, inferno, read, read_or, reflect, spherical_coordinates_to_direction,
     Factory, FRAC_1_TWOPI,
 };
 use crate::materials::{Material, MaterialFactory, MaterialType};
@@ -25,11 +28,15 @@ pub struct MaterialTest {
     material: Arc<MaterialType>,
     incoming: Vec3,
     hit: HitInfo,
+    /// Whether the material may scatter below the surface
+    transmission: bool,
 }
 
 pub struct SampleTestParameters {
     any_specular: bool,
     any_below_hemisphere: bool,
+    /// Samples whose density differs from the pdf of their direction
+    pdf_mismatches: usize,
 
     name: String,
     image_width: usize,
@@ -43,34 +50,29 @@ impl MaterialTest {
         let material = mf.create_material(&v["material"]);
         let normal = normalize(&read(v, "normal"));
         let incoming = normalize(&read_or(v, "incoming", Vec3::new(0.25, 0.0, -1.0)));
+        let transmission = read_or(v, "transmission", false);
+        let uvw = Onb::build_from_w(&normal);
         let hit = HitInfo {
             t: 1.0,
             p: Vec3::zeros(),
             gn: normal,
             sn: normal,
+            dpdu: uvw.local(&Vec3::x()),
+            dpdv: uvw.local(&Vec3::y()),
             uv: Vec2::new(0.5, 0.5),
+            duvdx: Vec2::zeros(),
+            duvdy: Vec2::zeros(),
             mat: material.clone(),
+            emitter: None,
         };
-        let name = read(v, "name");
-        let image_width = read_or(v, "image_width", 512);
-        let image_height = read_or(v, "image_height", 256);
-        let num_samples = read_or(v, "num_samples", 50) * image_width * image_height;
-
         let test = MaterialTest {
             material,
             // normal: normal,
             incoming,
             hit,
+            transmission,
         };
-        let parameters = SampleTestParameters {
-            any_specular: false,
-            any_below_hemisphere: false,
-            name,
-            image_width,
-            image_height,
-            num_samples,
-        };
-        (test, parameters)
+        (test, SampleTestParameters::new(v))
     }
 }
 
@@ -82,7 +84,7 @@ impl SampleTest for MaterialTest {
                 params.any_specular = true;
             }
             let wo = normalize(&srec.wo);
-            if dot(&wo, &self.hit.sn) < -1e-8 {
+            if !self.transmission && dot(&wo, &self.hit.sn) < -1e-8 {
                 params.any_below_hemisphere = true;
                 return None;
             }
@@ -128,21 +130,7 @@ impl SurfaceTest {
         let surface_group = create_surface_group(&Map::new(), &mut surfaces_vec);
 
         let test = SurfaceTest { surface_group };
-
-        let name = read(v, "name");
-        let image_width = read_or(v, "image_width", 512);
-        let image_height = read_or(v, "image_height", 256);
-        let num_samples = read_or(v, "num_samples", 50) * image_width * image_height;
-
-        let parameters = SampleTestParameters {
-            any_specular: false,
-            any_below_hemisphere: false,
-            name,
-            image_width,
-            image_height,
-            num_samples,
-        };
-        (test, parameters)
+        (test, SampleTestParameters::new(v))
     }
 }
 
@@ -150,17 +138,72 @@ impl SampleTest for SurfaceTest {
     fn sample(&self, _params: &mut SampleTestParameters, rv: Vec2, rv1: f32) -> Option<Vec3> {
         let erec = self
             .surface_group
-            .sample_from_group(&Vec3::zeros(), rv, rv1)?;
+            .sample_from_group(&Vec3::zeros(), 0.0, rv, rv1)?;
         let dir = normalize(&erec.wi);
         Some(dir)
     }
 
     fn pdf(&self, _params: &mut SampleTestParameters, dir: &Vec3, rv: f32) -> f32 {
-        self.surface_group.pdf_child(&Vec3::zeros(), dir, rv)
+        self.surface_group.pdf_child(&Vec3::zeros(), 0.0, dir, rv)
+    }
+}
+
+/// Sampled direction and its density from two random variables and a third one
+type SampleFn<'a> = dyn Fn(Vec2, f32) -> Option<(Vec3, f32)> + 'a;
+
+/// Test of a sampling routine given as closures, for emitters and backgrounds sampled from a fixed point
+///
+/// `sample` returns the sampled direction along with its density, which has to agree with `pdf`.
+pub struct DirectionTest<'a> {
+    sample: Box<SampleFn<'a>>,
+    pdf: Box<dyn Fn(&Vec3) -> f32 + 'a>,
+}
+
+impl<'a> DirectionTest<'a> {
+    pub fn new(
+        v: &Value,
+        sample: impl Fn(Vec2, f32) -> Option<(Vec3, f32)> + 'a,
+        pdf: impl Fn(&Vec3) -> f32 + 'a,
+    ) -> (DirectionTest<'a>, SampleTestParameters) {
+        let test = DirectionTest {
+            sample: Box::new(sample),
+            pdf: Box::new(pdf),
+        };
+        (test, SampleTestParameters::new(v))
+    }
+}
+
+impl SampleTest for DirectionTest<'_> {
+    fn sample(&self, params: &mut SampleTestParameters, rv: Vec2, rv1: f32) -> Option<Vec3> {
+        let (dir, pdf) = (self.sample)(rv, rv1)?;
+        let dir = normalize(&dir);
+        if !approx::relative_eq!(pdf, (self.pdf)(&dir), max_relative = 1e-3) {
+            params.pdf_mismatches += 1;
+        }
+        Some(dir)
+    }
+
+    fn pdf(&self, _params: &mut SampleTestParameters, dir: &Vec3, _rv: f32) -> f32 {
+        (self.pdf)(dir)
     }
 }
 
 impl SampleTestParameters {
+    /// Reads the name of the heat maps, their resolution and the number of samples per pixel
+    fn new(v: &Value) -> SampleTestParameters {
+        let image_width = read_or(v, "image_width", 512);
+        let image_height = read_or(v, "image_height", 256);
+        SampleTestParameters {
+            any_specular: false,
+            any_below_hemisphere: false,
+            pdf_mismatches: 0,
+            name: read(v, "name"),
+            image_width,
+            image_height,
+            num_samples: read_or(v, "num_samples", 50) * image_width * image_height,
+        }
+    }
+
     fn print_more_statistics(&self) {
         if self.any_specular {
             println!("is_specular is set. It should not be.");
@@ -298,12 +341,29 @@ impl SampleTestParameters {
         approx::assert_abs_diff_eq!(sample_integral, target, epsilon = epsilon);
         approx::assert_abs_diff_eq!(sample_integral, integral, epsilon = epsilon);
 
+        // rounding may move a direction across the border of two regions sampled with different densities
+        assert!(
+            (self.pdf_mismatches as f32) < 1e-5 * (self.num_samples as f32),
+            "{} sampled densities differ from the pdf of their direction",
+            self.pdf_mismatches
+        );
         assert!(!nan_or_inf, "Some directions/PDFs contained invalid values (NaN or infinity). This should not happen. 
         Make sure you catch all corner cases in your code.");
         self.print_more_statistics();
     }
 }
 
+/// Part of the reflections of the normals of `ggx` visible from `wo` (in the local frame) that stay above the surface.
+///
+/// Materials reject the other ones, so this is the expected result of `run` for a single microfacet reflection lobe.
+pub fn ggx_reflection_above(ggx: &Ggx, wo: &Vec3) -> f32 {
+    let n = 512;
+    let above = stratified_grid(n)
+        .filter(|rv| reflect(&-wo, &ggx.sample_visible(wo, *rv)).z > 0.0)
+        .count();
+    above as f32 / (n * n) as f32
+}
+
 fn generate_heatmap(density: &Array2d<f32>, max_value: f32) -> Image2d {
     let mut result = Image2d::new(density.size_x, density.size_y);
 
@@ -3,7 +3,7 @@ use serde_json::{from_value, Value};
 
 use crate::core::utils::read;
 use crate::surfaces::HitInfo;
-use crate::textures::Texture;
+use crate::textures::{Texture, TextureType};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct ConstantTexture {
@@ -16,6 +16,18 @@ impl Texture for ConstantTexture {
     }
 }
 
+impl From<Vec3> for TextureType {
+    fn from(color: Vec3) -> TextureType {
+        TextureType::Constant(ConstantTexture { color })
+    }
+}
+
+impl From<f32> for TextureType {
+    fn from(value: f32) -> TextureType {
+        TextureType::from(Vec3::new(value, value, value))
+    }
+}
+
 impl ConstantTexture {
     pub fn new(v: &Value) -> ConstantTexture {
         let color = if v.is_number() {
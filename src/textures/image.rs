@@ -1,30 +1,44 @@
-use nalgebra_glm::Vec3;
+use nalgebra_glm::{Vec2, Vec3};
 use serde_json::Value;
 
-use crate::core::image2d::Image2d;
-use crate::core::utils::read;
+use crate::core::assets::load_mipmap;
+use crate::core::image2d::{Channel, ColorSpace};
+use crate::core::mipmap::{FilterMode, MipMap, WrapMode};
+use crate::core::utils::{read, read_or};
 use crate::surfaces::HitInfo;
 use crate::textures::Texture;
 
+/// Texture looked up in an image, filtered over the footprint of the ray.
+///
+/// The `wrap` mode handles the uv coordinates outside of [0, 1] and `filter` chooses how the texels under the
+/// footprint are combined, trilinear mipmapping by default. The `color_space` of the file is guessed from its format
+/// unless given, and a single `channel` can be kept, e.g. the alpha channel for a mask.
 #[derive(Debug, PartialEq, Clone)]
 pub struct ImageTexture {
-    image: Image2d,
+    mipmap: MipMap,
 }
 
 impl Texture for ImageTexture {
     fn value(&self, hit: &HitInfo) -> Option<Vec3> {
-        let x = (self.image.size_x as f32) * hit.uv.x;
-        let y = (self.image.size_y as f32) * (1.0 - hit.uv.y);
-        let v = self.image[(x as usize, y as usize)];
-        Some(v)
+        // the rows of the image go down while v goes up
+        let flip = |uv: Vec2| Vec2::new(uv.x, -uv.y);
+        let st = Vec2::new(hit.uv.x, 1.0 - hit.uv.y);
+        Some(self.mipmap.filter(st, flip(hit.duvdx), flip(hit.duvdy)))
     }
 }
 
 impl ImageTexture {
     pub fn new(v: &Value) -> ImageTexture {
         let filename: String = read(v, "filename");
-        let image = Image2d::load(filename);
+        let mipmap = load_mipmap(
+            &filename,
+            read_or(v, "color_space", ColorSpace::Auto),
+            read_or(v, "channel", Channel::Rgb),
+            read_or(v, "wrap", WrapMode::Repeat),
+            read_or(v, "filter", FilterMode::Trilinear),
+        )
+        .max_anisotropy(read_or(v, "max_anisotropy", 8.0));
 
-        ImageTexture { image }
+        ImageTexture { mipmap }
     }
 }
@@ -15,10 +15,10 @@ pub trait Texture {
     fn value(&self, hit: &HitInfo) -> Option<Vec3>;
 }
 
-use crate::textures::checker::CheckerTexture;
-use crate::textures::constant::ConstantTexture;
-use crate::textures::image::ImageTexture;
-use crate::textures::marble::MarbleTexture;
+pub use crate::textures::checker::CheckerTexture;
+pub use crate::textures::constant::ConstantTexture;
+pub use crate::textures::image::ImageTexture;
+pub use crate::textures::marble::MarbleTexture;
 
 #[enum_dispatch(Texture)]
 #[derive(Debug, PartialEq, Clone)]
@@ -55,11 +55,38 @@ pub fn create_texture(j: &Value, thing_name: &str) -> TextureType {
     texture
 }
 
+/// Like `create_texture`, for the textures holding data rather than colors, like roughness or normal maps: their
+/// images, including the ones nested in other textures, are linear unless their description says otherwise
+pub fn create_linear_texture(j: &Value, thing_name: &str) -> TextureType {
+    let mut j = j.clone();
+    if let Some(texture) = j.get_mut(thing_name) {
+        default_to_linear(texture);
+    }
+    create_texture(&j, thing_name)
+}
+
+fn default_to_linear(v: &mut Value) {
+    if let Value::Object(texture) = v {
+        if texture.get("type").and_then(Value::as_str) == Some("image") {
+            texture
+                .entry("color_space")
+                .or_insert_with(|| Value::from("linear"));
+        }
+        texture.values_mut().for_each(default_to_linear);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::textures::{create_texture, TextureType};
+    use image::{ImageBuffer, Luma};
+    use nalgebra_glm::Vec3;
     use serde_json::json;
 
+    use crate::core::ray::Ray;
+    use crate::materials::LambertianBuilder;
+    use crate::surfaces::{QuadBuilder, Surface};
+    use crate::textures::{create_linear_texture, create_texture, Texture, TextureType};
+
     #[test]
     #[should_panic]
     fn create_texture_panic() {
@@ -130,4 +157,30 @@ mod tests {
 
         assert!(matches!(texture, TextureType::Image { .. }));
     }
+
+    #[test]
+    fn data_textures_are_linear() {
+        // a roughness map stored in an 8 bits image
+        let file = tempfile::Builder::new().suffix(".png").tempfile().unwrap();
+        ImageBuffer::from_pixel(1, 1, Luma([128u8]))
+            .save(file.path())
+            .unwrap();
+        let image = json!({"type": "image", "filename": file.path().to_str().unwrap()});
+        let v = json!({
+            "roughness": image,
+            "checker": {"type": "checker", "even": image, "odd": image, "scale": 1.0}
+        });
+        let hit = QuadBuilder::new(LambertianBuilder::new().build())
+            .build()
+            .intersect(&Ray::new(Vec3::z(), -Vec3::z()))
+            .unwrap();
+        let value = |texture: TextureType| texture.value(&hit).unwrap().x;
+
+        // the stored value is read back unchanged, also when nested in another texture
+        let stored = 128.0 / 255.0;
+        approx::assert_abs_diff_eq!(value(create_linear_texture(&v, "roughness")), stored);
+        approx::assert_abs_diff_eq!(value(create_linear_texture(&v, "checker")), stored);
+        // while colors are decoded from sRGB
+        assert!(value(create_texture(&v, "roughness")) < 0.25);
+    }
 }